@@ -1,126 +1,134 @@
+use crate::call_stack::{CallKind, CallStack, Frame};
 use crate::register::Registers;
 use crate::instruction::*;
+use crate::trace::{TraceRecord, TraceSink};
+use crate::watchpoint::{Access, Watchpoints};
 
-pub struct CPU {
+pub use crate::bus::{Bus, MemoryBus};
+
+const INTERRUPT_DISPATCH_CYCLES: u8 = 20;
+
+pub struct CPU<B = MemoryBus> {
   pub registers: Registers,
   pub pc: u16,
   pub sp: u16,
-  pub bus: MemoryBus,
-}
-
-pub struct MemoryBus {
-  memory: [u8; 0x10000]
+  pub bus: B,
+  pub ime: bool,
+  pub ime_scheduled: bool,
+  pub halted: bool,
+  // 未定義のオペコード（0xD3 など）を実行して固まったときのオペコード。
+  // 実機と同じく、割り込みでも抜けず、PC はそのオペコードを指したまま何もしない
+  pub illegal_opcode: Option<u8>,
+  // これまでに消費した合計Tサイクル数
+  pub cycles: u64,
+  // 設定されている場合、各命令を実行する直前の状態を記録する
+  pub tracer: Option<Box<dyn TraceSink>>,
+  // CALL/RST/割り込みと RET/RETI から組み立てた呼び出し履歴
+  pub call_stack: CallStack,
+  // true のときは LD B,B (0x40) をソフトウェアブレークポイントとして扱う（Mooneye のテストROMが終了の合図に使う）
+  pub break_on_ld_b_b: bool,
+  // LD B,B のブレークポイントに当たったかどうか。GameBoy の run_* はこれが false に戻されるまで進まない
+  pub software_breakpoint_hit: bool,
+  // 直前に実行した条件付き分岐で条件が成立したかどうか。サイクル数の計算に使う
+  branch_taken: bool,
+  // 今の命令の中で、読み書きと内部サイクルのためにすでにバスを進めたTサイクル数
+  elapsed: u8,
 }
 
-impl MemoryBus {
-  pub fn new() -> MemoryBus {
-    MemoryBus { memory: [0; 0x10000] }
-  }
-
-  pub fn read_byte(&self, address: u16) -> u8 {
-    self.memory[address as usize]
-  }
-
-  pub fn write_byte(&mut self, address: u16, value: u8) {
-    self.memory[address as usize] = value;
+impl CPU {
+  pub fn new() -> CPU {
+    CPU::with_bus(MemoryBus::new())
   }
 }
 
-impl CPU {
-  pub fn new() -> CPU {
+impl<B: Bus> CPU<B> {
+  pub fn with_bus(bus: B) -> CPU<B> {
     CPU {
       registers: Registers::new(),
       pc: 0,
       sp: 0,
-      bus: MemoryBus::new(),
+      bus,
+      ime: false,
+      ime_scheduled: false,
+      halted: false,
+      illegal_opcode: None,
+      cycles: 0,
+      tracer: None,
+      call_stack: CallStack::new(),
+      break_on_ld_b_b: false,
+      software_breakpoint_hit: false,
+      branch_taken: false,
+      elapsed: 0,
     }
   }
 
-  fn read_next_byte(&self) -> u8 {
-    self.bus.read_byte(self.pc + 1)
+  fn read_next_byte(&mut self) -> u8 {
+    self.read_cycle(self.pc.wrapping_add(1))
   }
 
-  fn call(&mut self) -> u16 {
-    let next_pc = self.pc.wrapping_add(3);
-    self.push(next_pc);
-    self.read_immediate_16bit()
+  // 呼び出し先を読んでから戻り先を積む
+  fn call(&mut self, target: u16, return_address: u16) -> u16 {
+    self.push(return_address);
+    self.record_call(CallKind::Call, target, return_address);
+    target
   }
 
-  fn return_(&mut self, should_jump: bool) -> u16 {
+  // push した直後に呼ぶ。caller は今の PC
+  fn record_call(&mut self, kind: CallKind, target: u16, return_address: u16) {
+    self.call_stack.push(Frame {
+      kind,
+      caller: self.pc,
+      caller_bank: self.bus.rom_bank(self.pc),
+      target,
+      target_bank: self.bus.rom_bank(target),
+      sp: self.sp,
+      return_address,
+    });
+  }
+
+  fn return_(&mut self, should_jump: bool, next_pc: u16) -> u16 {
     if should_jump {
-      self.pop()
+      let sp = self.sp;
+      let address = self.pop();
+      self.call_stack.ret(self.pc, sp, address);
+      address
     } else {
-      self.pc.wrapping_add(1)
+      next_pc
     }
   }
 
-  pub fn execute(&mut self, instruction: Instruction) -> u16 {
+  // instruction を実行し、次に実行するアドレスを返す。next_pc は命令の直後のアドレス（分岐しなかった場合の行き先）
+  pub fn execute(&mut self, instruction: Instruction, next_pc: u16) -> u16 {
     match instruction {
-      Instruction::NOP => self.pc.wrapping_add(1),
+      Instruction::NOP => next_pc,
+      // 2バイト目は読み飛ばす。ボタン入力を待って止まる動作はまだない
+      Instruction::STOP => next_pc,
       Instruction::ADD(add_type) => {
         match add_type {
           AddType::Byte(target, source) => {
-            let source_value = match source {
-              AddByteSource::A => self.registers.a,
-              AddByteSource::B => self.registers.b,
-              AddByteSource::C => self.registers.c,
-              AddByteSource::D => self.registers.d,
-              AddByteSource::E => self.registers.e,
-              AddByteSource::H => self.registers.h,
-              AddByteSource::L => self.registers.l,
-              AddByteSource::D8 => self.read_next_byte(),
-              AddByteSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-              _ => panic!("TODO: implement other AddByteSource"),
-            };
+            let source_value = self.read_operand8(source);
             match target {
               AddByteTarget::A => self.add_to_a(source_value),
-              _ => panic!("TODO: implement other AddByteTarget"),
             };
-            match source {
-              AddByteSource::D8 => self.pc.wrapping_add(2),
-              _ => self.pc.wrapping_add(1),
-            }
           },
           AddType::TwoByte(target, source) => {
-            let source_value = match source {
-              AddTwoByteSource::BC => self.registers.get_bc(),
-              AddTwoByteSource::DE => self.registers.get_de(),
-              AddTwoByteSource::HL => self.registers.get_hl(),
-              AddTwoByteSource::SP => self.sp,
-              _ => panic!("TODO: implement other AddTwoByteSource"),
-            };
+            let source_value = self.read_operand16(source);
             match target {
               AddTwoByteTarget::HL => self.add_to_hl(source_value),
-              _ => panic!("TODO: implement other AddTwoByteTarget"),
             };
-            self.pc.wrapping_add(1)
-          }
+          },
+          AddType::SPOffset => {
+            self.sp = self.sp_plus_offset();
+          },
         }
+        next_pc
       },
       Instruction::ADC(target, source) => {
-        let source_value = match source {
-          AdcSource::A => self.registers.a,
-          AdcSource::B => self.registers.b,
-          AdcSource::C => self.registers.c,
-          AdcSource::D => self.registers.d,
-          AdcSource::E => self.registers.e,
-          AdcSource::H => self.registers.h,
-          AdcSource::L => self.registers.l,
-          AdcSource::D8 => self.read_next_byte(),
-          AdcSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-          _ => panic!("TODO: implement other AdcSource"),
-        };
+        let source_value = self.read_operand8(source);
         match target {
-          AdcTarget::A => {
-            let carry = if self.registers.f.carry { 1 } else { 0 };
-            self.add_to_a(source_value.wrapping_add(carry));
-          },
-          _ => panic!("TODO: implement other AdcTarget"),
+          AdcTarget::A => self.adc_to_a(source_value),
         };
-        match source {
-          AdcSource::D8 => self.pc.wrapping_add(2),
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::JP(condition) => {
         let condition_flag = match condition {
@@ -131,72 +139,43 @@ impl CPU {
           JumpConditions::Always => true,
           JumpConditions::HL => true,
         };
+        self.branch_taken = condition_flag;
         if let JumpConditions::HL = condition {
-          self.registers.get_hl()
-        } else if condition_flag {
-          self.read_immediate_16bit()
+          return self.registers.get_hl();
+        }
+        // 条件が成り立たなくても、飛び先の2バイトは読む
+        let target = self.read_immediate_16bit();
+        if condition_flag {
+          target
         } else {
-          self.pc.wrapping_add(3)
+          next_pc
         }
       },
       Instruction::SUB(source) => {
-        let source_value = match source {
-          SubSource::A => self.registers.a,
-          SubSource::B => self.registers.b,
-          SubSource::C => self.registers.c,
-          SubSource::D => self.registers.d,
-          SubSource::E => self.registers.e,
-          SubSource::H => self.registers.h,
-          SubSource::L => self.registers.l,
-          SubSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-          _ => panic!("TODO: implement other SubSource"),
-        };
+        let source_value = self.read_operand8(source);
         self.sub_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::SBC(source) => {
-        let source_value = match source {
-          SbcSource::A => self.registers.a,
-          SbcSource::B => self.registers.b,
-          SbcSource::C => self.registers.c,
-          SbcSource::D => self.registers.d,
-          SbcSource::E => self.registers.e,
-          SbcSource::H => self.registers.h,
-          SbcSource::L => self.registers.l,
-          SbcSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-          _ => panic!("TODO: implement other SbcSource"),
-        };
+        let source_value = self.read_operand8(source);
         self.sbc_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::JR(conditions) => {
         let skip_counts = self.read_next_byte() as i8;
         let condition_flag = match conditions {
-          JumpRelativeConditions::Always => {
-            true
-          },
-          JumpRelativeConditions::NoZeroFlag => {
-            self.registers.f.zero == false
-          },
-          JumpRelativeConditions::ZeroFlag => {
-            self.registers.f.zero == true
-          },
-          JumpRelativeConditions::NoCarryFlag => {
-            self.registers.f.carry == false
-          },
-          JumpRelativeConditions::CarryFlag => {
-            self.registers.f.carry == true
-          },
+          JumpRelativeConditions::Always => true,
+          JumpRelativeConditions::NoZeroFlag => !self.registers.f.zero,
+          JumpRelativeConditions::ZeroFlag => self.registers.f.zero,
+          JumpRelativeConditions::NoCarryFlag => !self.registers.f.carry,
+          JumpRelativeConditions::CarryFlag => self.registers.f.carry,
         };
+        self.branch_taken = condition_flag;
 
         if condition_flag {
-          (((self.pc.wrapping_add(2)) as i32).wrapping_add(skip_counts as i32)) as u16
+          ((next_pc as i32).wrapping_add(skip_counts as i32)) as u16
         } else {
-          self.pc.wrapping_add(2)
+          next_pc
         }
       },
       Instruction::RET(conditions) => {
@@ -207,15 +186,21 @@ impl CPU {
           RetConditions::CarryFlag => self.registers.f.carry,
           RetConditions::Always => true
         };
-        if condition_flag {
-          self.pop()
-        } else {
-          self.pc.wrapping_add(1)
+        // 条件付きの RET は、条件を調べるのに内部サイクルを1つ使う
+        if conditions != RetConditions::Always {
+          self.internal_cycle();
         }
+        self.branch_taken = condition_flag;
+        self.return_(condition_flag, next_pc)
+      },
+      Instruction::RETI => {
+        self.ime = true;
+        self.return_(true, next_pc)
       },
       Instruction::RST(target) => {
-        self.push(self.pc.wrapping_add(1));
-        match target {
+        let return_address = next_pc;
+        self.push(return_address);
+        let target = match target {
           RstTarget::RST00 => 0x00,
           RstTarget::RST08 => 0x08,
           RstTarget::RST10 => 0x10,
@@ -224,109 +209,40 @@ impl CPU {
           RstTarget::RST28 => 0x28,
           RstTarget::RST30 => 0x30,
           RstTarget::RST38 => 0x38,
-        }
+        };
+        self.record_call(CallKind::Rst, target, return_address);
+        target
       },
       Instruction::LD(load_type) => {
         match load_type {
           LoadType::Byte(target, source) => {
-            let source_value = match source {
-              LoadByteSource::A => self.registers.a,
-              LoadByteSource::B => self.registers.b,
-              LoadByteSource::C => self.registers.c,
-              LoadByteSource::D => self.registers.d,
-              LoadByteSource::E => self.registers.e,
-              LoadByteSource::H => self.registers.h,
-              LoadByteSource::L => self.registers.l,
-              LoadByteSource::D8 => self.read_next_byte(),
-              LoadByteSource::BCI => self.bus.read_byte(self.registers.get_bc()),
-              LoadByteSource::DEI => self.bus.read_byte(self.registers.get_de()),
-              LoadByteSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-              LoadByteSource::HLIP => {
-                let hl_value = self.registers.get_hl();
-                self.registers.set_hl(hl_value.wrapping_add(1));
-                self.bus.read_byte(hl_value)
-              },
-              LoadByteSource::HLIM => {
-                let hl_value = self.registers.get_hl();
-                self.registers.set_hl(hl_value.wrapping_sub(1));
-                self.bus.read_byte(hl_value)
-              },
-              _ => { panic!("TODO: implement other sources") }
-            };
-            match target {
-              LoadByteTarget::A => self.registers.a = source_value,
-              LoadByteTarget::B => self.registers.b = source_value,
-              LoadByteTarget::C => self.registers.c = source_value,
-              LoadByteTarget::D => self.registers.d = source_value,
-              LoadByteTarget::E => self.registers.e = source_value,
-              LoadByteTarget::H => self.registers.h = source_value,
-              LoadByteTarget::L => self.registers.l = source_value,
-              LoadByteTarget::BCI => self.bus.write_byte(self.registers.get_bc(), source_value),
-              LoadByteTarget::DEI => self.bus.write_byte(self.registers.get_de(), source_value),
-              LoadByteTarget::HLI => self.bus.write_byte(self.registers.get_hl(), source_value),
-              LoadByteTarget::HLIP => {
-                let hl_value = self.registers.get_hl();
-                self.bus.write_byte(self.registers.get_hl(), source_value);
-                self.registers.set_hl(hl_value.wrapping_add(1))
-              },
-              LoadByteTarget::HLIM => {
-                let hl_value = self.registers.get_hl();
-                self.bus.write_byte(self.registers.get_hl(), source_value);
-                self.registers.set_hl(hl_value.wrapping_sub(1))
-              },
-              _ => { panic!("TODO: implement other targets") }
-            };
-            match source {
-              LoadByteSource::D8  => self.pc.wrapping_add(2),
-              _                   => self.pc.wrapping_add(1),
+            if self.break_on_ld_b_b && target == Operand8::B && source == Operand8::B {
+              self.software_breakpoint_hit = true;
             }
+            let source_value = self.read_operand8(source);
+            self.write_operand8(target, source_value);
           },
           LoadType::TwoByte(target, source) => {
-            let source_value = match source {
-              LoadTwoByteSource::D16 => self.read_immediate_16bit(),
-              LoadTwoByteSource::SP => self.sp,
-              _ => { panic!("TODO: implement other sources") }
-            };
-            match target {
-              LoadTwoByteTarget::BC => self.registers.set_bc(source_value),
-              LoadTwoByteTarget::DE => self.registers.set_de(source_value),
-              LoadTwoByteTarget::HL => self.registers.set_hl(source_value),
-              LoadTwoByteTarget::SP => {
-                let value = self.read_immediate_16bit();
-                self.sp = value;
-              },
-              LoadTwoByteTarget::A16 => {
-                let address = self.read_immediate_16bit();
-
-                self.bus.write_byte(address, (source_value & 0xFF) as u8);
-                self.bus.write_byte(address + 1, (source_value >> 8) as u8);
-              },
-              _ => { panic!("TODO: implement other targets") }
-            };
-            self.pc.wrapping_add(3)
-          }
-          _ => { panic!("TODO: implement other load types") }
+            let source_value = self.read_operand16(source);
+            self.write_operand16(target, source_value);
+          },
+          LoadType::HLSPOffset => {
+            let value = self.sp_plus_offset();
+            self.registers.set_hl(value);
+          },
         }
+        next_pc
       },
       Instruction::PUSH(target) => {
-        let value = match target {
-          StackTarget::BC => self.registers.get_bc(),
-          StackTarget::DE => self.registers.get_de(),
-          StackTarget::HL => self.registers.get_hl(),
-          StackTarget::AF => self.registers.get_af(),
-        };
+        let value = self.read_operand16(target);
         self.push(value);
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::POP(target) => {
+        self.call_stack.pop(self.pc, self.sp);
         let result = self.pop();
-        match target {
-            StackTarget::BC => self.registers.set_bc(result),
-            StackTarget::DE => self.registers.set_de(result),
-            StackTarget::HL => self.registers.set_hl(result),
-            StackTarget::AF => self.registers.set_af(result),
-        };
-        self.pc.wrapping_add(1)
+        self.write_operand16(target, result);
+        next_pc
       },
       Instruction::CALL(conditions) => {
         let condition_flag = match conditions {
@@ -336,198 +252,139 @@ impl CPU {
           CallConditions::CarryFlag => self.registers.f.carry,
           CallConditions::Always => true,
         };
+        self.branch_taken = condition_flag;
+        let target = self.read_immediate_16bit();
         if condition_flag {
-          self.call()
+          self.call(target, next_pc)
         } else {
-          self.pc.wrapping_add(3)
+          next_pc
         }
       },
       Instruction::INC(target) => {
         match target {
-          IncDecTarget::A => {
-            let new_value = self.inc_8bit(self.registers.a);
-            self.registers.a = new_value;
-          },
-          IncDecTarget::B => {
-            let new_value = self.inc_8bit(self.registers.b);
-            self.registers.b = new_value;
-          },
-          IncDecTarget::C => {
-            let new_value = self.inc_8bit(self.registers.c);
-            self.registers.c = new_value;
-          },
-          IncDecTarget::D => {
-            let new_value = self.inc_8bit(self.registers.d);
-            self.registers.d = new_value;
-          },
-          IncDecTarget::E => {
-            let new_value = self.inc_8bit(self.registers.e);
-            self.registers.e = new_value;
-          },
-          IncDecTarget::H => {
-            let new_value = self.inc_8bit(self.registers.h);
-            self.registers.h = new_value;
-          },
-          IncDecTarget::L => {
-            let new_value = self.inc_8bit(self.registers.l);
-            self.registers.l = new_value;
-          },
-          IncDecTarget::BC => {
-            let value = self.registers.get_bc();
-            let new_value = value.wrapping_add(1);
-            self.registers.set_bc(new_value);
-          },
-          IncDecTarget::DE => {
-            let value = self.registers.get_de();
-            let new_value = value.wrapping_add(1);
-            self.registers.set_de(new_value);
-          },
-          IncDecTarget::HL => {
-            let value = self.registers.get_hl();
-            let new_value = value.wrapping_add(1);
-            self.registers.set_hl(new_value);
-          },
-          IncDecTarget::HLI => {
-            let address = self.registers.get_hl();
-            let value = self.bus.read_byte(address);
+          IncDecTarget::Byte(target) => {
+            let value = self.read_operand8(target);
             let new_value = self.inc_8bit(value);
-            self.bus.write_byte(address, new_value);
+            self.write_operand8(target, new_value);
           },
-          IncDecTarget::SP => {
-            let value = self.sp;
-            self.sp = value.wrapping_add(1);
+          IncDecTarget::Word(target) => {
+            let value = self.read_operand16(target);
+            self.write_operand16(target, value.wrapping_add(1));
           },
-          _ => { panic!("TODO: support more targets") }
         }
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::DEC(target) => {
         match target {
-          IncDecTarget::A => {
-            let value = self.registers.a;
-            let new_value = self.dec_8bit(value);
-            self.registers.a = new_value;
-          },
-          IncDecTarget::B => {
-            let value  = self.registers.b;
-            let new_value = self.dec_8bit(value);
-            self.registers.b = new_value;
-          },
-          IncDecTarget::C => {
-            let value  = self.registers.c;
-            let new_value = self.dec_8bit(value);
-            self.registers.c = new_value;
-          },
-          IncDecTarget::D => {
-            let value  = self.registers.d;
-            let new_value = self.dec_8bit(value);
-            self.registers.d = new_value;
-          },
-          IncDecTarget::E => {
-            let value  = self.registers.e;
-            let new_value = self.dec_8bit(value);
-            self.registers.e = new_value;
-          },
-          IncDecTarget::H => {
-            let value  = self.registers.h;
-            let new_value = self.dec_8bit(value);
-            self.registers.h = new_value;
-          },
-          IncDecTarget::L => {
-            let value  = self.registers.l;
-            let new_value = self.dec_8bit(value);
-            self.registers.l = new_value;
-          },
-
-          IncDecTarget::BC => {
-            let new_value = self.dec_16bit(self.registers.get_bc());
-            self.registers.set_bc(new_value);
-          },
-          IncDecTarget::DE => {
-            let new_value = self.dec_16bit(self.registers.get_de());
-            self.registers.set_de(new_value);
-          },
-          IncDecTarget::HL => {
-            let new_value = self.dec_16bit(self.registers.get_hl());
-            self.registers.set_hl(new_value);
-          },
-          IncDecTarget::HLI => {
-            let address = self.registers.get_hl();
-            let value = self.bus.read_byte(address);
+          IncDecTarget::Byte(target) => {
+            let value = self.read_operand8(target);
             let new_value = self.dec_8bit(value);
-            self.bus.write_byte(address, new_value);
+            self.write_operand8(target, new_value);
           },
-          IncDecTarget::SP => {
-            let new_value = self.dec_16bit(self.sp);
-            self.sp = new_value;
+          IncDecTarget::Word(target) => {
+            let value = self.read_operand16(target);
+            let new_value = self.dec_16bit(value);
+            self.write_operand16(target, new_value);
           },
-          _ => { panic!("TODO: support more targets") }
         }
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::AND(source) => {
-        let source_value = match source {
-          AndSource::A => self.registers.a,
-          AndSource::B => self.registers.b,
-          AndSource::C => self.registers.c,
-          AndSource::D => self.registers.d,
-          AndSource::E => self.registers.e,
-          AndSource::H => self.registers.h,
-          AndSource::L => self.registers.l,
-          AndSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-        };
+        let source_value = self.read_operand8(source);
         self.and_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::XOR(source) => {
-        let source_value = match source {
-          XorSource::A => self.registers.a,
-          XorSource::B => self.registers.b,
-          XorSource::C => self.registers.c,
-          XorSource::D => self.registers.d,
-          XorSource::E => self.registers.e,
-          XorSource::H => self.registers.h,
-          XorSource::L => self.registers.l,
-          XorSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-        };
+        let source_value = self.read_operand8(source);
         self.xor_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::OR(source) => {
-        let source_value = match source {
-          OrSource::A => self.registers.a,
-          OrSource::B => self.registers.b,
-          OrSource::C => self.registers.c,
-          OrSource::D => self.registers.d,
-          OrSource::E => self.registers.e,
-          OrSource::H => self.registers.h,
-          OrSource::L => self.registers.l,
-          OrSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-        };
+        let source_value = self.read_operand8(source);
         self.or_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
       },
       Instruction::CP(source) => {
-        let source_value = match source {
-          CpSource::A => self.registers.a,
-          CpSource::B => self.registers.b,
-          CpSource::C => self.registers.c,
-          CpSource::D => self.registers.d,
-          CpSource::E => self.registers.e,
-          CpSource::H => self.registers.h,
-          CpSource::L => self.registers.l,
-          CpSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-        };
+        let source_value = self.read_operand8(source);
         self.cp_a(source_value);
-        match source {
-          _ => self.pc.wrapping_add(1),
-        }
+        next_pc
+      },
+      Instruction::RLC(target) => {
+        let value = self.read_operand8(target);
+        let new_value = value.rotate_left(1);
+        self.set_shift_flags(new_value, value >> 7);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::RRC(target) => {
+        let value = self.read_operand8(target);
+        let new_value = value.rotate_right(1);
+        self.set_shift_flags(new_value, value & 1);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::RL(target) => {
+        let value = self.read_operand8(target);
+        let new_value = (value << 1) | self.registers.f.carry as u8;
+        self.set_shift_flags(new_value, value >> 7);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::RR(target) => {
+        let value = self.read_operand8(target);
+        let new_value = (value >> 1) | (self.registers.f.carry as u8) << 7;
+        self.set_shift_flags(new_value, value & 1);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::SLA(target) => {
+        let value = self.read_operand8(target);
+        let new_value = value << 1;
+        self.set_shift_flags(new_value, value >> 7);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::SRA(target) => {
+        // 最上位ビット（符号）はそのまま残す
+        let value = self.read_operand8(target);
+        let new_value = (value >> 1) | (value & 0x80);
+        self.set_shift_flags(new_value, value & 1);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::SWAP(target) => {
+        let value = self.read_operand8(target);
+        let new_value = value.rotate_left(4);
+        self.set_shift_flags(new_value, 0);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::SRL(target) => {
+        let value = self.read_operand8(target);
+        let new_value = value >> 1;
+        self.set_shift_flags(new_value, value & 1);
+        self.write_operand8(target, new_value);
+        next_pc
+      },
+      Instruction::BIT(bit, target) => {
+        let value = self.read_operand8(target);
+        self.registers.set_f(
+          Some(value & (1 << bit) == 0),
+          Some(false),
+          Some(true),
+          None
+        );
+        next_pc
+      },
+      Instruction::RES(bit, target) => {
+        let value = self.read_operand8(target);
+        self.write_operand8(target, value & !(1 << bit));
+        next_pc
+      },
+      Instruction::SET(bit, target) => {
+        let value = self.read_operand8(target);
+        self.write_operand8(target, value | (1 << bit));
+        next_pc
       },
       Instruction::RLCA => {
         let value = self.registers.a;
@@ -535,7 +392,7 @@ impl CPU {
         let new_value = (value << 1) | seventh_bit;
         self.registers.a = new_value;
         self.set_rotation_flags(seventh_bit);
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::RRCA => {
         let value = self.registers.a;
@@ -543,7 +400,7 @@ impl CPU {
         let new_value = (zeroth_bit << 7) | (value >> 1);
         self.registers.a = new_value;
         self.set_rotation_flags(zeroth_bit);
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::RLA => {
         let value = self.registers.a;
@@ -551,7 +408,7 @@ impl CPU {
         let new_value = (value << 1) | self.registers.f.carry as u8;
         self.registers.a = new_value;
         self.set_rotation_flags(seventh_bit);
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::RRA => {
         let value = self.registers.a;
@@ -560,7 +417,7 @@ impl CPU {
         let new_value = (value >> 1) | carry_flag << 7;
         self.registers.a = new_value;
         self.set_rotation_flags(zero_bit);
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::DAA => {
         let mut offset = 0;
@@ -595,7 +452,7 @@ impl CPU {
           Some(should_carry)
         );
 
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::CPL => {
         self.registers.a = !self.registers.a;
@@ -605,7 +462,7 @@ impl CPU {
           Some(true),
           None
         );
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::SCF => {
         self.registers.set_f(
@@ -614,7 +471,7 @@ impl CPU {
           Some(false),
           Some(true)
         );
-        self.pc.wrapping_add(1)
+        next_pc
       },
       Instruction::CCF => {
         self.registers.set_f(
@@ -623,43 +480,263 @@ impl CPU {
           Some(false),
           Some(!self.registers.f.carry)
         );
-        self.pc.wrapping_add(1)
+        next_pc
+      },
+      Instruction::HALT => {
+        self.halted = true;
+        next_pc
+      },
+      Instruction::DI => {
+        self.ime = false;
+        self.ime_scheduled = false;
+        next_pc
+      },
+      Instruction::EI => {
+        // IMEが有効になるのは次の命令を実行した後
+        self.ime_scheduled = true;
+        next_pc
       },
-      _ => { /* TODO: support more instructions */ self.pc },
     }
   }
 
-  pub fn step(&mut self) {
-    let mut instruction_byte = self.bus.read_byte(self.pc);
+  // 8ビットのオペランドを読む。メモリを読むものはそれぞれ1Mサイクルかかる
+  fn read_operand8(&mut self, operand: Operand8) -> u8 {
+    match operand {
+      Operand8::A => self.registers.a,
+      Operand8::B => self.registers.b,
+      Operand8::C => self.registers.c,
+      Operand8::D => self.registers.d,
+      Operand8::E => self.registers.e,
+      Operand8::H => self.registers.h,
+      Operand8::L => self.registers.l,
+      Operand8::D8 => self.read_next_byte(),
+      _ => {
+        let address = self.operand_address(operand);
+        self.read_cycle(address)
+      },
+    }
+  }
+
+  fn write_operand8(&mut self, operand: Operand8, value: u8) {
+    match operand {
+      Operand8::A => self.registers.a = value,
+      Operand8::B => self.registers.b = value,
+      Operand8::C => self.registers.c = value,
+      Operand8::D => self.registers.d = value,
+      Operand8::E => self.registers.e = value,
+      Operand8::H => self.registers.h = value,
+      Operand8::L => self.registers.l = value,
+      Operand8::D8 => unreachable!("immediate operand cannot be written"),
+      _ => {
+        let address = self.operand_address(operand);
+        self.write_cycle(address, value)
+      },
+    }
+  }
+
+  // メモリを指すオペランドのアドレス。(HL+) と (HL-) はここで HL を進める
+  fn operand_address(&mut self, operand: Operand8) -> u16 {
+    match operand {
+      Operand8::BCI => self.registers.get_bc(),
+      Operand8::DEI => self.registers.get_de(),
+      Operand8::HLI => self.registers.get_hl(),
+      Operand8::HLIP => {
+        let hl_value = self.registers.get_hl();
+        self.registers.set_hl(hl_value.wrapping_add(1));
+        hl_value
+      },
+      Operand8::HLIM => {
+        let hl_value = self.registers.get_hl();
+        self.registers.set_hl(hl_value.wrapping_sub(1));
+        hl_value
+      },
+      Operand8::A16I => self.read_immediate_16bit(),
+      Operand8::A8I => 0xFF00 | self.read_next_byte() as u16,
+      Operand8::CI => 0xFF00 | self.registers.c as u16,
+      _ => unreachable!("{:?} is not a memory operand", operand),
+    }
+  }
+
+  fn read_operand16(&mut self, operand: Operand16) -> u16 {
+    match operand {
+      Operand16::AF => self.registers.get_af(),
+      Operand16::BC => self.registers.get_bc(),
+      Operand16::DE => self.registers.get_de(),
+      Operand16::HL => self.registers.get_hl(),
+      Operand16::SP => self.sp,
+      Operand16::D16 => self.read_immediate_16bit(),
+      Operand16::A16I => {
+        let address = self.read_immediate_16bit();
+        self.read_cycle(address) as u16 | (self.read_cycle(address.wrapping_add(1)) as u16) << 8
+      },
+    }
+  }
+
+  fn write_operand16(&mut self, operand: Operand16, value: u16) {
+    match operand {
+      Operand16::AF => self.registers.set_af(value),
+      Operand16::BC => self.registers.set_bc(value),
+      Operand16::DE => self.registers.set_de(value),
+      Operand16::HL => self.registers.set_hl(value),
+      Operand16::SP => self.sp = value,
+      Operand16::D16 => unreachable!("immediate operand cannot be written"),
+      // 下位バイトから書き込む
+      Operand16::A16I => {
+        let address = self.read_immediate_16bit();
+        self.write_cycle(address, (value & 0xFF) as u8);
+        self.write_cycle(address.wrapping_add(1), (value >> 8) as u8);
+      },
+    }
+  }
+
+  // 1命令（または割り込みの受け付け）を実行し、消費したTサイクル数を返す。
+  // バスの読み書きはそれぞれ自分のMサイクルで行い、その間も周辺機器を進める
+  pub fn step(&mut self) -> u8 {
+    if let Some(watchpoints) = self.bus.watchpoints_mut() {
+      watchpoints.pc = self.pc;
+    }
+    self.elapsed = 0;
+    let cycles = self.step_instruction();
+    self.cycles += cycles as u64;
+    // 残りは命令の最後の内部サイクル（JP や RET のPCの設定など）と HALT 中の待ち
+    debug_assert!(self.elapsed <= cycles, "bus accesses exceed the instruction's {} cycles ({}) at {:04X}", cycles, self.elapsed, self.pc);
+    self.bus.tick(cycles.saturating_sub(self.elapsed));
+    if self.bus.watchpoints().is_some_and(Watchpoints::has_pending) {
+      // 条件式がレジスタやメモリを参照できるよう、いったんバスから取り出して処理する
+      let watchpoints = self.bus.watchpoints_mut().unwrap();
+      let mut taken = std::mem::take(watchpoints);
+      taken.dispatch(|condition, hits| condition.holds(self, hits));
+      *self.bus.watchpoints_mut().unwrap() = taken;
+    }
+    cycles
+  }
+
+  fn step_instruction(&mut self) -> u8 {
+    if self.illegal_opcode.is_some() {
+      return 4;
+    }
+    if let Some(cycles) = self.handle_interrupts() {
+      return cycles;
+    }
+    if self.halted {
+      return 4;
+    }
+
+    if self.tracer.is_some() {
+      let record = self.trace_record();
+      if let Some(tracer) = self.tracer.as_mut() {
+        tracer.record(&record);
+      }
+    }
+
+    let enable_ime = self.ime_scheduled;
+    let mut instruction_byte = self.read_cycle(self.pc);
+    if let Some(watchpoints) = self.bus.watchpoints_mut() {
+      watchpoints.check(Access::Execute, self.pc, instruction_byte, instruction_byte);
+    }
     let prefixed = instruction_byte == 0xCB;
     if prefixed {
-      instruction_byte = self.bus.read_byte(self.pc + 1);
+      instruction_byte = self.read_cycle(self.pc.wrapping_add(1));
     }
 
-    let next_pc = if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
-      self.execute(instruction)
-    } else {
-      let description = format!("0x{}{:x}", if prefixed { "cb" } else { "" }, instruction_byte);
-      panic!("Unkown instruction found for: {}", description)
+    let opcode = lookup(instruction_byte, prefixed);
+    // CB の後ろはすべて定義されているので、ここに来るのはプレフィックスなしの未定義オペコードだけ
+    let Some(instruction) = opcode.instruction else {
+      self.illegal_opcode = Some(instruction_byte);
+      return 4;
     };
 
-    self.pc = next_pc;
-    println!("{}", next_pc);
+    self.branch_taken = false;
+    self.pc = self.execute(instruction, self.pc.wrapping_add(opcode.length as u16));
+
+    if enable_ime && self.ime_scheduled {
+      self.ime = true;
+      self.ime_scheduled = false;
+    }
+
+    if self.branch_taken {
+      opcode.branch_cycles
+    } else {
+      opcode.cycles
+    }
+  }
+
+  pub fn trace_record(&self) -> TraceRecord {
+    let registers = &self.registers;
+    TraceRecord {
+      pc: self.pc,
+      memory: [0, 1, 2, 3].map(|offset| self.bus.peek(self.pc.wrapping_add(offset))),
+      a: registers.a,
+      f: u8::from(registers.f),
+      b: registers.b,
+      c: registers.c,
+      d: registers.d,
+      e: registers.e,
+      h: registers.h,
+      l: registers.l,
+      sp: self.sp,
+      rom_bank: self.bus.rom_bank(0x4000),
+      cycles: self.cycles,
+    }
+  }
+
+  // 割り込みが保留されていればHALTを解除し、IMEが有効ならハンドラへジャンプする
+  fn handle_interrupts(&mut self) -> Option<u8> {
+    let pending = self.bus.pending_interrupts();
+    if pending == 0 {
+      return None;
+    }
+    self.halted = false;
+    if !self.ime {
+      return None;
+    }
+
+    let interrupt = pending & pending.wrapping_neg();
+    self.bus.acknowledge_interrupt(interrupt);
+    self.ime = false;
+    self.ime_scheduled = false;
+    // 内部サイクル2つ（2つめは push の中）のあとにPCを積み、最後のMサイクルでPCを設定する
+    self.internal_cycle();
+    self.push(self.pc);
+    let vector = 0x40 + interrupt.trailing_zeros() as u16 * 8;
+    self.record_call(CallKind::Interrupt, vector, self.pc);
+    self.pc = vector;
+    Some(INTERRUPT_DISPATCH_CYCLES)
+  }
+
+  // 1Mサイクルかけてバスを読む。アクセスはMサイクルの頭で行い、そのあと4サイクル進める
+  fn read_cycle(&mut self, address: u16) -> u8 {
+    let value = self.bus.read(address);
+    self.internal_cycle();
+    value
+  }
+
+  fn write_cycle(&mut self, address: u16, value: u8) {
+    self.bus.write(address, value);
+    self.internal_cycle();
   }
 
+  // 読み書きのないMサイクル
+  fn internal_cycle(&mut self) {
+    self.bus.tick(4);
+    self.elapsed += 4;
+  }
+
+  // 積む前に SP を減らす内部サイクルがあり、上位バイトから書き込む
   fn push(&mut self, value: u16) {
+    self.internal_cycle();
     self.sp = self.sp.wrapping_sub(1);
-    self.bus.write_byte(self.sp, ((value & 0xFF00) >> 8) as u8);
+    self.write_cycle(self.sp, ((value & 0xFF00) >> 8) as u8);
 
     self.sp = self.sp.wrapping_sub(1);
-    self.bus.write_byte(self.sp, (value & 0xFF) as u8);
+    self.write_cycle(self.sp, (value & 0xFF) as u8);
   }
 
   fn pop(&mut self) -> u16 {
-    let lsb = self.bus.read_byte(self.sp) as u16;
+    let lsb = self.read_cycle(self.sp) as u16;
     self.sp = self.sp.wrapping_add(1);
 
-    let msb = self.bus.read_byte(self.sp) as u16;
+    let msb = self.read_cycle(self.sp) as u16;
     self.sp = self.sp.wrapping_add(1);
 
     (msb << 8) | lsb
@@ -692,12 +769,13 @@ impl CPU {
   fn adc_to_a(&mut self, value: u8) {
     let a_value = self.registers.a;
     let carry = if self.registers.f.carry { 1 } else { 0 };
-    let (result, did_overflow) = a_value.overflowing_add(value.wrapping_add(carry));
+    let sum = a_value as u16 + value as u16 + carry as u16;
+    let result = sum as u8;
     self.registers.set_f(
         Some(result == 0),
         Some(false),
         Some((a_value & 0x0F) + (value & 0x0F) + carry > 0x0F),
-        Some(did_overflow)
+        Some(sum > 0xFF)
     );
     self.registers.a = result;
   }
@@ -717,12 +795,13 @@ impl CPU {
   fn sbc_a(&mut self, value: u8) {
     let a = self.registers.a;
     let carry = if self.registers.f.carry { 1 } else { 0 };
-    let (result, borrow) = a.overflowing_sub(value.wrapping_add(carry));
+    // value が 0xFF でキャリーがあると8ビットでは桁あふれするので、借りは16ビットで求める
+    let result = a.wrapping_sub(value).wrapping_sub(carry);
     self.registers.set_f(
       Some(result == 0),
       Some(true),
       Some((a & 0x0F) < (value & 0x0F) + carry),
-      Some(borrow)
+      Some((a as u16) < value as u16 + carry as u16)
     );
     self.registers.a = result;
   }
@@ -750,12 +829,34 @@ impl CPU {
   }
 
   fn dec_16bit(&mut self, value: u16) -> u16 {
-    let new_value = value.wrapping_sub(1);
-    new_value
+    value.wrapping_sub(1)
   }
 
   fn read_immediate_16bit(&mut self) -> u16 {
-    self.bus.read_byte(self.pc + 1) as u16 | (self.bus.read_byte(self.pc + 2) as u16) << 8
+    self.read_cycle(self.pc.wrapping_add(1)) as u16 | (self.read_cycle(self.pc.wrapping_add(2)) as u16) << 8
+  }
+
+  // ADD SP,r8 と LD HL,SP+r8 の結果。H と C は下位バイト同士を符号なしで足したときの繰り上がり
+  fn sp_plus_offset(&mut self) -> u16 {
+    let offset = self.read_next_byte();
+    let sp = self.sp;
+    self.registers.set_f(
+        Some(false),
+        Some(false),
+        Some((sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F),
+        Some((sp & 0xFF) + offset as u16 > 0xFF)
+    );
+    sp.wrapping_add(offset as i8 as u16)
+  }
+
+  // CB命令の回転・シフトと SWAP のフラグ。RLCA などと違って Z は結果で決まる
+  fn set_shift_flags(&mut self, result: u8, carry: u8) {
+    self.registers.set_f(
+        Some(result == 0),
+        Some(false),
+        Some(false),
+        Some(carry != 0)
+    );
   }
 
   fn set_rotation_flags(&mut self, carry: u8) {
@@ -811,3 +912,8 @@ impl CPU {
   }
 }
 
+impl Default for CPU {
+  fn default() -> Self {
+    CPU::new()
+  }
+}
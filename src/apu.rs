@@ -0,0 +1,518 @@
+pub const SAMPLE_RATE: u32 = 44_100;
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+const FRAME_SEQUENCER_PERIOD: u16 = 8192;
+// 誰も取り出さない場合に備えて、保持するサンプルは1秒分までにする
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+  [0, 0, 0, 0, 0, 0, 0, 1],
+  [1, 0, 0, 0, 0, 0, 0, 1],
+  [1, 0, 0, 0, 0, 1, 1, 1],
+  [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// 読み出し時に常に1として見えるビット（0xFF10〜0xFF2F）
+const READ_MASKS: [u8; 0x20] = [
+  0x80, 0x3F, 0x00, 0xFF, 0xBF,
+  0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+  0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+  0xFF, 0xFF, 0x00, 0x00, 0xBF,
+  0x00, 0x00, 0x70,
+  0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+#[derive(Clone, Default)]
+pub struct Envelope {
+  pub initial_volume: u8,
+  pub increase: bool,
+  pub period: u8,
+  pub timer: u8,
+  pub volume: u8,
+}
+
+impl Envelope {
+  fn write(&mut self, value: u8) {
+    self.initial_volume = value >> 4;
+    self.increase = value & 0x08 != 0;
+    self.period = value & 0x07;
+  }
+
+  fn trigger(&mut self) {
+    self.volume = self.initial_volume;
+    self.timer = self.period;
+  }
+
+  fn step(&mut self) {
+    if self.period == 0 {
+      return;
+    }
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = self.period;
+      if self.increase && self.volume < 15 {
+        self.volume += 1;
+      } else if !self.increase && self.volume > 0 {
+        self.volume -= 1;
+      }
+    }
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct SquareChannel {
+  pub enabled: bool,
+  pub dac_enabled: bool,
+  pub length: u16,
+  pub length_enabled: bool,
+  pub duty: u8,
+  pub duty_step: u8,
+  pub frequency: u16,
+  pub timer: u16,
+  pub envelope: Envelope,
+  pub sweep_period: u8,
+  pub sweep_negate: bool,
+  pub sweep_shift: u8,
+  pub sweep_timer: u8,
+  pub sweep_enabled: bool,
+  pub shadow_frequency: u16,
+}
+
+impl SquareChannel {
+  fn tick(&mut self) {
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = (2048 - self.frequency) * 4;
+      self.duty_step = (self.duty_step + 1) & 0x07;
+    }
+  }
+
+  fn output(&self) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+    DUTY_PATTERNS[self.duty as usize][self.duty_step as usize] * self.envelope.volume
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.dac_enabled;
+    if self.length == 0 {
+      self.length = 64;
+    }
+    self.timer = (2048 - self.frequency) * 4;
+    self.envelope.trigger();
+
+    self.shadow_frequency = self.frequency;
+    self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+    self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+    if self.sweep_shift != 0 {
+      self.next_sweep_frequency();
+    }
+  }
+
+  fn step_length(&mut self) {
+    if self.length_enabled && self.length > 0 {
+      self.length -= 1;
+      if self.length == 0 {
+        self.enabled = false;
+      }
+    }
+  }
+
+  fn next_sweep_frequency(&mut self) -> u16 {
+    let delta = self.shadow_frequency >> self.sweep_shift;
+    let frequency = if self.sweep_negate {
+      self.shadow_frequency.wrapping_sub(delta)
+    } else {
+      self.shadow_frequency + delta
+    };
+    if frequency > 2047 {
+      self.enabled = false;
+    }
+    frequency
+  }
+
+  fn step_sweep(&mut self) {
+    if self.sweep_timer > 0 {
+      self.sweep_timer -= 1;
+    }
+    if self.sweep_timer != 0 {
+      return;
+    }
+    self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+    if !self.sweep_enabled || self.sweep_period == 0 {
+      return;
+    }
+    let frequency = self.next_sweep_frequency();
+    if frequency <= 2047 && self.sweep_shift != 0 {
+      self.shadow_frequency = frequency;
+      self.frequency = frequency;
+      self.next_sweep_frequency();
+    }
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct WaveChannel {
+  pub enabled: bool,
+  pub dac_enabled: bool,
+  pub length: u16,
+  pub length_enabled: bool,
+  pub volume_code: u8,
+  pub frequency: u16,
+  pub timer: u16,
+  pub position: u8,
+}
+
+impl WaveChannel {
+  fn tick(&mut self) {
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = (2048 - self.frequency) * 2;
+      self.position = (self.position + 1) & 0x1F;
+    }
+  }
+
+  fn output(&self, wave_ram: &[u8; 16]) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+    let byte = wave_ram[self.position as usize / 2];
+    let sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+    match self.volume_code {
+      0 => 0,
+      1 => sample,
+      2 => sample >> 1,
+      _ => sample >> 2,
+    }
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.dac_enabled;
+    if self.length == 0 {
+      self.length = 256;
+    }
+    self.timer = (2048 - self.frequency) * 2;
+    self.position = 0;
+  }
+
+  fn step_length(&mut self) {
+    if self.length_enabled && self.length > 0 {
+      self.length -= 1;
+      if self.length == 0 {
+        self.enabled = false;
+      }
+    }
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct NoiseChannel {
+  pub enabled: bool,
+  pub dac_enabled: bool,
+  pub length: u16,
+  pub length_enabled: bool,
+  pub envelope: Envelope,
+  pub clock_shift: u8,
+  pub width_mode: bool,
+  pub divisor_code: u8,
+  pub timer: u32,
+  pub lfsr: u16,
+}
+
+impl NoiseChannel {
+  fn period(&self) -> u32 {
+    NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+  }
+
+  fn tick(&mut self) {
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = self.period();
+      let bit = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+      self.lfsr = (self.lfsr >> 1) | (bit << 14);
+      if self.width_mode {
+        self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+      }
+    }
+  }
+
+  fn output(&self) -> u8 {
+    if !self.enabled || self.lfsr & 1 != 0 {
+      return 0;
+    }
+    self.envelope.volume
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.dac_enabled;
+    if self.length == 0 {
+      self.length = 64;
+    }
+    self.timer = self.period();
+    self.envelope.trigger();
+    self.lfsr = 0x7FFF;
+  }
+
+  fn step_length(&mut self) {
+    if self.length_enabled && self.length > 0 {
+      self.length -= 1;
+      if self.length == 0 {
+        self.enabled = false;
+      }
+    }
+  }
+}
+
+pub struct APU {
+  pub registers: [u8; 0x20],
+  pub wave_ram: [u8; 16],
+  pub enabled: bool,
+  pub square1: SquareChannel,
+  pub square2: SquareChannel,
+  pub wave: WaveChannel,
+  pub noise: NoiseChannel,
+  pub frame_sequencer_step: u8,
+  pub frame_sequencer_timer: u16,
+  pub sample_timer: u32,
+  // 左右交互に並んだステレオサンプル（-1.0〜1.0）
+  pub samples: Vec<f32>,
+}
+
+impl APU {
+  pub fn new() -> APU {
+    APU {
+      registers: [0; 0x20],
+      wave_ram: [0; 16],
+      enabled: false,
+      square1: SquareChannel::default(),
+      square2: SquareChannel::default(),
+      wave: WaveChannel::default(),
+      noise: NoiseChannel::default(),
+      frame_sequencer_step: 0,
+      frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+      sample_timer: 0,
+      samples: Vec::new(),
+    }
+  }
+
+  pub fn read_byte(&self, address: u16) -> u8 {
+    match address {
+      0xFF26 => {
+        let status = if self.enabled { 0x80 } else { 0 }
+          | if self.square1.enabled { 0x01 } else { 0 }
+          | if self.square2.enabled { 0x02 } else { 0 }
+          | if self.wave.enabled { 0x04 } else { 0 }
+          | if self.noise.enabled { 0x08 } else { 0 };
+        status | READ_MASKS[0x16]
+      },
+      0xFF10..=0xFF2F => {
+        let index = (address - 0xFF10) as usize;
+        self.registers[index] | READ_MASKS[index]
+      },
+      0xFF30..=0xFF3F => self.wave_ram[(address - 0xFF30) as usize],
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write_byte(&mut self, address: u16, value: u8) {
+    if let 0xFF30..=0xFF3F = address {
+      self.wave_ram[(address - 0xFF30) as usize] = value;
+      return;
+    }
+    if address == 0xFF26 {
+      let enable = value & 0x80 != 0;
+      if self.enabled && !enable {
+        self.power_off();
+      } else if !self.enabled && enable {
+        self.frame_sequencer_step = 0;
+      }
+      self.enabled = enable;
+      return;
+    }
+    // 電源オフ中はNR52以外のレジスタに書き込めない
+    if !self.enabled || !(0xFF10..=0xFF25).contains(&address) {
+      return;
+    }
+    self.registers[(address - 0xFF10) as usize] = value;
+
+    match address {
+      0xFF10 => {
+        self.square1.sweep_period = (value >> 4) & 0x07;
+        self.square1.sweep_negate = value & 0x08 != 0;
+        self.square1.sweep_shift = value & 0x07;
+      },
+      0xFF11 => {
+        self.square1.duty = value >> 6;
+        self.square1.length = 64 - (value & 0x3F) as u16;
+      },
+      0xFF12 => {
+        self.square1.envelope.write(value);
+        self.square1.dac_enabled = value & 0xF8 != 0;
+        self.square1.enabled &= self.square1.dac_enabled;
+      },
+      0xFF13 => self.square1.frequency = (self.square1.frequency & 0x700) | value as u16,
+      0xFF14 => {
+        self.square1.frequency = (self.square1.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+        self.square1.length_enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.square1.trigger();
+        }
+      },
+      0xFF16 => {
+        self.square2.duty = value >> 6;
+        self.square2.length = 64 - (value & 0x3F) as u16;
+      },
+      0xFF17 => {
+        self.square2.envelope.write(value);
+        self.square2.dac_enabled = value & 0xF8 != 0;
+        self.square2.enabled &= self.square2.dac_enabled;
+      },
+      0xFF18 => self.square2.frequency = (self.square2.frequency & 0x700) | value as u16,
+      0xFF19 => {
+        self.square2.frequency = (self.square2.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+        self.square2.length_enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.square2.trigger();
+        }
+      },
+      0xFF1A => {
+        self.wave.dac_enabled = value & 0x80 != 0;
+        self.wave.enabled &= self.wave.dac_enabled;
+      },
+      0xFF1B => self.wave.length = 256 - value as u16,
+      0xFF1C => self.wave.volume_code = (value >> 5) & 0x03,
+      0xFF1D => self.wave.frequency = (self.wave.frequency & 0x700) | value as u16,
+      0xFF1E => {
+        self.wave.frequency = (self.wave.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+        self.wave.length_enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.wave.trigger();
+        }
+      },
+      0xFF20 => self.noise.length = 64 - (value & 0x3F) as u16,
+      0xFF21 => {
+        self.noise.envelope.write(value);
+        self.noise.dac_enabled = value & 0xF8 != 0;
+        self.noise.enabled &= self.noise.dac_enabled;
+      },
+      0xFF22 => {
+        self.noise.clock_shift = value >> 4;
+        self.noise.width_mode = value & 0x08 != 0;
+        self.noise.divisor_code = value & 0x07;
+      },
+      0xFF23 => {
+        self.noise.length_enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.noise.trigger();
+        }
+      },
+      _ => {},
+    }
+  }
+
+  fn power_off(&mut self) {
+    self.registers = [0; 0x20];
+    self.square1 = SquareChannel::default();
+    self.square2 = SquareChannel::default();
+    self.wave = WaveChannel::default();
+    self.noise = NoiseChannel::default();
+  }
+
+  pub fn tick(&mut self, cycles: u8) {
+    for _ in 0..cycles {
+      if self.enabled {
+        self.square1.tick();
+        self.square2.tick();
+        self.wave.tick();
+        self.noise.tick();
+
+        self.frame_sequencer_timer -= 1;
+        if self.frame_sequencer_timer == 0 {
+          self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+          self.step_frame_sequencer();
+        }
+      }
+
+      self.sample_timer += SAMPLE_RATE;
+      if self.sample_timer >= CYCLES_PER_SECOND {
+        self.sample_timer -= CYCLES_PER_SECOND;
+        self.push_sample();
+      }
+    }
+  }
+
+  fn step_frame_sequencer(&mut self) {
+    let step = self.frame_sequencer_step;
+    if step.is_multiple_of(2) {
+      self.square1.step_length();
+      self.square2.step_length();
+      self.wave.step_length();
+      self.noise.step_length();
+    }
+    if step == 2 || step == 6 {
+      self.square1.step_sweep();
+    }
+    if step == 7 {
+      self.square1.envelope.step();
+      self.square2.envelope.step();
+      self.noise.envelope.step();
+    }
+    self.frame_sequencer_step = (step + 1) & 0x07;
+  }
+
+  fn push_sample(&mut self) {
+    if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+      return;
+    }
+    if !self.enabled {
+      self.samples.push(0.0);
+      self.samples.push(0.0);
+      return;
+    }
+
+    let outputs = [
+      (self.square1.dac_enabled, self.square1.output()),
+      (self.square2.dac_enabled, self.square2.output()),
+      (self.wave.dac_enabled, self.wave.output(&self.wave_ram)),
+      (self.noise.dac_enabled, self.noise.output()),
+    ];
+    let panning = self.registers[0x15];
+    let volume = self.registers[0x14];
+
+    let mut left = 0.0;
+    let mut right = 0.0;
+    for (channel, &(dac_enabled, amplitude)) in outputs.iter().enumerate() {
+      if !dac_enabled {
+        continue;
+      }
+      let analog = amplitude as f32 / 7.5 - 1.0;
+      if panning & (0x10 << channel) != 0 {
+        left += analog;
+      }
+      if panning & (0x01 << channel) != 0 {
+        right += analog;
+      }
+    }
+
+    let left_volume = (((volume >> 4) & 0x07) + 1) as f32 / 8.0;
+    let right_volume = ((volume & 0x07) + 1) as f32 / 8.0;
+    self.samples.push(left / 4.0 * left_volume);
+    self.samples.push(right / 4.0 * right_volume);
+  }
+}
+
+impl Default for APU {
+  fn default() -> Self {
+    APU::new()
+  }
+}
@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+// 即値を表す命令表の記号。disassembler と同じ名前を使う
+const PLACEHOLDERS: [&str; 5] = ["d16", "a16", "d8", "a8", "r8"];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssemblyError {
+  // 1から始まる行番号
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl Error for AssemblyError {}
+
+// テキストをアセンブルする。先頭のバイトは0x0000に置かれるものとしてラベルを解決する
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+  assemble_at(source, 0x0000)
+}
+
+// origin は先頭のバイトが置かれるアドレス。ラベルや JR の飛び先の計算に使う
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, AssemblyError> {
+  let opcodes = opcode_table();
+  let lines = parse_lines(source)?;
+
+  // 1パス目でラベルのアドレスを決め、2パス目で値を埋め込む。
+  // 命令の長さは即値の値に依存しないので、1パス目は未定義のラベルを0として扱えばよい
+  let mut labels = HashMap::new();
+  let mut address = origin;
+  for line in &lines {
+    if let Some(label) = &line.label
+      && labels.insert(label.clone(), address).is_some()
+    {
+      return Err(line.error(format!("duplicate label: {}", label)));
+    }
+    let size = encode(line, address, &labels, &opcodes, false)?.len();
+    address = address.wrapping_add(size as u16);
+  }
+
+  let mut bytes = Vec::new();
+  let mut address = origin;
+  for line in &lines {
+    let encoded = encode(line, address, &labels, &opcodes, true)?;
+    address = address.wrapping_add(encoded.len() as u16);
+    bytes.extend(encoded);
+  }
+  Ok(bytes)
+}
+
+// テスト用。文字列（複数なら1行ずつ）をアセンブルし、失敗したらpanicする
+//
+//   let program = asm!("ld bc, $0305", "inc b");
+#[macro_export]
+macro_rules! asm {
+  ($($line:expr),+ $(,)?) => {
+    $crate::assembler::assemble(&[$($line),+].join("\n")).unwrap_or_else(|error| panic!("{}", error))
+  };
+}
+
+struct Line {
+  number: usize,
+  label: Option<String>,
+  mnemonic: Option<String>,
+  operands: Vec<String>,
+}
+
+impl Line {
+  fn error(&self, message: String) -> AssemblyError {
+    AssemblyError { line: self.number, message }
+  }
+}
+
+// オペコードのバイト列と、命令表の表記を分解したもの（"LD (HL+),A" → "LD", ["(HL+)", "A"]）
+struct Opcode {
+  bytes: Vec<u8>,
+  mnemonic: String,
+  operands: Vec<String>,
+}
+
+fn opcode_table() -> Vec<Opcode> {
+  let mut table = Vec::new();
+  for prefixed in [false, true] {
+    for byte in 0..=0xFF {
+      let Some(instruction) = Instruction::from_byte(byte, prefixed) else { continue };
+      let template = instruction.to_string();
+      let (mnemonic, operands) = match template.split_once(' ') {
+        Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::to_string).collect()),
+        None => (template.as_str(), Vec::new()),
+      };
+      let bytes = if prefixed { vec![0xCB, byte] } else { vec![byte] };
+      table.push(Opcode { bytes, mnemonic: mnemonic.to_string(), operands });
+    }
+  }
+  table
+}
+
+fn parse_lines(source: &str) -> Result<Vec<Line>, AssemblyError> {
+  let mut lines = Vec::new();
+  let mut scope = String::new();
+  for (index, text) in source.lines().enumerate() {
+    let number = index + 1;
+    let mut text = strip_comment(text).trim();
+
+    // "Main:" や ".loop:" のようなラベル。"." で始まるローカルラベルは直前のラベルの下に入る（Main.loop）
+    let mut label = None;
+    if let Some((name, rest)) = text.split_once(':') {
+      let name = name.trim();
+      if is_label_name(name) {
+        let full_name = if let Some(local) = name.strip_prefix('.') {
+          if scope.is_empty() {
+            return Err(AssemblyError { line: number, message: format!("local label without a parent: {}", name) });
+          }
+          format!("{}.{}", scope, local)
+        } else {
+          scope = name.to_string();
+          name.to_string()
+        };
+        label = Some(full_name);
+        text = rest.trim();
+      }
+    }
+
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+      Some((mnemonic, operands)) => (Some(mnemonic), split_operands(operands.trim())),
+      None if text.is_empty() => (None, Vec::new()),
+      None => (Some(text), Vec::new()),
+    };
+    let operands = operands.into_iter().map(|operand| qualify_local_labels(&operand, &scope)).collect();
+    lines.push(Line { number, label, mnemonic: mnemonic.map(str::to_uppercase), operands });
+  }
+  Ok(lines)
+}
+
+fn strip_comment(text: &str) -> &str {
+  let mut in_string = false;
+  for (index, c) in text.char_indices() {
+    match c {
+      '"' => in_string = !in_string,
+      ';' if !in_string => return &text[..index],
+      _ => {},
+    }
+  }
+  text
+}
+
+fn is_label_name(name: &str) -> bool {
+  let name = name.strip_prefix('.').unwrap_or(name);
+  let mut chars = name.chars();
+  matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// カンマで区切る。文字列と括弧の中のカンマでは区切らない
+fn split_operands(text: &str) -> Vec<String> {
+  if text.is_empty() {
+    return Vec::new();
+  }
+  let mut operands = Vec::new();
+  let mut current = String::new();
+  let mut depth = 0;
+  let mut in_string = false;
+  for c in text.chars() {
+    match c {
+      '"' => in_string = !in_string,
+      '(' | '[' if !in_string => depth += 1,
+      ')' | ']' if !in_string => depth -= 1,
+      ',' if !in_string && depth == 0 => {
+        operands.push(current.trim().to_string());
+        current.clear();
+        continue;
+      },
+      _ => {},
+    }
+    current.push(c);
+  }
+  operands.push(current.trim().to_string());
+  operands
+}
+
+// オペランド中の ".loop" を "Main.loop" に書き換える
+fn qualify_local_labels(operand: &str, scope: &str) -> String {
+  if operand.starts_with('"') {
+    return operand.to_string();
+  }
+  let mut result = String::new();
+  let mut previous = None;
+  for c in operand.chars() {
+    let starts_local = c == '.' && !previous.is_some_and(|p: char| p.is_ascii_alphanumeric() || p == '_');
+    if starts_local {
+      result.push_str(scope);
+    }
+    result.push(c);
+    previous = Some(c);
+  }
+  result
+}
+
+fn encode(
+  line: &Line,
+  address: u16,
+  labels: &HashMap<String, u16>,
+  opcodes: &[Opcode],
+  resolve: bool,
+) -> Result<Vec<u8>, AssemblyError> {
+  let Some(mnemonic) = &line.mnemonic else { return Ok(Vec::new()) };
+  let evaluate = |expression: &str| -> Result<i64, AssemblyError> {
+    match evaluate(expression, address, labels) {
+      Err(ExpressionError::UndefinedLabel(_)) if !resolve => Ok(0),
+      Err(error) => Err(line.error(error.to_string())),
+      Ok(value) => Ok(value),
+    }
+  };
+
+  match mnemonic.as_str() {
+    "DB" => {
+      let mut bytes = Vec::new();
+      for operand in &line.operands {
+        if let Some(text) = operand.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+          bytes.extend_from_slice(text.as_bytes());
+        } else {
+          bytes.push(fit_byte(evaluate(operand)?).ok_or_else(|| line.error(format!("value out of range for db: {}", operand)))?);
+        }
+      }
+      return Ok(bytes);
+    },
+    "DW" => {
+      let mut bytes = Vec::new();
+      for operand in &line.operands {
+        let word = fit_word(evaluate(operand)?).ok_or_else(|| line.error(format!("value out of range for dw: {}", operand)))?;
+        bytes.extend_from_slice(&word.to_le_bytes());
+      }
+      return Ok(bytes);
+    },
+    _ => {},
+  }
+
+  let mut known_mnemonic = false;
+  for opcode in opcodes.iter().filter(|opcode| &opcode.mnemonic == mnemonic) {
+    known_mnemonic = true;
+    if let Some(bytes) = match_operands(opcode, line, address, &evaluate, resolve)? {
+      return Ok(bytes);
+    }
+  }
+
+  // "SUB A,B" のように、Aを省略する命令にAを書いてもよい
+  if matches!(mnemonic.as_str(), "SUB" | "AND" | "XOR" | "OR" | "CP") && line.operands.len() == 2 && compact(&line.operands[0]).eq_ignore_ascii_case("A") {
+    let shorter = Line { number: line.number, label: None, mnemonic: line.mnemonic.clone(), operands: line.operands[1..].to_vec() };
+    return encode(&shorter, address, labels, opcodes, resolve);
+  }
+
+  // 2バイト目を省略した "STOP" は "STOP 0" として扱う
+  if mnemonic == "STOP" && line.operands.is_empty() {
+    let with_operand = Line { number: line.number, label: None, mnemonic: line.mnemonic.clone(), operands: vec!["0".to_string()] };
+    return encode(&with_operand, address, labels, opcodes, resolve);
+  }
+
+  if known_mnemonic {
+    Err(line.error(format!("invalid operands for {}: {}", mnemonic, line.operands.join(", "))))
+  } else {
+    Err(line.error(format!("unknown instruction: {}", mnemonic)))
+  }
+}
+
+// オペランドが命令表の表記に合えば、オペコードと即値のバイト列を返す。
+// resolve が false（1パス目）のときは長さだけが要るので、飛び先が決まらない JR のオフセットは範囲を調べない
+fn match_operands<F>(opcode: &Opcode, line: &Line, address: u16, evaluate: &F, resolve: bool) -> Result<Option<Vec<u8>>, AssemblyError>
+where
+  F: Fn(&str) -> Result<i64, AssemblyError>,
+{
+  if opcode.operands.len() != line.operands.len() {
+    return Ok(None);
+  }
+
+  let mut immediate = Vec::new();
+  for (template, operand) in opcode.operands.iter().zip(&line.operands) {
+    let operand = compact(operand);
+    let upper = operand.to_uppercase();
+
+    if let Some(placeholder) = PLACEHOLDERS.iter().find(|placeholder| template.contains(*placeholder)) {
+      let (prefix, suffix) = template.split_once(placeholder).unwrap();
+      if !upper.starts_with(prefix) || !upper.ends_with(suffix) || upper.len() <= prefix.len() + suffix.len() {
+        return Ok(None);
+      }
+      let expression = &operand[prefix.len()..operand.len() - suffix.len()];
+      // "LD A,(1)" を "LD A,d8" として読まないように、全体が括弧で囲まれた即値は受け付けない。
+      // "(DE)" を "(a16)" と読まないように、レジスタ名も即値にはしない
+      if (prefix.is_empty() && suffix.is_empty() && is_indirect(expression)) || is_reserved(expression) {
+        return Ok(None);
+      }
+      immediate.push((*placeholder, evaluate(expression)?, operand.clone()));
+      continue;
+    }
+
+    // RST $38 の飛び先は数値として比べる
+    if let Some(hex) = template.strip_prefix('$') {
+      let target = i64::from_str_radix(hex, 16).unwrap();
+      if evaluate(&operand)? != target {
+        return Ok(None);
+      }
+      continue;
+    }
+
+    if !register_matches(&opcode.mnemonic, template, &upper) {
+      return Ok(None);
+    }
+  }
+
+  let length = opcode.bytes.len() as u16 + immediate.iter().map(|(placeholder, _, _)| placeholder_size(placeholder)).sum::<u16>();
+  let mut bytes = opcode.bytes.clone();
+  for (placeholder, value, operand) in immediate {
+    let out_of_range = || line.error(format!("value out of range: {}", operand));
+    match placeholder {
+      "d16" | "a16" => bytes.extend_from_slice(&fit_word(value).ok_or_else(out_of_range)?.to_le_bytes()),
+      "d8" => bytes.push(fit_byte(value).ok_or_else(out_of_range)?),
+      "a8" => {
+        let value = if (0xFF00..=0xFFFF).contains(&value) { value - 0xFF00 } else { value };
+        bytes.push(u8::try_from(value).map_err(|_| out_of_range())?);
+      },
+      _ if opcode.mnemonic == "JR" && !resolve => bytes.push(0),
+      _ => {
+        let offset = if opcode.mnemonic == "JR" { value - (address as i64 + length as i64) } else { value };
+        bytes.push(i8::try_from(offset).map_err(|_| out_of_range())? as u8);
+      },
+    }
+  }
+  Ok(Some(bytes))
+}
+
+fn placeholder_size(placeholder: &str) -> u16 {
+  if placeholder.ends_with("16") { 2 } else { 1 }
+}
+
+// 空白を除き、RGBDS形式の [HL] を (HL) に揃える
+fn compact(operand: &str) -> String {
+  operand
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .map(|c| match c {
+      '[' => '(',
+      ']' => ')',
+      c => c,
+    })
+    .collect()
+}
+
+fn is_indirect(expression: &str) -> bool {
+  if !expression.starts_with('(') || !expression.ends_with(')') {
+    return false;
+  }
+  // "(1+2)*(3)" のように、先頭の括弧が途中で閉じていれば全体は囲まれていない
+  let mut depth = 0;
+  for (index, c) in expression.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' => {
+        depth -= 1;
+        if depth == 0 && index != expression.len() - 1 {
+          return false;
+        }
+      },
+      _ => {},
+    }
+  }
+  true
+}
+
+fn register_matches(mnemonic: &str, template: &str, operand: &str) -> bool {
+  if template == operand {
+    return true;
+  }
+  // RGBDSの別表記と、よく使われる "JP (HL)"
+  match (template, operand) {
+    ("(HL+)", "(HLI)") | ("(HL-)", "(HLD)") => true,
+    ("HL", "(HL)") => mnemonic == "JP",
+    _ => false,
+  }
+}
+
+fn is_reserved(expression: &str) -> bool {
+  const RESERVED: [&str; 19] = [
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "HL+", "HL-", "HLI", "HLD", "NZ", "Z", "NC",
+  ];
+  if is_indirect(expression) {
+    return is_reserved(&expression[1..expression.len() - 1]);
+  }
+  let upper = expression.to_uppercase();
+  // "LD HL,SP+r8" を "LD HL,d16" と読まないように
+  upper.starts_with("SP+") || RESERVED.contains(&upper.as_str())
+}
+
+fn fit_byte(value: i64) -> Option<u8> {
+  if (-0x80..=0xFF).contains(&value) { Some(value as u8) } else { None }
+}
+
+fn fit_word(value: i64) -> Option<u16> {
+  if (-0x8000..=0xFFFF).contains(&value) { Some(value as u16) } else { None }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum ExpressionError {
+  UndefinedLabel(String),
+  Syntax(String),
+}
+
+impl fmt::Display for ExpressionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ExpressionError::UndefinedLabel(name) => write!(f, "undefined label: {}", name),
+      ExpressionError::Syntax(expression) => write!(f, "invalid expression: {}", expression),
+    }
+  }
+}
+
+// 式を評価する。数値は $FF / 0xFF / %1010 / 255、@ は今の命令のアドレス。
+// 演算子の優先順位は C と同じ（| < ^ < & < シフト < 加減 < 乗除 < 単項）
+fn evaluate(expression: &str, address: u16, labels: &HashMap<String, u16>) -> Result<i64, ExpressionError> {
+  let mut parser = ExpressionParser { text: expression.as_bytes(), position: 0, address, labels, undefined: None };
+  let value = parser.or();
+  parser.skip_whitespace();
+  if let Some(name) = parser.undefined {
+    return Err(ExpressionError::UndefinedLabel(name));
+  }
+  match value {
+    Some(value) if parser.position == parser.text.len() => Ok(value),
+    _ => Err(ExpressionError::Syntax(expression.to_string())),
+  }
+}
+
+struct ExpressionParser<'a> {
+  text: &'a [u8],
+  position: usize,
+  address: u16,
+  labels: &'a HashMap<String, u16>,
+  // 未定義のラベルがあっても最後まで構文を確かめられるよう、0として続行して覚えておく
+  undefined: Option<String>,
+}
+
+impl ExpressionParser<'_> {
+  fn skip_whitespace(&mut self) {
+    while self.text.get(self.position).is_some_and(u8::is_ascii_whitespace) {
+      self.position += 1;
+    }
+  }
+
+  fn eat(&mut self, token: &str) -> bool {
+    self.skip_whitespace();
+    if self.text[self.position..].starts_with(token.as_bytes()) {
+      self.position += token.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn or(&mut self) -> Option<i64> {
+    let mut value = self.xor()?;
+    while self.eat("|") {
+      value |= self.xor()?;
+    }
+    Some(value)
+  }
+
+  fn xor(&mut self) -> Option<i64> {
+    let mut value = self.and()?;
+    while self.eat("^") {
+      value ^= self.and()?;
+    }
+    Some(value)
+  }
+
+  fn and(&mut self) -> Option<i64> {
+    let mut value = self.shift()?;
+    while self.eat("&") {
+      value &= self.shift()?;
+    }
+    Some(value)
+  }
+
+  fn shift(&mut self) -> Option<i64> {
+    let mut value = self.additive()?;
+    loop {
+      if self.eat("<<") {
+        value = value.checked_shl(self.additive()? as u32)?;
+      } else if self.eat(">>") {
+        value = value.checked_shr(self.additive()? as u32)?;
+      } else {
+        return Some(value);
+      }
+    }
+  }
+
+  fn additive(&mut self) -> Option<i64> {
+    let mut value = self.multiplicative()?;
+    loop {
+      if self.eat("+") {
+        value = value.checked_add(self.multiplicative()?)?;
+      } else if self.eat("-") {
+        value = value.checked_sub(self.multiplicative()?)?;
+      } else {
+        return Some(value);
+      }
+    }
+  }
+
+  fn multiplicative(&mut self) -> Option<i64> {
+    let mut value = self.unary()?;
+    loop {
+      if self.eat("*") {
+        value = value.checked_mul(self.unary()?)?;
+      } else if self.eat("/") {
+        value = value.checked_div(self.unary()?)?;
+      } else if self.eat("%") {
+        value = value.checked_rem(self.unary()?)?;
+      } else {
+        return Some(value);
+      }
+    }
+  }
+
+  fn unary(&mut self) -> Option<i64> {
+    if self.eat("-") {
+      self.unary()?.checked_neg()
+    } else if self.eat("+") {
+      self.unary()
+    } else if self.eat("~") {
+      Some(!self.unary()?)
+    } else {
+      self.primary()
+    }
+  }
+
+  fn primary(&mut self) -> Option<i64> {
+    if self.eat("(") || self.eat("[") {
+      let value = self.or()?;
+      return (self.eat(")") || self.eat("]")).then_some(value);
+    }
+    if self.eat("@") {
+      return Some(self.address as i64);
+    }
+    if self.eat("$") {
+      return self.number(16);
+    }
+    if self.eat("0x") || self.eat("0X") {
+      return self.number(16);
+    }
+    if self.eat("%") {
+      return self.number(2);
+    }
+
+    let start = self.position;
+    let first = *self.text.get(start)?;
+    if first.is_ascii_digit() {
+      return self.number(10);
+    }
+    if first.is_ascii_alphabetic() || first == b'_' || first == b'.' {
+      while self.text.get(self.position).is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.') {
+        self.position += 1;
+      }
+      let name = std::str::from_utf8(&self.text[start..self.position]).ok()?;
+      return match self.labels.get(name) {
+        Some(&value) => Some(value as i64),
+        None => {
+          self.undefined.get_or_insert_with(|| name.to_string());
+          Some(0)
+        },
+      };
+    }
+    None
+  }
+
+  fn number(&mut self, radix: u32) -> Option<i64> {
+    let start = self.position;
+    while self.text.get(self.position).is_some_and(|&c| (c as char).is_digit(radix) || c == b'_') {
+      self.position += 1;
+    }
+    let digits: String = std::str::from_utf8(&self.text[start..self.position]).ok()?.replace('_', "");
+    i64::from_str_radix(&digits, radix).ok()
+  }
+}
@@ -0,0 +1,344 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bus::MemoryBus;
+use crate::cartridge::{Cartridge, MbcKind};
+use crate::gameboy::{GameBoy, Model};
+use crate::ppu::Mode;
+use crate::savestate::SaveStateError;
+
+// BESS（Best Effort Save State）の版。https://github.com/LIJI32/SameBoy/blob/master/BESS.md
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+const CORE_LENGTH: usize = 0xD0;
+const RTC_LENGTH: usize = 0x30;
+
+// BESS のバッファが指す、ネイティブ形式のセクションの中身の位置（ファイル先頭からのオフセット）
+pub struct Layout {
+  // MEM セクションの 64KB の配列
+  pub memory: usize,
+  // CART セクションの外部RAM
+  pub cartridge_ram: usize,
+  // PPU セクションの VRAM と、その直後の OAM
+  pub ppu: usize,
+}
+
+// data の末尾に BESS のブロックを足す。RAM などの大きなバッファは Layout の位置を指すので、二重には持たない
+pub fn write_footer(data: &mut Vec<u8>, gameboy: &GameBoy, layout: &Layout) {
+  let start = data.len();
+  let bus = &gameboy.cpu.bus;
+  let cartridge = bus.cartridge.as_ref().expect("GameBoy always has a cartridge");
+
+  let name = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+  write_block(data, b"NAME", name.as_bytes());
+
+  let mut info = cartridge.rom[0x134..0x144].to_vec();
+  info.extend_from_slice(&cartridge.rom[0x14E..0x150]);
+  write_block(data, b"INFO", &info);
+
+  write_block(data, b"CORE", &core(gameboy, cartridge, layout));
+
+  let mbc = mbc_writes(cartridge);
+  if !mbc.is_empty() {
+    write_block(data, b"MBC ", &mbc);
+  }
+
+  if let Some(rtc) = &cartridge.rtc {
+    let mut block = Vec::with_capacity(RTC_LENGTH);
+    let current = [rtc.seconds, rtc.minutes, rtc.hours, (rtc.days & 0xFF) as u8, rtc.control()];
+    for value in current.iter().chain(rtc.latched.iter()) {
+      block.extend_from_slice(&(*value as u32).to_le_bytes());
+    }
+    // ほかのエミュレータは、この時刻から読み込むまでの実時間だけ RTC を進める
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    block.extend_from_slice(&now.to_le_bytes());
+    write_block(data, b"RTC ", &block);
+  }
+
+  write_block(data, b"END ", &[]);
+  data.extend_from_slice(&(start as u32).to_le_bytes());
+  data.extend_from_slice(b"BESS");
+}
+
+// BESS のブロックが始まる位置。末尾に BESS のフッタがなければ None
+pub fn footer_start(data: &[u8]) -> Option<usize> {
+  let length = data.len();
+  if length < 8 || &data[length - 4..] != b"BESS" {
+    return None;
+  }
+  let start = u32::from_le_bytes(data[length - 8..length - 4].try_into().unwrap()) as usize;
+  (start <= length - 8).then_some(start)
+}
+
+fn write_block(data: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+  data.extend_from_slice(id);
+  data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+  data.extend_from_slice(body);
+}
+
+fn core(gameboy: &GameBoy, cartridge: &Cartridge, layout: &Layout) -> Vec<u8> {
+  let cpu = &gameboy.cpu;
+  let bus = &cpu.bus;
+  let mut block = Vec::with_capacity(CORE_LENGTH);
+  block.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+  block.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+  // 機種ファミリーと機種。リビジョンは区別していないので空白にする
+  block.extend_from_slice(match gameboy.model {
+    Model::Dmg => b"GD  ",
+    Model::Cgb => b"CC  ",
+  });
+  for value in [
+    cpu.pc, cpu.registers.get_af(), cpu.registers.get_bc(),
+    cpu.registers.get_de(), cpu.registers.get_hl(), cpu.sp,
+  ] {
+    block.extend_from_slice(&value.to_le_bytes());
+  }
+  block.extend_from_slice(&[cpu.ime as u8, bus.memory()[0xFFFF], cpu.halted as u8, 0]);
+  for address in 0xFF00..0xFF80 {
+    block.push(bus.peek_byte(address));
+  }
+
+  // (大きさ, 位置) の組。RAM、VRAM、外部RAM、OAM、HRAM、背景パレット、スプライトパレットの順
+  let buffers = [
+    (0x2000, layout.memory + 0xC000),
+    (0x2000, layout.ppu),
+    (cartridge.ram.len(), layout.cartridge_ram),
+    (0xA0, layout.ppu + 0x2000),
+    (0x7F, layout.memory + 0xFF80),
+    (0, 0),
+    (0, 0),
+  ];
+  for (size, offset) in buffers {
+    block.extend_from_slice(&(size as u32).to_le_bytes());
+    block.extend_from_slice(&(offset as u32).to_le_bytes());
+  }
+  block
+}
+
+// 今の MBC の状態を作り直す書き込みの列。(アドレス u16, 値 u8) の3バイトずつ
+fn mbc_writes(cartridge: &Cartridge) -> Vec<u8> {
+  let ram_enable = if cartridge.ram_enabled { 0x0A } else { 0x00 };
+  let writes: Vec<(u16, u8)> = match cartridge.kind {
+    MbcKind::None => Vec::new(),
+    MbcKind::Mbc1 => vec![
+      (0x0000, ram_enable),
+      (0x2000, cartridge.rom_bank as u8),
+      (0x4000, cartridge.ram_bank),
+      (0x6000, cartridge.banking_mode),
+    ],
+    MbcKind::Mbc3 => vec![(0x0000, ram_enable), (0x2000, cartridge.rom_bank as u8), (0x4000, cartridge.ram_bank)],
+    MbcKind::Mbc5 => vec![
+      (0x0000, ram_enable),
+      (0x2000, cartridge.rom_bank as u8),
+      (0x3000, (cartridge.rom_bank >> 8) as u8),
+      (0x4000, cartridge.ram_bank),
+    ],
+  };
+  let mut block = Vec::with_capacity(writes.len() * 3);
+  for (address, value) in writes {
+    block.extend_from_slice(&address.to_le_bytes());
+    block.push(value);
+  }
+  block
+}
+
+// ほかのエミュレータが書き出したステートを、BESS のブロックだけを頼りに読み込む。
+// BESS にない状態（PPU のドット位置、タイマーやサウンドの内部カウンタなど）は、レジスタの値から分かる範囲で合わせる
+pub fn load(gameboy: &mut GameBoy, data: &[u8]) -> Result<(), SaveStateError> {
+  let start = footer_start(data).ok_or(SaveStateError::NotASaveState)?;
+  let blocks = parse_blocks(&data[start..data.len() - 8])?;
+  let block = |id: &[u8; 4]| blocks.iter().find(|(found, _)| found == id).map(|&(_, body)| body);
+
+  if let Some(info) = block(b"INFO") {
+    let cartridge = gameboy.cpu.bus.cartridge.as_ref().expect("GameBoy always has a cartridge");
+    if info.len() < 0x12 {
+      return Err(SaveStateError::InvalidSection(*b"INFO", "block is too short".to_string()));
+    }
+    let saved = u16::from_be_bytes([info[0x10], info[0x11]]);
+    let current = u16::from_be_bytes([cartridge.rom[0x14E], cartridge.rom[0x14F]]);
+    if info[..0x10] != cartridge.rom[0x134..0x144] || saved != current {
+      let saved_title = info[..0x10].iter().take_while(|&&byte| byte != 0).map(|&byte| byte as char).collect();
+      return Err(SaveStateError::GlobalChecksumMismatch { saved, saved_title, current });
+    }
+  }
+
+  let core = block(b"CORE").ok_or(SaveStateError::MissingSection(*b"CORE"))?;
+  load_core(gameboy, core, data)?;
+  if let Some(mbc) = block(b"MBC ") {
+    load_mbc(gameboy, mbc)?;
+  }
+  if let Some(rtc) = block(b"RTC ") {
+    load_rtc(gameboy, rtc)?;
+  }
+  Ok(())
+}
+
+// (ID, 中身)
+type Blocks<'a> = Vec<([u8; 4], &'a [u8])>;
+
+// END までのブロックを (ID, 中身) の一覧にする
+fn parse_blocks(data: &[u8]) -> Result<Blocks<'_>, SaveStateError> {
+  let mut blocks = Vec::new();
+  let mut position = 0;
+  loop {
+    let header = data.get(position..position + 8).ok_or(SaveStateError::Truncated)?;
+    let id: [u8; 4] = header[..4].try_into().unwrap();
+    let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    position += 8;
+    let body = position.checked_add(length).and_then(|end| data.get(position..end)).ok_or(SaveStateError::Truncated)?;
+    position += length;
+    if &id == b"END " {
+      return Ok(blocks);
+    }
+    blocks.push((id, body));
+  }
+}
+
+fn load_core(gameboy: &mut GameBoy, core: &[u8], data: &[u8]) -> Result<(), SaveStateError> {
+  let invalid = |message: String| SaveStateError::InvalidSection(*b"CORE", message);
+  if core.len() < CORE_LENGTH {
+    return Err(invalid("block is too short".to_string()));
+  }
+  let word = |offset: usize| u16::from_le_bytes([core[offset], core[offset + 1]]);
+  let major = word(0x00);
+  if major != MAJOR_VERSION {
+    return Err(invalid(format!("unsupported BESS version {}.{}", major, word(0x02))));
+  }
+  gameboy.model = match core[0x04] {
+    b'G' | b'S' => Model::Dmg,
+    b'C' => Model::Cgb,
+    _ => return Err(invalid(format!("unknown model {}", String::from_utf8_lossy(&core[0x04..0x08])))),
+  };
+
+  // 先にバッファがファイルの範囲内にあるか確かめる
+  let mut buffers = Vec::new();
+  for index in 0..7 {
+    let offset = 0x98 + index * 8;
+    let size = u32::from_le_bytes(core[offset..offset + 4].try_into().unwrap()) as usize;
+    let position = u32::from_le_bytes(core[offset + 4..offset + 8].try_into().unwrap()) as usize;
+    let buffer = position
+      .checked_add(size)
+      .and_then(|end| data.get(position..end))
+      .ok_or_else(|| invalid(format!("buffer {} is out of range", index)))?;
+    buffers.push(buffer);
+  }
+
+  let cpu = &mut gameboy.cpu;
+  cpu.pc = word(0x08);
+  cpu.registers.set_af(word(0x0A));
+  cpu.registers.set_bc(word(0x0C));
+  cpu.registers.set_de(word(0x0E));
+  cpu.registers.set_hl(word(0x10));
+  cpu.sp = word(0x12);
+  cpu.ime = core[0x14] != 0;
+  cpu.ime_scheduled = false;
+  // STOP はエミュレートしていないので HALT として扱う
+  cpu.halted = core[0x16] != 0;
+  cpu.illegal_opcode = None;
+  cpu.call_stack.clear();
+
+  let bus = &mut cpu.bus;
+  bus.memory_mut()[0xFFFF] = core[0x15];
+  load_io(bus, &core[0x18..0x98]);
+
+  let copy = |target: &mut [u8], source: &[u8]| {
+    let length = target.len().min(source.len());
+    target[..length].copy_from_slice(&source[..length]);
+  };
+  // CGB の WRAM はバンク0と1だけを使う
+  copy(&mut bus.memory_mut()[0xC000..0xE000], buffers[0]);
+  copy(&mut bus.ppu.vram, buffers[1]);
+  if let Some(cartridge) = bus.cartridge.as_mut() {
+    copy(&mut cartridge.ram, buffers[2]);
+  }
+  copy(&mut bus.ppu.oam, buffers[3]);
+  copy(&mut bus.memory_mut()[0xFF80..0xFFFF], buffers[4]);
+  Ok(())
+}
+
+// 0xFF00〜0xFF7F。書き込みに副作用のあるレジスタ（DIV、DMA、サウンドのトリガーなど）は、副作用なしに値だけを設定する
+fn load_io(bus: &mut MemoryBus, io: &[u8]) {
+  // NR52 で電源を入れてからでないと、ほかのサウンドレジスタに書き込めない
+  bus.apu.write_byte(0xFF26, io[0x26]);
+  for (offset, &value) in io.iter().enumerate() {
+    let address = 0xFF00 + offset as u16;
+    match address {
+      0xFF00 => bus.joypad.write_byte(value),
+      0xFF01 => bus.serial.data = value,
+      0xFF02 => bus.serial.control = value & 0x81,
+      0xFF04 => bus.timer.counter = (value as u16) << 8,
+      0xFF05 => bus.timer.tima = value,
+      0xFF06 => bus.timer.tma = value,
+      0xFF07 => bus.timer.tac = value & 0x07,
+      0xFF0F => bus.interrupt_flag = value & 0x1F,
+      0xFF26 => {},
+      0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => bus.apu.write_byte(address, value & 0x7F),
+      0xFF10..=0xFF3F => bus.apu.write_byte(address, value),
+      0xFF40 => bus.ppu.lcdc = value,
+      0xFF41 => {
+        bus.ppu.stat = value & 0x78;
+        bus.ppu.mode = match value & 0x03 {
+          0 => Mode::HBlank,
+          1 => Mode::VBlank,
+          2 => Mode::OamScan,
+          _ => Mode::Drawing,
+        };
+        bus.ppu.dot = 0;
+      },
+      0xFF42 => bus.ppu.scy = value,
+      0xFF43 => bus.ppu.scx = value,
+      0xFF44 => bus.ppu.ly = value,
+      0xFF45 => bus.ppu.lyc = value,
+      0xFF47 => bus.ppu.bgp = value,
+      0xFF48 => bus.ppu.obp0 = value,
+      0xFF49 => bus.ppu.obp1 = value,
+      0xFF4A => bus.ppu.wy = value,
+      0xFF4B => bus.ppu.wx = value,
+      0xFF50 => {
+        bus.memory_mut()[address as usize] = value;
+        if value != 0 {
+          bus.boot_rom = None;
+        }
+      },
+      _ => bus.memory_mut()[address as usize] = value,
+    }
+  }
+}
+
+fn load_mbc(gameboy: &mut GameBoy, mbc: &[u8]) -> Result<(), SaveStateError> {
+  if !mbc.len().is_multiple_of(3) {
+    return Err(SaveStateError::InvalidSection(*b"MBC ", "length is not a multiple of 3".to_string()));
+  }
+  let bus = &mut gameboy.cpu.bus;
+  for write in mbc.chunks(3) {
+    let address = u16::from_le_bytes([write[0], write[1]]);
+    if let Some(cartridge) = bus.cartridge.as_mut() {
+      match address {
+        0x0000..=0x7FFF => cartridge.write_rom(address, write[2]),
+        0xA000..=0xBFFF => cartridge.write_ram(address, write[2]),
+        _ => {},
+      }
+    }
+  }
+  Ok(())
+}
+
+// 読み込みまでに経った実時間は足さない（RTC はエミュレートしたサイクル数で進める）
+fn load_rtc(gameboy: &mut GameBoy, block: &[u8]) -> Result<(), SaveStateError> {
+  if block.len() < RTC_LENGTH {
+    return Err(SaveStateError::InvalidSection(*b"RTC ", "block is too short".to_string()));
+  }
+  let register = |index: usize| block[index * 4];
+  let cartridge = gameboy.cpu.bus.cartridge.as_mut().expect("GameBoy always has a cartridge");
+  if let Some(rtc) = cartridge.rtc.as_mut() {
+    rtc.seconds = register(0) & 0x3F;
+    rtc.minutes = register(1) & 0x3F;
+    rtc.hours = register(2) & 0x1F;
+    rtc.days = register(3) as u16;
+    rtc.set_control(register(4));
+    for index in 0..5 {
+      rtc.latched[index] = register(5 + index);
+    }
+    rtc.sub_cycles = 0;
+  }
+  Ok(())
+}
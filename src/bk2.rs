@@ -0,0 +1,223 @@
+use crate::gameboy::Model;
+use crate::movie::{Frame, Movie, MovieError, Start};
+use crate::png::crc32;
+use crate::zip::{self, Entry};
+
+// BizHawk のムービー (.bk2) との変換。Gambatte コアで記録した Game Boy / Game Boy Color のムービーに対応する。
+// .bk2 は ZIP で、Header.txt（ROM の SHA1 など）、Input Log.txt（フレームごとの入力）、
+// SyncSettings.json（機種、ブートROM、RTC の初期値）などが入っている
+
+// Input Log.txt の列。ボタンの名前と、押しているときに書く文字
+const BUTTONS: [(&str, char, u8); 8] = [
+  ("Up", 'U', 0x04),
+  ("Down", 'D', 0x08),
+  ("Left", 'L', 0x02),
+  ("Right", 'R', 0x01),
+  ("Start", 'S', 0x80),
+  ("Select", 's', 0x40),
+  ("B", 'B', 0x20),
+  ("A", 'A', 0x10),
+];
+
+// Gambatte の SyncSettings の ConsoleMode
+const CONSOLE_MODE_AUTO: u32 = 0;
+const CONSOLE_MODE_GB: u32 = 1;
+const CONSOLE_MODE_GBC: u32 = 2;
+
+// .bk2 を読み込む。ROM は SHA1 の確認とネイティブのムービーの ROM の CRC32 に、
+// ブートROMは SyncSettings で有効になっているときに使う
+pub fn import(data: &[u8], rom: &[u8], boot_rom: Option<&[u8]>) -> Result<Movie, MovieError> {
+  let entries = zip::read(data).map_err(MovieError::Invalid)?;
+  let text = |name: &str| {
+    entries.iter().find(|entry| entry.name == name).map(|entry| String::from_utf8_lossy(&entry.data).into_owned())
+  };
+  let header = text("Header.txt").ok_or_else(|| MovieError::Invalid("Header.txt is missing".to_string()))?;
+  let input_log = text("Input Log.txt").ok_or_else(|| MovieError::Invalid("Input Log.txt is missing".to_string()))?;
+  let sync_settings = text("SyncSettings.json").unwrap_or_default();
+  let header_value = |key: &str| {
+    header.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix(' ').map(str::trim))
+  };
+
+  if let Some(expected) = header_value("SHA1") {
+    let actual = hex(&sha1(rom));
+    if !expected.eq_ignore_ascii_case(&actual) {
+      return Err(MovieError::ForeignRomMismatch { expected: format!("SHA1 {}", expected), actual: format!("SHA1 {}", actual) });
+    }
+  }
+  if header_value("StartsFromSavestate").is_some_and(|value| value.eq_ignore_ascii_case("true")) {
+    return Err(MovieError::Unsupported("movie starts from a BizHawk savestate".to_string()));
+  }
+  let start = if header_value("StartsFromSaveRam").is_some_and(|value| value.eq_ignore_ascii_case("true")) {
+    let entry = entries.iter().find(|entry| entry.name == "SaveRam");
+    Start::SaveRam(entry.ok_or_else(|| MovieError::Invalid("SaveRam is missing".to_string()))?.data.clone())
+  } else {
+    Start::PowerOn
+  };
+
+  let model = match json_value(&sync_settings, "ConsoleMode").and_then(|value| value.parse().ok()) {
+    Some(CONSOLE_MODE_GB) => Model::Dmg,
+    // 自動のときは、カートリッジのヘッダの CGB フラグで決まる
+    Some(CONSOLE_MODE_AUTO) if rom.get(0x143).is_some_and(|flag| flag & 0x80 != 0) => Model::Cgb,
+    Some(CONSOLE_MODE_AUTO) => Model::Dmg,
+    // GBC と GBA
+    Some(_) => Model::Cgb,
+    None if header_value("IsCGBMode") == Some("1") || header_value("Platform") == Some("GBC") => Model::Cgb,
+    None => Model::Dmg,
+  };
+  let boot_rom_crc32 = match json_value(&sync_settings, "EnableBIOS") {
+    Some("true") => Some(crc32(
+      boot_rom.ok_or_else(|| MovieError::Unsupported("movie was recorded with a boot ROM; pass the same boot ROM".to_string()))?,
+    )),
+    _ => None,
+  };
+  let rtc_seed = json_value(&sync_settings, "RTCInitialTime").and_then(|value| value.parse().ok()).unwrap_or(0);
+
+  Ok(Movie { rom_crc32: crc32(rom), model, boot_rom_crc32, rtc_seed, start, frames: parse_input_log(&input_log)? })
+}
+
+// LogKey の列の順に、入力の行を読む。BizHawk が付ける "P1 " は取り除いて名前で対応させる
+fn parse_input_log(input_log: &str) -> Result<Vec<Frame>, MovieError> {
+  let mut columns: Vec<String> = BUTTONS.iter().map(|(name, _, _)| name.to_string()).chain(["Power".to_string()]).collect();
+  let mut frames = Vec::new();
+  for line in input_log.lines().map(str::trim) {
+    if let Some(key) = line.strip_prefix("LogKey:") {
+      columns = key
+        .split(['#', '|'])
+        .filter(|name| !name.is_empty())
+        .map(|name| name.strip_prefix("P1 ").unwrap_or(name).to_string())
+        .collect();
+      continue;
+    }
+    if !line.starts_with('|') {
+      continue;
+    }
+    let inputs: Vec<char> = line.chars().filter(|&c| c != '|').collect();
+    if inputs.len() != columns.len() {
+      return Err(MovieError::Invalid(format!("frame {} has {} inputs, expected {}", frames.len(), inputs.len(), columns.len())));
+    }
+    let mut buttons = 0;
+    for (name, &input) in columns.iter().zip(&inputs) {
+      if input == '.' || input == ' ' {
+        continue;
+      }
+      match BUTTONS.iter().find(|(button, _, _)| button == name) {
+        Some(&(_, _, mask)) => buttons |= mask,
+        // 最初のフレームの電源投入は、電源を入れた直後から始まるのと同じ
+        None if name == "Power" && frames.is_empty() => {},
+        None => return Err(MovieError::Unsupported(format!("input {} at frame {}", name, frames.len()))),
+      }
+    }
+    frames.push(Frame { buttons, hash: None });
+  }
+  Ok(frames)
+}
+
+// .bk2 を書き出す。状態のハッシュは BizHawk にはないので捨てる
+pub fn export(movie: &Movie, rom: &[u8]) -> Result<Vec<u8>, MovieError> {
+  let mut entries = Vec::new();
+  let mut header = String::from("MovieVersion BizHawk v2.0.0\nAuthor \nEmulationVersion 2.9.1\n");
+  header.push_str(match movie.model {
+    Model::Dmg => "Platform GB\n",
+    Model::Cgb => "Platform GBC\n",
+  });
+  let title: String = rom.get(0x134..0x144).unwrap_or_default().iter().take_while(|&&c| c != 0).map(|&c| c as char).collect();
+  header.push_str(&format!("GameName {}\nSHA1 {}\nCore Gambatte\nrerecordCount 0\n", title.trim(), hex(&sha1(rom))));
+  if movie.model == Model::Cgb {
+    header.push_str("IsCGBMode 1\n");
+  }
+  match &movie.start {
+    Start::PowerOn => {},
+    Start::SaveRam(ram) => {
+      header.push_str("StartsFromSaveRam True\n");
+      entries.push(Entry { name: "SaveRam".to_string(), data: ram.clone() });
+    },
+    Start::SaveState(_) => return Err(MovieError::Unsupported("BizHawk cannot start from our save states".to_string())),
+  }
+
+  let mut input_log = String::from("[Input]\nLogKey:#");
+  for (name, _, _) in BUTTONS {
+    input_log.push_str(&format!("{}|", name));
+  }
+  input_log.push_str("Power|\n");
+  for frame in &movie.frames {
+    input_log.push('|');
+    for (_, symbol, mask) in BUTTONS {
+      input_log.push(if frame.buttons & mask != 0 { symbol } else { '.' });
+    }
+    input_log.push_str(".|\n");
+  }
+  input_log.push_str("[/Input]\n");
+
+  let console_mode = match movie.model {
+    Model::Dmg => CONSOLE_MODE_GB,
+    Model::Cgb => CONSOLE_MODE_GBC,
+  };
+  let sync_settings = format!(
+    "{{\"o\":{{\"$type\":\"BizHawk.Emulation.Cores.Nintendo.Gameboy.Gameboy+GambatteSyncSettings, BizHawk.Emulation.Cores\",\"EnableBIOS\":{},\"ConsoleMode\":{},\"RTCInitialTime\":{}}}}}",
+    movie.boot_rom_crc32.is_some(),
+    console_mode,
+    movie.rtc_seed
+  );
+
+  entries.push(Entry { name: "Header.txt".to_string(), data: header.into_bytes() });
+  entries.push(Entry { name: "Input Log.txt".to_string(), data: input_log.into_bytes() });
+  entries.push(Entry { name: "SyncSettings.json".to_string(), data: sync_settings.into_bytes() });
+  Ok(zip::write(&entries))
+}
+
+// "key": に続く値を、次の , か } の手前まで取り出す。文字列なら引用符を外す
+fn json_value<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+  let start = json.find(&format!("\"{}\"", key))? + key.len() + 2;
+  let rest = json[start..].trim_start().strip_prefix(':')?.trim_start();
+  let end = rest.find([',', '}']).unwrap_or(rest.len());
+  Some(rest[..end].trim().trim_matches('"'))
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// BizHawk は ROM を SHA1 で確かめる
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+  for block in message.chunks(64) {
+    let mut words = [0u32; 80];
+    for (index, word) in block.chunks(4).enumerate() {
+      words[index] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    for index in 16..80 {
+      words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = state;
+    for (index, &word) in words.iter().enumerate() {
+      let (f, k) = match index {
+        0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+        20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+        _ => (b ^ c ^ d, 0xCA62_C1D6),
+      };
+      let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+      *value = value.wrapping_add(add);
+    }
+  }
+
+  let mut digest = [0; 20];
+  for (index, value) in state.iter().enumerate() {
+    digest[index * 4..index * 4 + 4].copy_from_slice(&value.to_be_bytes());
+  }
+  digest
+}
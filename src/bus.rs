@@ -0,0 +1,266 @@
+use crate::apu::APU;
+use crate::cartridge::Cartridge;
+use crate::joypad::Joypad;
+use crate::ppu::PPU;
+use crate::serial::Serial;
+use crate::timer::Timer;
+use crate::watchpoint::{Access, Watchpoints};
+
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const LCD_STAT_INTERRUPT: u8 = 0x02;
+pub const TIMER_INTERRUPT: u8 = 0x04;
+pub const SERIAL_INTERRUPT: u8 = 0x08;
+pub const JOYPAD_INTERRUPT: u8 = 0x10;
+
+// CPU から見たバス。CPU はこれを通してメモリを読み書きし、命令にかかったTサイクル数だけ時間を進める。
+// 実機と同じ割り当ての MemoryBus のほかに、テスト用の平らなメモリ（flat_bus::FlatBus）がある
+pub trait Bus {
+  // 読み込みにも副作用がある（ウォッチポイントや FlatBus の記録）ので &mut self。副作用のない読み込みは peek
+  fn read(&mut self, address: u16) -> u8;
+  fn write(&mut self, address: u16, value: u8);
+  fn tick(&mut self, cycles: u8);
+
+  // IE と IF の両方が立っている割り込み。割り込みのないバスは 0 のまま
+  fn pending_interrupts(&self) -> u8 {
+    0
+  }
+
+  fn acknowledge_interrupt(&mut self, _interrupt: u8) {}
+
+  // 記録やウォッチポイントに引っかからない読み込み。トレースや条件式、逆アセンブラがメモリを覗くときに使う
+  fn peek(&self, address: u16) -> u8;
+
+  // address に見えているROMのバンク番号。コールスタックとトレースに使う
+  fn rom_bank(&self, _address: u16) -> usize {
+    0
+  }
+
+  // ウォッチポイントを置けるバスだけが返す
+  fn watchpoints(&self) -> Option<&Watchpoints> {
+    None
+  }
+
+  fn watchpoints_mut(&mut self) -> Option<&mut Watchpoints> {
+    None
+  }
+}
+
+pub struct MemoryBus {
+  // カートリッジや周辺機器に割り当てられていないアドレスはこの配列で扱う
+  memory: [u8; 0x10000],
+  pub cartridge: Option<Cartridge>,
+  // 0xFF50に書き込まれるまで、ROMの先頭に重ねて見えるブートROM
+  pub boot_rom: Option<Vec<u8>>,
+  pub ppu: PPU,
+  pub apu: APU,
+  pub timer: Timer,
+  pub joypad: Joypad,
+  pub serial: Serial,
+  pub interrupt_flag: u8,
+  pub watchpoints: Watchpoints,
+}
+
+impl MemoryBus {
+  pub fn new() -> MemoryBus {
+    MemoryBus {
+      memory: [0; 0x10000],
+      cartridge: None,
+      boot_rom: None,
+      ppu: PPU::new(),
+      apu: APU::new(),
+      timer: Timer::new(),
+      joypad: Joypad::new(),
+      serial: Serial::new(),
+      interrupt_flag: 0,
+      watchpoints: Watchpoints::new(),
+    }
+  }
+
+  // 周辺機器に割り当てられていない領域（WRAM、HRAM、IE など）の生の内容。セーブステートで使う
+  pub fn memory(&self) -> &[u8; 0x10000] {
+    &self.memory
+  }
+
+  pub fn memory_mut(&mut self) -> &mut [u8; 0x10000] {
+    &mut self.memory
+  }
+
+  pub fn read_byte(&mut self, address: u16) -> u8 {
+    let value = self.peek_byte(address);
+    self.watchpoints.check(Access::Read, address, value, value);
+    value
+  }
+
+  // ウォッチポイントに引っかからない読み込み。デバッガやトレースがメモリを覗くときに使う
+  pub fn peek_byte(&self, address: u16) -> u8 {
+    if let Some(value) = self.read_boot_rom(address) {
+      return value;
+    }
+    match address {
+      0x0000..=0x7FFF => match &self.cartridge {
+        Some(cartridge) => cartridge.read_rom(address),
+        None => self.memory[address as usize],
+      },
+      0x8000..=0x9FFF => self.ppu.vram[(address - 0x8000) as usize],
+      0xA000..=0xBFFF => match &self.cartridge {
+        Some(cartridge) => cartridge.read_ram(address),
+        None => self.memory[address as usize],
+      },
+      0xE000..=0xFDFF => self.memory[(address - 0x2000) as usize],
+      0xFE00..=0xFE9F => self.ppu.oam[(address - 0xFE00) as usize],
+      0xFEA0..=0xFEFF => 0xFF,
+      0xFF00 => self.joypad.read_byte(),
+      0xFF01..=0xFF02 => self.serial.read_byte(address),
+      0xFF04..=0xFF07 => self.timer.read_byte(address),
+      0xFF0F => self.interrupt_flag | 0xE0,
+      0xFF10..=0xFF3F => self.apu.read_byte(address),
+      0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read_register(address),
+      _ => self.memory[address as usize],
+    }
+  }
+
+  pub fn write_byte(&mut self, address: u16, value: u8) {
+    if !self.watchpoints.is_empty() {
+      self.watchpoints.check(Access::Write, address, self.peek_byte(address), value);
+    }
+    match address {
+      0x0000..=0x7FFF => match &mut self.cartridge {
+        Some(cartridge) => cartridge.write_rom(address, value),
+        None => self.memory[address as usize] = value,
+      },
+      0x8000..=0x9FFF => self.ppu.vram[(address - 0x8000) as usize] = value,
+      0xA000..=0xBFFF => match &mut self.cartridge {
+        Some(cartridge) => cartridge.write_ram(address, value),
+        None => self.memory[address as usize] = value,
+      },
+      0xE000..=0xFDFF => self.memory[(address - 0x2000) as usize] = value,
+      0xFE00..=0xFE9F => self.ppu.oam[(address - 0xFE00) as usize] = value,
+      0xFEA0..=0xFEFF => {},
+      0xFF00 => self.joypad.write_byte(value),
+      0xFF01..=0xFF02 => self.serial.write_byte(address, value),
+      0xFF04..=0xFF07 => {
+        if self.timer.write_byte(address, value) {
+          self.interrupt_flag |= TIMER_INTERRUPT;
+        }
+      },
+      0xFF0F => self.interrupt_flag = value & 0x1F,
+      0xFF10..=0xFF3F => self.apu.write_byte(address, value),
+      0xFF50 => {
+        self.memory[address as usize] = value;
+        if value != 0 {
+          self.boot_rom = None;
+        }
+      },
+      0xFF46 => {
+        self.memory[address as usize] = value;
+        self.oam_dma(value);
+      },
+      0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write_register(address, value),
+      _ => self.memory[address as usize] = value,
+    }
+  }
+
+  // DMGのブートROMは0x0000-0x00FF、CGBのものは加えて0x0200-0x08FFに重なる
+  fn read_boot_rom(&self, address: u16) -> Option<u8> {
+    let boot_rom = self.boot_rom.as_ref()?;
+    match address {
+      0x0000..=0x00FF | 0x0200..=0x08FF => boot_rom.get(address as usize).copied(),
+      _ => None,
+    }
+  }
+
+  // OAM DMAは本来160 Mサイクルかけて転送されるが、ここでは書き込み時に一括でコピーする
+  fn oam_dma(&mut self, page: u8) {
+    let source = (page as u16) << 8;
+    for offset in 0..0xA0 {
+      let value = self.peek_byte(source + offset);
+      self.ppu.oam[offset as usize] = value;
+    }
+  }
+
+  // address に見えているROMのバンク番号。ROM以外のアドレスやカートリッジが無い場合は0
+  pub fn rom_bank(&self, address: u16) -> usize {
+    match &self.cartridge {
+      Some(cartridge) if address < 0x8000 => cartridge.rom_bank_at(address),
+      _ => 0,
+    }
+  }
+
+  pub fn pending_interrupts(&self) -> u8 {
+    self.memory[0xFFFF] & self.interrupt_flag & 0x1F
+  }
+
+  pub fn request_interrupt(&mut self, interrupt: u8) {
+    self.interrupt_flag |= interrupt;
+  }
+
+  pub fn acknowledge_interrupt(&mut self, interrupt: u8) {
+    self.interrupt_flag &= !interrupt;
+  }
+
+  pub fn set_joypad_state(&mut self, pressed: u8) {
+    if self.joypad.set_state(pressed) {
+      self.request_interrupt(JOYPAD_INTERRUPT);
+    }
+  }
+
+  // 周辺機器を指定したTサイクル分だけ進める
+  pub fn tick(&mut self, cycles: u8) {
+    if self.timer.tick(cycles) {
+      self.request_interrupt(TIMER_INTERRUPT);
+    }
+    if self.serial.tick(cycles) {
+      self.request_interrupt(SERIAL_INTERRUPT);
+    }
+    let ppu_interrupts = self.ppu.tick(cycles);
+    self.request_interrupt(ppu_interrupts);
+    self.apu.tick(cycles);
+    if let Some(cartridge) = self.cartridge.as_mut() {
+      cartridge.tick(cycles);
+    }
+  }
+}
+
+impl Bus for MemoryBus {
+  fn read(&mut self, address: u16) -> u8 {
+    self.read_byte(address)
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    self.write_byte(address, value);
+  }
+
+  fn tick(&mut self, cycles: u8) {
+    MemoryBus::tick(self, cycles);
+  }
+
+  fn pending_interrupts(&self) -> u8 {
+    MemoryBus::pending_interrupts(self)
+  }
+
+  fn acknowledge_interrupt(&mut self, interrupt: u8) {
+    MemoryBus::acknowledge_interrupt(self, interrupt);
+  }
+
+  fn peek(&self, address: u16) -> u8 {
+    self.peek_byte(address)
+  }
+
+  fn rom_bank(&self, address: u16) -> usize {
+    MemoryBus::rom_bank(self, address)
+  }
+
+  fn watchpoints(&self) -> Option<&Watchpoints> {
+    Some(&self.watchpoints)
+  }
+
+  fn watchpoints_mut(&mut self) -> Option<&mut Watchpoints> {
+    Some(&mut self.watchpoints)
+  }
+}
+
+impl Default for MemoryBus {
+  fn default() -> Self {
+    MemoryBus::new()
+  }
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::symbols::Symbols;
+
+// 覚えておく異常の数。古いものから捨てる
+const MAX_ANOMALIES: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CallKind {
+  Call,
+  Rst,
+  Interrupt,
+}
+
+// CALL/RST/割り込みで積まれた1段分
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Frame {
+  pub kind: CallKind,
+  // CALL/RST 命令のアドレス。割り込みでは割り込まれた命令のアドレス
+  pub caller: u16,
+  pub caller_bank: usize,
+  pub target: u16,
+  pub target_bank: usize,
+  // 戻りアドレスを積んだ後の SP（戻りアドレスの置き場所）
+  pub sp: u16,
+  pub return_address: u16,
+}
+
+impl fmt::Display for Frame {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{:02X}:{:04X}  {} {:02X}:{:04X}  SP={:04X}",
+      self.caller_bank, self.caller, self.kind, self.target_bank, self.target, self.sp
+    )
+  }
+}
+
+impl fmt::Display for CallKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      CallKind::Call => "call",
+      CallKind::Rst => "rst",
+      CallKind::Interrupt => "interrupt",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+fn location(bank: usize, address: u16, symbols: &Symbols) -> String {
+  match symbols.describe(bank, address) {
+    Some(label) => format!("{:02X}:{:04X} <{}>", bank, address, label),
+    None => format!("{:02X}:{:04X}", bank, address),
+  }
+}
+
+// 呼び出しと戻りが対応しなかったときの記録。pc は RET/RETI/POP 命令のアドレス
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anomaly {
+  // 一番上のフレームより浅い SP で戻った。途中のフレームは戻らずに捨てられた
+  Unbalanced { pc: u16, sp: u16, dropped: usize },
+  // 対応する呼び出しのない RET（PUSH してから RET で飛ぶなど）
+  Unmatched { pc: u16, sp: u16, address: u16 },
+  // 積んだときと違う戻りアドレスで戻った
+  ReturnAddressChanged { pc: u16, expected: u16, actual: u16 },
+  // RET ではなく POP で戻りアドレスを取り出した
+  PopReturn { pc: u16, frame: Frame },
+}
+
+impl fmt::Display for Anomaly {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Anomaly::Unbalanced { pc, sp, dropped } => {
+        write!(f, "{:04X}: unbalanced return (SP={:04X}), dropped {} frame(s)", pc, sp, dropped)
+      },
+      Anomaly::Unmatched { pc, sp, address } => {
+        write!(f, "{:04X}: return to {:04X} without a matching call (SP={:04X})", pc, address, sp)
+      },
+      Anomaly::ReturnAddressChanged { pc, expected, actual } => {
+        write!(f, "{:04X}: returned to {:04X} instead of {:04X}", pc, actual, expected)
+      },
+      Anomaly::PopReturn { pc, frame } => {
+        write!(f, "{:04X}: pop took return address {:04X} of {}", pc, frame.return_address, frame)
+      },
+    }
+  }
+}
+
+// CPU の実行に合わせて更新する影のコールスタック。
+// メモリ上のスタックは読まず、CALL/RST/割り込みで積み、RET/RETI で SP を比べて降ろす
+#[derive(Clone, Default, Debug)]
+pub struct CallStack {
+  frames: Vec<Frame>,
+  anomalies: VecDeque<Anomaly>,
+}
+
+impl CallStack {
+  pub fn new() -> CallStack {
+    CallStack::default()
+  }
+
+  // 外側から順に並ぶ（最後が一番内側）
+  pub fn frames(&self) -> &[Frame] {
+    &self.frames
+  }
+
+  pub fn anomalies(&self) -> impl Iterator<Item = &Anomaly> {
+    self.anomalies.iter()
+  }
+
+  pub fn clear(&mut self) {
+    self.frames.clear();
+    self.anomalies.clear();
+  }
+
+  pub fn push(&mut self, frame: Frame) {
+    // SP が書き換えられて（LD SP,HL など）もう戻ってこないフレームを捨てる
+    self.drop_frames(|sp| sp <= frame.sp);
+    self.frames.push(frame);
+  }
+
+  // RET/RETI。sp は戻りアドレスを取り出す前の SP、address は取り出した戻りアドレス
+  pub fn ret(&mut self, pc: u16, sp: u16, address: u16) {
+    let dropped = self.drop_frames(|frame_sp| frame_sp < sp);
+    if dropped > 0 {
+      self.record(Anomaly::Unbalanced { pc, sp, dropped });
+    }
+    match self.frames.last() {
+      Some(frame) if frame.sp == sp => {
+        if frame.return_address != address {
+          let expected = frame.return_address;
+          self.record(Anomaly::ReturnAddressChanged { pc, expected, actual: address });
+        }
+        self.frames.pop();
+      },
+      _ => self.record(Anomaly::Unmatched { pc, sp, address }),
+    }
+  }
+
+  // POP。一番上のフレームの戻りアドレスを取り出した場合は、そのフレームから戻ったものとして扱う
+  pub fn pop(&mut self, pc: u16, sp: u16) {
+    if let Some(&frame) = self.frames.last()
+      && frame.sp == sp
+    {
+      self.frames.pop();
+      self.record(Anomaly::PopReturn { pc, frame });
+    }
+  }
+
+  // 一番内側から順に、#0 が今の位置になるように並べる。ラベルがあれば <Main+$3> のように添える
+  pub fn backtrace(&self, pc: u16, bank: usize, symbols: &Symbols) -> String {
+    let mut lines = vec![format!("#0  {}", location(bank, pc, symbols))];
+    for (depth, frame) in self.frames.iter().rev().enumerate() {
+      lines.push(format!(
+        "#{:<2} {}  {} {}  SP={:04X}",
+        depth + 1,
+        location(frame.caller_bank, frame.caller, symbols),
+        frame.kind,
+        location(frame.target_bank, frame.target, symbols),
+        frame.sp
+      ));
+    }
+    if !self.anomalies.is_empty() {
+      lines.push("anomalies:".to_string());
+      lines.extend(self.anomalies.iter().map(|anomaly| format!("  {}", anomaly)));
+    }
+    lines.join("\n")
+  }
+
+  // 内側から、SP が is_stale を満たすフレームを捨て、その数を返す
+  fn drop_frames<F: Fn(u16) -> bool>(&mut self, is_stale: F) -> usize {
+    let count = self.frames.len();
+    while self.frames.last().is_some_and(|frame| is_stale(frame.sp)) {
+      self.frames.pop();
+    }
+    count - self.frames.len()
+  }
+
+  fn record(&mut self, anomaly: Anomaly) {
+    if self.anomalies.len() == MAX_ANOMALIES {
+      self.anomalies.pop_front();
+    }
+    self.anomalies.push_back(anomaly);
+  }
+}
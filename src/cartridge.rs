@@ -0,0 +1,296 @@
+use std::fmt;
+
+use crate::png::crc32;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+  TooSmall(usize),
+  UnsupportedType(u8),
+}
+
+impl fmt::Display for CartridgeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CartridgeError::TooSmall(size) => write!(f, "ROM is too small to contain a header ({} bytes)", size),
+      CartridgeError::UnsupportedType(kind) => write!(f, "unsupported cartridge type: 0x{:02X}", kind),
+    }
+  }
+}
+
+impl std::error::Error for CartridgeError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MbcKind {
+  None,
+  Mbc1,
+  Mbc3,
+  Mbc5,
+}
+
+// MBC3のリアルタイムクロック。時間はエミュレートしたサイクル数で進めるので、
+// 実行結果は実時間に依存しない
+#[derive(Clone, Default)]
+pub struct Rtc {
+  pub seconds: u8,
+  pub minutes: u8,
+  pub hours: u8,
+  pub days: u16,
+  pub halted: bool,
+  pub day_carry: bool,
+  pub latched: [u8; 5],
+  pub latch_armed: bool,
+  pub sub_cycles: u32,
+}
+
+impl Rtc {
+  fn tick(&mut self, cycles: u32) {
+    if self.halted {
+      return;
+    }
+    self.sub_cycles += cycles;
+    while self.sub_cycles >= CYCLES_PER_SECOND {
+      self.sub_cycles -= CYCLES_PER_SECOND;
+      self.advance_second();
+    }
+  }
+
+  fn advance_second(&mut self) {
+    self.seconds = (self.seconds + 1) & 0x3F;
+    if self.seconds != 60 {
+      return;
+    }
+    self.seconds = 0;
+    self.minutes = (self.minutes + 1) & 0x3F;
+    if self.minutes != 60 {
+      return;
+    }
+    self.minutes = 0;
+    self.hours = (self.hours + 1) & 0x1F;
+    if self.hours != 24 {
+      return;
+    }
+    self.hours = 0;
+    self.days += 1;
+    if self.days > 0x1FF {
+      self.days = 0;
+      self.day_carry = true;
+    }
+  }
+
+  fn latch(&mut self) {
+    self.latched = [
+      self.seconds,
+      self.minutes,
+      self.hours,
+      (self.days & 0xFF) as u8,
+      self.control(),
+    ];
+  }
+
+  // 0x0C レジスタ（日数の最上位ビット、停止、日数の桁あふれ）
+  pub fn control(&self) -> u8 {
+    ((self.days >> 8) as u8 & 0x01)
+      | if self.halted { 0x40 } else { 0 }
+      | if self.day_carry { 0x80 } else { 0 }
+  }
+
+  fn read(&self, register: u8) -> u8 {
+    match register {
+      0x08..=0x0C => self.latched[(register - 0x08) as usize],
+      _ => 0xFF,
+    }
+  }
+
+  fn write(&mut self, register: u8, value: u8) {
+    match register {
+      0x08 => { self.seconds = value & 0x3F; self.sub_cycles = 0; },
+      0x09 => self.minutes = value & 0x3F,
+      0x0A => self.hours = value & 0x1F,
+      0x0B => self.days = (self.days & 0x100) | value as u16,
+      0x0C => self.set_control(value),
+      _ => {}
+    }
+  }
+
+  pub fn set_control(&mut self, value: u8) {
+    self.days = (self.days & 0xFF) | ((value as u16 & 0x01) << 8);
+    self.halted = value & 0x40 != 0;
+    self.day_carry = value & 0x80 != 0;
+  }
+}
+
+pub struct Cartridge {
+  pub rom: Vec<u8>,
+  // rom の CRC32。セーブステートやムービーが毎フレーム ROM を照合するので、作るときに一度だけ求める
+  pub rom_crc32: u32,
+  pub ram: Vec<u8>,
+  pub kind: MbcKind,
+  pub has_battery: bool,
+  pub rtc: Option<Rtc>,
+  pub ram_enabled: bool,
+  pub rom_bank: u16,
+  pub ram_bank: u8,
+  pub banking_mode: u8,
+}
+
+impl Cartridge {
+  pub fn new(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+    if rom.len() < 0x150 {
+      return Err(CartridgeError::TooSmall(rom.len()));
+    }
+
+    let cartridge_type = rom[0x147];
+    let (kind, has_battery, has_rtc) = match cartridge_type {
+      0x00 | 0x08 => (MbcKind::None, false, false),
+      0x09 => (MbcKind::None, true, false),
+      0x01 | 0x02 => (MbcKind::Mbc1, false, false),
+      0x03 => (MbcKind::Mbc1, true, false),
+      0x0F | 0x10 => (MbcKind::Mbc3, true, true),
+      0x11 | 0x12 => (MbcKind::Mbc3, false, false),
+      0x13 => (MbcKind::Mbc3, true, false),
+      0x19 | 0x1A | 0x1C | 0x1D => (MbcKind::Mbc5, false, false),
+      0x1B | 0x1E => (MbcKind::Mbc5, true, false),
+      other => return Err(CartridgeError::UnsupportedType(other)),
+    };
+
+    let ram_size = match rom[0x149] {
+      0x01 => 0x800,
+      0x02 => 0x2000,
+      0x03 => 0x8000,
+      0x04 => 0x20000,
+      0x05 => 0x10000,
+      _ => 0,
+    };
+
+    Ok(Cartridge {
+      rom_crc32: crc32(&rom),
+      rom,
+      ram: vec![0; ram_size],
+      kind,
+      has_battery,
+      rtc: if has_rtc { Some(Rtc::default()) } else { None },
+      ram_enabled: false,
+      rom_bank: 1,
+      ram_bank: 0,
+      banking_mode: 0,
+    })
+  }
+
+  pub fn title(&self) -> String {
+    self.rom[0x134..0x144]
+      .iter()
+      .take_while(|&&byte| byte != 0)
+      .map(|&byte| byte as char)
+      .collect()
+  }
+
+  fn rom_bank_count(&self) -> usize {
+    self.rom.len().div_ceil(ROM_BANK_SIZE).max(1)
+  }
+
+  fn ram_bank_count(&self) -> usize {
+    self.ram.len().div_ceil(RAM_BANK_SIZE).max(1)
+  }
+
+  // 0x4000-0x7FFF に見えているROMバンク番号
+  pub fn current_rom_bank(&self) -> usize {
+    let bank = match self.kind {
+      MbcKind::None => 1,
+      MbcKind::Mbc1 => {
+        let low = (self.rom_bank & 0x1F).max(1) as usize;
+        low | ((self.ram_bank as usize & 0x03) << 5)
+      },
+      MbcKind::Mbc3 => (self.rom_bank & 0x7F).max(1) as usize,
+      MbcKind::Mbc5 => (self.rom_bank & 0x1FF) as usize,
+    };
+    bank % self.rom_bank_count()
+  }
+
+  // address（0x0000-0x7FFF）に見えているROMバンク番号
+  pub fn rom_bank_at(&self, address: u16) -> usize {
+    if address < 0x4000 { self.zero_bank() } else { self.current_rom_bank() }
+  }
+
+  fn zero_bank(&self) -> usize {
+    match self.kind {
+      MbcKind::Mbc1 if self.banking_mode == 1 => ((self.ram_bank as usize & 0x03) << 5) % self.rom_bank_count(),
+      _ => 0,
+    }
+  }
+
+  fn current_ram_bank(&self) -> usize {
+    let bank = match self.kind {
+      MbcKind::Mbc1 if self.banking_mode == 1 => self.ram_bank as usize & 0x03,
+      MbcKind::Mbc1 | MbcKind::None => 0,
+      MbcKind::Mbc3 => self.ram_bank as usize & 0x03,
+      MbcKind::Mbc5 => self.ram_bank as usize & 0x0F,
+    };
+    bank % self.ram_bank_count()
+  }
+
+  pub fn read_rom(&self, address: u16) -> u8 {
+    let offset = self.rom_bank_at(address) * ROM_BANK_SIZE + (address as usize & 0x3FFF);
+    self.rom.get(offset).copied().unwrap_or(0xFF)
+  }
+
+  pub fn write_rom(&mut self, address: u16, value: u8) {
+    match (self.kind, address) {
+      (MbcKind::None, _) => {},
+      (_, 0x0000..=0x1FFF) => self.ram_enabled = value & 0x0F == 0x0A,
+      (MbcKind::Mbc1, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x1F) as u16,
+      (MbcKind::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x7F) as u16,
+      (MbcKind::Mbc5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+      (MbcKind::Mbc5, 0x3000..=0x3FFF) => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8),
+      (MbcKind::Mbc1, 0x4000..=0x5FFF) => self.ram_bank = value & 0x03,
+      (MbcKind::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+      (MbcKind::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+      (MbcKind::Mbc1, 0x6000..=0x7FFF) => self.banking_mode = value & 0x01,
+      (MbcKind::Mbc3, 0x6000..=0x7FFF) => {
+        if let Some(rtc) = self.rtc.as_mut() {
+          if value == 0x01 && rtc.latch_armed {
+            rtc.latch();
+          }
+          rtc.latch_armed = value == 0x00;
+        }
+      },
+      _ => {},
+    }
+  }
+
+  pub fn read_ram(&self, address: u16) -> u8 {
+    if !self.ram_enabled && self.kind != MbcKind::None {
+      return 0xFF;
+    }
+    if self.kind == MbcKind::Mbc3 && self.ram_bank >= 0x08 {
+      return self.rtc.as_ref().map_or(0xFF, |rtc| rtc.read(self.ram_bank));
+    }
+    let offset = self.current_ram_bank() * RAM_BANK_SIZE + (address as usize & 0x1FFF);
+    self.ram.get(offset).copied().unwrap_or(0xFF)
+  }
+
+  pub fn write_ram(&mut self, address: u16, value: u8) {
+    if !self.ram_enabled && self.kind != MbcKind::None {
+      return;
+    }
+    if self.kind == MbcKind::Mbc3 && self.ram_bank >= 0x08 {
+      if let Some(rtc) = self.rtc.as_mut() {
+        rtc.write(self.ram_bank, value);
+      }
+      return;
+    }
+    let offset = self.current_ram_bank() * RAM_BANK_SIZE + (address as usize & 0x1FFF);
+    if let Some(byte) = self.ram.get_mut(offset) {
+      *byte = value;
+    }
+  }
+
+  pub fn tick(&mut self, cycles: u8) {
+    if let Some(rtc) = self.rtc.as_mut() {
+      rtc.tick(cycles as u32);
+    }
+  }
+}
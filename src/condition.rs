@@ -0,0 +1,339 @@
+use std::fmt;
+
+use crate::cpu::{Bus, CPU};
+use crate::symbols::Symbols;
+
+// ブレークポイントやウォッチポイントの条件式。
+//
+//   pc == $0150 && a > 3
+//   [hl] == $FF
+//   bank == 2 && sp < $C100
+//   hits >= 10
+//
+// 数値は 10進数（3）、$FF / 0xFF（16進数）、%1010（2進数）。[式] はその番地の1バイト。
+// parse_with_symbols で読むと、レジスタ名以外の名前はラベルのアドレスになる（[wPlayerX] == 3）。
+// 演算子と優先順位は C と同じで、比較と論理演算の結果は 1 か 0
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Condition {
+  source: String,
+  expression: Expression,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Variable {
+  A, F, B, C, D, E, H, L,
+  AF, BC, DE, HL, SP, PC,
+  // FlagsRegister の各フラグ（0 か 1）
+  ZeroFlag, SubtractFlag, HalfCarryFlag, CarryFlag,
+  // PCに見えているROMバンク
+  Bank,
+  Ime,
+  // 条件を評価した回数（今回を含む）。ブレークポイントでは、そのアドレスに来た回数
+  Hits,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnaryOperator {
+  Negate,
+  Not,
+  Complement,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BinaryOperator {
+  Or, And,
+  BitOr, BitXor, BitAnd,
+  Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual,
+  ShiftLeft, ShiftRight,
+  Add, Subtract,
+  Multiply, Divide, Remainder,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expression {
+  Number(i64),
+  Variable(Variable),
+  // [address] のメモリの1バイト
+  Memory(Box<Expression>),
+  Unary(UnaryOperator, Box<Expression>),
+  Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+impl Condition {
+  pub fn parse(source: &str) -> Result<Condition, String> {
+    Condition::parse_with_symbols(source, &Symbols::new())
+  }
+
+  pub fn parse_with_symbols(source: &str, symbols: &Symbols) -> Result<Condition, String> {
+    let mut parser = Parser { text: source.as_bytes(), position: 0, symbols };
+    let expression = parser.expression(0)?;
+    parser.skip_whitespace();
+    if parser.position != parser.text.len() {
+      return Err(format!("unexpected '{}' in condition: {}", &source[parser.position..], source));
+    }
+    Ok(Condition { source: source.trim().to_string(), expression })
+  }
+
+  pub fn expression(&self) -> &Expression {
+    &self.expression
+  }
+
+  pub fn value<B: Bus>(&self, cpu: &CPU<B>, hits: u64) -> i64 {
+    self.expression.evaluate(cpu, hits)
+  }
+
+  // 値が0でなければ成立
+  pub fn holds<B: Bus>(&self, cpu: &CPU<B>, hits: u64) -> bool {
+    self.value(cpu, hits) != 0
+  }
+}
+
+impl fmt::Display for Condition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.source)
+  }
+}
+
+impl Expression {
+  // メモリは peek_byte で読むので、評価してもウォッチポイントには引っかからない。
+  // 0で割った場合は0になる
+  pub fn evaluate<B: Bus>(&self, cpu: &CPU<B>, hits: u64) -> i64 {
+    match self {
+      Expression::Number(value) => *value,
+      Expression::Variable(variable) => variable.value(cpu, hits),
+      Expression::Memory(address) => cpu.bus.peek(address.evaluate(cpu, hits) as u16) as i64,
+      Expression::Unary(operator, operand) => {
+        let value = operand.evaluate(cpu, hits);
+        match operator {
+          UnaryOperator::Negate => value.wrapping_neg(),
+          UnaryOperator::Not => (value == 0) as i64,
+          UnaryOperator::Complement => !value,
+        }
+      },
+      // && と || は左辺で結果が決まれば右辺を評価しない
+      Expression::Binary(BinaryOperator::And, left, right) => {
+        (left.evaluate(cpu, hits) != 0 && right.evaluate(cpu, hits) != 0) as i64
+      },
+      Expression::Binary(BinaryOperator::Or, left, right) => {
+        (left.evaluate(cpu, hits) != 0 || right.evaluate(cpu, hits) != 0) as i64
+      },
+      Expression::Binary(operator, left, right) => {
+        let left = left.evaluate(cpu, hits);
+        let right = right.evaluate(cpu, hits);
+        match operator {
+          BinaryOperator::BitOr => left | right,
+          BinaryOperator::BitXor => left ^ right,
+          BinaryOperator::BitAnd => left & right,
+          BinaryOperator::Equal => (left == right) as i64,
+          BinaryOperator::NotEqual => (left != right) as i64,
+          BinaryOperator::Less => (left < right) as i64,
+          BinaryOperator::LessEqual => (left <= right) as i64,
+          BinaryOperator::Greater => (left > right) as i64,
+          BinaryOperator::GreaterEqual => (left >= right) as i64,
+          BinaryOperator::ShiftLeft => left.checked_shl(right as u32).unwrap_or(0),
+          BinaryOperator::ShiftRight => left.checked_shr(right as u32).unwrap_or(0),
+          BinaryOperator::Add => left.wrapping_add(right),
+          BinaryOperator::Subtract => left.wrapping_sub(right),
+          BinaryOperator::Multiply => left.wrapping_mul(right),
+          BinaryOperator::Divide => left.checked_div(right).unwrap_or(0),
+          BinaryOperator::Remainder => left.checked_rem(right).unwrap_or(0),
+          BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+        }
+      },
+    }
+  }
+}
+
+impl Variable {
+  fn from_name(name: &str) -> Option<Variable> {
+    let variable = match name.to_lowercase().as_str() {
+      "a" => Variable::A,
+      "f" => Variable::F,
+      "b" => Variable::B,
+      "c" => Variable::C,
+      "d" => Variable::D,
+      "e" => Variable::E,
+      "h" => Variable::H,
+      "l" => Variable::L,
+      "af" => Variable::AF,
+      "bc" => Variable::BC,
+      "de" => Variable::DE,
+      "hl" => Variable::HL,
+      "sp" => Variable::SP,
+      "pc" => Variable::PC,
+      "zf" => Variable::ZeroFlag,
+      "nf" => Variable::SubtractFlag,
+      "hf" => Variable::HalfCarryFlag,
+      "cf" => Variable::CarryFlag,
+      "bank" => Variable::Bank,
+      "ime" => Variable::Ime,
+      "hits" => Variable::Hits,
+      _ => return None,
+    };
+    Some(variable)
+  }
+
+  fn value<B: Bus>(self, cpu: &CPU<B>, hits: u64) -> i64 {
+    let registers = &cpu.registers;
+    let value = match self {
+      Variable::A => registers.a as u64,
+      Variable::F => u8::from(registers.f) as u64,
+      Variable::B => registers.b as u64,
+      Variable::C => registers.c as u64,
+      Variable::D => registers.d as u64,
+      Variable::E => registers.e as u64,
+      Variable::H => registers.h as u64,
+      Variable::L => registers.l as u64,
+      Variable::AF => registers.get_af() as u64,
+      Variable::BC => registers.get_bc() as u64,
+      Variable::DE => registers.get_de() as u64,
+      Variable::HL => registers.get_hl() as u64,
+      Variable::SP => cpu.sp as u64,
+      Variable::PC => cpu.pc as u64,
+      Variable::ZeroFlag => registers.f.zero as u64,
+      Variable::SubtractFlag => registers.f.subtract as u64,
+      Variable::HalfCarryFlag => registers.f.half_carry as u64,
+      Variable::CarryFlag => registers.f.carry as u64,
+      Variable::Bank => cpu.bus.rom_bank(cpu.pc) as u64,
+      Variable::Ime => cpu.ime as u64,
+      Variable::Hits => hits,
+    };
+    value as i64
+  }
+}
+
+// 二項演算子と優先順位（数字が大きいほど強く結びつく）。長い記号を先に並べる
+const BINARY_OPERATORS: [(&str, BinaryOperator, u8); 18] = [
+  ("||", BinaryOperator::Or, 1),
+  ("&&", BinaryOperator::And, 2),
+  ("==", BinaryOperator::Equal, 6),
+  ("!=", BinaryOperator::NotEqual, 6),
+  ("<=", BinaryOperator::LessEqual, 7),
+  (">=", BinaryOperator::GreaterEqual, 7),
+  ("<<", BinaryOperator::ShiftLeft, 8),
+  (">>", BinaryOperator::ShiftRight, 8),
+  ("|", BinaryOperator::BitOr, 3),
+  ("^", BinaryOperator::BitXor, 4),
+  ("&", BinaryOperator::BitAnd, 5),
+  ("<", BinaryOperator::Less, 7),
+  (">", BinaryOperator::Greater, 7),
+  ("+", BinaryOperator::Add, 9),
+  ("-", BinaryOperator::Subtract, 9),
+  ("*", BinaryOperator::Multiply, 10),
+  ("/", BinaryOperator::Divide, 10),
+  ("%", BinaryOperator::Remainder, 10),
+];
+
+struct Parser<'a> {
+  text: &'a [u8],
+  position: usize,
+  symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+  fn skip_whitespace(&mut self) {
+    while self.text.get(self.position).is_some_and(u8::is_ascii_whitespace) {
+      self.position += 1;
+    }
+  }
+
+  fn eat(&mut self, token: &str) -> bool {
+    self.skip_whitespace();
+    if self.text[self.position..].starts_with(token.as_bytes()) {
+      self.position += token.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn rest(&self) -> String {
+    String::from_utf8_lossy(&self.text[self.position..]).into_owned()
+  }
+
+  fn peek_operator(&mut self) -> Option<(&'static str, BinaryOperator, u8)> {
+    self.skip_whitespace();
+    let rest = &self.text[self.position..];
+    BINARY_OPERATORS.iter().copied().find(|(symbol, _, _)| rest.starts_with(symbol.as_bytes()))
+  }
+
+  // 優先順位 min_precedence 以上の演算子だけをまとめる（優先順位上昇法）
+  fn expression(&mut self, min_precedence: u8) -> Result<Expression, String> {
+    let mut left = self.unary()?;
+    while let Some((symbol, operator, precedence)) = self.peek_operator() {
+      if precedence < min_precedence {
+        break;
+      }
+      self.position += symbol.len();
+      let right = self.expression(precedence + 1)?;
+      left = Expression::Binary(operator, Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn unary(&mut self) -> Result<Expression, String> {
+    let operator = if self.eat("-") {
+      UnaryOperator::Negate
+    } else if self.eat("!") {
+      UnaryOperator::Not
+    } else if self.eat("~") {
+      UnaryOperator::Complement
+    } else {
+      return self.primary();
+    };
+    Ok(Expression::Unary(operator, Box::new(self.unary()?)))
+  }
+
+  fn primary(&mut self) -> Result<Expression, String> {
+    if self.eat("(") {
+      let expression = self.expression(0)?;
+      return if self.eat(")") { Ok(expression) } else { Err(format!("missing ')' before: {}", self.rest())) };
+    }
+    if self.eat("[") {
+      let address = self.expression(0)?;
+      return if self.eat("]") {
+        Ok(Expression::Memory(Box::new(address)))
+      } else {
+        Err(format!("missing ']' before: {}", self.rest()))
+      };
+    }
+    if self.eat("$") || self.eat("0x") || self.eat("0X") {
+      return self.number(16);
+    }
+    if self.eat("%") {
+      return self.number(2);
+    }
+
+    self.skip_whitespace();
+    let start = self.position;
+    match self.text.get(start) {
+      Some(c) if c.is_ascii_digit() => self.number(10),
+      Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+        // RGBDS のラベルは "Main.loop" や "wBuffer@" のように . や @ も使える
+        while self.text.get(self.position).is_some_and(|&c| c.is_ascii_alphanumeric() || b"_.@#".contains(&c)) {
+          self.position += 1;
+        }
+        let name = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+        if let Some(variable) = Variable::from_name(&name) {
+          return Ok(Expression::Variable(variable));
+        }
+        match self.symbols.find(&name) {
+          Some(symbol) => Ok(Expression::Number(symbol.address as i64)),
+          None => Err(format!("unknown variable: {}", name)),
+        }
+      },
+      _ => Err(format!("expected a value at: {}", self.rest())),
+    }
+  }
+
+  fn number(&mut self, radix: u32) -> Result<Expression, String> {
+    let start = self.position;
+    while self.text.get(self.position).is_some_and(|&c| (c as char).is_digit(radix)) {
+      self.position += 1;
+    }
+    let digits = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+    i64::from_str_radix(&digits, radix)
+      .map(Expression::Number)
+      .map_err(|_| format!("invalid number at: {}", self.rest()))
+  }
+}
@@ -0,0 +1,640 @@
+use crate::condition::Condition;
+use crate::disassembler::{disassemble_at, disassemble_with_symbols, Disassembly};
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::history::History;
+use crate::instruction::Instruction;
+use crate::register::FlagsRegister;
+use crate::watchpoint::{WatchHit, WatchKind, Watchpoint};
+
+pub const HELP: &str = "commands:
+  step [n]            execute n instructions (default 1)
+  next                like step, but runs called subroutines to completion
+  continue [frames]   run until a breakpoint or watchpoint is hit,
+                      giving up after the given number of frames (default 600)
+  finish              run until the current subroutine returns
+  reverse-step [n]    go back n instructions (default 1)
+  reverse-continue    run backwards to the previous breakpoint or watchpoint hit
+  break <addr> [if <cond>]
+                      set a breakpoint (bank:addr matches only that ROM bank)
+  break if <cond>     stop before any instruction where the condition holds
+  break               list breakpoints
+  cond <n> [<cond>]   set or remove the condition of breakpoint n
+  delete [n]          delete breakpoint n, or all breakpoints
+  watch <kind> <addr>[-<end>] [value] [if <cond>]
+                      stop when memory is accessed (kind: read, write, access, exec);
+                      with a value, only when that value is read/written/executed
+  watch               list watchpoints
+  unwatch [n]         delete watchpoint n, or all watchpoints
+  regs                show CPU registers
+  bt                  show the call stack (innermost first) and unbalanced returns
+  mem <addr> [len]    dump memory (default 16 bytes)
+  disasm [addr]       disassemble from addr (default: PC)
+  set <reg> <value>   set a register (a, f, b, c, d, e, h, l, af, bc, de, hl, sp, pc)
+  print <expr>        evaluate an expression
+  quit                exit the emulator
+addresses and values are hexadecimal ($ or 0x prefix optional); counts are decimal.
+conditions use decimal unless prefixed ($FF, 0xFF, %1010), e.g.
+  pc == $0150 && a > 3    [hl] == $FF    bank == 2 && sp < $C100    hits >= 10";
+
+// run が止まった理由
+enum Stop {
+  Done,
+  Breakpoint(usize),
+  Watchpoint(Vec<WatchHit>),
+  // フレーム数の上限まで実行しても止まらなかった
+  FrameLimit(u64),
+  // 未定義のオペコードで CPU が固まった
+  IllegalOpcode(u8),
+}
+
+// 実行し直した1命令
+struct Replayed {
+  // 命令の前と後の GameBoy::cycles
+  start: u64,
+  end: u64,
+  // この命令のあとで止まったかどうか
+  stop: Option<Stop>,
+}
+
+// disasm で表示する命令数
+const DISASM_LINES: usize = 8;
+
+// continue などで、ブレークポイントに当たらなくても止まるまでのフレーム数（約10秒）。
+// 割り込んで止める手段がないので、上限がないと REPL が戻ってこなくなる
+const RUN_FRAME_LIMIT: u64 = 600;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Breakpoint {
+  pub id: usize,
+  // None のときはバンクを問わずアドレスだけで止まる
+  pub bank: Option<usize>,
+  // None のときはアドレスを問わず、命令ごとに条件を評価する
+  pub address: Option<u16>,
+  pub condition: Option<Condition>,
+  // アドレスが一致した回数（条件式の hits）
+  pub hits: u64,
+}
+
+impl Breakpoint {
+  fn matches(&mut self, gameboy: &GameBoy) -> bool {
+    let pc = gameboy.cpu.pc;
+    if self.address.is_some_and(|address| address != pc) || self.bank.is_some_and(|bank| gameboy.cpu.bus.rom_bank(pc) != bank) {
+      return false;
+    }
+    self.hits += 1;
+    self.condition.as_ref().is_none_or(|condition| condition.holds(&gameboy.cpu, self.hits))
+  }
+
+  fn describe(&self) -> String {
+    let mut text = match self.address {
+      Some(address) => format_location(self.bank, address),
+      None => "any address".to_string(),
+    };
+    if let Some(condition) = &self.condition {
+      text.push_str(&format!(" if {}", condition));
+    }
+    text
+  }
+}
+
+// コマンドを1行ずつ受け取って GameBoy を操作する。
+// ブレークポイントの判定は命令と命令の間で行うだけなので、エミュレートされるタイミングは変わらない
+pub struct Debugger {
+  breakpoints: Vec<Breakpoint>,
+  next_id: usize,
+  // reverse-step と reverse-continue のためのチェックポイント
+  history: History,
+}
+
+impl Debugger {
+  pub fn new() -> Debugger {
+    Debugger { breakpoints: Vec::new(), next_id: 1, history: History::new() }
+  }
+
+  pub fn breakpoints(&self) -> &[Breakpoint] {
+    &self.breakpoints
+  }
+
+  pub fn add_breakpoint(&mut self, bank: Option<usize>, address: Option<u16>, condition: Option<Condition>) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.breakpoints.push(Breakpoint { id, bank, address, condition, hits: 0 });
+    id
+  }
+
+  pub fn delete_breakpoint(&mut self, id: usize) -> bool {
+    let count = self.breakpoints.len();
+    self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+    self.breakpoints.len() != count
+  }
+
+  // 1行分のコマンドを実行し、表示する文字列を返す
+  pub fn execute(&mut self, gameboy: &mut GameBoy, line: &str) -> Result<String, String> {
+    // "break 0150 if a > 3" の if 以降は条件式
+    let (line, condition) = match line.split_once(" if ") {
+      Some((line, condition)) => (line, Some(Condition::parse_with_symbols(condition, &gameboy.symbols)?)),
+      None => (line, None),
+    };
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else { return Ok(String::new()) };
+    let arguments: Vec<&str> = words.collect();
+
+    match (command, arguments.as_slice(), condition) {
+      ("break" | "b", [], Some(condition)) => {
+        let id = self.add_breakpoint(None, None, Some(condition));
+        Ok(format!("Breakpoint {} at {}", id, self.breakpoints.last().unwrap().describe()))
+      },
+      ("break" | "b", [location], condition) => {
+        let (bank, address) = resolve_location(gameboy, location)?;
+        let id = self.add_breakpoint(bank, Some(address), condition);
+        Ok(format!("Breakpoint {} at {}", id, self.breakpoints.last().unwrap().describe()))
+      },
+      ("watch", [kind, range], condition) => add_watchpoint(gameboy, kind, range, None, condition),
+      ("watch", [kind, range, value], condition) => {
+        let value = u8::try_from(parse_value(value)?).map_err(|_| format!("watch value must be a byte: {}", value))?;
+        add_watchpoint(gameboy, kind, range, Some(value), condition)
+      },
+      (_, _, Some(_)) => Err(format!("{} does not take a condition", command)),
+      (_, arguments, None) => self.execute_command(gameboy, command, arguments, line),
+    }
+  }
+
+  fn execute_command(&mut self, gameboy: &mut GameBoy, command: &str, arguments: &[&str], line: &str) -> Result<String, String> {
+    match (command, arguments) {
+      ("help" | "h", []) => Ok(HELP.to_string()),
+      ("step" | "s", []) => Ok(self.step(gameboy, 1)),
+      ("step" | "s", [count]) => {
+        let count = count.parse().map_err(|_| format!("invalid count: {}", count))?;
+        Ok(self.step(gameboy, count))
+      },
+      ("next" | "n", []) => Ok(self.next(gameboy)),
+      ("continue" | "c", []) => {
+        let stop = self.run(gameboy, None, RUN_FRAME_LIMIT, |_, _| false);
+        Ok(self.describe_stop(gameboy, stop))
+      },
+      ("continue" | "c", [frames]) => {
+        let frames = frames.parse().map_err(|_| format!("invalid frame count: {}", frames))?;
+        let stop = self.run(gameboy, None, frames, |_, _| false);
+        Ok(self.describe_stop(gameboy, stop))
+      },
+      ("finish", []) => Ok(self.finish(gameboy)),
+      ("reverse-step" | "rs", []) => self.reverse_step(gameboy, 1),
+      ("reverse-step" | "rs", [count]) => {
+        let count = count.parse().map_err(|_| format!("invalid count: {}", count))?;
+        self.reverse_step(gameboy, count)
+      },
+      ("reverse-continue" | "rc", []) => self.reverse_continue(gameboy),
+      ("break" | "b", []) => Ok(self.list_breakpoints()),
+      ("cond", [id, expression @ ..]) => {
+        let id: usize = id.parse().map_err(|_| format!("invalid breakpoint number: {}", id))?;
+        let condition = if expression.is_empty() {
+          None
+        } else {
+          Some(Condition::parse_with_symbols(&expression.join(" "), &gameboy.symbols)?)
+        };
+        let breakpoint = self
+          .breakpoints
+          .iter_mut()
+          .find(|breakpoint| breakpoint.id == id)
+          .ok_or_else(|| format!("no breakpoint number {}", id))?;
+        breakpoint.condition = condition;
+        breakpoint.hits = 0;
+        Ok(format!("Breakpoint {} at {}", id, breakpoint.describe()))
+      },
+      ("print" | "p", [_, ..]) => {
+        let expression = Condition::parse_with_symbols(&arguments.join(" "), &gameboy.symbols)?;
+        let value = expression.value(&gameboy.cpu, 0);
+        Ok(format!("{} = ${:X} ({})", expression, value, value))
+      },
+      ("delete" | "d", []) => {
+        self.breakpoints.clear();
+        Ok("Deleted all breakpoints".to_string())
+      },
+      ("delete" | "d", [id]) => {
+        let id = id.parse().map_err(|_| format!("invalid breakpoint number: {}", id))?;
+        if self.delete_breakpoint(id) {
+          Ok(format!("Deleted breakpoint {}", id))
+        } else {
+          Err(format!("no breakpoint number {}", id))
+        }
+      },
+      ("watch", []) => Ok(list_watchpoints(gameboy)),
+      ("unwatch", []) => {
+        gameboy.cpu.bus.watchpoints.clear();
+        Ok("Deleted all watchpoints".to_string())
+      },
+      ("unwatch", [id]) => {
+        let id = id.parse().map_err(|_| format!("invalid watchpoint number: {}", id))?;
+        if gameboy.cpu.bus.watchpoints.remove(id) {
+          Ok(format!("Deleted watchpoint {}", id))
+        } else {
+          Err(format!("no watchpoint number {}", id))
+        }
+      },
+      ("regs", []) => Ok(registers(gameboy)),
+      ("bt" | "backtrace", []) => Ok(backtrace(gameboy)),
+      ("mem", [address]) => Ok(memory_dump(gameboy, resolve_value(gameboy, address)?, 16)),
+      ("mem", [address, length]) => {
+        let length = length.parse().map_err(|_| format!("invalid length: {}", length))?;
+        Ok(memory_dump(gameboy, resolve_value(gameboy, address)?, length))
+      },
+      ("disasm", []) => Ok(disassembly(gameboy, gameboy.cpu.pc)),
+      ("disasm", [address]) => Ok(disassembly(gameboy, resolve_value(gameboy, address)?)),
+      ("set", [register, value]) => {
+        set_register(gameboy, register, parse_value(value)?)?;
+        // 書き換える前の実行には戻れなくなる
+        self.history.clear();
+        Ok(registers(gameboy))
+      },
+      _ => Err(format!("unknown command or wrong arguments: {} (try help)", line.trim())),
+    }
+  }
+
+  // 今のPCの位置と命令（停止したときやプロンプトの前に表示する）。ラベルがあれば <Main+$3> を添える
+  pub fn location(&self, gameboy: &GameBoy) -> String {
+    let pc = gameboy.cpu.pc;
+    let bus = &gameboy.cpu.bus;
+    let bank = bus.rom_bank(pc);
+    let line = format!("{:02X}:{}", bank, disassemble_symbolic(gameboy, pc));
+    match gameboy.symbols.describe(bank, pc) {
+      Some(label) => format!("{}  <{}>", line, label),
+      None => line,
+    }
+  }
+
+  fn step(&mut self, gameboy: &mut GameBoy, count: u64) -> String {
+    if count == 0 {
+      return self.location(gameboy);
+    }
+    let stop = self.run(gameboy, Some(count), RUN_FRAME_LIMIT, |_, _| false);
+    self.describe_stop(gameboy, stop)
+  }
+
+  // CALL と RST は呼び出し先から戻ってくるまで実行する。それ以外は step と同じ
+  fn next(&mut self, gameboy: &mut GameBoy) -> String {
+    let cpu = &gameboy.cpu;
+    let opcode = cpu.bus.peek_byte(cpu.pc);
+    let is_call = matches!(Instruction::from_byte(opcode, false), Some(Instruction::CALL(_) | Instruction::RST(_)));
+    if !is_call {
+      return self.step(gameboy, 1);
+    }
+    let return_address = disassemble_at(&cpu.bus, cpu.pc).next_address();
+    let sp = cpu.sp;
+    let stop = self.run(gameboy, None, RUN_FRAME_LIMIT, |gameboy, _| gameboy.cpu.pc == return_address && gameboy.cpu.sp >= sp);
+    self.describe_stop(gameboy, stop)
+  }
+
+  // RET/RETI でスタックが今より浅くなるまで実行する
+  fn finish(&mut self, gameboy: &mut GameBoy) -> String {
+    let sp = gameboy.cpu.sp;
+    let stop = self.run(gameboy, None, RUN_FRAME_LIMIT, |gameboy, opcode| {
+      matches!(Instruction::from_byte(opcode, false), Some(Instruction::RET(_) | Instruction::RETI)) && gameboy.cpu.sp > sp
+    });
+    self.describe_stop(gameboy, stop)
+  }
+
+  // done が true を返すか、limit 命令を実行するか、ブレークポイントかウォッチポイントに当たるまで実行する。
+  // frames フレーム分のサイクルを過ぎたらそこであきらめる。
+  // done には実行した命令のオペコードも渡す。ウォッチポイントは命令を実行し終えたところで止まる
+  fn run<F>(&mut self, gameboy: &mut GameBoy, limit: Option<u64>, frames: u64, mut done: F) -> Stop
+  where
+    F: FnMut(&GameBoy, u8) -> bool,
+  {
+    let mut count = 0;
+    let deadline = gameboy.cycles.saturating_add(frames.saturating_mul(CYCLES_PER_FRAME));
+    loop {
+      let breakpoints = &self.breakpoints;
+      self.history.record(gameboy, || breakpoint_hits(breakpoints));
+      let opcode = gameboy.cpu.bus.peek_byte(gameboy.cpu.pc);
+      gameboy.step();
+      count += 1;
+      if let Some(stop) = self.check_stop(gameboy) {
+        return stop;
+      }
+      if done(gameboy, opcode) || limit.is_some_and(|limit| count >= limit) {
+        return Stop::Done;
+      }
+      if gameboy.cycles >= deadline {
+        return Stop::FrameLimit(frames);
+      }
+    }
+  }
+
+  // 命令を実行し終えたところで、ウォッチポイントかブレークポイントに当たったかを調べる
+  fn check_stop(&mut self, gameboy: &mut GameBoy) -> Option<Stop> {
+    if let Some(opcode) = gameboy.cpu.illegal_opcode {
+      return Some(Stop::IllegalOpcode(opcode));
+    }
+    let hits = gameboy.cpu.bus.watchpoints.take_hits();
+    if !hits.is_empty() {
+      return Some(Stop::Watchpoint(hits));
+    }
+    // 同じアドレスのブレークポイントがすべて hits を数えるよう、最初に一致したもので止めずに全部調べる
+    let mut hit = None;
+    for breakpoint in &mut self.breakpoints {
+      if breakpoint.matches(gameboy) && hit.is_none() {
+        hit = Some(breakpoint.id);
+      }
+    }
+    hit.map(Stop::Breakpoint)
+  }
+
+  // count 命令前に戻る。チェックポイントに戻してから、目的の命令の手前まで実行し直す
+  fn reverse_step(&mut self, gameboy: &mut GameBoy, count: usize) -> Result<String, String> {
+    if self.history.is_empty() {
+      return Err("no execution history to go back to".to_string());
+    }
+    if count == 0 {
+      return Ok(self.location(gameboy));
+    }
+    let mut current = gameboy.cycles;
+    let mut remaining = count;
+    let mut target = None;
+    while let Some(index) = self.history.before(current) {
+      let steps = self.replay(gameboy, index, current);
+      if steps.len() >= remaining {
+        target = Some(steps[steps.len() - remaining].start);
+        break;
+      }
+      remaining -= steps.len();
+      current = self.history.cycles(index);
+    }
+    self.seek(gameboy, target.unwrap_or(current));
+    match target {
+      Some(_) => Ok(self.location(gameboy)),
+      None => Ok(format!("Reached the oldest checkpoint\n{}", self.location(gameboy))),
+    }
+  }
+
+  // 今より前で最後にブレークポイントかウォッチポイントに当たった命令の直後に戻る。
+  // 新しいチェックポイントから順に、その区間を実行し直して探す
+  fn reverse_continue(&mut self, gameboy: &mut GameBoy) -> Result<String, String> {
+    if self.history.is_empty() {
+      return Err("no execution history to go back to".to_string());
+    }
+    let origin = gameboy.cycles;
+    let mut current = origin;
+    let mut found = None;
+    while let Some(index) = self.history.before(current) {
+      let steps = self.replay(gameboy, index, current);
+      // 今止まっている位置の当たりは除く
+      if let Some(step) = steps.into_iter().rev().find(|step| step.stop.is_some() && step.end < origin) {
+        found = Some(step);
+        break;
+      }
+      current = self.history.cycles(index);
+    }
+    match found {
+      Some(Replayed { end, stop: Some(stop), .. }) => {
+        self.seek(gameboy, end);
+        Ok(self.describe_stop(gameboy, stop))
+      },
+      _ => {
+        self.seek(gameboy, current);
+        Ok(format!("No earlier breakpoint or watchpoint hit; reached the oldest checkpoint\n{}", self.location(gameboy)))
+      },
+    }
+  }
+
+  // target の位置（GameBoy::cycles）に戻る
+  fn seek(&mut self, gameboy: &mut GameBoy, target: u64) {
+    let index = self.history.at_or_before(target).expect("target is within the history");
+    self.replay(gameboy, index, target);
+  }
+
+  // index のチェックポイントに戻し、gameboy.cycles が until に達するまで実行し直す。
+  // ブレークポイントとウォッチポイントの hits もチェックポイントの時点から数え直す
+  fn replay(&mut self, gameboy: &mut GameBoy, index: usize, until: u64) -> Vec<Replayed> {
+    let hits = self.history.restore(index, gameboy);
+    for breakpoint in &mut self.breakpoints {
+      breakpoint.hits = hits.iter().find(|&&(id, _)| id == breakpoint.id).map_or(0, |&(_, hits)| hits);
+    }
+    // 実行し直した命令をもう一度トレースに書かない
+    let tracer = gameboy.cpu.tracer.take();
+    let mut steps = Vec::new();
+    while gameboy.cycles < until {
+      let start = gameboy.cycles;
+      gameboy.step();
+      let stop = self.check_stop(gameboy);
+      steps.push(Replayed { start, end: gameboy.cycles, stop });
+    }
+    gameboy.cpu.tracer = tracer;
+    steps
+  }
+
+  fn describe_stop(&self, gameboy: &GameBoy, stop: Stop) -> String {
+    match stop {
+      Stop::Done => self.location(gameboy),
+      Stop::Breakpoint(id) => format!("Breakpoint {}, {}", id, self.location(gameboy)),
+      Stop::FrameLimit(frames) => format!("No breakpoint hit in {} frames, {}", frames, self.location(gameboy)),
+      Stop::IllegalOpcode(opcode) => format!("CPU locked up on illegal opcode ${:02X}, {}", opcode, self.location(gameboy)),
+      Stop::Watchpoint(hits) => {
+        let mut lines: Vec<String> = hits.iter().map(WatchHit::to_string).collect();
+        lines.push(self.location(gameboy));
+        lines.join("\n")
+      },
+    }
+  }
+
+  fn list_breakpoints(&self) -> String {
+    if self.breakpoints.is_empty() {
+      return "No breakpoints".to_string();
+    }
+    self
+      .breakpoints
+      .iter()
+      .map(|breakpoint| format!("{}: {}", breakpoint.id, breakpoint.describe()))
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+}
+
+impl Default for Debugger {
+  fn default() -> Self {
+    Debugger::new()
+  }
+}
+
+fn breakpoint_hits(breakpoints: &[Breakpoint]) -> Vec<(usize, u64)> {
+  breakpoints.iter().map(|breakpoint| (breakpoint.id, breakpoint.hits)).collect()
+}
+
+fn add_watchpoint(
+  gameboy: &mut GameBoy,
+  kind: &str,
+  range: &str,
+  value: Option<u8>,
+  condition: Option<Condition>,
+) -> Result<String, String> {
+  let kind = match kind {
+    "read" | "r" => WatchKind::Read,
+    "write" | "w" => WatchKind::Write,
+    "access" | "rw" => WatchKind::Access,
+    "exec" | "x" => WatchKind::Execute,
+    _ => return Err(format!("unknown watch kind: {} (read, write, access or exec)", kind)),
+  };
+  let (start, end) = match range.split_once('-') {
+    Some((start, end)) => (resolve_value(gameboy, start)?, resolve_value(gameboy, end)?),
+    None => (resolve_value(gameboy, range)?, resolve_value(gameboy, range)?),
+  };
+  if start > end {
+    return Err(format!("invalid range: {}", range));
+  }
+  let watchpoints = &mut gameboy.cpu.bus.watchpoints;
+  let id = watchpoints.add(kind, start..=end, value);
+  watchpoints.set_condition(id, condition);
+  let watchpoint = gameboy.cpu.bus.watchpoints.list().find(|watchpoint| watchpoint.id == id).unwrap();
+  Ok(format!("Watchpoint {}: {}", id, format_watchpoint(watchpoint)))
+}
+
+fn list_watchpoints(gameboy: &GameBoy) -> String {
+  let lines: Vec<String> = gameboy
+    .cpu
+    .bus
+    .watchpoints
+    .list()
+    .map(|watchpoint| format!("{}: {}", watchpoint.id, format_watchpoint(watchpoint)))
+    .collect();
+  if lines.is_empty() { "No watchpoints".to_string() } else { lines.join("\n") }
+}
+
+fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+  let kind = match watchpoint.kind {
+    WatchKind::Read => "read",
+    WatchKind::Write => "write",
+    WatchKind::Access => "access",
+    WatchKind::Execute => "exec",
+  };
+  let mut text = format!("{} {:04X}", kind, watchpoint.range.start());
+  if watchpoint.range.end() != watchpoint.range.start() {
+    text.push_str(&format!("-{:04X}", watchpoint.range.end()));
+  }
+  if let Some(value) = watchpoint.value {
+    text.push_str(&format!(" == {:02X}", value));
+  }
+  if let Some(condition) = &watchpoint.condition {
+    text.push_str(&format!(" if {}", condition));
+  }
+  text
+}
+
+fn format_location(bank: Option<usize>, address: u16) -> String {
+  match bank {
+    Some(bank) => format!("{:02X}:{:04X}", bank, address),
+    None => format!("{:04X}", address),
+  }
+}
+
+// "0150"、"$0150"、"0x0150" はすべて16進数として読む
+pub fn parse_value(text: &str) -> Result<u16, String> {
+  let digits = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+  u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hexadecimal value: {}", text))
+}
+
+// "0150" または "bank:addr"（"01:4000"）
+pub fn parse_location(text: &str) -> Result<(Option<usize>, u16), String> {
+  match text.split_once(':') {
+    Some((bank, address)) => Ok((Some(parse_value(bank)? as usize), parse_value(address)?)),
+    None => Ok((None, parse_value(text)?)),
+  }
+}
+
+// ラベル名か、parse_value で読める16進数
+fn resolve_value(gameboy: &GameBoy, text: &str) -> Result<u16, String> {
+  match gameboy.symbols.find(text) {
+    Some(symbol) => Ok(symbol.address),
+    None => parse_value(text),
+  }
+}
+
+// ラベル名か、parse_location で読める "bank:addr"。ROMX のラベルはそのバンクでだけ止まる
+fn resolve_location(gameboy: &GameBoy, text: &str) -> Result<(Option<usize>, u16), String> {
+  match gameboy.symbols.find(text) {
+    Some(symbol) if (0x4000..=0x7FFF).contains(&symbol.address) => Ok((Some(symbol.bank), symbol.address)),
+    Some(symbol) => Ok((None, symbol.address)),
+    None => parse_location(text),
+  }
+}
+
+fn disassemble_symbolic(gameboy: &GameBoy, address: u16) -> Disassembly {
+  let bus = &gameboy.cpu.bus;
+  let bytes: Vec<u8> = (0..3).map(|offset| bus.peek_byte(address.wrapping_add(offset))).collect();
+  disassemble_with_symbols(&bytes, address, &gameboy.symbols, bus.rom_bank(0x4000))
+}
+
+fn registers(gameboy: &GameBoy) -> String {
+  let cpu = &gameboy.cpu;
+  let registers = &cpu.registers;
+  format!(
+    "AF={:04X} BC={:04X} DE={:04X} HL={:04X}\nSP={:04X} PC={:04X} F={} IME={} HALT={} CY={}",
+    registers.get_af(), registers.get_bc(), registers.get_de(), registers.get_hl(),
+    cpu.sp, cpu.pc, cpu.trace_record().flags(), cpu.ime as u8, cpu.halted as u8, gameboy.cycles,
+  )
+}
+
+fn memory_dump(gameboy: &GameBoy, start: u16, length: usize) -> String {
+  let mut lines = Vec::new();
+  let mut previous = None;
+  for row in (0..length).step_by(16) {
+    let address = start.wrapping_add(row as u16);
+    let bytes: Vec<String> = (0..16.min(length - row))
+      .map(|offset| format!("{:02X}", gameboy.cpu.bus.peek_byte(address.wrapping_add(offset as u16))))
+      .collect();
+    let mut line = format!("{:04X}: {}", address, bytes.join(" "));
+    // .map を読み込んでいれば、行の先頭が入っているセクションを添える（続く行で同じなら省く）
+    let section = gameboy.symbols.section(gameboy.cpu.bus.rom_bank(address), address);
+    if let Some(section) = section
+      && Some(section) != previous
+    {
+      line.push_str(&format!("  ; {} \"{}\"", section.region, section.name));
+    }
+    previous = section;
+    lines.push(line);
+  }
+  lines.join("\n")
+}
+
+fn disassembly(gameboy: &GameBoy, start: u16) -> String {
+  let bus = &gameboy.cpu.bus;
+  let mut lines = Vec::new();
+  let mut address = start;
+  for _ in 0..DISASM_LINES {
+    let disassembly = disassemble_symbolic(gameboy, address);
+    let marker = if address == gameboy.cpu.pc { "=>" } else { "  " };
+    let bank = bus.rom_bank(address);
+    if let Some(label) = gameboy.symbols.label(bank, address) {
+      lines.push(format!("{}:", label));
+    }
+    lines.push(format!("{} {:02X}:{}", marker, bank, disassembly));
+    address = disassembly.next_address();
+  }
+  lines.join("\n")
+}
+
+pub fn backtrace(gameboy: &GameBoy) -> String {
+  let cpu = &gameboy.cpu;
+  cpu.call_stack.backtrace(cpu.pc, cpu.bus.rom_bank(cpu.pc), &gameboy.symbols)
+}
+
+fn set_register(gameboy: &mut GameBoy, register: &str, value: u16) -> Result<(), String> {
+  let cpu = &mut gameboy.cpu;
+  let byte = || u8::try_from(value).map_err(|_| format!("value too large for {}: {:X}", register, value));
+  match register.to_lowercase().as_str() {
+    "a" => cpu.registers.a = byte()?,
+    "f" => cpu.registers.f = FlagsRegister::from(byte()?),
+    "b" => cpu.registers.b = byte()?,
+    "c" => cpu.registers.c = byte()?,
+    "d" => cpu.registers.d = byte()?,
+    "e" => cpu.registers.e = byte()?,
+    "h" => cpu.registers.h = byte()?,
+    "l" => cpu.registers.l = byte()?,
+    "af" => cpu.registers.set_af(value),
+    "bc" => cpu.registers.set_bc(value),
+    "de" => cpu.registers.set_de(value),
+    "hl" => cpu.registers.set_hl(value),
+    "sp" => cpu.sp = value,
+    "pc" => cpu.pc = value,
+    _ => return Err(format!("unknown register: {}", register)),
+  }
+  Ok(())
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::instruction::lookup;
+use crate::symbols::Symbols;
+
+// 1命令分の逆アセンブル結果
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Disassembly {
+  pub address: u16,
+  // オペコードとオペランドのバイト列（長さが命令長）
+  pub bytes: Vec<u8>,
+  pub text: String,
+}
+
+impl Disassembly {
+  pub fn length(&self) -> u16 {
+    self.bytes.len() as u16
+  }
+
+  pub fn next_address(&self) -> u16 {
+    self.address.wrapping_add(self.length())
+  }
+}
+
+impl fmt::Display for Disassembly {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    write!(f, "{:04X}: {:<8}  {}", self.address, bytes.join(" "), self.text)
+  }
+}
+
+// address に置かれた命令を逆アセンブルする。bytes[0] がオペコードで、オペランドが続く。
+// デコードできないバイトや、オペランドが bytes に収まらない命令は "DB $XX" として1バイトずつ扱う
+pub fn disassemble(bytes: &[u8], address: u16) -> Disassembly {
+  disassemble_with_symbols(bytes, address, &Symbols::new(), 0)
+}
+
+// disassemble と同じだが、JP/CALL/JR の飛び先や (a16) などのアドレスにラベルがあればラベルで表示する。
+// rom_bank は 0x4000〜0x7FFF に見えているROMバンク
+pub fn disassemble_with_symbols(bytes: &[u8], address: u16, symbols: &Symbols, rom_bank: usize) -> Disassembly {
+  let Some(&opcode) = bytes.first() else {
+    return Disassembly { address, bytes: Vec::new(), text: String::new() };
+  };
+
+  let (info, opcode_length) = if opcode == 0xCB {
+    match bytes.get(1) {
+      Some(&byte) => (Some(lookup(byte, true)), 2),
+      None => (None, 1),
+    }
+  } else {
+    (Some(lookup(opcode, false)), 1)
+  };
+
+  let Some(info) = info.filter(|info| info.instruction.is_some()) else {
+    let length = opcode_length.min(bytes.len());
+    let values: Vec<String> = bytes[..length].iter().map(|byte| format!("${:02X}", byte)).collect();
+    return Disassembly { address, bytes: bytes[..length].to_vec(), text: format!("DB {}", values.join(",")) };
+  };
+
+  let template = info.instruction.unwrap().to_string();
+  let length = info.length as usize;
+  if bytes.len() < length {
+    return Disassembly { address, bytes: vec![opcode], text: format!("DB ${:02X}", opcode) };
+  }
+
+  let label = |address: u16| symbols.label(rom_bank, address).map(str::to_string);
+  let text = fill_operands(&template, &bytes[opcode_length..length], address.wrapping_add(length as u16), label);
+  Disassembly { address, bytes: bytes[..length].to_vec(), text }
+}
+
+// バス経由で読み出して逆アセンブルする。peek で読むのでウォッチポイントやアクセスの記録には引っかからない
+pub fn disassemble_at(bus: &impl Bus, address: u16) -> Disassembly {
+  let bytes: Vec<u8> = (0..3).map(|offset| bus.peek(address.wrapping_add(offset))).collect();
+  disassemble(&bytes, address)
+}
+
+// bytes の先頭を start_address として、末尾まで順番に逆アセンブルする
+pub fn disassemble_range(bytes: &[u8], start_address: u16) -> Vec<Disassembly> {
+  let mut result = Vec::new();
+  let mut offset = 0;
+  while offset < bytes.len() {
+    let disassembly = disassemble(&bytes[offset..], start_address.wrapping_add(offset as u16));
+    offset += disassembly.bytes.len();
+    result.push(disassembly);
+  }
+  result
+}
+
+// 命令表の d8/d16/a8/a16/r8 を実際の値に置き換える。
+// JR の r8 は飛び先のアドレスで表示し、それ以外（ADD SP,r8 など）は符号付きの値で表示する。
+// アドレス（a16、a8、JR の飛び先）は label が返すラベルがあればそれで表示する。d16 は定数のことも多いので数値のまま
+fn fill_operands<F>(template: &str, operands: &[u8], next_address: u16, label: F) -> String
+where
+  F: Fn(u16) -> Option<String>,
+{
+  let address = |value: u16, digits: String| label(value).unwrap_or(digits);
+  if template.contains("d16") || template.contains("a16") {
+    let value = u16::from_le_bytes([operands[0], operands[1]]);
+    return template.replace("d16", &format!("${:04X}", value)).replace("a16", &address(value, format!("${:04X}", value)));
+  }
+  if template.contains("d8") {
+    return template.replace("d8", &format!("${:02X}", operands[0]));
+  }
+  if template.contains("a8") {
+    let value = 0xFF00 | operands[0] as u16;
+    return template.replace("a8", &address(value, format!("${:04X}", value)));
+  }
+  if template.contains("r8") {
+    let offset = operands[0] as i8;
+    let value = if template.starts_with("JR") {
+      let target = next_address.wrapping_add(offset as u16);
+      address(target, format!("${:04X}", target))
+    } else if offset < 0 {
+      format!("-${:02X}", offset.unsigned_abs())
+    } else {
+      format!("${:02X}", offset)
+    };
+    return template.replace("r8", &value);
+  }
+  template.to_string()
+}
@@ -0,0 +1,68 @@
+use crate::bus::Bus;
+use crate::watchpoint::Access;
+
+// FlatBus が記録したバスの読み書き
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BusAccess {
+  // Read か Write（オペコードのフェッチも Read になる）
+  pub access: Access,
+  pub address: u16,
+  pub value: u8,
+  // アクセスした時点までに tick で進んだTサイクル数
+  pub cycle: u64,
+}
+
+// 64KBをそのまま読み書きできるバス。周辺機器も割り込みもなく、CPUの読み書きを順に記録する。
+// 命令単体のテストや、バスの動きを比べる適合テストで、CPU::with_bus に渡して使う
+pub struct FlatBus {
+  pub memory: Vec<u8>,
+  // tick で進んだTサイクル数の合計
+  pub cycles: u64,
+  accesses: Vec<BusAccess>,
+}
+
+impl FlatBus {
+  pub fn new() -> FlatBus {
+    FlatBus { memory: vec![0; 0x10000], cycles: 0, accesses: Vec::new() }
+  }
+
+  // まだ取り出されていない読み書き
+  pub fn accesses(&self) -> &[BusAccess] {
+    &self.accesses
+  }
+
+  pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+    std::mem::take(&mut self.accesses)
+  }
+
+  fn record(&mut self, access: Access, address: u16, value: u8) {
+    self.accesses.push(BusAccess { access, address, value, cycle: self.cycles });
+  }
+}
+
+impl Bus for FlatBus {
+  fn read(&mut self, address: u16) -> u8 {
+    let value = self.memory[address as usize];
+    self.record(Access::Read, address, value);
+    value
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    self.record(Access::Write, address, value);
+    self.memory[address as usize] = value;
+  }
+
+  fn tick(&mut self, cycles: u8) {
+    self.cycles += cycles as u64;
+  }
+
+  fn peek(&self, address: u16) -> u8 {
+    self.memory[address as usize]
+  }
+}
+
+impl Default for FlatBus {
+  fn default() -> Self {
+    FlatBus::new()
+  }
+}
@@ -0,0 +1,174 @@
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::CPU;
+use crate::joypad::Button;
+use crate::savestate::{self, SaveStateError};
+use crate::symbols::Symbols;
+
+// LCDが1画面を描き終えるのにかかるTサイクル数（154ライン × 456ドット）
+pub const CYCLES_PER_FRAME: u64 = 70224;
+
+// CGBを選んだ場合も、現状で変わるのはブートROM終了時のレジスタの値だけ
+// （CGB固有のVRAMバンクやカラーパレットはまだエミュレートしていない）
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Model {
+  Dmg,
+  Cgb,
+}
+
+pub struct GameBoy {
+  pub cpu: CPU,
+  pub cycles: u64,
+  pub model: Model,
+  // デバッガや逆アセンブル、コールスタックの表示に使うラベル
+  pub symbols: Symbols,
+}
+
+impl GameBoy {
+  pub fn new(rom: Vec<u8>) -> Result<GameBoy, CartridgeError> {
+    GameBoy::with_config(rom, Model::Dmg, None)
+  }
+
+  // ブートROMを渡した場合は0x0000からブートROMを実行する。渡さない場合はブートROM終了直後の状態から始める
+  pub fn with_config(rom: Vec<u8>, model: Model, boot_rom: Option<Vec<u8>>) -> Result<GameBoy, CartridgeError> {
+    let cartridge = Cartridge::new(rom)?;
+    let mut cpu = CPU::new();
+    cpu.bus.cartridge = Some(cartridge);
+
+    let mut gameboy = GameBoy { cpu, cycles: 0, model, symbols: Symbols::new() };
+    if boot_rom.is_some() {
+      gameboy.cpu.bus.boot_rom = boot_rom;
+    } else {
+      gameboy.reset_to_post_boot();
+    }
+    Ok(gameboy)
+  }
+
+  // ブートROMを実行し終えた直後の状態にする
+  fn reset_to_post_boot(&mut self) {
+    let cpu = &mut self.cpu;
+    match self.model {
+      Model::Dmg => {
+        cpu.registers.set_af(0x01B0);
+        cpu.registers.set_bc(0x0013);
+        cpu.registers.set_de(0x00D8);
+        cpu.registers.set_hl(0x014D);
+      },
+      Model::Cgb => {
+        cpu.registers.set_af(0x1180);
+        cpu.registers.set_bc(0x0000);
+        cpu.registers.set_de(0xFF56);
+        cpu.registers.set_hl(0x000D);
+      },
+    }
+    cpu.sp = 0xFFFE;
+    cpu.pc = 0x0100;
+
+    let bus = &mut cpu.bus;
+    bus.timer.counter = 0xABCC;
+    bus.interrupt_flag = 0x01;
+    for (address, value) in [
+      (0xFF26, 0xF1), (0xFF24, 0x77), (0xFF25, 0xF3),
+      (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3),
+      (0xFF40, 0x91), (0xFF47, 0xFC), (0xFF48, 0xFF), (0xFF49, 0xFF),
+    ] {
+      bus.write_byte(address, value);
+    }
+    bus.write_byte(0xFF50, 0x01);
+  }
+
+  // 1命令を実行し、かかったサイクル数だけ周辺機器を進める
+  pub fn step(&mut self) -> u8 {
+    let cycles = self.cpu.step();
+    self.cycles += cycles as u64;
+    cycles
+  }
+
+  // 次のVBlankに入るまで実行する。LCDがオフの場合は1フレーム分のサイクルだけ進める。
+  // run_* はどれも、止まる設定のウォッチポイントが反応したとき（watchpoint_hit で確かめる）と、
+  // LD B,B のブレークポイントに当たったとき（cpu.software_breakpoint_hit）はそこで戻る
+  pub fn run_frame(&mut self) {
+    let limit = self.cycles + CYCLES_PER_FRAME;
+    self.cpu.bus.ppu.frame_ready = false;
+    while !self.cpu.bus.ppu.frame_ready && self.cycles < limit && !self.stopped() {
+      self.step();
+    }
+  }
+
+  pub fn run_cycles(&mut self, cycles: u64) {
+    let target = self.cycles + cycles;
+    while self.cycles < target && !self.stopped() {
+      self.step();
+    }
+  }
+
+  pub fn run_until<F>(&mut self, mut predicate: F)
+  where
+    F: FnMut(&GameBoy) -> bool,
+  {
+    while !predicate(self) && !self.stopped() {
+      self.step();
+    }
+  }
+
+  fn stopped(&self) -> bool {
+    self.watchpoint_hit() || self.cpu.software_breakpoint_hit
+  }
+
+  // 反応したウォッチポイントがまだ取り出されていなければ true。
+  // 取り出すには cpu.bus.watchpoints.take_hits() を呼ぶ
+  pub fn watchpoint_hit(&self) -> bool {
+    self.cpu.bus.watchpoints.stopped()
+  }
+
+  // マシン全体の状態（CPU、RAM、MBC、RTC、PPU、APU、タイマー）をバイト列にする。形式は savestate を参照
+  pub fn save_state(&self) -> Vec<u8> {
+    savestate::save(self)
+  }
+
+  // 別のROMのステートや読めない版のステートはエラーにし、状態は変えない
+  pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+    savestate::load(self, data)
+  }
+
+  // 160×144の各ピクセルの階調（0〜3）
+  pub fn framebuffer(&self) -> &[u8] {
+    &self.cpu.bus.ppu.framebuffer
+  }
+
+  // まだ取り出されていないステレオサンプル（左右交互）
+  pub fn audio_buffer(&self) -> &[f32] {
+    &self.cpu.bus.apu.samples
+  }
+
+  pub fn take_audio_buffer(&mut self) -> Vec<f32> {
+    std::mem::take(&mut self.cpu.bus.apu.samples)
+  }
+
+  // まだ取り出されていない、シリアルポートから送り出したバイト
+  pub fn serial_output(&self) -> &[u8] {
+    &self.cpu.bus.serial.output
+  }
+
+  pub fn take_serial_output(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.cpu.bus.serial.output)
+  }
+
+  pub fn press(&mut self, button: Button) {
+    let pressed = self.cpu.bus.joypad.pressed | button.mask();
+    self.cpu.bus.set_joypad_state(pressed);
+  }
+
+  pub fn release(&mut self, button: Button) {
+    let pressed = self.cpu.bus.joypad.pressed & !button.mask();
+    self.cpu.bus.set_joypad_state(pressed);
+  }
+
+  // 全ボタンの押下状態を Button::mask() のビットでまとめて設定する
+  pub fn set_buttons(&mut self, pressed: u8) {
+    self.cpu.bus.set_joypad_state(pressed);
+  }
+
+  pub fn buttons(&self) -> u8 {
+    self.cpu.bus.joypad.pressed
+  }
+}
@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::gameboy::GameBoy;
+use crate::register::FlagsRegister;
+use crate::watchpoint::{WatchHit, WatchKind};
+
+// GDB に渡すターゲット記述（qXfer:features:read:target.xml）。
+// レジスタ番号は g パケットの並び順と同じ: a f b c d e h l sp pc
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="f" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="b" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="c" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="d" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="e" bitsize="8" regnum="5" type="uint8"/>
+    <reg name="h" bitsize="8" regnum="6" type="uint8"/>
+    <reg name="l" bitsize="8" regnum="7" type="uint8"/>
+    <reg name="sp" bitsize="16" regnum="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// continue 中に Ctrl-C（0x03）が届いていないかを調べる間隔（命令数）
+const INTERRUPT_POLL_INTERVAL: u64 = 4096;
+
+// 停止理由のシグナル番号
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// 1つのパケットを処理した結果
+enum Response {
+  Reply(String),
+  Resume { step: bool },
+  Detach,
+  Kill,
+}
+
+// GDB の Z2〜Z4 で置いたウォッチポイント。id は MemoryBus の Watchpoints での番号
+struct GdbWatchpoint {
+  kind: WatchKind,
+  address: u16,
+  length: u16,
+  id: usize,
+}
+
+// GDB リモートシリアルプロトコル（RSP）のサーバ。
+// ブレークポイントは命令と命令の間で PC を比べるだけなので、ソフトウェアとハードウェアの違いは停止理由の表示だけ。
+// ウォッチポイントは MemoryBus のウォッチポイントを使うので、アクセスした命令を実行し終えたところで止まる
+#[derive(Default)]
+pub struct GdbServer {
+  software_breakpoints: Vec<u16>,
+  hardware_breakpoints: Vec<u16>,
+  watchpoints: Vec<GdbWatchpoint>,
+}
+
+impl GdbServer {
+  pub fn new() -> GdbServer {
+    GdbServer::default()
+  }
+
+  // address で待ち受け、最初の1接続を切断（D か k）まで処理する
+  pub fn listen(&mut self, gameboy: &mut GameBoy, address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    self.accept(gameboy, &listener)
+  }
+
+  pub fn accept(&mut self, gameboy: &mut GameBoy, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    self.serve(gameboy, stream)
+  }
+
+  pub fn serve(&mut self, gameboy: &mut GameBoy, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut connection = Connection::new(stream);
+    while let Some(packet) = connection.receive()? {
+      match self.handle(gameboy, &packet) {
+        Response::Reply(reply) => connection.send(&reply)?,
+        Response::Resume { step } => {
+          let reply = self.resume(gameboy, &mut connection, step)?;
+          connection.send(&reply)?;
+        },
+        Response::Detach => {
+          connection.send("OK")?;
+          break;
+        },
+        Response::Kill => break,
+      }
+      if packet == "QStartNoAckMode" {
+        connection.no_ack = true;
+      }
+    }
+    self.clear(gameboy);
+    Ok(())
+  }
+
+  // 切断したら、置いたウォッチポイントを MemoryBus から外す
+  fn clear(&mut self, gameboy: &mut GameBoy) {
+    for watchpoint in self.watchpoints.drain(..) {
+      gameboy.cpu.bus.watchpoints.remove(watchpoint.id);
+    }
+    self.software_breakpoints.clear();
+    self.hardware_breakpoints.clear();
+  }
+
+  fn handle(&mut self, gameboy: &mut GameBoy, packet: &str) -> Response {
+    let reply = |result: Result<String, ()>| Response::Reply(result.unwrap_or_else(|_| "E01".to_string()));
+    let Some(command) = packet.chars().next() else { return Response::Reply(String::new()) };
+    let arguments = &packet[command.len_utf8()..];
+    match command {
+      '?' => Response::Reply(format!("S{:02x}", SIGTRAP)),
+      'g' => Response::Reply(read_registers(gameboy)),
+      'G' => reply(write_registers(gameboy, arguments).map(|_| "OK".to_string())),
+      'p' => reply(parse_hex(arguments).and_then(|number| read_register(gameboy, number))),
+      'P' => reply(write_register(gameboy, arguments).map(|_| "OK".to_string())),
+      'm' => reply(read_memory(gameboy, arguments)),
+      'M' => reply(write_memory(gameboy, arguments).map(|_| "OK".to_string())),
+      'c' | 's' => match set_resume_address(gameboy, arguments) {
+        Ok(()) => Response::Resume { step: command == 's' },
+        Err(()) => Response::Reply("E01".to_string()),
+      },
+      // シグナル付きの再開。シグナルは無視する
+      'C' | 'S' => Response::Resume { step: command == 'S' },
+      'Z' => reply(self.insert(gameboy, arguments).map(|_| "OK".to_string())),
+      'z' => reply(self.remove(gameboy, arguments).map(|_| "OK".to_string())),
+      'H' | 'T' => Response::Reply("OK".to_string()),
+      'D' => Response::Detach,
+      'k' => Response::Kill,
+      'v' => self.handle_v(packet),
+      'q' | 'Q' => Response::Reply(query(packet)),
+      _ => Response::Reply(String::new()),
+    }
+  }
+
+  fn handle_v(&mut self, packet: &str) -> Response {
+    if packet == "vCont?" {
+      return Response::Reply("vCont;c;C;s;S".to_string());
+    }
+    // スレッドは1つしかないので、最初の動作だけを見る
+    match packet.strip_prefix("vCont;").and_then(|actions| actions.chars().next()) {
+      Some('c' | 'C') => Response::Resume { step: false },
+      Some('s' | 'S') => Response::Resume { step: true },
+      _ => Response::Reply(String::new()),
+    }
+  }
+
+  // Z<type>,<addr>,<kind>。type は 0 ソフトウェアブレーク、1 ハードウェアブレーク、2 書き込み、3 読み込み、4 アクセス
+  fn insert(&mut self, gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+    let (kind, address, length) = parse_point(arguments)?;
+    match kind {
+      0 => add_address(&mut self.software_breakpoints, address),
+      1 => add_address(&mut self.hardware_breakpoints, address),
+      _ => {
+        let kind = watch_kind(kind)?;
+        let end = address.checked_add(length.max(1) - 1).ok_or(())?;
+        let id = gameboy.cpu.bus.watchpoints.add(kind, address..=end, None);
+        self.watchpoints.push(GdbWatchpoint { kind, address, length, id });
+      },
+    }
+    Ok(())
+  }
+
+  fn remove(&mut self, gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+    let (kind, address, length) = parse_point(arguments)?;
+    match kind {
+      0 => self.software_breakpoints.retain(|&breakpoint| breakpoint != address),
+      1 => self.hardware_breakpoints.retain(|&breakpoint| breakpoint != address),
+      _ => {
+        let kind = watch_kind(kind)?;
+        let index = self
+          .watchpoints
+          .iter()
+          .position(|watchpoint| watchpoint.kind == kind && watchpoint.address == address && watchpoint.length == length)
+          .ok_or(())?;
+        let watchpoint = self.watchpoints.remove(index);
+        gameboy.cpu.bus.watchpoints.remove(watchpoint.id);
+      },
+    }
+    Ok(())
+  }
+
+  // 止まるまで実行し、停止理由のパケットを返す。
+  // continue 中は INTERRUPT_POLL_INTERVAL 命令ごとに Ctrl-C が届いていないかを調べる
+  fn resume(&mut self, gameboy: &mut GameBoy, connection: &mut Connection, step: bool) -> io::Result<String> {
+    let mut count: u64 = 0;
+    loop {
+      if !step && count.is_multiple_of(INTERRUPT_POLL_INTERVAL) && connection.poll_interrupt()? {
+        return Ok(format!("S{:02x}", SIGINT));
+      }
+      gameboy.step();
+      // 未定義のオペコードで CPU が固まったら、不正命令として止まる
+      if gameboy.cpu.illegal_opcode.is_some() {
+        return Ok(format!("S{:02x}", SIGILL));
+      }
+      count += 1;
+
+      let hits = gameboy.cpu.bus.watchpoints.take_hits();
+      if let Some(hit) = hits.first() {
+        return Ok(self.watch_reply(hit));
+      }
+      let pc = gameboy.cpu.pc;
+      if self.software_breakpoints.contains(&pc) {
+        return Ok(format!("T{:02x}swbreak:;", SIGTRAP));
+      }
+      if self.hardware_breakpoints.contains(&pc) {
+        return Ok(format!("T{:02x}hwbreak:;", SIGTRAP));
+      }
+      if step {
+        return Ok(format!("S{:02x}", SIGTRAP));
+      }
+    }
+  }
+
+  fn watch_reply(&self, hit: &WatchHit) -> String {
+    let reason = match self.watchpoints.iter().find(|watchpoint| watchpoint.id == hit.id).map(|watchpoint| watchpoint.kind) {
+      Some(WatchKind::Write) => "watch",
+      Some(WatchKind::Read) => "rwatch",
+      Some(WatchKind::Access) => "awatch",
+      // GDB 以外から置かれたウォッチポイント
+      _ => return format!("S{:02x}", SIGTRAP),
+    };
+    format!("T{:02x}{}:{:x};", SIGTRAP, reason, hit.address)
+  }
+}
+
+fn add_address(addresses: &mut Vec<u16>, address: u16) {
+  if !addresses.contains(&address) {
+    addresses.push(address);
+  }
+}
+
+fn watch_kind(kind: u8) -> Result<WatchKind, ()> {
+  match kind {
+    2 => Ok(WatchKind::Write),
+    3 => Ok(WatchKind::Read),
+    4 => Ok(WatchKind::Access),
+    _ => Err(()),
+  }
+}
+
+fn parse_point(arguments: &str) -> Result<(u8, u16, u16), ()> {
+  // 条件式付き（;X...）は扱わないので切り捨てる
+  let arguments = arguments.split(';').next().unwrap_or_default();
+  let mut fields = arguments.split(',');
+  let kind = parse_hex(fields.next().ok_or(())?)?;
+  let address = parse_hex(fields.next().ok_or(())?)?;
+  let length = parse_hex(fields.next().ok_or(())?)?;
+  Ok((u8::try_from(kind).map_err(|_| ())?, u16::try_from(address).map_err(|_| ())?, u16::try_from(length).map_err(|_| ())?))
+}
+
+fn query(packet: &str) -> String {
+  if packet.starts_with("qSupported") {
+    return "PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+;vContSupported+".to_string();
+  }
+  if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+    return read_target_xml(range).unwrap_or_else(|_| "E01".to_string());
+  }
+  match packet {
+    "QStartNoAckMode" => "OK".to_string(),
+    "qAttached" => "1".to_string(),
+    "qC" => "QC1".to_string(),
+    "qfThreadInfo" => "m1".to_string(),
+    "qsThreadInfo" => "l".to_string(),
+    _ => String::new(),
+  }
+}
+
+// <offset>,<length> の範囲を返す。続きがあれば先頭が m、最後なら l
+fn read_target_xml(range: &str) -> Result<String, ()> {
+  let (offset, length) = range.split_once(',').ok_or(())?;
+  let offset = (parse_hex(offset)? as usize).min(TARGET_XML.len());
+  let end = offset.saturating_add(parse_hex(length)? as usize).min(TARGET_XML.len());
+  let prefix = if end < TARGET_XML.len() { 'm' } else { 'l' };
+  Ok(format!("{}{}", prefix, &TARGET_XML[offset..end]))
+}
+
+fn register_values(gameboy: &GameBoy) -> [u16; 10] {
+  let cpu = &gameboy.cpu;
+  let registers = &cpu.registers;
+  [
+    registers.a as u16,
+    u8::from(registers.f) as u16,
+    registers.b as u16,
+    registers.c as u16,
+    registers.d as u16,
+    registers.e as u16,
+    registers.h as u16,
+    registers.l as u16,
+    cpu.sp,
+    cpu.pc,
+  ]
+}
+
+// 8ビットレジスタは1バイト、SP と PC はリトルエンディアンの2バイト
+fn encode_register(number: usize, value: u16) -> String {
+  if number < 8 { format!("{:02x}", value) } else { format!("{:02x}{:02x}", value as u8, (value >> 8) as u8) }
+}
+
+fn read_registers(gameboy: &GameBoy) -> String {
+  register_values(gameboy).iter().enumerate().map(|(number, &value)| encode_register(number, value)).collect()
+}
+
+fn read_register(gameboy: &GameBoy, number: u64) -> Result<String, ()> {
+  let values = register_values(gameboy);
+  let value = values.get(number as usize).ok_or(())?;
+  Ok(encode_register(number as usize, *value))
+}
+
+fn set_register(gameboy: &mut GameBoy, number: usize, bytes: &[u8]) -> Result<(), ()> {
+  let cpu = &mut gameboy.cpu;
+  let byte = || if bytes.len() == 1 { Ok(bytes[0]) } else { Err(()) };
+  let word = || if bytes.len() == 2 { Ok(u16::from_le_bytes([bytes[0], bytes[1]])) } else { Err(()) };
+  match number {
+    0 => cpu.registers.a = byte()?,
+    1 => cpu.registers.f = FlagsRegister::from(byte()?),
+    2 => cpu.registers.b = byte()?,
+    3 => cpu.registers.c = byte()?,
+    4 => cpu.registers.d = byte()?,
+    5 => cpu.registers.e = byte()?,
+    6 => cpu.registers.h = byte()?,
+    7 => cpu.registers.l = byte()?,
+    8 => cpu.sp = word()?,
+    9 => cpu.pc = word()?,
+    _ => return Err(()),
+  }
+  Ok(())
+}
+
+fn write_registers(gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+  let bytes = decode_hex(arguments)?;
+  if bytes.len() != 12 {
+    return Err(());
+  }
+  for number in 0..8 {
+    set_register(gameboy, number, &bytes[number..number + 1])?;
+  }
+  set_register(gameboy, 8, &bytes[8..10])?;
+  set_register(gameboy, 9, &bytes[10..12])
+}
+
+// P<n>=<value>
+fn write_register(gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+  let (number, value) = arguments.split_once('=').ok_or(())?;
+  set_register(gameboy, parse_hex(number)? as usize, &decode_hex(value)?)
+}
+
+// m<addr>,<length>。peek_byte で読むのでウォッチポイントには引っかからない
+fn read_memory(gameboy: &GameBoy, arguments: &str) -> Result<String, ()> {
+  let (address, length) = arguments.split_once(',').ok_or(())?;
+  let address = parse_hex(address)?;
+  let end = address.saturating_add(parse_hex(length)?).min(0x10000);
+  Ok((address..end).map(|address| format!("{:02x}", gameboy.cpu.bus.peek_byte(address as u16))).collect())
+}
+
+// M<addr>,<length>:<data>。CPU の書き込みと同じく MemoryBus::write_byte を通すので、
+// ROM の範囲に書くとMBCのレジスタへの書き込みになる。ウォッチポイントは反応させない
+fn write_memory(gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+  let (location, data) = arguments.split_once(':').ok_or(())?;
+  let (address, length) = location.split_once(',').ok_or(())?;
+  let address = parse_hex(address)?;
+  let data = decode_hex(data)?;
+  if data.len() as u64 != parse_hex(length)? || address.checked_add(data.len() as u64).is_none_or(|end| end > 0x10000) {
+    return Err(());
+  }
+  let bus = &mut gameboy.cpu.bus;
+  let watchpoints = std::mem::take(&mut bus.watchpoints);
+  for (offset, &byte) in data.iter().enumerate() {
+    bus.write_byte(address as u16 + offset as u16, byte);
+  }
+  bus.watchpoints = watchpoints;
+  Ok(())
+}
+
+// c<addr> / s<addr> のアドレスは省略できる
+fn set_resume_address(gameboy: &mut GameBoy, arguments: &str) -> Result<(), ()> {
+  if !arguments.is_empty() {
+    gameboy.cpu.pc = u16::try_from(parse_hex(arguments)?).map_err(|_| ())?;
+  }
+  Ok(())
+}
+
+fn parse_hex(text: &str) -> Result<u64, ()> {
+  u64::from_str_radix(text, 16).map_err(|_| ())
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>, ()> {
+  if !text.len().is_multiple_of(2) || !text.is_ascii() {
+    return Err(());
+  }
+  (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).map_err(|_| ())).collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+  data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// パケットの送受信。$<data>#<checksum> の枠と、+/- の確認応答を扱う
+struct Connection {
+  stream: TcpStream,
+  buffer: VecDeque<u8>,
+  // QStartNoAckMode のあとは +/- を送らず、待たない
+  no_ack: bool,
+}
+
+impl Connection {
+  fn new(stream: TcpStream) -> Connection {
+    Connection { stream, buffer: VecDeque::new(), no_ack: false }
+  }
+
+  fn fill(&mut self) -> io::Result<bool> {
+    let mut chunk = [0; 1024];
+    let count = self.stream.read(&mut chunk)?;
+    self.buffer.extend(&chunk[..count]);
+    Ok(count > 0)
+  }
+
+  fn next_byte(&mut self) -> io::Result<Option<u8>> {
+    if self.buffer.is_empty() && !self.fill()? {
+      return Ok(None);
+    }
+    Ok(self.buffer.pop_front())
+  }
+
+  // 次のパケットの中身を返す。接続が切れたら None。
+  // 止まっているときに届いた Ctrl-C や確認応答は読み捨てる
+  fn receive(&mut self) -> io::Result<Option<String>> {
+    loop {
+      match self.next_byte()? {
+        None => return Ok(None),
+        Some(b'$') => {},
+        Some(_) => continue,
+      }
+      let mut data = Vec::new();
+      loop {
+        match self.next_byte()? {
+          None => return Ok(None),
+          Some(b'#') => break,
+          Some(byte) => data.push(byte),
+        }
+      }
+      let mut digits = [0; 2];
+      for digit in &mut digits {
+        let Some(byte) = self.next_byte()? else { return Ok(None) };
+        *digit = byte;
+      }
+      let expected = std::str::from_utf8(&digits).ok().and_then(|digits| u8::from_str_radix(digits, 16).ok());
+      if self.no_ack {
+        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+      }
+      if expected == Some(checksum(&data)) {
+        self.stream.write_all(b"+")?;
+        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+      }
+      self.stream.write_all(b"-")?;
+    }
+  }
+
+  // '-' が返ってきたら送り直す
+  fn send(&mut self, data: &str) -> io::Result<()> {
+    let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+    loop {
+      self.stream.write_all(packet.as_bytes())?;
+      if self.no_ack {
+        return Ok(());
+      }
+      loop {
+        match self.next_byte()? {
+          Some(b'+') | None => return Ok(()),
+          Some(b'-') => break,
+          Some(_) => {},
+        }
+      }
+    }
+  }
+
+  // 実行中に Ctrl-C（0x03）が届いていれば true。接続が切れた場合も止めるため true を返す
+  fn poll_interrupt(&mut self) -> io::Result<bool> {
+    self.stream.set_nonblocking(true)?;
+    let result = self.fill();
+    self.stream.set_nonblocking(false)?;
+    match result {
+      Ok(false) => return Ok(true),
+      Ok(true) => {},
+      Err(error) if error.kind() == ErrorKind::WouldBlock => {},
+      Err(error) => return Err(error),
+    }
+    match self.buffer.iter().position(|&byte| byte == 0x03) {
+      Some(index) => {
+        self.buffer.remove(index);
+        Ok(true)
+      },
+      None => Ok(false),
+    }
+  }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+use crate::call_stack::CallStack;
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::rewind::{compress, decompress};
+
+// 覚えておくチェックポイントの数（1フレームごとなら約5秒分）
+const DEFAULT_LIMIT: usize = 300;
+
+// ある時点のマシンの状態と、そこから実行し直すのに必要なデバッガ側の状態
+struct Checkpoint {
+  cycles: u64,
+  // 圧縮したセーブステート
+  state: Vec<u8>,
+  length: usize,
+  call_stack: CallStack,
+  watch_hits: Vec<(usize, u64)>,
+  breakpoint_hits: Vec<(usize, u64)>,
+}
+
+// 逆実行のためのチェックポイントの列。古い順に並び、上限を超えたら古いものから捨てる。
+// エミュレーションは決定的なので、チェックポイントに戻して同じだけ GameBoy::step を呼べば、間のどの命令の位置にも戻れる
+pub struct History {
+  checkpoints: VecDeque<Checkpoint>,
+  interval: u64,
+  limit: usize,
+}
+
+impl History {
+  pub fn new() -> History {
+    History { checkpoints: VecDeque::new(), interval: CYCLES_PER_FRAME, limit: DEFAULT_LIMIT }
+  }
+
+  // チェックポイントを取る間隔（Tサイクル）。短くすると戻るのが速くなるが、メモリを使う
+  pub fn with_interval(mut self, cycles: u64) -> History {
+    self.interval = cycles.max(1);
+    self
+  }
+
+  pub fn with_limit(mut self, limit: usize) -> History {
+    self.limit = limit.max(1);
+    self
+  }
+
+  pub fn len(&self) -> usize {
+    self.checkpoints.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.checkpoints.is_empty()
+  }
+
+  // レジスタを書き換えたときなど、記録した実行と今の状態がつながらなくなったときに呼ぶ
+  pub fn clear(&mut self) {
+    self.checkpoints.clear();
+  }
+
+  // 一番古いチェックポイントの位置（GameBoy::cycles）
+  pub fn oldest(&self) -> Option<u64> {
+    self.checkpoints.front().map(|checkpoint| checkpoint.cycles)
+  }
+
+  // 命令を実行する前に呼ぶ。最後のチェックポイントから interval 以上進んでいれば、今の状態を覚える。
+  // breakpoint_hits は覚えるときだけ呼ぶ
+  pub fn record<F>(&mut self, gameboy: &GameBoy, breakpoint_hits: F)
+  where
+    F: FnOnce() -> Vec<(usize, u64)>,
+  {
+    // 逆実行で戻ったあとは、もっと先のチェックポイントが残っている。実行は決定的なのでそのまま使える
+    if self.checkpoints.back().is_some_and(|last| gameboy.cycles < last.cycles + self.interval) {
+      return;
+    }
+    let state = gameboy.save_state();
+    self.checkpoints.push_back(Checkpoint {
+      cycles: gameboy.cycles,
+      state: compress(&state),
+      length: state.len(),
+      call_stack: gameboy.cpu.call_stack.clone(),
+      watch_hits: gameboy.cpu.bus.watchpoints.hit_counts(),
+      breakpoint_hits: breakpoint_hits(),
+    });
+    if self.checkpoints.len() > self.limit {
+      self.checkpoints.pop_front();
+    }
+  }
+
+  // cycles より前にある一番新しいチェックポイント
+  pub fn before(&self, cycles: u64) -> Option<usize> {
+    self.checkpoints.iter().rposition(|checkpoint| checkpoint.cycles < cycles)
+  }
+
+  // cycles 以前にある一番新しいチェックポイント
+  pub fn at_or_before(&self, cycles: u64) -> Option<usize> {
+    self.checkpoints.iter().rposition(|checkpoint| checkpoint.cycles <= cycles)
+  }
+
+  pub fn cycles(&self, index: usize) -> u64 {
+    self.checkpoints[index].cycles
+  }
+
+  // index のチェックポイントに戻し、そのときのブレークポイントの hits を返す
+  pub fn restore(&self, index: usize, gameboy: &mut GameBoy) -> &[(usize, u64)] {
+    let checkpoint = &self.checkpoints[index];
+    let state = decompress(&checkpoint.state, checkpoint.length);
+    gameboy.load_state(&state).expect("checkpoint was saved from this machine");
+    gameboy.cpu.call_stack = checkpoint.call_stack.clone();
+    let watchpoints = &mut gameboy.cpu.bus.watchpoints;
+    watchpoints.take_hits();
+    watchpoints.set_hit_counts(&checkpoint.watch_hits);
+    &checkpoint.breakpoint_hits
+  }
+}
+
+impl Default for History {
+  fn default() -> Self {
+    History::new()
+  }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum JumpConditions {
   HL,
   NoZeroFlag,
@@ -7,6 +10,7 @@ pub enum JumpConditions {
   Always
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum JumpRelativeConditions {
   NoZeroFlag,
   ZeroFlag,
@@ -15,6 +19,7 @@ pub enum JumpRelativeConditions {
   Always
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum CallConditions {
   NoZeroFlag,
   ZeroFlag,
@@ -23,6 +28,7 @@ pub enum CallConditions {
   Always
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RetConditions {
   NoZeroFlag,
   ZeroFlag,
@@ -31,50 +37,77 @@ pub enum RetConditions {
   Always
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RstTarget {
   RST00, RST08, RST10, RST18, RST20, RST28, RST30, RST38
 }
 
-pub enum LoadByteTarget {
-  A, B, C, D, E, H, L, BCI, DEI, HLI, HLIP, HLIM
-}
-
-pub enum LoadByteSource {
-  A, B, C, D, E, H, L, D8, BCI, DEI, HLI, HLIP, HLIM
-}
-
-pub enum LoadTwoByteTarget {
-  BC, DE, HL, SP, A16
+// 8ビットのオペランド。どの命令でも CPU::read_operand8 / write_operand8 で読み書きする
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operand8 {
+  A, B, C, D, E, H, L,
+  // 命令に続く1バイト
+  D8,
+  // (BC) (DE) (HL) (HL+) (HL-)
+  BCI, DEI, HLI, HLIP, HLIM,
+  // 命令に続く2バイトが指すアドレス
+  A16I,
+  // 0xFF00 + 命令に続く1バイト（LDH）
+  A8I,
+  // 0xFF00 + C
+  CI,
 }
 
-pub enum LoadTwoByteSource {
-  D16, SP
+// 16ビットのオペランド。CPU::read_operand16 / write_operand16 で読み書きする
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operand16 {
+  AF, BC, DE, HL, SP,
+  // 命令に続く2バイト
+  D16,
+  // 命令に続く2バイトが指すアドレスから2バイト
+  A16I,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum LoadType {
-  Byte(LoadByteTarget, LoadByteSource),
-  TwoByte(LoadTwoByteTarget, LoadTwoByteSource)
+  Byte(Operand8, Operand8),
+  TwoByte(Operand16, Operand16),
+  // LD HL,SP+r8
+  HLSPOffset,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Instruction {
   NOP,
+  STOP,
   ADD(AddType),
-  ADC(AdcTarget, AdcSource),
-  SUB(SubSource),
-  SBC(SbcSource),
-  PUSH(StackTarget),
-  POP(StackTarget),
+  ADC(AdcTarget, Operand8),
+  SUB(Operand8),
+  SBC(Operand8),
+  PUSH(Operand16),
+  POP(Operand16),
   CALL(CallConditions),
-  RLC(PrefixTarget),
+  RLC(Operand8),
+  RRC(Operand8),
+  RL(Operand8),
+  RR(Operand8),
+  SLA(Operand8),
+  SRA(Operand8),
+  SWAP(Operand8),
+  SRL(Operand8),
+  BIT(u8, Operand8),
+  RES(u8, Operand8),
+  SET(u8, Operand8),
   INC(IncDecTarget),
   DEC(IncDecTarget),
-  AND(AndSource),
-  XOR(XorSource),
-  OR(OrSource),
-  CP(CpSource),
+  AND(Operand8),
+  XOR(Operand8),
+  OR(Operand8),
+  CP(Operand8),
   JP(JumpConditions),
   JR(JumpRelativeConditions),
   RET(RetConditions),
+  RETI,
   RST(RstTarget),
   LD(LoadType),
   RLCA,
@@ -84,324 +117,763 @@ pub enum Instruction {
   DAA,
   CPL,
   SCF,
-  CCF
+  CCF,
+  HALT,
+  DI,
+  EI
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AddByteTarget {
   A
 }
 
-pub enum AddByteSource {
-  A, B, C, D, E, H, L, D8, HLI
-}
-
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AddTwoByteTarget {
   HL
 }
 
-pub enum AddTwoByteSource {
-  BC, DE, HL, SP
-}
-
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AddType {
-  Byte(AddByteTarget, AddByteSource),
-  TwoByte(AddTwoByteTarget, AddTwoByteSource)
+  Byte(AddByteTarget, Operand8),
+  TwoByte(AddTwoByteTarget, Operand16),
+  // ADD SP,r8
+  SPOffset,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AdcTarget {
   A
 }
 
-pub enum AdcSource {
-  A, B, C, D, E, H, L, D8, HLI
-}
-
-pub enum SubSource {
-  A, B, C, D, E, H, L, D8, HLI
-}
-
-pub enum SbcSource {
-  A, B, C, D, E, H, L, D8, HLI
-}
-
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum IncDecTarget {
-  A, B, C, D, E, H, L, BC, DE, HL, HLI, SP
-}
-
-pub enum AndSource {
-  A, B, C, D, E, H, L, HLI
-}
-
-pub enum XorSource {
-  A, B, C, D, E, H, L, HLI
-}
-
-pub enum OrSource {
-  A, B, C, D, E, H, L, HLI
-}
-
-pub enum CpSource {
-  A, B, C, D, E, H, L, HLI
-}
-
-pub enum PrefixTarget {
-	A, B, C, D, E, H, L, HLI
-}
-
-pub enum StackTarget {
-	AF, BC, DE, HL
+  Byte(Operand8),
+  Word(Operand16),
 }
 
 impl Instruction {
+  // lookup の表を引くだけなので、毎回デコードし直すことはない
   pub fn from_byte(byte: u8, prefixed: bool) -> Option<Instruction> {
-    if prefixed {
-      Instruction::from_byte_prefixed(byte)
-    } else {
-      Instruction::from_byte_not_prefixed(byte)
-    }
+    lookup(byte, prefixed).instruction
   }
 
-  fn from_byte_prefixed(byte: u8) -> Option<Instruction> {
-    match byte {
-      0x00 => Some(Instruction::RLC(PrefixTarget::B)),
-      0x01 => Some(Instruction::RLC(PrefixTarget::C)),
-      0x02 => Some(Instruction::RLC(PrefixTarget::D)),
-      0x03 => Some(Instruction::RLC(PrefixTarget::E)),
-      0x04 => Some(Instruction::RLC(PrefixTarget::H)),
-      0x05 => Some(Instruction::RLC(PrefixTarget::L)),
-      0x06 => Some(Instruction::RLC(PrefixTarget::HLI)),
-      0x07 => Some(Instruction::RLC(PrefixTarget::A)),
-      _ => None
-    }
+  // CB命令は下位3ビットがオペランド、上位5ビットが命令（BIT/RES/SET はそのうち3ビットがビット番号）
+  const fn from_byte_prefixed(byte: u8) -> Option<Instruction> {
+    let target = match byte & 0x07 {
+      0x00 => Operand8::B,
+      0x01 => Operand8::C,
+      0x02 => Operand8::D,
+      0x03 => Operand8::E,
+      0x04 => Operand8::H,
+      0x05 => Operand8::L,
+      0x06 => Operand8::HLI,
+      _ => Operand8::A,
+    };
+    let bit = (byte >> 3) & 0x07;
+    let instruction = match byte >> 3 {
+      0x00 => Instruction::RLC(target),
+      0x01 => Instruction::RRC(target),
+      0x02 => Instruction::RL(target),
+      0x03 => Instruction::RR(target),
+      0x04 => Instruction::SLA(target),
+      0x05 => Instruction::SRA(target),
+      0x06 => Instruction::SWAP(target),
+      0x07 => Instruction::SRL(target),
+      0x08..=0x0F => Instruction::BIT(bit, target),
+      0x10..=0x17 => Instruction::RES(bit, target),
+      _ => Instruction::SET(bit, target),
+    };
+    Some(instruction)
   }
 
-  fn from_byte_not_prefixed(byte: u8) -> Option<Instruction> {
+  const fn from_byte_not_prefixed(byte: u8) -> Option<Instruction> {
     match byte {
       0x00 => Some(Instruction::NOP),
-      0x01 => Some(Instruction::LD(LoadType::TwoByte(LoadTwoByteTarget::BC, LoadTwoByteSource::D16))),
-      0x02 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::BCI, LoadByteSource::A))),
-      0x03 => Some(Instruction::INC(IncDecTarget::BC)),
-      0x04 => Some(Instruction::INC(IncDecTarget::B)),
-      0x05 => Some(Instruction::DEC(IncDecTarget::B)),
-      0x06 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::D8))),
+      0x01 => Some(Instruction::LD(LoadType::TwoByte(Operand16::BC, Operand16::D16))),
+      0x02 => Some(Instruction::LD(LoadType::Byte(Operand8::BCI, Operand8::A))),
+      0x03 => Some(Instruction::INC(IncDecTarget::Word(Operand16::BC))),
+      0x04 => Some(Instruction::INC(IncDecTarget::Byte(Operand8::B))),
+      0x05 => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::B))),
+      0x06 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::D8))),
       0x07 => Some(Instruction::RLCA),
-      0x08 => Some(Instruction::LD(LoadType::TwoByte(LoadTwoByteTarget::A16, LoadTwoByteSource::SP))),
-      0x09 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, AddTwoByteSource::BC))),
-      0x0A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::BCI))),
-      0x0B => Some(Instruction::DEC(IncDecTarget::BC)),
-      0x0C => Some(Instruction::INC(IncDecTarget::C)),
-      0x0D => Some(Instruction::DEC(IncDecTarget::C)),
-      0x0E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::D8))),
+      0x08 => Some(Instruction::LD(LoadType::TwoByte(Operand16::A16I, Operand16::SP))),
+      0x09 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, Operand16::BC))),
+      0x0A => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::BCI))),
+      0x0B => Some(Instruction::DEC(IncDecTarget::Word(Operand16::BC))),
+      0x0C => Some(Instruction::INC(IncDecTarget::Byte(Operand8::C))),
+      0x0D => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::C))),
+      0x0E => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::D8))),
       0x0F => Some(Instruction::RRCA),
-      0x11 => Some(Instruction::LD(LoadType::TwoByte(LoadTwoByteTarget::DE, LoadTwoByteSource::D16))),
-      0x12 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::DEI, LoadByteSource::A))),
-      0x13 => Some(Instruction::INC(IncDecTarget::DE)),
-      0x14 => Some(Instruction::INC(IncDecTarget::D)),
-      0x15 => Some(Instruction::DEC(IncDecTarget::D)),
-      0x16 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::D8))),
+      0x10 => Some(Instruction::STOP),
+      0x11 => Some(Instruction::LD(LoadType::TwoByte(Operand16::DE, Operand16::D16))),
+      0x12 => Some(Instruction::LD(LoadType::Byte(Operand8::DEI, Operand8::A))),
+      0x13 => Some(Instruction::INC(IncDecTarget::Word(Operand16::DE))),
+      0x14 => Some(Instruction::INC(IncDecTarget::Byte(Operand8::D))),
+      0x15 => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::D))),
+      0x16 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::D8))),
       0x17 => Some(Instruction::RLA),
       0x18 => Some(Instruction::JR(JumpRelativeConditions::Always)),
-      0x19 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, AddTwoByteSource::DE))),
-      0x1A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::DEI))),
-      0x1B => Some(Instruction::DEC(IncDecTarget::DE)),
-      0x1C => Some(Instruction::INC(IncDecTarget::E)),
-      0x1D => Some(Instruction::DEC(IncDecTarget::E)),
-      0x1E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::D8))),
+      0x19 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, Operand16::DE))),
+      0x1A => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::DEI))),
+      0x1B => Some(Instruction::DEC(IncDecTarget::Word(Operand16::DE))),
+      0x1C => Some(Instruction::INC(IncDecTarget::Byte(Operand8::E))),
+      0x1D => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::E))),
+      0x1E => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::D8))),
       0x1F => Some(Instruction::RRA),
       0x20 => Some(Instruction::JR(JumpRelativeConditions::NoZeroFlag)),
-      0x21 => Some(Instruction::LD(LoadType::TwoByte(LoadTwoByteTarget::HL, LoadTwoByteSource::D16))),
-      0x22 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLIP, LoadByteSource::A))),
-      0x23 => Some(Instruction::INC(IncDecTarget::HL)),
-      0x24 => Some(Instruction::INC(IncDecTarget::H)),
-      0x25 => Some(Instruction::DEC(IncDecTarget::H)),
-      0x26 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::D8))),
+      0x21 => Some(Instruction::LD(LoadType::TwoByte(Operand16::HL, Operand16::D16))),
+      0x22 => Some(Instruction::LD(LoadType::Byte(Operand8::HLIP, Operand8::A))),
+      0x23 => Some(Instruction::INC(IncDecTarget::Word(Operand16::HL))),
+      0x24 => Some(Instruction::INC(IncDecTarget::Byte(Operand8::H))),
+      0x25 => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::H))),
+      0x26 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::D8))),
       0x27 => Some(Instruction::DAA),
       0x28 => Some(Instruction::JR(JumpRelativeConditions::ZeroFlag)),
-      0x29 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, AddTwoByteSource::HL))),
-      0x2A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::HLIP))),
-      0x2B => Some(Instruction::DEC(IncDecTarget::HL)),
-      0x2C => Some(Instruction::INC(IncDecTarget::L)),
-      0x2D => Some(Instruction::DEC(IncDecTarget::L)),
-      0x2E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::D8))),
+      0x29 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, Operand16::HL))),
+      0x2A => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::HLIP))),
+      0x2B => Some(Instruction::DEC(IncDecTarget::Word(Operand16::HL))),
+      0x2C => Some(Instruction::INC(IncDecTarget::Byte(Operand8::L))),
+      0x2D => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::L))),
+      0x2E => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::D8))),
       0x2F => Some(Instruction::CPL),
       0x30 => Some(Instruction::JR(JumpRelativeConditions::NoCarryFlag)),
-      0x31 => Some(Instruction::LD(LoadType::TwoByte(LoadTwoByteTarget::SP, LoadTwoByteSource::D16))),
-      0x32 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLIM, LoadByteSource::A))),
-      0x33 => Some(Instruction::INC(IncDecTarget::SP)),
-      0x34 => Some(Instruction::INC(IncDecTarget::HLI)),
-      0x35 => Some(Instruction::DEC(IncDecTarget::HLI)),
-      0x36 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::D8))),
+      0x31 => Some(Instruction::LD(LoadType::TwoByte(Operand16::SP, Operand16::D16))),
+      0x32 => Some(Instruction::LD(LoadType::Byte(Operand8::HLIM, Operand8::A))),
+      0x33 => Some(Instruction::INC(IncDecTarget::Word(Operand16::SP))),
+      0x34 => Some(Instruction::INC(IncDecTarget::Byte(Operand8::HLI))),
+      0x35 => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::HLI))),
+      0x36 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::D8))),
       0x37 => Some(Instruction::SCF),
       0x38 => Some(Instruction::JR(JumpRelativeConditions::CarryFlag)),
-      0x39 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, AddTwoByteSource::SP))),
-      0x3A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::HLIM))),
-      0x3B => Some(Instruction::DEC(IncDecTarget::SP)),
-      0x3C => Some(Instruction::INC(IncDecTarget::A)),
-      0x3D => Some(Instruction::DEC(IncDecTarget::A)),
-      0x3E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::D8))),
+      0x39 => Some(Instruction::ADD(AddType::TwoByte(AddTwoByteTarget::HL, Operand16::SP))),
+      0x3A => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::HLIM))),
+      0x3B => Some(Instruction::DEC(IncDecTarget::Word(Operand16::SP))),
+      0x3C => Some(Instruction::INC(IncDecTarget::Byte(Operand8::A))),
+      0x3D => Some(Instruction::DEC(IncDecTarget::Byte(Operand8::A))),
+      0x3E => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::D8))),
       0x3F => Some(Instruction::CCF),
-      0x40 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::B))),
-      0x41 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::C))),
-      0x42 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::D))),
-      0x43 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::E))),
-      0x44 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::H))),
-      0x45 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::L))),
-      0x46 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::HLI))),
-      0x47 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::B, LoadByteSource::A))),
-      0x48 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::B))),
-      0x49 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::C))),
-      0x4A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::D))),
-      0x4B => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::E))),
-      0x4C => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::H))),
-      0x4D => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::L))),
-      0x4E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::HLI))),
-      0x4F => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::C, LoadByteSource::A))),
-      0x50 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::B))),
-      0x51 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::C))),
-      0x52 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::D))),
-      0x53 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::E))),
-      0x54 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::H))),
-      0x55 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::L))),
-      0x56 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::HLI))),
-      0x57 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::D, LoadByteSource::A))),
-      0x58 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::B))),
-      0x59 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::C))),
-      0x5A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::D))),
-      0x5B => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::E))),
-      0x5C => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::H))),
-      0x5D => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::L))),
-      0x5E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::HLI))),
-      0x5F => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::E, LoadByteSource::A))),
-      0x60 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::B))),
-      0x61 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::C))),
-      0x62 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::D))),
-      0x63 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::E))),
-      0x64 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::H))),
-      0x65 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::L))),
-      0x66 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::HLI))),
-      0x67 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::H, LoadByteSource::A))),
-      0x68 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::B))),
-      0x69 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::C))),
-      0x6A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::D))),
-      0x6B => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::E))),
-      0x6C => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::H))),
-      0x6D => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::L))),
-      0x6E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::HLI))),
-      0x6F => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::L, LoadByteSource::A))),
-      0x70 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::B))),
-      0x71 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::C))),
-      0x72 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::D))),
-      0x73 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::E))),
-      0x74 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::H))),
-      0x75 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::L))),
-      // TODO: 0x76 (HALT)
-      0x77 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HLI, LoadByteSource::A))),
-      0x78 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::B))),
-      0x79 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::C))),
-      0x7A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::D))),
-      0x7B => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::E))),
-      0x7C => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::H))),
-      0x7D => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::L))),
-      0x7E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::HLI))),
-      0x7F => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::A))),
-      0x80 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::B))),
-      0x81 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::C))),
-      0x82 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::D))),
-      0x83 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::E))),
-      0x84 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::H))),
-      0x85 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::L))),
-      0x86 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::HLI))),
-      0x87 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::A))),
-      0x88 => Some(Instruction::ADC(AdcTarget::A, AdcSource::B)),
-      0x89 => Some(Instruction::ADC(AdcTarget::A, AdcSource::C)),
-      0x8A => Some(Instruction::ADC(AdcTarget::A, AdcSource::D)),
-      0x8B => Some(Instruction::ADC(AdcTarget::A, AdcSource::E)),
-      0x8C => Some(Instruction::ADC(AdcTarget::A, AdcSource::H)),
-      0x8D => Some(Instruction::ADC(AdcTarget::A, AdcSource::L)),
-      0x8E => Some(Instruction::ADC(AdcTarget::A, AdcSource::HLI)),
-      0x8F => Some(Instruction::ADC(AdcTarget::A, AdcSource::A)),
-      0x90 => Some(Instruction::SUB(SubSource::B)),
-      0x91 => Some(Instruction::SUB(SubSource::C)),
-      0x92 => Some(Instruction::SUB(SubSource::D)),
-      0x93 => Some(Instruction::SUB(SubSource::E)),
-      0x94 => Some(Instruction::SUB(SubSource::H)),
-      0x95 => Some(Instruction::SUB(SubSource::L)),
-      0x96 => Some(Instruction::SUB(SubSource::HLI)),
-      0x97 => Some(Instruction::SUB(SubSource::A)),
-      0x98 => Some(Instruction::SBC(SbcSource::B)),
-      0x99 => Some(Instruction::SBC(SbcSource::C)),
-      0x9A => Some(Instruction::SBC(SbcSource::D)),
-      0x9B => Some(Instruction::SBC(SbcSource::E)),
-      0x9C => Some(Instruction::SBC(SbcSource::H)),
-      0x9D => Some(Instruction::SBC(SbcSource::L)),
-      0x9E => Some(Instruction::SBC(SbcSource::HLI)),
-      0x9F => Some(Instruction::SBC(SbcSource::A)),
-      0xA0 => Some(Instruction::AND(AndSource::B)),
-      0xA1 => Some(Instruction::AND(AndSource::C)),
-      0xA2 => Some(Instruction::AND(AndSource::D)),
-      0xA3 => Some(Instruction::AND(AndSource::E)),
-      0xA4 => Some(Instruction::AND(AndSource::H)),
-      0xA5 => Some(Instruction::AND(AndSource::L)),
-      0xA6 => Some(Instruction::AND(AndSource::HLI)),
-      0xA7 => Some(Instruction::AND(AndSource::A)),
-      0xA8 => Some(Instruction::XOR(XorSource::B)),
-      0xA9 => Some(Instruction::XOR(XorSource::C)),
-      0xAA => Some(Instruction::XOR(XorSource::D)),
-      0xAB => Some(Instruction::XOR(XorSource::E)),
-      0xAC => Some(Instruction::XOR(XorSource::H)),
-      0xAD => Some(Instruction::XOR(XorSource::L)),
-      0xAE => Some(Instruction::XOR(XorSource::HLI)),
-      0xAF => Some(Instruction::XOR(XorSource::A)),
-      0xB0 => Some(Instruction::OR(OrSource::B)),
-      0xB1 => Some(Instruction::OR(OrSource::C)),
-      0xB2 => Some(Instruction::OR(OrSource::D)),
-      0xB3 => Some(Instruction::OR(OrSource::E)),
-      0xB4 => Some(Instruction::OR(OrSource::H)),
-      0xB5 => Some(Instruction::OR(OrSource::L)),
-      0xB6 => Some(Instruction::OR(OrSource::HLI)),
-      0xB7 => Some(Instruction::OR(OrSource::A)),
-      0xB8 => Some(Instruction::CP(CpSource::B)),
-      0xB9 => Some(Instruction::CP(CpSource::C)),
-      0xBA => Some(Instruction::CP(CpSource::D)),
-      0xBB => Some(Instruction::CP(CpSource::E)),
-      0xBC => Some(Instruction::CP(CpSource::H)),
-      0xBD => Some(Instruction::CP(CpSource::L)),
-      0xBE => Some(Instruction::CP(CpSource::HLI)),
-      0xBF => Some(Instruction::CP(CpSource::A)),
+      0x40 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::B))),
+      0x41 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::C))),
+      0x42 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::D))),
+      0x43 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::E))),
+      0x44 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::H))),
+      0x45 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::L))),
+      0x46 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::HLI))),
+      0x47 => Some(Instruction::LD(LoadType::Byte(Operand8::B, Operand8::A))),
+      0x48 => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::B))),
+      0x49 => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::C))),
+      0x4A => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::D))),
+      0x4B => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::E))),
+      0x4C => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::H))),
+      0x4D => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::L))),
+      0x4E => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::HLI))),
+      0x4F => Some(Instruction::LD(LoadType::Byte(Operand8::C, Operand8::A))),
+      0x50 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::B))),
+      0x51 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::C))),
+      0x52 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::D))),
+      0x53 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::E))),
+      0x54 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::H))),
+      0x55 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::L))),
+      0x56 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::HLI))),
+      0x57 => Some(Instruction::LD(LoadType::Byte(Operand8::D, Operand8::A))),
+      0x58 => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::B))),
+      0x59 => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::C))),
+      0x5A => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::D))),
+      0x5B => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::E))),
+      0x5C => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::H))),
+      0x5D => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::L))),
+      0x5E => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::HLI))),
+      0x5F => Some(Instruction::LD(LoadType::Byte(Operand8::E, Operand8::A))),
+      0x60 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::B))),
+      0x61 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::C))),
+      0x62 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::D))),
+      0x63 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::E))),
+      0x64 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::H))),
+      0x65 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::L))),
+      0x66 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::HLI))),
+      0x67 => Some(Instruction::LD(LoadType::Byte(Operand8::H, Operand8::A))),
+      0x68 => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::B))),
+      0x69 => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::C))),
+      0x6A => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::D))),
+      0x6B => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::E))),
+      0x6C => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::H))),
+      0x6D => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::L))),
+      0x6E => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::HLI))),
+      0x6F => Some(Instruction::LD(LoadType::Byte(Operand8::L, Operand8::A))),
+      0x70 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::B))),
+      0x71 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::C))),
+      0x72 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::D))),
+      0x73 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::E))),
+      0x74 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::H))),
+      0x75 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::L))),
+      0x76 => Some(Instruction::HALT),
+      0x77 => Some(Instruction::LD(LoadType::Byte(Operand8::HLI, Operand8::A))),
+      0x78 => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::B))),
+      0x79 => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::C))),
+      0x7A => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::D))),
+      0x7B => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::E))),
+      0x7C => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::H))),
+      0x7D => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::L))),
+      0x7E => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::HLI))),
+      0x7F => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::A))),
+      0x80 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::B))),
+      0x81 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::C))),
+      0x82 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::D))),
+      0x83 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::E))),
+      0x84 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::H))),
+      0x85 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::L))),
+      0x86 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::HLI))),
+      0x87 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::A))),
+      0x88 => Some(Instruction::ADC(AdcTarget::A, Operand8::B)),
+      0x89 => Some(Instruction::ADC(AdcTarget::A, Operand8::C)),
+      0x8A => Some(Instruction::ADC(AdcTarget::A, Operand8::D)),
+      0x8B => Some(Instruction::ADC(AdcTarget::A, Operand8::E)),
+      0x8C => Some(Instruction::ADC(AdcTarget::A, Operand8::H)),
+      0x8D => Some(Instruction::ADC(AdcTarget::A, Operand8::L)),
+      0x8E => Some(Instruction::ADC(AdcTarget::A, Operand8::HLI)),
+      0x8F => Some(Instruction::ADC(AdcTarget::A, Operand8::A)),
+      0x90 => Some(Instruction::SUB(Operand8::B)),
+      0x91 => Some(Instruction::SUB(Operand8::C)),
+      0x92 => Some(Instruction::SUB(Operand8::D)),
+      0x93 => Some(Instruction::SUB(Operand8::E)),
+      0x94 => Some(Instruction::SUB(Operand8::H)),
+      0x95 => Some(Instruction::SUB(Operand8::L)),
+      0x96 => Some(Instruction::SUB(Operand8::HLI)),
+      0x97 => Some(Instruction::SUB(Operand8::A)),
+      0x98 => Some(Instruction::SBC(Operand8::B)),
+      0x99 => Some(Instruction::SBC(Operand8::C)),
+      0x9A => Some(Instruction::SBC(Operand8::D)),
+      0x9B => Some(Instruction::SBC(Operand8::E)),
+      0x9C => Some(Instruction::SBC(Operand8::H)),
+      0x9D => Some(Instruction::SBC(Operand8::L)),
+      0x9E => Some(Instruction::SBC(Operand8::HLI)),
+      0x9F => Some(Instruction::SBC(Operand8::A)),
+      0xA0 => Some(Instruction::AND(Operand8::B)),
+      0xA1 => Some(Instruction::AND(Operand8::C)),
+      0xA2 => Some(Instruction::AND(Operand8::D)),
+      0xA3 => Some(Instruction::AND(Operand8::E)),
+      0xA4 => Some(Instruction::AND(Operand8::H)),
+      0xA5 => Some(Instruction::AND(Operand8::L)),
+      0xA6 => Some(Instruction::AND(Operand8::HLI)),
+      0xA7 => Some(Instruction::AND(Operand8::A)),
+      0xA8 => Some(Instruction::XOR(Operand8::B)),
+      0xA9 => Some(Instruction::XOR(Operand8::C)),
+      0xAA => Some(Instruction::XOR(Operand8::D)),
+      0xAB => Some(Instruction::XOR(Operand8::E)),
+      0xAC => Some(Instruction::XOR(Operand8::H)),
+      0xAD => Some(Instruction::XOR(Operand8::L)),
+      0xAE => Some(Instruction::XOR(Operand8::HLI)),
+      0xAF => Some(Instruction::XOR(Operand8::A)),
+      0xB0 => Some(Instruction::OR(Operand8::B)),
+      0xB1 => Some(Instruction::OR(Operand8::C)),
+      0xB2 => Some(Instruction::OR(Operand8::D)),
+      0xB3 => Some(Instruction::OR(Operand8::E)),
+      0xB4 => Some(Instruction::OR(Operand8::H)),
+      0xB5 => Some(Instruction::OR(Operand8::L)),
+      0xB6 => Some(Instruction::OR(Operand8::HLI)),
+      0xB7 => Some(Instruction::OR(Operand8::A)),
+      0xB8 => Some(Instruction::CP(Operand8::B)),
+      0xB9 => Some(Instruction::CP(Operand8::C)),
+      0xBA => Some(Instruction::CP(Operand8::D)),
+      0xBB => Some(Instruction::CP(Operand8::E)),
+      0xBC => Some(Instruction::CP(Operand8::H)),
+      0xBD => Some(Instruction::CP(Operand8::L)),
+      0xBE => Some(Instruction::CP(Operand8::HLI)),
+      0xBF => Some(Instruction::CP(Operand8::A)),
       0xC0 => Some(Instruction::RET(RetConditions::NoZeroFlag)),
-      0xC1 => Some(Instruction::POP(StackTarget::BC)),
+      0xC1 => Some(Instruction::POP(Operand16::BC)),
       0xC2 => Some(Instruction::JP(JumpConditions::NoZeroFlag)),
       0xC3 => Some(Instruction::JP(JumpConditions::Always)),
       0xC4 => Some(Instruction::CALL(CallConditions::NoZeroFlag)),
-      0xC5 => Some(Instruction::PUSH(StackTarget::BC)),
-      0xC6 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, AddByteSource::D8))),
+      0xC5 => Some(Instruction::PUSH(Operand16::BC)),
+      0xC6 => Some(Instruction::ADD(AddType::Byte(AddByteTarget::A, Operand8::D8))),
       0xC7 => Some(Instruction::RST(RstTarget::RST00)),
-      0xCC => Some(Instruction::CALL(CallConditions::ZeroFlag)),
-      0xCD => Some(Instruction::CALL(CallConditions::Always)),
       0xC8 => Some(Instruction::RET(RetConditions::ZeroFlag)),
       0xC9 => Some(Instruction::RET(RetConditions::Always)),
       0xCA => Some(Instruction::JP(JumpConditions::ZeroFlag)),
+      0xCC => Some(Instruction::CALL(CallConditions::ZeroFlag)),
+      0xCD => Some(Instruction::CALL(CallConditions::Always)),
+      0xCE => Some(Instruction::ADC(AdcTarget::A, Operand8::D8)),
       0xCF => Some(Instruction::RST(RstTarget::RST08)),
       0xD0 => Some(Instruction::RET(RetConditions::NoCarryFlag)),
-      0xD1 => Some(Instruction::POP(StackTarget::DE)),
+      0xD1 => Some(Instruction::POP(Operand16::DE)),
       0xD2 => Some(Instruction::JP(JumpConditions::NoCarryFlag)),
       0xD4 => Some(Instruction::CALL(CallConditions::NoCarryFlag)),
-      0xD5 => Some(Instruction::PUSH(StackTarget::DE)),
+      0xD5 => Some(Instruction::PUSH(Operand16::DE)),
+      0xD6 => Some(Instruction::SUB(Operand8::D8)),
       0xD7 => Some(Instruction::RST(RstTarget::RST10)),
-      0xDC => Some(Instruction::CALL(CallConditions::CarryFlag)),
       0xD8 => Some(Instruction::RET(RetConditions::CarryFlag)),
+      0xD9 => Some(Instruction::RETI),
       0xDA => Some(Instruction::JP(JumpConditions::CarryFlag)),
+      0xDC => Some(Instruction::CALL(CallConditions::CarryFlag)),
+      0xDE => Some(Instruction::SBC(Operand8::D8)),
       0xDF => Some(Instruction::RST(RstTarget::RST18)),
-      0xE1 => Some(Instruction::POP(StackTarget::HL)),
-      0xE5 => Some(Instruction::PUSH(StackTarget::HL)),
+      0xE0 => Some(Instruction::LD(LoadType::Byte(Operand8::A8I, Operand8::A))),
+      0xE1 => Some(Instruction::POP(Operand16::HL)),
+      0xE2 => Some(Instruction::LD(LoadType::Byte(Operand8::CI, Operand8::A))),
+      0xE5 => Some(Instruction::PUSH(Operand16::HL)),
+      0xE6 => Some(Instruction::AND(Operand8::D8)),
       0xE7 => Some(Instruction::RST(RstTarget::RST20)),
+      0xE8 => Some(Instruction::ADD(AddType::SPOffset)),
       0xE9 => Some(Instruction::JP(JumpConditions::HL)),
+      0xEA => Some(Instruction::LD(LoadType::Byte(Operand8::A16I, Operand8::A))),
+      0xEE => Some(Instruction::XOR(Operand8::D8)),
       0xEF => Some(Instruction::RST(RstTarget::RST28)),
-      0xF1 => Some(Instruction::POP(StackTarget::AF)),
-      0xF5 => Some(Instruction::PUSH(StackTarget::AF)),
+      0xF0 => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::A8I))),
+      0xF1 => Some(Instruction::POP(Operand16::AF)),
+      0xF2 => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::CI))),
+      0xF3 => Some(Instruction::DI),
+      0xF5 => Some(Instruction::PUSH(Operand16::AF)),
+      0xF6 => Some(Instruction::OR(Operand8::D8)),
       0xF7 => Some(Instruction::RST(RstTarget::RST30)),
+      0xF8 => Some(Instruction::LD(LoadType::HLSPOffset)),
+      0xF9 => Some(Instruction::LD(LoadType::TwoByte(Operand16::SP, Operand16::HL))),
+      0xFA => Some(Instruction::LD(LoadType::Byte(Operand8::A, Operand8::A16I))),
+      0xFB => Some(Instruction::EI),
+      0xFE => Some(Instruction::CP(Operand8::D8)),
       0xFF => Some(Instruction::RST(RstTarget::RST38)),
       _ => None
     }
   }
 }
+
+// 1つのオペコードについて、デコード結果と実行に必要な情報をまとめたもの。
+// 逆アセンブラやアセンブラ、プロファイラなど、オペコードごとの情報が要るツールはこれを引く
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OpcodeInfo {
+  // 未定義のオペコードと、通常の表の CB は None
+  pub instruction: Option<Instruction>,
+  // "LD" や "BIT" のような命令名。通常の表の CB は "PREFIX"、未定義のオペコードは空文字列
+  pub mnemonic: &'static str,
+  // オペコード（CB命令は CB も含む）とオペランドを合わせたバイト数
+  pub length: u8,
+  // Tサイクル数。条件分岐は条件が成立しなかった場合の値
+  pub cycles: u8,
+  // 条件が成立した場合のTサイクル数。条件分岐でなければ cycles と同じ
+  pub branch_cycles: u8,
+  pub flags: FlagEffects,
+}
+
+// 命令を実行したあとのフラグ
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlagEffect {
+  // 常に1になる
+  Set,
+  // 常に0になる
+  Reset,
+  // 結果によって決まる
+  Affected,
+  // 実行前のまま
+  Unchanged,
+}
+
+// 命令が Z/N/H/C の各フラグをどう変えるか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FlagEffects {
+  pub zero: FlagEffect,
+  pub subtract: FlagEffect,
+  pub half_carry: FlagEffect,
+  pub carry: FlagEffect,
+}
+
+impl FlagEffects {
+  // 命令表と同じ "Z0HC" の書き方（1: Set、0: Reset、-: Unchanged、それ以外: Affected）から作る
+  pub const fn from_notation(notation: &str) -> FlagEffects {
+    let bytes = notation.as_bytes();
+    FlagEffects {
+      zero: FlagEffect::from_notation(bytes[0]),
+      subtract: FlagEffect::from_notation(bytes[1]),
+      half_carry: FlagEffect::from_notation(bytes[2]),
+      carry: FlagEffect::from_notation(bytes[3]),
+    }
+  }
+}
+
+impl FlagEffect {
+  const fn from_notation(byte: u8) -> FlagEffect {
+    match byte {
+      b'1' => FlagEffect::Set,
+      b'0' => FlagEffect::Reset,
+      b'-' => FlagEffect::Unchanged,
+      _ => FlagEffect::Affected,
+    }
+  }
+}
+
+// 通常オペコードの命令長。未定義のオペコードと CB は1
+const INSTRUCTION_LENGTHS: [u8; 256] = [
+  1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+  2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+  2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+  2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 1, 3, 3, 2, 1,
+  1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,
+  2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+  2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+];
+
+// 通常オペコードのTサイクル数。条件分岐は条件が成立しなかった場合の値
+const INSTRUCTION_CYCLES: [u8; 256] = [
+   4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+   4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+   8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+   8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+   8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16,
+   8, 12, 12,  4, 12, 16,  8, 16,  8, 16, 12,  4, 12,  4,  8, 16,
+  12, 12,  8,  4,  4, 16,  8, 16, 16,  4, 16,  4,  4,  4,  8, 16,
+  12, 12,  8,  4,  4, 16,  8, 16, 12,  8, 16,  4,  4,  4,  8, 16,
+];
+
+// 条件が成立したときに増えるTサイクル数
+const fn branch_extra_cycles(byte: u8) -> u8 {
+  match byte {
+    0x20 | 0x28 | 0x30 | 0x38 => 4,
+    0xC2 | 0xCA | 0xD2 | 0xDA => 4,
+    0xC0 | 0xC8 | 0xD0 | 0xD8 => 12,
+    0xC4 | 0xCC | 0xD4 | 0xDC => 12,
+    _ => 0,
+  }
+}
+
+// CB命令は (HL) を読み書きするものだけ長い。BIT は書き戻さないぶん短い
+const fn prefixed_cycles(byte: u8) -> u8 {
+  match (byte & 0x07, byte) {
+    (0x06, 0x40..=0x7F) => 12,
+    (0x06, _) => 16,
+    _ => 8,
+  }
+}
+
+const fn flag_effects(instruction: Instruction) -> FlagEffects {
+  let notation = match instruction {
+    Instruction::ADD(AddType::Byte(_, _)) | Instruction::ADC(_, _) => "Z0HC",
+    Instruction::ADD(AddType::TwoByte(_, _)) => "-0HC",
+    Instruction::ADD(AddType::SPOffset) | Instruction::LD(LoadType::HLSPOffset) => "00HC",
+    Instruction::SUB(_) | Instruction::SBC(_) | Instruction::CP(_) => "Z1HC",
+    Instruction::AND(_) => "Z010",
+    Instruction::XOR(_) | Instruction::OR(_) => "Z000",
+    Instruction::INC(IncDecTarget::Byte(_)) => "Z0H-",
+    Instruction::DEC(IncDecTarget::Byte(_)) => "Z1H-",
+    Instruction::RLCA | Instruction::RRCA | Instruction::RLA | Instruction::RRA => "000C",
+    Instruction::RLC(_) | Instruction::RRC(_) | Instruction::RL(_) | Instruction::RR(_) => "Z00C",
+    Instruction::SLA(_) | Instruction::SRA(_) | Instruction::SRL(_) => "Z00C",
+    Instruction::SWAP(_) => "Z000",
+    Instruction::BIT(_, _) => "Z01-",
+    Instruction::DAA => "Z-0C",
+    Instruction::CPL => "-11-",
+    Instruction::SCF => "-001",
+    Instruction::CCF => "-00C",
+    // F も一緒に取り出す
+    Instruction::POP(Operand16::AF) => "ZNHC",
+    _ => "----",
+  };
+  FlagEffects::from_notation(notation)
+}
+
+const fn mnemonic(instruction: Instruction) -> &'static str {
+  match instruction {
+    Instruction::NOP => "NOP",
+    Instruction::STOP => "STOP",
+    Instruction::ADD(_) => "ADD",
+    Instruction::ADC(_, _) => "ADC",
+    Instruction::SUB(_) => "SUB",
+    Instruction::SBC(_) => "SBC",
+    Instruction::PUSH(_) => "PUSH",
+    Instruction::POP(_) => "POP",
+    Instruction::CALL(_) => "CALL",
+    Instruction::RLC(_) => "RLC",
+    Instruction::RRC(_) => "RRC",
+    Instruction::RL(_) => "RL",
+    Instruction::RR(_) => "RR",
+    Instruction::SLA(_) => "SLA",
+    Instruction::SRA(_) => "SRA",
+    Instruction::SWAP(_) => "SWAP",
+    Instruction::SRL(_) => "SRL",
+    Instruction::BIT(_, _) => "BIT",
+    Instruction::RES(_, _) => "RES",
+    Instruction::SET(_, _) => "SET",
+    Instruction::INC(_) => "INC",
+    Instruction::DEC(_) => "DEC",
+    Instruction::AND(_) => "AND",
+    Instruction::XOR(_) => "XOR",
+    Instruction::OR(_) => "OR",
+    Instruction::CP(_) => "CP",
+    Instruction::JP(_) => "JP",
+    Instruction::JR(_) => "JR",
+    Instruction::RET(_) => "RET",
+    Instruction::RETI => "RETI",
+    Instruction::RST(_) => "RST",
+    Instruction::LD(LoadType::Byte(Operand8::A8I, _) | LoadType::Byte(_, Operand8::A8I)) => "LDH",
+    Instruction::LD(_) => "LD",
+    Instruction::RLCA => "RLCA",
+    Instruction::RRCA => "RRCA",
+    Instruction::RLA => "RLA",
+    Instruction::RRA => "RRA",
+    Instruction::DAA => "DAA",
+    Instruction::CPL => "CPL",
+    Instruction::SCF => "SCF",
+    Instruction::CCF => "CCF",
+    Instruction::HALT => "HALT",
+    Instruction::DI => "DI",
+    Instruction::EI => "EI",
+  }
+}
+
+const fn build_opcodes(prefixed: bool) -> [OpcodeInfo; 256] {
+  let unchanged = FlagEffects::from_notation("----");
+  let empty = OpcodeInfo { instruction: None, mnemonic: "", length: 1, cycles: 4, branch_cycles: 4, flags: unchanged };
+  let mut opcodes = [empty; 256];
+  let mut index = 0;
+  while index < 256 {
+    let byte = index as u8;
+    let instruction = if prefixed {
+      Instruction::from_byte_prefixed(byte)
+    } else {
+      Instruction::from_byte_not_prefixed(byte)
+    };
+    let (length, cycles, branch_cycles) = if prefixed {
+      (2, prefixed_cycles(byte), prefixed_cycles(byte))
+    } else {
+      (INSTRUCTION_LENGTHS[index], INSTRUCTION_CYCLES[index], INSTRUCTION_CYCLES[index] + branch_extra_cycles(byte))
+    };
+    let (mnemonic, flags) = match instruction {
+      Some(instruction) => (mnemonic(instruction), flag_effects(instruction)),
+      None if !prefixed && byte == 0xCB => ("PREFIX", unchanged),
+      None => ("", unchanged),
+    };
+    opcodes[index] = OpcodeInfo { instruction, mnemonic, length, cycles, branch_cycles, flags };
+    index += 1;
+  }
+  opcodes
+}
+
+// コンパイル時に作るデコード表。CPU の実行、逆アセンブラ、アセンブラはどれもこれを引く
+pub static OPCODES: [OpcodeInfo; 256] = build_opcodes(false);
+pub static CB_OPCODES: [OpcodeInfo; 256] = build_opcodes(true);
+
+// prefixed が true なら CB に続くバイトとして引く
+pub fn lookup(byte: u8, prefixed: bool) -> &'static OpcodeInfo {
+  if prefixed {
+    &CB_OPCODES[byte as usize]
+  } else {
+    &OPCODES[byte as usize]
+  }
+}
+
+// 命令表と同じ書き方で表示する。即値は d8/d16/a16/r8 のまま残るので、
+// 実際の値を埋め込むには disassembler::disassemble を使う
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Instruction::NOP => write!(f, "NOP"),
+      Instruction::STOP => write!(f, "STOP d8"),
+      Instruction::ADD(AddType::Byte(target, source)) => write!(f, "ADD {},{}", target, source),
+      Instruction::ADD(AddType::TwoByte(target, source)) => write!(f, "ADD {},{}", target, source),
+      Instruction::ADD(AddType::SPOffset) => write!(f, "ADD SP,r8"),
+      Instruction::ADC(target, source) => write!(f, "ADC {},{}", target, source),
+      Instruction::SUB(source) => write!(f, "SUB {}", source),
+      Instruction::SBC(source) => write!(f, "SBC A,{}", source),
+      Instruction::PUSH(target) => write!(f, "PUSH {}", target),
+      Instruction::POP(target) => write!(f, "POP {}", target),
+      Instruction::CALL(CallConditions::Always) => write!(f, "CALL a16"),
+      Instruction::CALL(condition) => write!(f, "CALL {},a16", condition),
+      Instruction::RLC(target) => write!(f, "RLC {}", target),
+      Instruction::RRC(target) => write!(f, "RRC {}", target),
+      Instruction::RL(target) => write!(f, "RL {}", target),
+      Instruction::RR(target) => write!(f, "RR {}", target),
+      Instruction::SLA(target) => write!(f, "SLA {}", target),
+      Instruction::SRA(target) => write!(f, "SRA {}", target),
+      Instruction::SWAP(target) => write!(f, "SWAP {}", target),
+      Instruction::SRL(target) => write!(f, "SRL {}", target),
+      Instruction::BIT(bit, target) => write!(f, "BIT {},{}", bit, target),
+      Instruction::RES(bit, target) => write!(f, "RES {},{}", bit, target),
+      Instruction::SET(bit, target) => write!(f, "SET {},{}", bit, target),
+      Instruction::INC(target) => write!(f, "INC {}", target),
+      Instruction::DEC(target) => write!(f, "DEC {}", target),
+      Instruction::AND(source) => write!(f, "AND {}", source),
+      Instruction::XOR(source) => write!(f, "XOR {}", source),
+      Instruction::OR(source) => write!(f, "OR {}", source),
+      Instruction::CP(source) => write!(f, "CP {}", source),
+      Instruction::JP(JumpConditions::HL) => write!(f, "JP HL"),
+      Instruction::JP(JumpConditions::Always) => write!(f, "JP a16"),
+      Instruction::JP(condition) => write!(f, "JP {},a16", condition),
+      Instruction::JR(JumpRelativeConditions::Always) => write!(f, "JR r8"),
+      Instruction::JR(condition) => write!(f, "JR {},r8", condition),
+      Instruction::RET(RetConditions::Always) => write!(f, "RET"),
+      Instruction::RET(condition) => write!(f, "RET {}", condition),
+      Instruction::RETI => write!(f, "RETI"),
+      Instruction::RST(target) => write!(f, "RST {}", target),
+      // 0xFF00 からの相対アドレスで読み書きするものは LDH と書く
+      Instruction::LD(LoadType::Byte(target, source)) if *target == Operand8::A8I || *source == Operand8::A8I => {
+        write!(f, "LDH {},{}", target, source)
+      },
+      Instruction::LD(LoadType::Byte(target, source)) => write!(f, "LD {},{}", target, source),
+      Instruction::LD(LoadType::TwoByte(target, source)) => write!(f, "LD {},{}", target, source),
+      Instruction::LD(LoadType::HLSPOffset) => write!(f, "LD HL,SP+r8"),
+      Instruction::RLCA => write!(f, "RLCA"),
+      Instruction::RRCA => write!(f, "RRCA"),
+      Instruction::RLA => write!(f, "RLA"),
+      Instruction::RRA => write!(f, "RRA"),
+      Instruction::DAA => write!(f, "DAA"),
+      Instruction::CPL => write!(f, "CPL"),
+      Instruction::SCF => write!(f, "SCF"),
+      Instruction::CCF => write!(f, "CCF"),
+      Instruction::HALT => write!(f, "HALT"),
+      Instruction::DI => write!(f, "DI"),
+      Instruction::EI => write!(f, "EI"),
+    }
+  }
+}
+
+// 条件なし（Always）は空文字列になる。命令側で "JP a16" のように書き分ける
+impl fmt::Display for JumpConditions {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      JumpConditions::HL => "HL",
+      JumpConditions::NoZeroFlag => "NZ",
+      JumpConditions::ZeroFlag => "Z",
+      JumpConditions::NoCarryFlag => "NC",
+      JumpConditions::CarryFlag => "C",
+      JumpConditions::Always => "",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for JumpRelativeConditions {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      JumpRelativeConditions::NoZeroFlag => "NZ",
+      JumpRelativeConditions::ZeroFlag => "Z",
+      JumpRelativeConditions::NoCarryFlag => "NC",
+      JumpRelativeConditions::CarryFlag => "C",
+      JumpRelativeConditions::Always => "",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for CallConditions {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      CallConditions::NoZeroFlag => "NZ",
+      CallConditions::ZeroFlag => "Z",
+      CallConditions::NoCarryFlag => "NC",
+      CallConditions::CarryFlag => "C",
+      CallConditions::Always => "",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for RetConditions {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      RetConditions::NoZeroFlag => "NZ",
+      RetConditions::ZeroFlag => "Z",
+      RetConditions::NoCarryFlag => "NC",
+      RetConditions::CarryFlag => "C",
+      RetConditions::Always => "",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for RstTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let address = match self {
+      RstTarget::RST00 => 0x00,
+      RstTarget::RST08 => 0x08,
+      RstTarget::RST10 => 0x10,
+      RstTarget::RST18 => 0x18,
+      RstTarget::RST20 => 0x20,
+      RstTarget::RST28 => 0x28,
+      RstTarget::RST30 => 0x30,
+      RstTarget::RST38 => 0x38,
+    };
+    write!(f, "${:02X}", address)
+  }
+}
+
+impl fmt::Display for Operand8 {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Operand8::A => "A",
+      Operand8::B => "B",
+      Operand8::C => "C",
+      Operand8::D => "D",
+      Operand8::E => "E",
+      Operand8::H => "H",
+      Operand8::L => "L",
+      Operand8::D8 => "d8",
+      Operand8::BCI => "(BC)",
+      Operand8::DEI => "(DE)",
+      Operand8::HLI => "(HL)",
+      Operand8::HLIP => "(HL+)",
+      Operand8::HLIM => "(HL-)",
+      Operand8::A16I => "(a16)",
+      Operand8::A8I => "(a8)",
+      Operand8::CI => "(C)",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for Operand16 {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Operand16::AF => "AF",
+      Operand16::BC => "BC",
+      Operand16::DE => "DE",
+      Operand16::HL => "HL",
+      Operand16::SP => "SP",
+      Operand16::D16 => "d16",
+      Operand16::A16I => "(a16)",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl fmt::Display for AddByteTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "A")
+  }
+}
+
+impl fmt::Display for AddTwoByteTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "HL")
+  }
+}
+
+impl fmt::Display for AdcTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "A")
+  }
+}
+
+impl fmt::Display for IncDecTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      IncDecTarget::Byte(target) => write!(f, "{}", target),
+      IncDecTarget::Word(target) => write!(f, "{}", target),
+    }
+  }
+}
@@ -0,0 +1,74 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+  Right,
+  Left,
+  Up,
+  Down,
+  A,
+  B,
+  Select,
+  Start,
+}
+
+impl Button {
+  pub const ALL: [Button; 8] = [
+    Button::Right, Button::Left, Button::Up, Button::Down,
+    Button::A, Button::B, Button::Select, Button::Start,
+  ];
+
+  // 押下状態をまとめた1バイトでのビット位置
+  pub fn mask(self) -> u8 {
+    match self {
+      Button::Right => 0x01,
+      Button::Left => 0x02,
+      Button::Up => 0x04,
+      Button::Down => 0x08,
+      Button::A => 0x10,
+      Button::B => 0x20,
+      Button::Select => 0x40,
+      Button::Start => 0x80,
+    }
+  }
+}
+
+pub struct Joypad {
+  pub pressed: u8,
+  pub select: u8,
+}
+
+impl Joypad {
+  pub fn new() -> Joypad {
+    Joypad {
+      pressed: 0,
+      select: 0x30,
+    }
+  }
+
+  pub fn read_byte(&self) -> u8 {
+    let mut lines = 0x0F;
+    if self.select & 0x10 == 0 {
+      lines &= !(self.pressed & 0x0F);
+    }
+    if self.select & 0x20 == 0 {
+      lines &= !(self.pressed >> 4);
+    }
+    0xC0 | self.select | lines
+  }
+
+  pub fn write_byte(&mut self, value: u8) {
+    self.select = value & 0x30;
+  }
+
+  // 戻り値は新たに押されたボタンがあるかどうか（ジョイパッド割り込みの要求）
+  pub fn set_state(&mut self, pressed: u8) -> bool {
+    let newly_pressed = pressed & !self.pressed;
+    self.pressed = pressed;
+    newly_pressed != 0
+  }
+}
+
+impl Default for Joypad {
+  fn default() -> Self {
+    Joypad::new()
+  }
+}
@@ -1,3 +1,30 @@
+pub mod apu;
+pub mod assembler;
+pub mod bess;
+pub mod bk2;
+pub mod bus;
+pub mod call_stack;
+pub mod cartridge;
+pub mod condition;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod flat_bus;
+pub mod gameboy;
+pub mod gdb;
+pub mod history;
 pub mod instruction;
-pub mod register;
\ No newline at end of file
+pub mod joypad;
+pub mod movie;
+pub mod png;
+pub mod ppu;
+pub mod symbols;
+pub mod register;
+pub mod rewind;
+pub mod savestate;
+pub mod serial;
+pub mod timer;
+pub mod trace;
+pub mod vbm;
+pub mod watchpoint;
+pub mod zip;
@@ -1,11 +1,578 @@
-use emulator::cpu::CPU;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn main() {
-	println!("Emulator is starting!");
+use emulator::bk2;
+use emulator::debugger::{self, Debugger};
+use emulator::disassembler::disassemble_with_symbols;
+use emulator::gameboy::{GameBoy, Model, CYCLES_PER_FRAME};
+use emulator::gdb::GdbServer;
+use emulator::movie::Movie;
+use emulator::png;
+use emulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use emulator::symbols::Symbols;
+use emulator::trace::{TraceFormat, WriterTraceSink};
+use emulator::vbm;
 
-	let mut cpu = CPU::new();
+const USAGE: &str = "usage: emulator <rom> [options]
+       emulator disasm <rom> [--bank <n>]
+       emulator convert <rom> <input movie> <output movie> [--boot-rom <path>]
+
+options:
+  --model <dmg|cgb>     hardware model to emulate (default: dmg)
+  --boot-rom <path>     run the given boot ROM before the cartridge
+  --headless            do not draw frames to the terminal
+  --frames <n>          stop after n frames
+  --screenshot <path>   write the last frame as PNG on exit
+  --trace <path>        write the CPU state before every executed instruction
+  --trace-format <fmt>  full or doctor (Gameboy Doctor log format, LY reads as 0x90)
+  --speed <x>           speed multiplier, 0 for unthrottled (default: 1, headless: 0)
+  --save-dir <dir>      directory for battery saves (default: next to the ROM)
+  --debug               start the interactive debugger (type help for commands)
+  --gdb <port>          wait for a GDB remote connection on 127.0.0.1:<port>
+  --movie <path>        play back an input movie (.gbm, .bk2 or .vbm); .gbm movies are checked every frame for desyncs
+  -h, --help            show this message
+
+disasm options:
+  --bank <n>            disassemble only ROM bank n (default: every bank)
+
+convert reads and writes .gbm (native), .bk2 (BizHawk) and .vbm (VisualBoyAdvance) movies";
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const CYCLES_PER_SECOND: f64 = 4_194_304.0;
+const SAVE_INTERVAL_FRAMES: u64 = 60;
+
+// 終了コード: 0 正常終了、2 引数の誤り、3 ファイル入出力の失敗、4 ROMが不正、5 エミュレーション中のエラー、
+// 6 ムービーが読めないか、再生がずれた
+enum CliError {
+	Usage(String),
+	Io(PathBuf, io::Error),
+	InvalidRom(String),
+	Emulation(String),
+	Movie(String),
+}
+
+impl CliError {
+	fn exit_code(&self) -> u8 {
+		match self {
+			CliError::Usage(_) => 2,
+			CliError::Io(_, _) => 3,
+			CliError::InvalidRom(_) => 4,
+			CliError::Emulation(_) => 5,
+			CliError::Movie(_) => 6,
+		}
+	}
+}
+
+impl fmt::Display for CliError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CliError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+			CliError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+			CliError::InvalidRom(message) => write!(f, "invalid ROM: {}", message),
+			CliError::Emulation(message) => write!(f, "emulation stopped: {}", message),
+			CliError::Movie(message) => write!(f, "movie: {}", message),
+		}
+	}
+}
+
+struct Options {
+	rom: PathBuf,
+	model: Model,
+	boot_rom: Option<PathBuf>,
+	headless: bool,
+	frames: Option<u64>,
+	screenshot: Option<PathBuf>,
+	trace: Option<PathBuf>,
+	trace_format: TraceFormat,
+	speed: Option<f64>,
+	save_dir: Option<PathBuf>,
+	debug: bool,
+	gdb: Option<u16>,
+	movie: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, CliError> {
+	let mut rom = None;
+	let mut options = Options {
+		rom: PathBuf::new(),
+		model: Model::Dmg,
+		boot_rom: None,
+		headless: false,
+		frames: None,
+		screenshot: None,
+		trace: None,
+		trace_format: TraceFormat::Full,
+		speed: None,
+		save_dir: None,
+		debug: false,
+		gdb: None,
+		movie: None,
+	};
+
+	while let Some(arg) = args.next() {
+		let mut value = |name: &str| {
+			args.next().ok_or_else(|| CliError::Usage(format!("{} requires a value", name)))
+		};
+		match arg.as_str() {
+			"-h" | "--help" => return Ok(None),
+			"--model" => {
+				options.model = match value("--model")?.as_str() {
+					"dmg" => Model::Dmg,
+					"cgb" => Model::Cgb,
+					other => return Err(CliError::Usage(format!("unknown model: {}", other))),
+				};
+			},
+			"--boot-rom" => options.boot_rom = Some(PathBuf::from(value("--boot-rom")?)),
+			"--headless" => options.headless = true,
+			"--frames" => {
+				let frames = value("--frames")?;
+				options.frames = Some(frames.parse().map_err(|_| CliError::Usage(format!("invalid frame count: {}", frames)))?);
+			},
+			"--screenshot" => options.screenshot = Some(PathBuf::from(value("--screenshot")?)),
+			"--trace" => options.trace = Some(PathBuf::from(value("--trace")?)),
+			"--trace-format" => {
+				options.trace_format = match value("--trace-format")?.as_str() {
+					"full" => TraceFormat::Full,
+					"doctor" => TraceFormat::GameboyDoctor,
+					other => return Err(CliError::Usage(format!("unknown trace format: {}", other))),
+				};
+			},
+			"--speed" => {
+				let speed = value("--speed")?;
+				match speed.parse::<f64>() {
+					Ok(value) if frame_duration(value).is_some() => options.speed = Some(value),
+					_ => return Err(CliError::Usage(format!("invalid speed: {}", speed))),
+				}
+			},
+			"--save-dir" => options.save_dir = Some(PathBuf::from(value("--save-dir")?)),
+			"--debug" => options.debug = true,
+			"--gdb" => {
+				let port = value("--gdb")?;
+				options.gdb = Some(port.parse().map_err(|_| CliError::Usage(format!("invalid port: {}", port)))?);
+			},
+			"--movie" => options.movie = Some(PathBuf::from(value("--movie")?)),
+			flag if flag.starts_with('-') => return Err(CliError::Usage(format!("unknown option: {}", flag))),
+			path => {
+				if rom.is_some() {
+					return Err(CliError::Usage(format!("unexpected argument: {}", path)));
+				}
+				rom = Some(PathBuf::from(path));
+			},
+		}
+	}
+
+	options.rom = rom.ok_or_else(|| CliError::Usage("missing ROM path".to_string()))?;
+	Ok(Some(options))
+}
+
+struct DisasmOptions {
+	rom: PathBuf,
+	bank: Option<usize>,
+}
+
+fn parse_disasm_args(mut args: impl Iterator<Item = String>) -> Result<Option<DisasmOptions>, CliError> {
+	let mut rom = None;
+	let mut bank = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-h" | "--help" => return Ok(None),
+			"--bank" => {
+				let value = args.next().ok_or_else(|| CliError::Usage("--bank requires a value".to_string()))?;
+				bank = Some(value.parse().map_err(|_| CliError::Usage(format!("invalid bank: {}", value)))?);
+			},
+			flag if flag.starts_with('-') => return Err(CliError::Usage(format!("unknown option: {}", flag))),
+			path => {
+				if rom.is_some() {
+					return Err(CliError::Usage(format!("unexpected argument: {}", path)));
+				}
+				rom = Some(PathBuf::from(path));
+			},
+		}
+	}
+	let rom = rom.ok_or_else(|| CliError::Usage("missing ROM path".to_string()))?;
+	Ok(Some(DisasmOptions { rom, bank }))
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, CliError> {
+	fs::read(path).map_err(|error| CliError::Io(path.to_path_buf(), error))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), CliError> {
+	fs::write(path, data).map_err(|error| CliError::Io(path.to_path_buf(), error))
+}
+
+fn save_path(options: &Options) -> PathBuf {
+	let directory = match &options.save_dir {
+		Some(directory) => directory.clone(),
+		None => options.rom.parent().map(Path::to_path_buf).unwrap_or_default(),
+	};
+	// with_extension だと game.v1.gb と game.v2.gb がどちらも game.sav になるので、語幹の後ろに足す
+	let mut name = options.rom.file_stem().unwrap_or_default().to_os_string();
+	name.push(".sav");
+	directory.join(name)
+}
+
+// バッテリー付きカートリッジの外部RAMを読み込む
+fn load_battery(gameboy: &mut GameBoy, path: &Path) -> Result<(), CliError> {
+	let Some(cartridge) = gameboy.cpu.bus.cartridge.as_mut() else { return Ok(()) };
+	if !cartridge.has_battery || !path.exists() {
+		return Ok(());
+	}
+	let data = read_file(path)?;
+	let length = data.len().min(cartridge.ram.len());
+	cartridge.ram[..length].copy_from_slice(&data[..length]);
+	Ok(())
+}
+
+// ROMと同じ名前の .sym と .map があれば読み込む（RGBDS の出力）。
+// .sym が読めない行を含む場合は警告を出してラベルなしで続ける
+fn load_symbols(rom: &Path) -> Result<Symbols, CliError> {
+	let sym_path = rom.with_extension("sym");
+	let mut symbols = Symbols::new();
+	if sym_path.is_file() {
+		let text = String::from_utf8_lossy(&read_file(&sym_path)?).into_owned();
+		match Symbols::parse_sym(&text) {
+			Ok(parsed) => symbols = parsed,
+			Err(error) => eprintln!("warning: {}: {}", sym_path.display(), error),
+		}
+	}
+	let map_path = rom.with_extension("map");
+	if map_path.is_file() {
+		symbols.load_map(&String::from_utf8_lossy(&read_file(&map_path)?));
+	}
+	Ok(symbols)
+}
+
+fn battery_ram(gameboy: &GameBoy) -> Option<&[u8]> {
+	match &gameboy.cpu.bus.cartridge {
+		Some(cartridge) if cartridge.has_battery && !cartridge.ram.is_empty() => Some(&cartridge.ram),
+		_ => None,
+	}
+}
+
+// path が None のとき（ムービーの再生中）は書き出さない
+fn save_battery(gameboy: &GameBoy, path: Option<&Path>, last_saved: &mut Vec<u8>) -> Result<(), CliError> {
+	let (Some(path), Some(ram)) = (path, battery_ram(gameboy)) else { return Ok(()) };
+	if ram == last_saved.as_slice() {
+		return Ok(());
+	}
+	if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+		fs::create_dir_all(directory).map_err(|error| CliError::Io(directory.to_path_buf(), error))?;
+	}
+	write_file(path, ram)?;
+	*last_saved = ram.to_vec();
+	Ok(())
+}
+
+// 上下2ピクセルを1文字（▀）にまとめて端末に描画する
+fn draw_frame(framebuffer: &[u8], out: &mut impl Write) -> io::Result<()> {
+	const COLORS: [u8; 4] = [231, 250, 244, 16];
+	let mut text = String::from("\x1b[H");
+	for y in (0..SCREEN_HEIGHT).step_by(2) {
+		for x in 0..SCREEN_WIDTH {
+			let top = framebuffer[y * SCREEN_WIDTH + x] as usize;
+			let bottom = framebuffer[(y + 1) * SCREEN_WIDTH + x] as usize;
+			text.push_str(&format!("\x1b[38;5;{}m\x1b[48;5;{}m▀", COLORS[top], COLORS[bottom]));
+		}
+		text.push_str("\x1b[0m\n");
+	}
+	out.write_all(text.as_bytes())?;
+	out.flush()
+}
+
+fn flush_trace(gameboy: &mut GameBoy, options: &Options) -> Result<(), CliError> {
+	match gameboy.cpu.tracer.as_mut() {
+		Some(tracer) => tracer.flush().map_err(|error| CliError::Io(options.trace.clone().unwrap_or_default(), error)),
+		None => Ok(()),
+	}
+}
+
+// 速度から1フレームの長さを求める。0なら待たないので Some(None)。
+// 負の値、有限でない値、小さすぎて Duration に収まらない値は None
+fn frame_duration(speed: f64) -> Option<Option<Duration>> {
+	if !speed.is_finite() || speed < 0.0 {
+		return None;
+	}
+	if speed == 0.0 {
+		return Some(None);
+	}
+	Duration::try_from_secs_f64(CYCLES_PER_FRAME as f64 / CYCLES_PER_SECOND / speed).ok().map(Some)
+}
+
+// movie があれば、その入力でフレームを進め、記録と状態がずれたら止める。--frames がなければムービーの最後で終わる
+fn run_frames(
+	gameboy: &mut GameBoy,
+	options: &Options,
+	movie: Option<&Movie>,
+	save_path: Option<&Path>,
+	last_saved: &mut Vec<u8>,
+) -> Result<(), CliError> {
+	let speed = options.speed.unwrap_or(if options.headless { 0.0 } else { 1.0 });
+	let frame_duration = frame_duration(speed).ok_or_else(|| CliError::Usage(format!("invalid speed: {}", speed)))?;
+
+	let mut frame = 0;
+	let mut next_frame_at = Instant::now();
+	while options.frames.is_none_or(|frames| frame < frames) && movie.is_none_or(|movie| (frame as usize) < movie.len()) {
+		let result = match movie {
+			Some(movie) => movie.play_frame(gameboy, frame as usize),
+			None => {
+				gameboy.run_frame();
+				Ok(())
+			},
+		};
+		if let Err(error) = result {
+			flush_trace(gameboy, options)?;
+			return Err(CliError::Movie(error.to_string()));
+		}
+		// 未定義のオペコードで固まったCPUはもう進まないので、終了コードに変換する
+		if let Some(opcode) = gameboy.cpu.illegal_opcode {
+			flush_trace(gameboy, options)?;
+			save_battery(gameboy, save_path, last_saved)?;
+			return Err(CliError::Emulation(format!(
+				"CPU locked up on illegal opcode ${:02X} (PC={:04X})\ncall stack:\n{}",
+				opcode,
+				gameboy.cpu.pc,
+				debugger::backtrace(gameboy)
+			)));
+		}
+		frame += 1;
+
+		// 音声出力はまだないので、溜まったサンプルは捨てる
+		gameboy.take_audio_buffer();
+
+		if !options.headless {
+			draw_frame(gameboy.framebuffer(), &mut io::stdout().lock())
+				.map_err(|error| CliError::Io(PathBuf::from("<stdout>"), error))?;
+		}
+		if frame % SAVE_INTERVAL_FRAMES == 0 {
+			save_battery(gameboy, save_path, last_saved)?;
+		}
+		if let Some(duration) = frame_duration {
+			next_frame_at += duration;
+			let now = Instant::now();
+			if next_frame_at > now {
+				thread::sleep(next_frame_at - now);
+			} else {
+				next_frame_at = now;
+			}
+		}
+	}
+	Ok(())
+}
+
+// 1行ずつコマンドを読んで実行する。空行は直前のコマンドを繰り返す
+fn run_debugger(gameboy: &mut GameBoy, input: &mut impl BufRead, out: &mut impl Write) -> io::Result<()> {
+	let mut debugger = Debugger::new();
+	let mut last_command = String::new();
+	writeln!(out, "{}", debugger.location(gameboy))?;
 
 	loop {
-		cpu.step();
+		write!(out, "(debug) ")?;
+		out.flush()?;
+		let mut line = String::new();
+		if input.read_line(&mut line)? == 0 {
+			break;
+		}
+		let command = match line.trim() {
+			"" => last_command.clone(),
+			command => command.to_string(),
+		};
+		if matches!(command.as_str(), "quit" | "q") {
+			break;
+		}
+
+		match debugger.execute(gameboy, &command) {
+			Ok(output) if output.is_empty() => {},
+			Ok(output) => writeln!(out, "{}", output)?,
+			Err(message) => writeln!(out, "error: {}", message)?,
+		}
+		last_command = command;
+	}
+	Ok(())
+}
+
+// GDB が接続してきて切断するまで、GDB の指示で実行する
+fn run_gdb(gameboy: &mut GameBoy, port: u16) -> Result<(), CliError> {
+	let address = format!("127.0.0.1:{}", port);
+	let listener = TcpListener::bind(&address).map_err(|error| CliError::Io(PathBuf::from(&address), error))?;
+	eprintln!("waiting for GDB on {}", address);
+	GdbServer::new().accept(gameboy, &listener).map_err(|error| CliError::Io(PathBuf::from(&address), error))
+}
+
+fn run(options: Options) -> Result<(), CliError> {
+	let rom = read_file(&options.rom)?;
+	let boot_rom = match &options.boot_rom {
+		Some(path) => {
+			let data = read_file(path)?;
+			if data.len() != 0x100 && data.len() != 0x900 {
+				return Err(CliError::InvalidRom(format!("boot ROM must be 256 or 2304 bytes, got {}", data.len())));
+			}
+			Some(data)
+		},
+		None => None,
+	};
+	let movie = match &options.movie {
+		Some(path) => Some(read_movie(path, &rom, boot_rom.as_deref())?),
+		None => None,
+	};
+	// ムービーは記録したときの機種とブートROMで始める。外部RAMは空（電源投入）かセーブステートの中身を使う
+	let mut gameboy = match &movie {
+		Some(movie) => movie.start(rom, boot_rom).map_err(|error| CliError::Movie(error.to_string()))?,
+		None => GameBoy::with_config(rom, options.model, boot_rom).map_err(|error| CliError::InvalidRom(error.to_string()))?,
+	};
+
+	gameboy.symbols = load_symbols(&options.rom)?;
+
+	let save_path = movie.is_none().then(|| save_path(&options));
+	if let Some(path) = &save_path {
+		load_battery(&mut gameboy, path)?;
+	}
+	let mut last_saved = battery_ram(&gameboy).map(<[u8]>::to_vec).unwrap_or_default();
+
+	if let Some(path) = &options.trace {
+		let file = File::create(path).map_err(|error| CliError::Io(path.clone(), error))?;
+		let sink = WriterTraceSink::new(BufWriter::new(file), options.trace_format).with_symbols(gameboy.symbols.clone());
+		gameboy.cpu.tracer = Some(Box::new(sink));
+		if options.trace_format == TraceFormat::GameboyDoctor {
+			gameboy.cpu.bus.ppu.ly_override = Some(0x90);
+		}
+	}
+
+	if let Some(port) = options.gdb {
+		run_gdb(&mut gameboy, port)?;
+	} else if options.debug {
+		run_debugger(&mut gameboy, &mut io::stdin().lock(), &mut io::stdout().lock())
+			.map_err(|error| CliError::Io(PathBuf::from("<stdio>"), error))?;
+	} else {
+		run_frames(&mut gameboy, &options, movie.as_ref(), save_path.as_deref(), &mut last_saved)?;
+	}
+
+	flush_trace(&mut gameboy, &options)?;
+	if let Some(path) = &options.screenshot {
+		write_file(path, &png::encode_framebuffer(gameboy.framebuffer()))?;
+	}
+	save_battery(&gameboy, save_path.as_deref(), &mut last_saved)
+}
+
+// 拡張子で形式を選ぶ。.bk2 と .vbm は ROM（とブートROM）と照らし合わせてネイティブの形式に変換する
+fn read_movie(path: &Path, rom: &[u8], boot_rom: Option<&[u8]>) -> Result<Movie, CliError> {
+	let data = read_file(path)?;
+	let movie = match path.extension().and_then(|extension| extension.to_str()) {
+		Some("bk2") => bk2::import(&data, rom, boot_rom),
+		Some("vbm") => vbm::import(&data, rom),
+		_ => Movie::parse(&data),
+	};
+	movie.map_err(|error| CliError::Movie(format!("{}: {}", path.display(), error)))
+}
+
+fn write_movie(path: &Path, movie: &Movie, rom: &[u8]) -> Result<(), CliError> {
+	let data = match path.extension().and_then(|extension| extension.to_str()) {
+		Some("bk2") => bk2::export(movie, rom),
+		Some("vbm") => vbm::export(movie, rom),
+		_ => Ok(movie.to_bytes()),
+	};
+	write_file(path, &data.map_err(|error| CliError::Movie(format!("{}: {}", path.display(), error)))?)
+}
+
+struct ConvertOptions {
+	rom: PathBuf,
+	input: PathBuf,
+	output: PathBuf,
+	boot_rom: Option<PathBuf>,
+}
+
+fn parse_convert_args(mut args: impl Iterator<Item = String>) -> Result<Option<ConvertOptions>, CliError> {
+	let mut paths = Vec::new();
+	let mut boot_rom = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-h" | "--help" => return Ok(None),
+			"--boot-rom" => {
+				let value = args.next().ok_or_else(|| CliError::Usage("--boot-rom requires a value".to_string()))?;
+				boot_rom = Some(PathBuf::from(value));
+			},
+			flag if flag.starts_with('-') => return Err(CliError::Usage(format!("unknown option: {}", flag))),
+			path => paths.push(PathBuf::from(path)),
+		}
+	}
+	let [rom, input, output]: [PathBuf; 3] =
+		paths.try_into().map_err(|_| CliError::Usage("convert needs a ROM, an input movie and an output movie".to_string()))?;
+	Ok(Some(ConvertOptions { rom, input, output, boot_rom }))
+}
+
+fn convert_movie(options: ConvertOptions) -> Result<(), CliError> {
+	let rom = read_file(&options.rom)?;
+	let boot_rom = options.boot_rom.as_deref().map(read_file).transpose()?;
+	let movie = read_movie(&options.input, &rom, boot_rom.as_deref())?;
+	write_movie(&options.output, &movie, &rom)
+}
+
+// ROMをバンクごとに頭から順に逆アセンブルする。データ領域も命令として読むので、
+// 途中から命令の区切りがずれることがある
+fn disassemble_rom(options: DisasmOptions) -> Result<(), CliError> {
+	let rom = read_file(&options.rom)?;
+	let bank_count = rom.len().div_ceil(ROM_BANK_SIZE);
+	let banks = match options.bank {
+		Some(bank) if bank >= bank_count => {
+			return Err(CliError::Usage(format!("bank {} out of range (ROM has {} banks)", bank, bank_count)));
+		},
+		Some(bank) => bank..bank + 1,
+		None => 0..bank_count,
+	};
+
+	let symbols = load_symbols(&options.rom)?;
+
+	let stdout_error = |error| CliError::Io(PathBuf::from("<stdout>"), error);
+	let mut out = BufWriter::new(io::stdout().lock());
+	for bank in banks {
+		let start = bank * ROM_BANK_SIZE;
+		let data = &rom[start..rom.len().min(start + ROM_BANK_SIZE)];
+		// バンク0は0x0000〜、それ以外は切り替え領域の0x4000〜に見える。
+		// バンク0から切り替え領域への参照は、バンク1が見えているものとしてラベルを探す
+		let base = if bank == 0 { 0x0000 } else { 0x4000 };
+		let rom_bank = bank.max(1);
+		writeln!(out, "; bank {:02X}", bank).map_err(stdout_error)?;
+		let mut offset = 0;
+		while offset < data.len() {
+			let address = base + offset as u16;
+			if let Some(label) = symbols.label(rom_bank, address) {
+				writeln!(out, "{}:", label).map_err(stdout_error)?;
+			}
+			let line = disassemble_with_symbols(&data[offset..], address, &symbols, rom_bank);
+			writeln!(out, "{:02X}:{}", bank, line).map_err(stdout_error)?;
+			offset += line.bytes.len();
+		}
+	}
+	out.flush().map_err(stdout_error)
+}
+
+fn main() -> ExitCode {
+	let mut args = std::env::args().skip(1).peekable();
+	let result = if args.peek().map(String::as_str) == Some("disasm") {
+		args.next();
+		parse_disasm_args(args).and_then(|options| options.map(disassemble_rom).transpose())
+	} else if args.peek().map(String::as_str) == Some("convert") {
+		args.next();
+		parse_convert_args(args).and_then(|options| options.map(convert_movie).transpose())
+	} else {
+		parse_args(args).and_then(|options| options.map(run).transpose())
+	};
+
+	match result {
+		Ok(Some(())) => ExitCode::SUCCESS,
+		Ok(None) => {
+			println!("{}", USAGE);
+			ExitCode::SUCCESS
+		},
+		Err(error) => {
+			eprintln!("error: {}", error);
+			ExitCode::from(error.exit_code())
+		},
 	}
 }
@@ -0,0 +1,303 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::bess::footer_start;
+use crate::gameboy::{GameBoy, Model};
+use crate::png::crc32;
+use crate::savestate::SaveStateError;
+
+pub const MAGIC: [u8; 8] = *b"GBMOVIE\0";
+pub const VERSION: u16 = 1;
+
+// ムービーがどこから始まるか
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Start {
+  // 電源を入れた直後（カートリッジのRAMは空）
+  PowerOn,
+  // 電源を入れた直後。カートリッジのRAMにはこの内容が入っている
+  SaveRam(Vec<u8>),
+  SaveState(Vec<u8>),
+}
+
+// 1フレーム分の記録
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Frame {
+  // このフレームの間押していたボタン（Button::mask() のビット）
+  pub buttons: u8,
+  // フレームを実行し終えたあとの state_hash。ほかのエミュレータのムービーから変換したものにはない
+  pub hash: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MovieError {
+  Invalid(String),
+  UnsupportedVersion(u16),
+  RomMismatch { expected: u32, actual: u32 },
+  // None はブートROMなし
+  BootRomMismatch { expected: Option<u32>, actual: Option<u32> },
+  InvalidRom(String),
+  SaveState(SaveStateError),
+  // ほかのエミュレータのムービーが記録したROMと違う。ハッシュの種類ごとに文字列にしてある
+  ForeignRomMismatch { expected: String, actual: String },
+  // この形式に変換できない、またはこのエミュレータで再現できない
+  Unsupported(String),
+  // frame は0から数える
+  Desync { frame: usize, expected: u32, actual: u32 },
+}
+
+impl fmt::Display for MovieError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let boot_rom = |crc: &Option<u32>| match crc {
+      Some(crc) => format!("CRC32 {:08X}", crc),
+      None => "none".to_string(),
+    };
+    match self {
+      MovieError::Invalid(message) => write!(f, "invalid movie: {}", message),
+      MovieError::UnsupportedVersion(version) => {
+        write!(f, "unsupported movie version {} (this build reads version {})", version, VERSION)
+      },
+      MovieError::RomMismatch { expected, actual } => write!(
+        f,
+        "movie was recorded with another ROM (CRC32 {:08X}; loaded ROM has CRC32 {:08X})",
+        expected, actual
+      ),
+      MovieError::BootRomMismatch { expected, actual } => write!(
+        f,
+        "movie was recorded with boot ROM {} but got {}",
+        boot_rom(expected), boot_rom(actual)
+      ),
+      MovieError::InvalidRom(message) => write!(f, "invalid ROM: {}", message),
+      MovieError::SaveState(error) => write!(f, "cannot load the starting state: {}", error),
+      MovieError::ForeignRomMismatch { expected, actual } => {
+        write!(f, "movie was recorded with another ROM ({}; loaded ROM has {})", expected, actual)
+      },
+      MovieError::Unsupported(message) => write!(f, "unsupported movie: {}", message),
+      MovieError::Desync { frame, expected, actual } => write!(
+        f,
+        "desync at frame {}: state hash {:08X}, expected {:08X}",
+        frame, actual, expected
+      ),
+    }
+  }
+}
+
+impl Error for MovieError {}
+
+// 比較に使う状態のハッシュ。セーブステートのうち、実時間を含む BESS のフッタを除いた部分の FNV-1a。
+// 各セクションの末尾にはそのセクションの CRC32 が付いているので、全体の CRC32 を取ると中身によらず同じ値になってしまう
+pub fn state_hash(gameboy: &GameBoy) -> u32 {
+  let state = gameboy.save_state();
+  let end = footer_start(&state).unwrap_or(state.len());
+  state[..end].iter().fold(0x811C_9DC5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+// フレームごとの入力と、それを再現するのに必要な初期条件
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+  pub rom_crc32: u32,
+  pub model: Model,
+  pub boot_rom_crc32: Option<u32>,
+  // MBC3 の RTC の初期値（秒）。RTC のないカートリッジでは使わない
+  pub rtc_seed: u64,
+  pub start: Start,
+  pub frames: Vec<Frame>,
+}
+
+impl Movie {
+  // 電源を入れた直後から記録する。まだ1命令も実行していない GameBoy と、そのとき渡したブートROMを渡す
+  pub fn power_on(gameboy: &GameBoy, boot_rom: Option<&[u8]>) -> Movie {
+    let mut movie = Movie::new(gameboy, Start::PowerOn);
+    movie.boot_rom_crc32 = boot_rom.map(crc32);
+    movie
+  }
+
+  // 今の状態から記録する
+  pub fn from_state(gameboy: &GameBoy) -> Movie {
+    Movie::new(gameboy, Start::SaveState(gameboy.save_state()))
+  }
+
+  fn new(gameboy: &GameBoy, start: Start) -> Movie {
+    let cartridge = gameboy.cpu.bus.cartridge.as_ref().expect("GameBoy always has a cartridge");
+    let rtc_seed = cartridge.rtc.as_ref().map_or(0, |rtc| {
+      rtc.days as u64 * 86400 + rtc.hours as u64 * 3600 + rtc.minutes as u64 * 60 + rtc.seconds as u64
+    });
+    Movie {
+      rom_crc32: cartridge.rom_crc32,
+      model: gameboy.model,
+      boot_rom_crc32: None,
+      rtc_seed,
+      start,
+      frames: Vec::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  // buttons を押したまま1フレーム実行し、その入力と実行後の状態のハッシュを記録する
+  pub fn record_frame(&mut self, gameboy: &mut GameBoy, buttons: u8) {
+    gameboy.set_buttons(buttons);
+    gameboy.run_frame();
+    self.frames.push(Frame { buttons, hash: Some(state_hash(gameboy)) });
+  }
+
+  // 記録したときと同じ初期条件の GameBoy を作る
+  pub fn start(&self, rom: Vec<u8>, boot_rom: Option<Vec<u8>>) -> Result<GameBoy, MovieError> {
+    // セーブステートから始めるときは、ブートROMの状態もセーブステートに入っている
+    let boot_rom = match self.start {
+      Start::PowerOn | Start::SaveRam(_) => {
+        let boot_rom_crc32 = boot_rom.as_deref().map(crc32);
+        if boot_rom_crc32 != self.boot_rom_crc32 {
+          return Err(MovieError::BootRomMismatch { expected: self.boot_rom_crc32, actual: boot_rom_crc32 });
+        }
+        boot_rom
+      },
+      Start::SaveState(_) => None,
+    };
+    let mut gameboy =
+      GameBoy::with_config(rom, self.model, boot_rom).map_err(|error| MovieError::InvalidRom(error.to_string()))?;
+    let rom_crc32 = gameboy.cpu.bus.cartridge.as_ref().expect("GameBoy always has a cartridge").rom_crc32;
+    if rom_crc32 != self.rom_crc32 {
+      return Err(MovieError::RomMismatch { expected: self.rom_crc32, actual: rom_crc32 });
+    }
+    match &self.start {
+      Start::PowerOn => self.seed_rtc(&mut gameboy),
+      Start::SaveRam(ram) => {
+        self.seed_rtc(&mut gameboy);
+        let cartridge = gameboy.cpu.bus.cartridge.as_mut().expect("GameBoy always has a cartridge");
+        let length = ram.len().min(cartridge.ram.len());
+        cartridge.ram[..length].copy_from_slice(&ram[..length]);
+      },
+      Start::SaveState(state) => gameboy.load_state(state).map_err(MovieError::SaveState)?,
+    }
+    Ok(gameboy)
+  }
+
+  fn seed_rtc(&self, gameboy: &mut GameBoy) {
+    let cartridge = gameboy.cpu.bus.cartridge.as_mut().expect("GameBoy always has a cartridge");
+    if let Some(rtc) = cartridge.rtc.as_mut() {
+      rtc.seconds = (self.rtc_seed % 60) as u8;
+      rtc.minutes = (self.rtc_seed / 60 % 60) as u8;
+      rtc.hours = (self.rtc_seed / 3600 % 24) as u8;
+      rtc.days = (self.rtc_seed / 86400 % 0x200) as u16;
+    }
+  }
+
+  // frame 番目のフレームを再生し、記録したハッシュがあれば比べる
+  pub fn play_frame(&self, gameboy: &mut GameBoy, frame: usize) -> Result<(), MovieError> {
+    let recorded = self.frames[frame];
+    gameboy.set_buttons(recorded.buttons);
+    gameboy.run_frame();
+    let Some(expected) = recorded.hash else {
+      return Ok(());
+    };
+    let actual = state_hash(gameboy);
+    if actual != expected {
+      return Err(MovieError::Desync { frame, expected, actual });
+    }
+    Ok(())
+  }
+
+  // 最初から最後まで再生する。ずれたらそのフレームで止まる
+  pub fn play(&self, gameboy: &mut GameBoy) -> Result<(), MovieError> {
+    (0..self.frames.len()).try_for_each(|frame| self.play_frame(gameboy, frame))
+  }
+
+  // MAGIC、版、ROMの CRC32、機種、ブートROMの CRC32、RTC の初期値、開始状態、フレーム数、
+  // [ボタン u8][ハッシュの有無 u8][ハッシュ u32（あれば）] × フレーム数 の順に並べる。数値はリトルエンディアン
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&self.rom_crc32.to_le_bytes());
+    data.push(match self.model {
+      Model::Dmg => 0,
+      Model::Cgb => 1,
+    });
+    match self.boot_rom_crc32 {
+      Some(crc) => {
+        data.push(1);
+        data.extend_from_slice(&crc.to_le_bytes());
+      },
+      None => data.push(0),
+    }
+    data.extend_from_slice(&self.rtc_seed.to_le_bytes());
+    match &self.start {
+      Start::PowerOn => data.push(0),
+      Start::SaveState(state) => {
+        data.push(1);
+        data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        data.extend_from_slice(state);
+      },
+      Start::SaveRam(ram) => {
+        data.push(2);
+        data.extend_from_slice(&(ram.len() as u32).to_le_bytes());
+        data.extend_from_slice(ram);
+      },
+    }
+    data.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+    for frame in &self.frames {
+      data.push(frame.buttons);
+      match frame.hash {
+        Some(hash) => {
+          data.push(1);
+          data.extend_from_slice(&hash.to_le_bytes());
+        },
+        None => data.push(0),
+      }
+    }
+    data
+  }
+
+  pub fn parse(data: &[u8]) -> Result<Movie, MovieError> {
+    let mut position = 0;
+    let mut take = |length: usize| {
+      let bytes = data.get(position..position + length).ok_or_else(|| MovieError::Invalid("file is truncated".to_string()))?;
+      position += length;
+      Ok::<&[u8], MovieError>(bytes)
+    };
+    if take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+      return Err(MovieError::Invalid("not a movie file".to_string()));
+    }
+    let version = u16::from_le_bytes(take(2)?.try_into().unwrap());
+    if version != VERSION {
+      return Err(MovieError::UnsupportedVersion(version));
+    }
+    let rom_crc32 = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let model = match take(1)?[0] {
+      0 => Model::Dmg,
+      1 => Model::Cgb,
+      other => return Err(MovieError::Invalid(format!("unknown model {}", other))),
+    };
+    let boot_rom_crc32 = match take(1)?[0] {
+      0 => None,
+      _ => Some(u32::from_le_bytes(take(4)?.try_into().unwrap())),
+    };
+    let rtc_seed = u64::from_le_bytes(take(8)?.try_into().unwrap());
+    let start = match take(1)?[0] {
+      0 => Start::PowerOn,
+      kind @ (1 | 2) => {
+        let length = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let data = take(length)?.to_vec();
+        if kind == 1 { Start::SaveState(data) } else { Start::SaveRam(data) }
+      },
+      other => return Err(MovieError::Invalid(format!("unknown start kind {}", other))),
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+    let mut frames = Vec::with_capacity(count.min(data.len() / 2));
+    for _ in 0..count {
+      let frame = take(2)?;
+      let buttons = frame[0];
+      let hash = match frame[1] {
+        0 => None,
+        _ => Some(u32::from_le_bytes(take(4)?.try_into().unwrap())),
+      };
+      frames.push(Frame { buttons, hash });
+    }
+    Ok(Movie { rom_crc32, model, boot_rom_crc32, rtc_seed, start, frames })
+  }
+}
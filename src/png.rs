@@ -0,0 +1,89 @@
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// DMGの4階調をグレースケールの輝度に対応させる（0が白、3が黒）
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+// フレームバッファを8ビットグレースケールのPNGにする。
+// 外部クレートを使わないため、圧縮はせずに無圧縮のDeflateブロックで格納する
+pub fn encode_framebuffer(framebuffer: &[u8]) -> Vec<u8> {
+  let mut raw = Vec::with_capacity((SCREEN_WIDTH + 1) * SCREEN_HEIGHT);
+  for row in framebuffer.chunks(SCREEN_WIDTH) {
+    raw.push(0); // フィルタなし
+    raw.extend(row.iter().map(|&shade| SHADES[(shade & 0x03) as usize]));
+  }
+
+  let mut header = Vec::with_capacity(13);
+  header.extend_from_slice(&(SCREEN_WIDTH as u32).to_be_bytes());
+  header.extend_from_slice(&(SCREEN_HEIGHT as u32).to_be_bytes());
+  header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+  let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+  write_chunk(&mut png, b"IHDR", &header);
+  write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+  write_chunk(&mut png, b"IEND", &[]);
+  png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = png.len();
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  let crc = crc32(&png[start..]);
+  png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  let mut out = vec![0x78, 0x01];
+  let mut blocks = data.chunks(0xFFFF).peekable();
+  if blocks.peek().is_none() {
+    out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+  }
+  while let Some(block) = blocks.next() {
+    let last = if blocks.peek().is_none() { 1 } else { 0 };
+    let length = block.len() as u16;
+    out.push(last);
+    out.extend_from_slice(&length.to_le_bytes());
+    out.extend_from_slice(&(!length).to_le_bytes());
+    out.extend_from_slice(block);
+  }
+  out.extend_from_slice(&adler32(data).to_be_bytes());
+  out
+}
+
+// 下位8ビットごとの CRC32 の剰余。1バイトを表引き1回で進める
+static CRC32_TABLE: [u32; 256] = build_crc32_table();
+
+const fn build_crc32_table() -> [u32; 256] {
+  let mut table = [0; 256];
+  let mut index = 0;
+  while index < 256 {
+    let mut crc = index as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+      bit += 1;
+    }
+    table[index] = crc;
+    index += 1;
+  }
+  table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFF_FFFFu32;
+  for &byte in data {
+    crc = (crc >> 8) ^ CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize];
+  }
+  !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let mut a = 1u32;
+  let mut b = 0u32;
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  (b << 16) | a
+}
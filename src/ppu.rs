@@ -0,0 +1,282 @@
+use crate::bus::{LCD_STAT_INTERRUPT, VBLANK_INTERRUPT};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+const DOTS_PER_LINE: u16 = 456;
+const LINES_PER_FRAME: u8 = 154;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+  HBlank = 0,
+  VBlank = 1,
+  OamScan = 2,
+  Drawing = 3,
+}
+
+pub struct PPU {
+  pub vram: [u8; 0x2000],
+  pub oam: [u8; 0xA0],
+  pub lcdc: u8,
+  pub stat: u8,
+  pub scy: u8,
+  pub scx: u8,
+  pub ly: u8,
+  pub lyc: u8,
+  pub bgp: u8,
+  pub obp0: u8,
+  pub obp1: u8,
+  pub wy: u8,
+  pub wx: u8,
+  pub mode: Mode,
+  pub dot: u16,
+  pub window_line: u8,
+  pub stat_line: bool,
+  pub frame_ready: bool,
+  // 設定されている場合、LYの読み出しは常にこの値を返す（Gameboy Doctorとトレースを比較するときに使う）
+  pub ly_override: Option<u8>,
+  // 各ピクセルは0〜3の階調（0が白、3が黒）
+  pub framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl PPU {
+  pub fn new() -> PPU {
+    PPU {
+      vram: [0; 0x2000],
+      oam: [0; 0xA0],
+      lcdc: 0,
+      stat: 0,
+      scy: 0,
+      scx: 0,
+      ly: 0,
+      lyc: 0,
+      bgp: 0,
+      obp0: 0,
+      obp1: 0,
+      wy: 0,
+      wx: 0,
+      mode: Mode::HBlank,
+      dot: 0,
+      window_line: 0,
+      stat_line: false,
+      frame_ready: false,
+      ly_override: None,
+      framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+    }
+  }
+
+  pub fn lcd_enabled(&self) -> bool {
+    self.lcdc & 0x80 != 0
+  }
+
+  pub fn read_register(&self, address: u16) -> u8 {
+    match address {
+      0xFF40 => self.lcdc,
+      0xFF41 => {
+        let coincidence = if self.ly == self.lyc { 0x04 } else { 0 };
+        let mode = if self.lcd_enabled() { self.mode as u8 } else { 0 };
+        0x80 | (self.stat & 0x78) | coincidence | mode
+      },
+      0xFF42 => self.scy,
+      0xFF43 => self.scx,
+      0xFF44 => self.ly_override.unwrap_or(self.ly),
+      0xFF45 => self.lyc,
+      0xFF47 => self.bgp,
+      0xFF48 => self.obp0,
+      0xFF49 => self.obp1,
+      0xFF4A => self.wy,
+      0xFF4B => self.wx,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write_register(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF40 => {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        if was_enabled && !self.lcd_enabled() {
+          self.ly = 0;
+          self.dot = 0;
+          self.window_line = 0;
+          self.mode = Mode::HBlank;
+        } else if !was_enabled && self.lcd_enabled() {
+          self.dot = 0;
+          self.mode = Mode::OamScan;
+        }
+      },
+      0xFF41 => self.stat = value & 0x78,
+      0xFF42 => self.scy = value,
+      0xFF43 => self.scx = value,
+      0xFF44 => {},
+      0xFF45 => self.lyc = value,
+      0xFF47 => self.bgp = value,
+      0xFF48 => self.obp0 = value,
+      0xFF49 => self.obp1 = value,
+      0xFF4A => self.wy = value,
+      0xFF4B => self.wx = value,
+      _ => {},
+    }
+  }
+
+  // 戻り値は要求する割り込みのビット（IFレジスタと同じ並び）
+  pub fn tick(&mut self, cycles: u8) -> u8 {
+    if !self.lcd_enabled() {
+      return 0;
+    }
+
+    let mut interrupts = 0;
+    for _ in 0..cycles {
+      self.dot += 1;
+      match self.mode {
+        Mode::OamScan if self.dot == OAM_SCAN_DOTS => self.mode = Mode::Drawing,
+        Mode::Drawing if self.dot == OAM_SCAN_DOTS + DRAWING_DOTS => {
+          self.render_scanline();
+          self.mode = Mode::HBlank;
+        },
+        _ => {},
+      }
+
+      if self.dot == DOTS_PER_LINE {
+        self.dot = 0;
+        self.ly += 1;
+        if self.ly == SCREEN_HEIGHT as u8 {
+          self.mode = Mode::VBlank;
+          self.frame_ready = true;
+          interrupts |= VBLANK_INTERRUPT;
+        } else if self.ly == LINES_PER_FRAME {
+          self.ly = 0;
+          self.window_line = 0;
+          self.mode = Mode::OamScan;
+        } else if self.ly < SCREEN_HEIGHT as u8 {
+          self.mode = Mode::OamScan;
+        }
+      }
+
+      // STAT割り込みは各条件のORが立ち上がった瞬間にだけ発生する
+      let line = self.stat_condition();
+      if line && !self.stat_line {
+        interrupts |= LCD_STAT_INTERRUPT;
+      }
+      self.stat_line = line;
+    }
+    interrupts
+  }
+
+  fn stat_condition(&self) -> bool {
+    (self.stat & 0x40 != 0 && self.ly == self.lyc)
+      || (self.stat & 0x08 != 0 && self.mode == Mode::HBlank)
+      || (self.stat & 0x10 != 0 && self.mode == Mode::VBlank)
+      || (self.stat & 0x20 != 0 && self.mode == Mode::OamScan)
+  }
+
+  fn tile_pixel(&self, tile_address: u16, x: u8, y: u8) -> u8 {
+    let offset = (tile_address - 0x8000) as usize + y as usize * 2;
+    let low = self.vram[offset];
+    let high = self.vram[offset + 1];
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+  }
+
+  fn bg_tile_address(&self, tile_index: u8) -> u16 {
+    if self.lcdc & 0x10 != 0 {
+      0x8000 + tile_index as u16 * 16
+    } else {
+      (0x9000i32 + (tile_index as i8) as i32 * 16) as u16
+    }
+  }
+
+  fn render_scanline(&mut self) {
+    let ly = self.ly;
+    let row = ly as usize * SCREEN_WIDTH;
+    let mut bg_colors = [0u8; SCREEN_WIDTH];
+
+    let window_visible = self.lcdc & 0x20 != 0 && self.wy <= ly && self.wx <= 166;
+    let mut window_drawn = false;
+
+    if self.lcdc & 0x01 != 0 {
+      for (x, bg_color) in bg_colors.iter_mut().enumerate() {
+        let in_window = window_visible && x as i16 >= self.wx as i16 - 7;
+        let (map_base, map_x, map_y) = if in_window {
+          window_drawn = true;
+          let map = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+          (map, (x as i16 - (self.wx as i16 - 7)) as u8, self.window_line)
+        } else {
+          let map = if self.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+          (map, (x as u8).wrapping_add(self.scx), ly.wrapping_add(self.scy))
+        };
+
+        let map_address = map_base + (map_y as u16 / 8) * 32 + map_x as u16 / 8;
+        let tile_index = self.vram[(map_address - 0x8000) as usize];
+        let color = self.tile_pixel(self.bg_tile_address(tile_index), map_x % 8, map_y % 8);
+        *bg_color = color;
+        self.framebuffer[row + x] = (self.bgp >> (color * 2)) & 0x03;
+      }
+    } else {
+      self.framebuffer[row..row + SCREEN_WIDTH].fill(0);
+    }
+
+    if window_drawn {
+      self.window_line += 1;
+    }
+
+    if self.lcdc & 0x02 != 0 {
+      self.render_sprites(ly, row, &bg_colors);
+    }
+  }
+
+  fn render_sprites(&mut self, ly: u8, row: usize, bg_colors: &[u8; SCREEN_WIDTH]) {
+    let height: i16 = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+
+    // 1ラインに表示できるスプライトはOAMの先頭から10個まで
+    let mut sprites: Vec<usize> = (0..40)
+      .filter(|&index| {
+        let top = self.oam[index * 4] as i16 - 16;
+        (top..top + height).contains(&(ly as i16))
+      })
+      .take(10)
+      .collect();
+
+    // X座標が小さいものが優先、同じならOAMで先にあるものが優先。優先度の低い順に描画する
+    sprites.sort_by_key(|&index| (self.oam[index * 4 + 1], index));
+    for &index in sprites.iter().rev() {
+      let top = self.oam[index * 4] as i16 - 16;
+      let left = self.oam[index * 4 + 1] as i16 - 8;
+      let mut tile = self.oam[index * 4 + 2];
+      let attributes = self.oam[index * 4 + 3];
+
+      let mut line = ly as i16 - top;
+      if attributes & 0x40 != 0 {
+        line = height - 1 - line;
+      }
+      if height == 16 {
+        tile = (tile & 0xFE) + (line / 8) as u8;
+      }
+      let palette = if attributes & 0x10 != 0 { self.obp1 } else { self.obp0 };
+
+      for pixel in 0..8 {
+        let x = left + pixel;
+        if !(0..SCREEN_WIDTH as i16).contains(&x) {
+          continue;
+        }
+        let tile_x = if attributes & 0x20 != 0 { 7 - pixel } else { pixel };
+        let color = self.tile_pixel(0x8000 + tile as u16 * 16, tile_x as u8, (line % 8) as u8);
+        if color == 0 {
+          continue;
+        }
+        if attributes & 0x80 != 0 && bg_colors[x as usize] != 0 {
+          continue;
+        }
+        self.framebuffer[row + x as usize] = (palette >> (color * 2)) & 0x03;
+      }
+    }
+  }
+}
+
+impl Default for PPU {
+  fn default() -> Self {
+    PPU::new()
+  }
+}
@@ -32,7 +32,7 @@ impl Registers {
   }
 
   pub fn get_af(&self) -> u16 {
-    (self.a as u16) << 8 | u8::from(self.f.clone()) as u16
+    (self.a as u16) << 8 | u8::from(self.f) as u16
   }
 
   pub fn set_af(&mut self, value: u16) {
@@ -92,6 +92,12 @@ impl Registers {
   }
 }
 
+impl Default for Registers {
+  fn default() -> Self {
+    Registers::new()
+  }
+}
+
 const ZERO_FLAG_BYTE_POSITION: u8 = 7;
 const SUBTRACT_FLAG_BYTE_POSITION: u8 = 6;
 const HALF_CARRY_FLAG_BYTE_POSITION: u8 = 5;
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+
+use crate::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use crate::savestate::SaveStateError;
+
+// 1秒あたりのTサイクル数
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+// キーフレームを取り直す間隔（スナップショットの数）
+const DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+
+// キーフレームと、それに対する差分の並び
+struct Group {
+  // 状態をそのまま圧縮したもの
+  keyframe: Vec<u8>,
+  // 元の状態の長さ
+  length: usize,
+  // キーフレームとの XOR を圧縮したもの
+  deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+  fn len(&self) -> usize {
+    1 + self.deltas.len()
+  }
+}
+
+// 巻き戻し用に、毎フレームの状態を古いものから捨てながら覚えておくリングバッファ。
+// 状態はキーフレームとの XOR を取ってからランレングス圧縮するので、変化の少ないフレームはほとんどメモリを使わない
+pub struct Rewind {
+  capacity: usize,
+  keyframe_interval: usize,
+  groups: VecDeque<Group>,
+}
+
+impl Rewind {
+  // seconds 秒分のフレームを覚えておく
+  pub fn new(seconds: u32) -> Rewind {
+    let frames = (seconds as u64 * CYCLES_PER_SECOND).div_ceil(CYCLES_PER_FRAME) as usize;
+    Rewind::with_capacity(frames)
+  }
+
+  // frames 個のスナップショットを覚えておく
+  pub fn with_capacity(frames: usize) -> Rewind {
+    Rewind { capacity: frames.max(1), keyframe_interval: DEFAULT_KEYFRAME_INTERVAL, groups: VecDeque::new() }
+  }
+
+  // 差分をいくつごとにキーフレームを取り直すか。大きくするとメモリは減るが、1つ戻るのに時間がかかる
+  pub fn with_keyframe_interval(mut self, interval: usize) -> Rewind {
+    self.keyframe_interval = interval.max(1);
+    self
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn len(&self) -> usize {
+    self.groups.iter().map(Group::len).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.groups.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.groups.clear();
+  }
+
+  // 圧縮後のスナップショットが使っているバイト数
+  pub fn memory_usage(&self) -> usize {
+    self
+      .groups
+      .iter()
+      .map(|group| group.keyframe.len() + group.deltas.iter().map(Vec::len).sum::<usize>())
+      .sum()
+  }
+
+  // 今の状態を覚える。フレームの頭（run_frame の前）に1回ずつ呼ぶ
+  pub fn push(&mut self, gameboy: &GameBoy) {
+    let state = gameboy.save_state();
+    match self.groups.back_mut() {
+      // ブートROMが外れたときなどは状態の長さが変わるので、キーフレームを取り直す
+      Some(group) if group.len() < self.keyframe_interval && group.length == state.len() => {
+        let keyframe = decompress(&group.keyframe, group.length);
+        let delta: Vec<u8> = state.iter().zip(&keyframe).map(|(a, b)| a ^ b).collect();
+        group.deltas.push(compress(&delta));
+      },
+      _ => self.groups.push_back(Group { keyframe: compress(&state), length: state.len(), deltas: Vec::new() }),
+    }
+
+    // 残りのグループで capacity 個を満たせる間は、一番古いグループを丸ごと捨てる
+    let mut count = self.len();
+    while let Some(front) = self.groups.front()
+      && count - front.len() >= self.capacity
+    {
+      count -= front.len();
+      self.groups.pop_front();
+    }
+  }
+
+  // 最後に覚えた状態を取り出して戻す。覚えている状態がなければ false
+  pub fn step_back(&mut self, gameboy: &mut GameBoy) -> Result<bool, SaveStateError> {
+    let Some(state) = self.pop() else {
+      return Ok(false);
+    };
+    gameboy.load_state(&state)?;
+    Ok(true)
+  }
+
+  // 最後に覚えた状態を取り出す
+  pub fn pop(&mut self) -> Option<Vec<u8>> {
+    let group = self.groups.back_mut()?;
+    let keyframe = decompress(&group.keyframe, group.length);
+    let state = match group.deltas.pop() {
+      Some(delta) => decompress(&delta, group.length).iter().zip(&keyframe).map(|(a, b)| a ^ b).collect(),
+      None => {
+        self.groups.pop_back();
+        keyframe
+      },
+    };
+    Some(state)
+  }
+}
+
+// 0 の並びと、そのあとに続く 0 以外のバイトの並びを交互に書く。
+// [0 の個数][そのままのバイトの個数][バイト…] の繰り返しで、個数は LEB128
+pub fn compress(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut position = 0;
+  while position < data.len() {
+    let zeros = data[position..].iter().take_while(|&&byte| byte == 0).count();
+    position += zeros;
+    let literal = data[position..].iter().take_while(|&&byte| byte != 0).count();
+    write_varint(&mut out, zeros);
+    write_varint(&mut out, literal);
+    out.extend_from_slice(&data[position..position + literal]);
+    position += literal;
+  }
+  out
+}
+
+// compress したものを元の長さ length に戻す
+pub fn decompress(data: &[u8], length: usize) -> Vec<u8> {
+  let mut out = Vec::with_capacity(length);
+  let mut position = 0;
+  while position < data.len() {
+    let zeros = read_varint(data, &mut position);
+    let literal = read_varint(data, &mut position);
+    out.resize(out.len() + zeros, 0);
+    out.extend_from_slice(&data[position..position + literal]);
+    position += literal;
+  }
+  out.resize(length, 0);
+  out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+  while value >= 0x80 {
+    out.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+  let mut value = 0;
+  let mut shift = 0;
+  loop {
+    let byte = data[*position];
+    *position += 1;
+    value |= ((byte & 0x7F) as usize) << shift;
+    if byte & 0x80 == 0 {
+      return value;
+    }
+    shift += 7;
+  }
+}
@@ -0,0 +1,577 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::apu::{Envelope, NoiseChannel, SquareChannel, WaveChannel, APU};
+use crate::bess::{self, Layout};
+use crate::cartridge::{Cartridge, Rtc};
+use crate::gameboy::{GameBoy, Model};
+use crate::png::crc32;
+use crate::ppu::{Mode, PPU};
+
+// ファイルの先頭に置く目印
+pub const MAGIC: [u8; 8] = *b"GBSTATE\0";
+// 既存のセクションの中身を変えたときだけ上げる。セクションを足すだけなら上げない
+// （古い版の読み込み側は知らないセクションを読み飛ばす）
+pub const VERSION: u16 = 1;
+
+// 読み込みに必要なセクション。どれかが欠けていたら読み込まない
+const REQUIRED_SECTIONS: [[u8; 4]; 7] = [*b"SYS ", *b"CPU ", *b"MEM ", *b"CART", *b"PPU ", *b"APU ", *b"TIMR"];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SaveStateError {
+  NotASaveState,
+  UnsupportedVersion(u16),
+  // セーブしたときのROMと今のROMの CRC32 とタイトル
+  RomMismatch { saved: u32, saved_title: String, current: u32 },
+  // BESS の INFO ブロックのタイトルとグローバルチェックサム（ROMの 0x14E〜0x14F）が今のROMと違う
+  GlobalChecksumMismatch { saved: u16, saved_title: String, current: u16 },
+  Truncated,
+  ChecksumMismatch([u8; 4]),
+  MissingSection([u8; 4]),
+  InvalidSection([u8; 4], String),
+}
+
+impl fmt::Display for SaveStateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SaveStateError::NotASaveState => write!(f, "not a save state"),
+      SaveStateError::UnsupportedVersion(version) => {
+        write!(f, "unsupported save state version {} (this build reads version {})", version, VERSION)
+      },
+      SaveStateError::RomMismatch { saved, saved_title, current } => write!(
+        f,
+        "save state belongs to another ROM (\"{}\", CRC32 {:08X}; loaded ROM has CRC32 {:08X})",
+        saved_title, saved, current
+      ),
+      SaveStateError::GlobalChecksumMismatch { saved, saved_title, current } => write!(
+        f,
+        "save state belongs to another ROM (\"{}\", global checksum {:04X}; loaded ROM has {:04X})",
+        saved_title, saved, current
+      ),
+      SaveStateError::Truncated => write!(f, "save state is truncated"),
+      SaveStateError::ChecksumMismatch(tag) => write!(f, "checksum mismatch in section {}", tag_name(tag)),
+      SaveStateError::MissingSection(tag) => write!(f, "missing section {}", tag_name(tag)),
+      SaveStateError::InvalidSection(tag, message) => write!(f, "invalid section {}: {}", tag_name(tag), message),
+    }
+  }
+}
+
+impl Error for SaveStateError {}
+
+// (タグ, 中身)
+type Sections<'a> = Vec<([u8; 4], &'a [u8])>;
+
+fn tag_name(tag: &[u8; 4]) -> String {
+  String::from_utf8_lossy(tag).trim_end().to_string()
+}
+
+// マシン全体の状態を書き出す。
+// ヘッダ（MAGIC、版、ROMの CRC32 とタイトル）の後に、[タグ4バイト][長さ u32][中身][中身の CRC32] のセクションが並ぶ。数値はすべてリトルエンディアン。
+// 最後に、ほかのエミュレータでも読める BESS のフッタを付ける
+pub fn save(gameboy: &GameBoy) -> Vec<u8> {
+  let cartridge = cartridge(gameboy);
+  let mut data = MAGIC.to_vec();
+  data.extend_from_slice(&VERSION.to_le_bytes());
+  data.extend_from_slice(&cartridge.rom_crc32.to_le_bytes());
+  let title = cartridge.title();
+  data.push(title.len() as u8);
+  data.extend_from_slice(title.as_bytes());
+
+  write_section(&mut data, b"SYS ", |out| write_system(out, gameboy));
+  write_section(&mut data, b"CPU ", |out| write_cpu(out, gameboy));
+  let memory = write_section(&mut data, b"MEM ", |out| write_memory(out, gameboy));
+  let cartridge_section = write_section(&mut data, b"CART", |out| write_cartridge(out, cartridge));
+  let ppu = write_section(&mut data, b"PPU ", |out| write_ppu(out, &gameboy.cpu.bus.ppu));
+  write_section(&mut data, b"APU ", |out| write_apu(out, &gameboy.cpu.bus.apu));
+  write_section(&mut data, b"TIMR", |out| write_timer(out, gameboy));
+  write_section(&mut data, b"SERL", |out| write_serial(out, gameboy));
+
+  let layout = Layout { memory, cartridge_ram: cartridge_section + CARTRIDGE_RAM_OFFSET, ppu };
+  bess::write_footer(&mut data, gameboy, &layout);
+  data
+}
+
+// save で書き出した状態に戻す。先頭が MAGIC でなく BESS のフッタだけがあるもの（ほかのエミュレータのステート）は BESS として読む。
+// エラーのときは何も変えない
+pub fn load(gameboy: &mut GameBoy, data: &[u8]) -> Result<(), SaveStateError> {
+  let backup = save(gameboy);
+  let result = if data.starts_with(&MAGIC) || bess::footer_start(data).is_none() {
+    parse(data, cartridge(gameboy).rom_crc32).and_then(|sections| apply(gameboy, &sections))
+  } else {
+    bess::load(gameboy, data)
+  };
+  if result.is_err() {
+    // 自分で書き出した状態なので読み込みに失敗することはない
+    let sections = parse(&backup, cartridge(gameboy).rom_crc32).expect("backup state is valid");
+    apply(gameboy, &sections).expect("backup state is valid");
+  }
+  result
+}
+
+fn cartridge(gameboy: &GameBoy) -> &Cartridge {
+  gameboy.cpu.bus.cartridge.as_ref().expect("GameBoy always has a cartridge")
+}
+
+// ヘッダを確かめ、チェックサムの合ったセクションを (タグ, 中身) の一覧にする
+fn parse(data: &[u8], rom_crc: u32) -> Result<Sections<'_>, SaveStateError> {
+  let mut reader = Reader::new(data);
+  if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+    return Err(SaveStateError::NotASaveState);
+  }
+  let version = reader.u16()?;
+  if version != VERSION {
+    return Err(SaveStateError::UnsupportedVersion(version));
+  }
+  let saved = reader.u32()?;
+  let title_length = reader.u8()? as usize;
+  let saved_title = String::from_utf8_lossy(reader.bytes(title_length)?).to_string();
+  if saved != rom_crc {
+    return Err(SaveStateError::RomMismatch { saved, saved_title, current: rom_crc });
+  }
+
+  // BESS のフッタはセクションに含めない
+  if let Some(start) = bess::footer_start(data) {
+    reader.data = &data[..start];
+  }
+  let mut sections = Vec::new();
+  while !reader.is_empty() {
+    let tag: [u8; 4] = reader.bytes(4)?.try_into().unwrap();
+    let length = reader.u32()? as usize;
+    let body = reader.bytes(length)?;
+    if reader.u32()? != crc32(body) {
+      return Err(SaveStateError::ChecksumMismatch(tag));
+    }
+    sections.push((tag, body));
+  }
+  for tag in REQUIRED_SECTIONS {
+    if !sections.iter().any(|(found, _)| *found == tag) {
+      return Err(SaveStateError::MissingSection(tag));
+    }
+  }
+  Ok(sections)
+}
+
+fn apply(gameboy: &mut GameBoy, sections: &Sections) -> Result<(), SaveStateError> {
+  for &(tag, body) in sections {
+    let mut reader = Reader::new(body);
+    let result = match &tag {
+      b"SYS " => read_system(&mut reader, gameboy),
+      b"CPU " => read_cpu(&mut reader, gameboy),
+      b"MEM " => read_memory(&mut reader, gameboy),
+      b"CART" => read_cartridge(&mut reader, gameboy.cpu.bus.cartridge.as_mut().expect("GameBoy always has a cartridge")),
+      b"PPU " => read_ppu(&mut reader, &mut gameboy.cpu.bus.ppu),
+      b"APU " => read_apu(&mut reader, &mut gameboy.cpu.bus.apu),
+      b"TIMR" => read_timer(&mut reader, gameboy),
+      b"SERL" => read_serial(&mut reader, gameboy),
+      // 新しい版で足されたセクション
+      _ => Ok(()),
+    };
+    // セクションの末尾に足された項目も読み飛ばせるよう、余りは気にしない
+    result.map_err(|error| match error {
+      SaveStateError::Truncated => SaveStateError::InvalidSection(tag, "section is too short".to_string()),
+      SaveStateError::InvalidSection(_, message) => SaveStateError::InvalidSection(tag, message),
+      other => other,
+    })?;
+  }
+  Ok(())
+}
+
+// 中身の位置を返す
+fn write_section<F: FnOnce(&mut Vec<u8>)>(data: &mut Vec<u8>, tag: &[u8; 4], write: F) -> usize {
+  let mut body = Vec::new();
+  write(&mut body);
+  data.extend_from_slice(tag);
+  data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+  let start = data.len();
+  data.extend_from_slice(&body);
+  data.extend_from_slice(&crc32(&body).to_le_bytes());
+  start
+}
+
+fn invalid(message: &str) -> SaveStateError {
+  // タグは apply で埋める
+  SaveStateError::InvalidSection(*b"    ", message.to_string())
+}
+
+fn write_system(out: &mut Vec<u8>, gameboy: &GameBoy) {
+  out.push(match gameboy.model {
+    Model::Dmg => 0,
+    Model::Cgb => 1,
+  });
+  out.extend_from_slice(&gameboy.cycles.to_le_bytes());
+}
+
+fn read_system(reader: &mut Reader, gameboy: &mut GameBoy) -> Result<(), SaveStateError> {
+  gameboy.model = match reader.u8()? {
+    0 => Model::Dmg,
+    1 => Model::Cgb,
+    _ => return Err(invalid("unknown model")),
+  };
+  gameboy.cycles = reader.u64()?;
+  Ok(())
+}
+
+fn write_cpu(out: &mut Vec<u8>, gameboy: &GameBoy) {
+  let cpu = &gameboy.cpu;
+  let registers = &cpu.registers;
+  out.extend_from_slice(&[
+    registers.a, u8::from(registers.f), registers.b, registers.c,
+    registers.d, registers.e, registers.h, registers.l,
+  ]);
+  out.extend_from_slice(&cpu.sp.to_le_bytes());
+  out.extend_from_slice(&cpu.pc.to_le_bytes());
+  out.extend_from_slice(&[cpu.ime as u8, cpu.ime_scheduled as u8, cpu.halted as u8]);
+  out.extend_from_slice(&cpu.cycles.to_le_bytes());
+}
+
+fn read_cpu(reader: &mut Reader, gameboy: &mut GameBoy) -> Result<(), SaveStateError> {
+  let cpu = &mut gameboy.cpu;
+  cpu.registers.a = reader.u8()?;
+  cpu.registers.f = reader.u8()?.into();
+  cpu.registers.b = reader.u8()?;
+  cpu.registers.c = reader.u8()?;
+  cpu.registers.d = reader.u8()?;
+  cpu.registers.e = reader.u8()?;
+  cpu.registers.h = reader.u8()?;
+  cpu.registers.l = reader.u8()?;
+  cpu.sp = reader.u16()?;
+  cpu.pc = reader.u16()?;
+  cpu.ime = reader.bool()?;
+  cpu.ime_scheduled = reader.bool()?;
+  cpu.halted = reader.bool()?;
+  cpu.cycles = reader.u64()?;
+  // 固まった状態は保存しない。PC が未定義のオペコードを指していれば、次の命令でまた固まる
+  cpu.illegal_opcode = None;
+  // 影のコールスタックはエミュレートしている状態ではないので保存しない
+  cpu.call_stack.clear();
+  Ok(())
+}
+
+// カートリッジと周辺機器以外のメモリ、IF、ブートROM
+fn write_memory(out: &mut Vec<u8>, gameboy: &GameBoy) {
+  let bus = &gameboy.cpu.bus;
+  out.extend_from_slice(bus.memory());
+  out.push(bus.interrupt_flag);
+  match &bus.boot_rom {
+    Some(boot_rom) => {
+      out.push(1);
+      out.extend_from_slice(&(boot_rom.len() as u32).to_le_bytes());
+      out.extend_from_slice(boot_rom);
+    },
+    None => out.push(0),
+  }
+}
+
+fn read_memory(reader: &mut Reader, gameboy: &mut GameBoy) -> Result<(), SaveStateError> {
+  let bus = &mut gameboy.cpu.bus;
+  bus.memory_mut().copy_from_slice(reader.bytes(0x10000)?);
+  bus.interrupt_flag = reader.u8()?;
+  bus.boot_rom = if reader.bool()? {
+    let length = reader.u32()? as usize;
+    Some(reader.bytes(length)?.to_vec())
+  } else {
+    None
+  };
+  Ok(())
+}
+
+// CART セクションの中で外部RAMが始まる位置（RAM有効、ROMバンク、RAMバンク、バンキングモード、RAMの大きさの後）
+const CARTRIDGE_RAM_OFFSET: usize = 9;
+
+fn write_cartridge(out: &mut Vec<u8>, cartridge: &Cartridge) {
+  out.push(cartridge.ram_enabled as u8);
+  out.extend_from_slice(&cartridge.rom_bank.to_le_bytes());
+  out.push(cartridge.ram_bank);
+  out.push(cartridge.banking_mode);
+  out.extend_from_slice(&(cartridge.ram.len() as u32).to_le_bytes());
+  out.extend_from_slice(&cartridge.ram);
+  match &cartridge.rtc {
+    Some(rtc) => {
+      out.push(1);
+      out.extend_from_slice(&[rtc.seconds, rtc.minutes, rtc.hours]);
+      out.extend_from_slice(&rtc.days.to_le_bytes());
+      out.extend_from_slice(&[rtc.halted as u8, rtc.day_carry as u8]);
+      out.extend_from_slice(&rtc.latched);
+      out.push(rtc.latch_armed as u8);
+      out.extend_from_slice(&rtc.sub_cycles.to_le_bytes());
+    },
+    None => out.push(0),
+  }
+}
+
+fn read_cartridge(reader: &mut Reader, cartridge: &mut Cartridge) -> Result<(), SaveStateError> {
+  cartridge.ram_enabled = reader.bool()?;
+  cartridge.rom_bank = reader.u16()?;
+  cartridge.ram_bank = reader.u8()?;
+  cartridge.banking_mode = reader.u8()?;
+  let length = reader.u32()? as usize;
+  if length != cartridge.ram.len() {
+    return Err(invalid("cartridge RAM size does not match"));
+  }
+  cartridge.ram.copy_from_slice(reader.bytes(length)?);
+  let has_rtc = reader.bool()?;
+  if has_rtc != cartridge.rtc.is_some() {
+    return Err(invalid("RTC presence does not match"));
+  }
+  if let Some(rtc) = &mut cartridge.rtc {
+    *rtc = Rtc {
+      seconds: reader.u8()?,
+      minutes: reader.u8()?,
+      hours: reader.u8()?,
+      days: reader.u16()?,
+      halted: reader.bool()?,
+      day_carry: reader.bool()?,
+      latched: reader.bytes(5)?.try_into().unwrap(),
+      latch_armed: reader.bool()?,
+      sub_cycles: reader.u32()?,
+    };
+  }
+  Ok(())
+}
+
+fn write_ppu(out: &mut Vec<u8>, ppu: &PPU) {
+  out.extend_from_slice(&ppu.vram);
+  out.extend_from_slice(&ppu.oam);
+  out.extend_from_slice(&[
+    ppu.lcdc, ppu.stat, ppu.scy, ppu.scx, ppu.ly, ppu.lyc,
+    ppu.bgp, ppu.obp0, ppu.obp1, ppu.wy, ppu.wx, ppu.mode as u8,
+  ]);
+  out.extend_from_slice(&ppu.dot.to_le_bytes());
+  out.extend_from_slice(&[ppu.window_line, ppu.stat_line as u8, ppu.frame_ready as u8]);
+  out.extend_from_slice(&ppu.framebuffer);
+}
+
+fn read_ppu(reader: &mut Reader, ppu: &mut PPU) -> Result<(), SaveStateError> {
+  reader.fill(&mut ppu.vram)?;
+  reader.fill(&mut ppu.oam)?;
+  ppu.lcdc = reader.u8()?;
+  ppu.stat = reader.u8()?;
+  ppu.scy = reader.u8()?;
+  ppu.scx = reader.u8()?;
+  ppu.ly = reader.u8()?;
+  ppu.lyc = reader.u8()?;
+  ppu.bgp = reader.u8()?;
+  ppu.obp0 = reader.u8()?;
+  ppu.obp1 = reader.u8()?;
+  ppu.wy = reader.u8()?;
+  ppu.wx = reader.u8()?;
+  ppu.mode = match reader.u8()? {
+    0 => Mode::HBlank,
+    1 => Mode::VBlank,
+    2 => Mode::OamScan,
+    3 => Mode::Drawing,
+    _ => return Err(invalid("unknown PPU mode")),
+  };
+  ppu.dot = reader.u16()?;
+  ppu.window_line = reader.u8()?;
+  ppu.stat_line = reader.bool()?;
+  ppu.frame_ready = reader.bool()?;
+  reader.fill(&mut ppu.framebuffer)?;
+  Ok(())
+}
+
+fn write_envelope(out: &mut Vec<u8>, envelope: &Envelope) {
+  out.extend_from_slice(&[
+    envelope.initial_volume, envelope.increase as u8, envelope.period, envelope.timer, envelope.volume,
+  ]);
+}
+
+fn read_envelope(reader: &mut Reader) -> Result<Envelope, SaveStateError> {
+  Ok(Envelope {
+    initial_volume: reader.u8()?,
+    increase: reader.bool()?,
+    period: reader.u8()?,
+    timer: reader.u8()?,
+    volume: reader.u8()?,
+  })
+}
+
+fn write_square(out: &mut Vec<u8>, channel: &SquareChannel) {
+  out.extend_from_slice(&[channel.enabled as u8, channel.dac_enabled as u8]);
+  out.extend_from_slice(&channel.length.to_le_bytes());
+  out.extend_from_slice(&[channel.length_enabled as u8, channel.duty, channel.duty_step]);
+  out.extend_from_slice(&channel.frequency.to_le_bytes());
+  out.extend_from_slice(&channel.timer.to_le_bytes());
+  write_envelope(out, &channel.envelope);
+  out.extend_from_slice(&[
+    channel.sweep_period, channel.sweep_negate as u8, channel.sweep_shift,
+    channel.sweep_timer, channel.sweep_enabled as u8,
+  ]);
+  out.extend_from_slice(&channel.shadow_frequency.to_le_bytes());
+}
+
+fn read_square(reader: &mut Reader) -> Result<SquareChannel, SaveStateError> {
+  Ok(SquareChannel {
+    enabled: reader.bool()?,
+    dac_enabled: reader.bool()?,
+    length: reader.u16()?,
+    length_enabled: reader.bool()?,
+    duty: reader.u8()?,
+    duty_step: reader.u8()?,
+    frequency: reader.u16()?,
+    timer: reader.u16()?,
+    envelope: read_envelope(reader)?,
+    sweep_period: reader.u8()?,
+    sweep_negate: reader.bool()?,
+    sweep_shift: reader.u8()?,
+    sweep_timer: reader.u8()?,
+    sweep_enabled: reader.bool()?,
+    shadow_frequency: reader.u16()?,
+  })
+}
+
+fn write_wave(out: &mut Vec<u8>, channel: &WaveChannel) {
+  out.extend_from_slice(&[channel.enabled as u8, channel.dac_enabled as u8]);
+  out.extend_from_slice(&channel.length.to_le_bytes());
+  out.extend_from_slice(&[channel.length_enabled as u8, channel.volume_code]);
+  out.extend_from_slice(&channel.frequency.to_le_bytes());
+  out.extend_from_slice(&channel.timer.to_le_bytes());
+  out.push(channel.position);
+}
+
+fn read_wave(reader: &mut Reader) -> Result<WaveChannel, SaveStateError> {
+  Ok(WaveChannel {
+    enabled: reader.bool()?,
+    dac_enabled: reader.bool()?,
+    length: reader.u16()?,
+    length_enabled: reader.bool()?,
+    volume_code: reader.u8()?,
+    frequency: reader.u16()?,
+    timer: reader.u16()?,
+    position: reader.u8()?,
+  })
+}
+
+fn write_noise(out: &mut Vec<u8>, channel: &NoiseChannel) {
+  out.extend_from_slice(&[channel.enabled as u8, channel.dac_enabled as u8]);
+  out.extend_from_slice(&channel.length.to_le_bytes());
+  out.push(channel.length_enabled as u8);
+  write_envelope(out, &channel.envelope);
+  out.extend_from_slice(&[channel.clock_shift, channel.width_mode as u8, channel.divisor_code]);
+  out.extend_from_slice(&channel.timer.to_le_bytes());
+  out.extend_from_slice(&channel.lfsr.to_le_bytes());
+}
+
+fn read_noise(reader: &mut Reader) -> Result<NoiseChannel, SaveStateError> {
+  Ok(NoiseChannel {
+    enabled: reader.bool()?,
+    dac_enabled: reader.bool()?,
+    length: reader.u16()?,
+    length_enabled: reader.bool()?,
+    envelope: read_envelope(reader)?,
+    clock_shift: reader.u8()?,
+    width_mode: reader.bool()?,
+    divisor_code: reader.u8()?,
+    timer: reader.u32()?,
+    lfsr: reader.u16()?,
+  })
+}
+
+// まだ取り出されていないサンプルは状態に含めない
+fn write_apu(out: &mut Vec<u8>, apu: &APU) {
+  out.extend_from_slice(&apu.registers);
+  out.extend_from_slice(&apu.wave_ram);
+  out.push(apu.enabled as u8);
+  write_square(out, &apu.square1);
+  write_square(out, &apu.square2);
+  write_wave(out, &apu.wave);
+  write_noise(out, &apu.noise);
+  out.push(apu.frame_sequencer_step);
+  out.extend_from_slice(&apu.frame_sequencer_timer.to_le_bytes());
+  out.extend_from_slice(&apu.sample_timer.to_le_bytes());
+}
+
+fn read_apu(reader: &mut Reader, apu: &mut APU) -> Result<(), SaveStateError> {
+  reader.fill(&mut apu.registers)?;
+  reader.fill(&mut apu.wave_ram)?;
+  apu.enabled = reader.bool()?;
+  apu.square1 = read_square(reader)?;
+  apu.square2 = read_square(reader)?;
+  apu.wave = read_wave(reader)?;
+  apu.noise = read_noise(reader)?;
+  apu.frame_sequencer_step = reader.u8()?;
+  apu.frame_sequencer_timer = reader.u16()?;
+  apu.sample_timer = reader.u32()?;
+  Ok(())
+}
+
+// タイマーとジョイパッド
+fn write_timer(out: &mut Vec<u8>, gameboy: &GameBoy) {
+  let bus = &gameboy.cpu.bus;
+  out.extend_from_slice(&bus.timer.counter.to_le_bytes());
+  out.extend_from_slice(&[bus.timer.tima, bus.timer.tma, bus.timer.tac]);
+  out.extend_from_slice(&[bus.joypad.pressed, bus.joypad.select]);
+}
+
+fn read_timer(reader: &mut Reader, gameboy: &mut GameBoy) -> Result<(), SaveStateError> {
+  let bus = &mut gameboy.cpu.bus;
+  bus.timer.counter = reader.u16()?;
+  bus.timer.tima = reader.u8()?;
+  bus.timer.tma = reader.u8()?;
+  bus.timer.tac = reader.u8()?;
+  bus.joypad.pressed = reader.u8()?;
+  bus.joypad.select = reader.u8()?;
+  Ok(())
+}
+
+// シリアル。必須のセクションではないので、これがないステートでは読み込み前の状態のまま
+fn write_serial(out: &mut Vec<u8>, gameboy: &GameBoy) {
+  let serial = &gameboy.cpu.bus.serial;
+  out.extend_from_slice(&[serial.data, serial.control]);
+  out.extend_from_slice(&serial.remaining.to_le_bytes());
+}
+
+fn read_serial(reader: &mut Reader, gameboy: &mut GameBoy) -> Result<(), SaveStateError> {
+  let serial = &mut gameboy.cpu.bus.serial;
+  serial.data = reader.u8()?;
+  serial.control = reader.u8()?;
+  serial.remaining = reader.u16()?;
+  Ok(())
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8]) -> Reader<'a> {
+    Reader { data, position: 0 }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.position >= self.data.len()
+  }
+
+  fn bytes(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+    let end = self.position.checked_add(length).filter(|&end| end <= self.data.len()).ok_or(SaveStateError::Truncated)?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  fn fill(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+    buffer.copy_from_slice(self.bytes(buffer.len())?);
+    Ok(())
+  }
+
+  fn u8(&mut self) -> Result<u8, SaveStateError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn bool(&mut self) -> Result<bool, SaveStateError> {
+    Ok(self.u8()? != 0)
+  }
+
+  fn u16(&mut self) -> Result<u16, SaveStateError> {
+    Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, SaveStateError> {
+    Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, SaveStateError> {
+    Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+  }
+}
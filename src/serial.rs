@@ -0,0 +1,67 @@
+// 1バイトの転送にかかるTサイクル数（内部クロック 8192Hz で8ビット）
+const TRANSFER_CYCLES: u16 = 4096;
+
+// シリアル通信 (SB 0xFF01, SC 0xFF02)。通信ケーブルの先には何もつながっていないものとして扱い、
+// 受け取るバイトは常に 0xFF になる
+pub struct Serial {
+  pub data: u8,
+  pub control: u8,
+  // 転送が終わるまでの残りTサイクル。0なら転送中でない
+  pub remaining: u16,
+  // 送り出したバイト。テストROMは結果をシリアルに書き出すので、それを読むのに使う
+  pub output: Vec<u8>,
+}
+
+impl Serial {
+  pub fn new() -> Serial {
+    Serial {
+      data: 0,
+      control: 0,
+      remaining: 0,
+      output: Vec::new(),
+    }
+  }
+
+  pub fn read_byte(&self, address: u16) -> u8 {
+    match address {
+      0xFF01 => self.data,
+      0xFF02 => self.control | 0x7E,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write_byte(&mut self, address: u16, value: u8) {
+    match address {
+      0xFF01 => self.data = value,
+      0xFF02 => {
+        self.control = value & 0x81;
+        // 外部クロックを選んだときは相手がクロックを送ってこないので、転送は終わらない
+        if self.control == 0x81 {
+          self.output.push(self.data);
+          self.remaining = TRANSFER_CYCLES;
+        }
+      },
+      _ => {},
+    }
+  }
+
+  // 戻り値はシリアル割り込みを要求するかどうか
+  pub fn tick(&mut self, cycles: u8) -> bool {
+    if self.remaining == 0 {
+      return false;
+    }
+    self.remaining = self.remaining.saturating_sub(cycles as u16);
+    if self.remaining > 0 {
+      return false;
+    }
+    self.data = 0xFF;
+    self.control &= 0x7F;
+    true
+  }
+}
+
+impl Default for Serial {
+  fn default() -> Self {
+    Serial::new()
+  }
+}
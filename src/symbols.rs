@@ -0,0 +1,185 @@
+use std::error::Error;
+use std::fmt;
+
+// RGBDS の .sym ファイルの1行（"01:4000 Main.loop"）
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Symbol {
+  pub bank: usize,
+  pub address: u16,
+  pub name: String,
+}
+
+// RGBDS の .map ファイルの SECTION 1つ分
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+  // ROM0、ROMX、WRAM0 など（古い rgblink では ROM、WRAM など）
+  pub region: String,
+  pub bank: usize,
+  pub start: u16,
+  // 最後のアドレス（この番地を含む）
+  pub end: u16,
+  pub name: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SymbolError {
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for SymbolError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl Error for SymbolError {}
+
+// ラベルとセクションの一覧。
+// bank を取るメソッドでは、0x4000〜0x7FFF のアドレスだけ bank が一致するラベルを探す。
+// それ以外のアドレスはバンクを区別しない（ROM0 はいつもバンク0、RAM のバンクはまだ追っていない）
+#[derive(Clone, Default, Debug)]
+pub struct Symbols {
+  // アドレス順
+  symbols: Vec<Symbol>,
+  sections: Vec<Section>,
+}
+
+impl Symbols {
+  pub fn new() -> Symbols {
+    Symbols::default()
+  }
+
+  // .sym ファイルを読む。";" から行末まではコメント
+  pub fn parse_sym(text: &str) -> Result<Symbols, SymbolError> {
+    let mut symbols = Symbols::new();
+    for (index, line) in text.lines().enumerate() {
+      let line = line.split(';').next().unwrap_or_default().trim();
+      if line.is_empty() {
+        continue;
+      }
+      let error = |message: String| SymbolError { line: index + 1, message };
+      let (location, name) = line.split_once(char::is_whitespace).ok_or_else(|| error(format!("expected 'bank:address name': {}", line)))?;
+      let (bank, address) = location.split_once(':').ok_or_else(|| error(format!("expected bank:address: {}", location)))?;
+      let bank = usize::from_str_radix(bank, 16).map_err(|_| error(format!("invalid bank: {}", bank)))?;
+      let address = u16::from_str_radix(address, 16).map_err(|_| error(format!("invalid address: {}", address)))?;
+      symbols.add(bank, address, name.trim());
+    }
+    Ok(symbols)
+  }
+
+  // .map ファイルから SECTION を読み込む。"$0150 = Main" の行は、.sym にない名前だけラベルとして足す。
+  // rgblink のバージョンで書式が少しずつ違うので、読めない行は読み飛ばす
+  pub fn load_map(&mut self, text: &str) {
+    let mut region = String::new();
+    let mut bank = 0;
+    for line in text.lines() {
+      let trimmed = line.trim();
+      if !line.starts_with(char::is_whitespace) && trimmed.ends_with(':') {
+        // "ROMX bank #2:"、"ROM Bank #0 (HOME):"、"HRAM:" など
+        region = trimmed.split_whitespace().next().unwrap_or_default().trim_end_matches(':').to_string();
+        bank = trimmed
+          .split_once('#')
+          .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next())
+          .and_then(|digits| digits.parse().ok())
+          .unwrap_or(0);
+      } else if let Some(rest) = trimmed.strip_prefix("SECTION:") {
+        if let Some(section) = parse_section(rest, &region, bank) {
+          self.sections.push(section);
+        }
+      } else if let Some((address, name)) = trimmed.split_once(" = ")
+        && let Some(address) = address.strip_prefix('$').and_then(|digits| u16::from_str_radix(digits, 16).ok())
+        && self.find(name).is_none()
+      {
+        self.add(bank, address, name);
+      }
+    }
+  }
+
+  pub fn add(&mut self, bank: usize, address: u16, name: &str) {
+    let index = self.symbols.partition_point(|symbol| symbol.address <= address);
+    self.symbols.insert(index, Symbol { bank, address, name: name.to_string() });
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.symbols.is_empty() && self.sections.is_empty()
+  }
+
+  pub fn symbols(&self) -> &[Symbol] {
+    &self.symbols
+  }
+
+  pub fn sections(&self) -> &[Section] {
+    &self.sections
+  }
+
+  // 名前は大文字と小文字を区別する
+  pub fn find(&self, name: &str) -> Option<&Symbol> {
+    self.symbols.iter().find(|symbol| symbol.name == name)
+  }
+
+  // address にちょうど置かれたラベル。複数あれば .sym で先に書かれたもの
+  pub fn label(&self, bank: usize, address: u16) -> Option<&str> {
+    self.symbol_at(bank, address).map(|symbol| symbol.name.as_str())
+  }
+
+  // address 以前で一番近いラベルと、そこからのオフセット。同じ領域（ROM0、ROMX、VRAM など）の中だけを探す
+  pub fn nearest(&self, bank: usize, address: u16) -> Option<(&Symbol, u16)> {
+    let end = self.symbols.partition_point(|symbol| symbol.address <= address);
+    let found = self.symbols[..end]
+      .iter()
+      .rev()
+      .take_while(|symbol| area(symbol.address) == area(address))
+      .find(|symbol| bank_matches(symbol.bank, bank, address))?;
+    let symbol = self.symbol_at(bank, found.address)?;
+    Some((symbol, address - symbol.address))
+  }
+
+  // "Main" や "Main+$3"。ラベルが見つからなければ None
+  pub fn describe(&self, bank: usize, address: u16) -> Option<String> {
+    let (symbol, offset) = self.nearest(bank, address)?;
+    Some(if offset == 0 { symbol.name.clone() } else { format!("{}+${:X}", symbol.name, offset) })
+  }
+
+  fn symbol_at(&self, bank: usize, address: u16) -> Option<&Symbol> {
+    let end = self.symbols.partition_point(|symbol| symbol.address <= address);
+    let start = self.symbols[..end].partition_point(|symbol| symbol.address < address);
+    self.symbols[start..end].iter().find(|symbol| bank_matches(symbol.bank, bank, address))
+  }
+
+  pub fn section(&self, bank: usize, address: u16) -> Option<&Section> {
+    self
+      .sections
+      .iter()
+      .find(|section| (section.start..=section.end).contains(&address) && bank_matches(section.bank, bank, address))
+  }
+}
+
+fn bank_matches(symbol_bank: usize, bank: usize, address: u16) -> bool {
+  !(0x4000..=0x7FFF).contains(&address) || symbol_bank == bank
+}
+
+// nearest がラベルを探す範囲
+fn area(address: u16) -> u8 {
+  match address {
+    0x0000..=0x3FFF => 0,
+    0x4000..=0x7FFF => 1,
+    0x8000..=0x9FFF => 2,
+    0xA000..=0xBFFF => 3,
+    0xC000..=0xFDFF => 4,
+    0xFE00..=0xFEFF => 5,
+    0xFF00..=0xFF7F => 6,
+    0xFF80..=0xFFFF => 7,
+  }
+}
+
+// "$0000-$00ff ($0100 bytes) ["Header"]"。大きさ0のセクション（"$0150 ($0 bytes) ..."）は None
+fn parse_section(text: &str, region: &str, bank: usize) -> Option<Section> {
+  let text = text.trim();
+  let (range, rest) = text.split_once(' ')?;
+  let (start, end) = range.split_once('-')?;
+  let start = u16::from_str_radix(start.strip_prefix('$')?, 16).ok()?;
+  let end = u16::from_str_radix(end.strip_prefix('$')?, 16).ok()?;
+  let name = rest.split_once("[\"")?.1.rsplit_once("\"]")?.0;
+  Some(Section { region: region.to_string(), bank, start, end, name: name.to_string() })
+}
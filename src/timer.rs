@@ -0,0 +1,88 @@
+pub struct Timer {
+  pub counter: u16,
+  pub tima: u8,
+  pub tma: u8,
+  pub tac: u8,
+}
+
+impl Timer {
+  pub fn new() -> Timer {
+    Timer {
+      counter: 0,
+      tima: 0,
+      tma: 0,
+      tac: 0,
+    }
+  }
+
+  pub fn read_byte(&self, address: u16) -> u8 {
+    match address {
+      0xFF04 => (self.counter >> 8) as u8,
+      0xFF05 => self.tima,
+      0xFF06 => self.tma,
+      0xFF07 => self.tac | 0xF8,
+      _ => 0xFF,
+    }
+  }
+
+  // 戻り値はタイマー割り込みを要求するかどうか
+  pub fn write_byte(&mut self, address: u16, value: u8) -> bool {
+    match address {
+      0xFF04 => {
+        // DIVへの書き込みはカウンタ全体をリセットする。選択中のビットが1だった場合は立ち下がりとして扱われる
+        let was_high = self.selected_bit_high();
+        self.counter = 0;
+        was_high && self.increment_tima()
+      },
+      0xFF05 => { self.tima = value; false },
+      0xFF06 => { self.tma = value; false },
+      0xFF07 => {
+        let was_high = self.selected_bit_high();
+        self.tac = value & 0x07;
+        was_high && !self.selected_bit_high() && self.increment_tima()
+      },
+      _ => false,
+    }
+  }
+
+  pub fn tick(&mut self, cycles: u8) -> bool {
+    let mut interrupt = false;
+    for _ in 0..cycles {
+      let was_high = self.selected_bit_high();
+      self.counter = self.counter.wrapping_add(1);
+      if was_high && !self.selected_bit_high() {
+        interrupt |= self.increment_tima();
+      }
+    }
+    interrupt
+  }
+
+  fn selected_bit_high(&self) -> bool {
+    if self.tac & 0x04 == 0 {
+      return false;
+    }
+    let bit = match self.tac & 0x03 {
+      0 => 9,
+      1 => 3,
+      2 => 5,
+      _ => 7,
+    };
+    (self.counter >> bit) & 1 != 0
+  }
+
+  fn increment_tima(&mut self) -> bool {
+    let (value, overflow) = self.tima.overflowing_add(1);
+    if overflow {
+      self.tima = self.tma;
+    } else {
+      self.tima = value;
+    }
+    overflow
+  }
+}
+
+impl Default for Timer {
+  fn default() -> Self {
+    Timer::new()
+  }
+}
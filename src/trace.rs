@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disassembler::disassemble_with_symbols;
+use crate::symbols::Symbols;
+
+// 命令を実行する直前のCPUの状態
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TraceRecord {
+  pub pc: u16,
+  // PCから4バイト分のメモリ（命令とオペランドを含む）
+  pub memory: [u8; 4],
+  pub a: u8,
+  pub f: u8,
+  pub b: u8,
+  pub c: u8,
+  pub d: u8,
+  pub e: u8,
+  pub h: u8,
+  pub l: u8,
+  pub sp: u16,
+  // 0x4000〜0x7FFF に見えているROMバンク。ラベルを探すのに使う
+  pub rom_bank: usize,
+  // この命令を実行する前までに消費した合計Tサイクル数
+  pub cycles: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceFormat {
+  Full,
+  // https://github.com/robert/gameboy-doctor と同じ行形式
+  GameboyDoctor,
+}
+
+impl TraceRecord {
+  pub fn flags(&self) -> String {
+    [(0x80, 'Z'), (0x40, 'N'), (0x20, 'H'), (0x10, 'C')]
+      .iter()
+      .map(|&(mask, name)| if self.f & mask != 0 { name } else { '-' })
+      .collect()
+  }
+
+  pub fn format(&self, format: TraceFormat) -> String {
+    self.format_with_symbols(format, &Symbols::new())
+  }
+
+  // Full 形式では、アドレスをラベルで表示し、ラベルの置かれた命令の前に "Label:" の行を入れる
+  pub fn format_with_symbols(&self, format: TraceFormat, symbols: &Symbols) -> String {
+    match format {
+      TraceFormat::Full => {
+        let line = self.full_line(symbols);
+        match symbols.label(self.rom_bank, self.pc) {
+          Some(label) => format!("{}:\n{}", label, line),
+          None => line,
+        }
+      },
+      TraceFormat::GameboyDoctor => format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc,
+        self.memory[0], self.memory[1], self.memory[2], self.memory[3],
+      ),
+    }
+  }
+
+  fn full_line(&self, symbols: &Symbols) -> String {
+    let disassembly = disassemble_with_symbols(&self.memory, self.pc, symbols, self.rom_bank);
+    format!(
+      "{:<32}A:{:02X} F:{} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} CY:{}",
+      disassembly.to_string(),
+      self.a, self.flags(), self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.cycles,
+    )
+  }
+}
+
+impl fmt::Display for TraceRecord {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.full_line(&Symbols::new()))
+  }
+}
+
+pub trait TraceSink {
+  fn record(&mut self, record: &TraceRecord);
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+// 1命令1行で書き出すトレース出力（Full 形式でラベルを渡した場合はラベルの行も入る）。書き込みエラーは最初の1つを覚えておき、flush時に返す
+pub struct WriterTraceSink<W: Write> {
+  writer: W,
+  format: TraceFormat,
+  symbols: Symbols,
+  error: Option<io::Error>,
+}
+
+impl<W: Write> WriterTraceSink<W> {
+  pub fn new(writer: W, format: TraceFormat) -> WriterTraceSink<W> {
+    WriterTraceSink { writer, format, symbols: Symbols::new(), error: None }
+  }
+
+  pub fn with_symbols(mut self, symbols: Symbols) -> WriterTraceSink<W> {
+    self.symbols = symbols;
+    self
+  }
+
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+}
+
+impl<W: Write> TraceSink for WriterTraceSink<W> {
+  fn record(&mut self, record: &TraceRecord) {
+    if self.error.is_some() {
+      return;
+    }
+    if let Err(error) = writeln!(self.writer, "{}", record.format_with_symbols(self.format, &self.symbols)) {
+      self.error = Some(error);
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if let Some(error) = self.error.take() {
+      return Err(error);
+    }
+    self.writer.flush()
+  }
+}
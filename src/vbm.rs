@@ -0,0 +1,151 @@
+use crate::gameboy::Model;
+use crate::movie::{Frame, Movie, MovieError, Start};
+use crate::png::crc32;
+
+// VisualBoyAdvance のムービー (.vbm) との変換。
+// 0x100バイトのヘッダのあとに、開始時の SRAM（あれば）と、フレームごとに2バイトの入力が並ぶ
+
+const SIGNATURE: [u8; 4] = *b"VBM\x1A";
+const HEADER_LENGTH: usize = 0x100;
+
+// 開始フラグ (0x14)
+const START_SNAPSHOT: u8 = 0x01;
+const START_SRAM: u8 = 0x02;
+// システムフラグ (0x16)
+const SYSTEM_GBA: u8 = 0x01;
+const SYSTEM_GBC: u8 = 0x02;
+// gbEmulatorType (0x20)
+const EMULATOR_TYPE_GBC: u32 = 1;
+const EMULATOR_TYPE_GB: u32 = 3;
+
+// 入力の2バイトのビット。VBA の並びは A, B, Select, Start, Right, Left, Up, Down
+const BUTTONS: [(u16, u8); 8] =
+  [(0x0001, 0x10), (0x0002, 0x20), (0x0004, 0x40), (0x0008, 0x80), (0x0010, 0x01), (0x0020, 0x02), (0x0040, 0x04), (0x0080, 0x08)];
+// リセット（古いタイミングと新しいタイミング）
+const RESET: u16 = 0x0C00;
+
+// .vbm を読み込む。ROM はヘッダのタイトルとチェックサムの確認と、ネイティブのムービーの ROM の CRC32 に使う
+pub fn import(data: &[u8], rom: &[u8]) -> Result<Movie, MovieError> {
+  if data.len() < HEADER_LENGTH || data[..4] != SIGNATURE {
+    return Err(MovieError::Invalid("not a VBM movie".to_string()));
+  }
+  let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+  let version = u32_at(0x04);
+  if version != 1 {
+    return Err(MovieError::UnsupportedVersion(version as u16));
+  }
+  let frame_count = u32_at(0x0C) as usize;
+  let start_flags = data[0x14];
+  let controllers = data[0x15] & 0x0F;
+  let system_flags = data[0x16];
+  if system_flags & SYSTEM_GBA != 0 {
+    return Err(MovieError::Unsupported("Game Boy Advance movie".to_string()));
+  }
+
+  let expected = rom_identity(&data[0x24..0x30], data[0x31], u16::from_le_bytes([data[0x32], data[0x33]]));
+  let actual = identity_of(rom);
+  if expected != actual {
+    return Err(MovieError::ForeignRomMismatch { expected, actual });
+  }
+
+  let start_offset = u32_at(0x38) as usize;
+  let input_offset = u32_at(0x3C) as usize;
+  let start = match start_flags & (START_SNAPSHOT | START_SRAM) {
+    0 => Start::PowerOn,
+    START_SRAM => {
+      let ram = data.get(start_offset..input_offset).ok_or_else(|| MovieError::Invalid("SRAM is truncated".to_string()))?;
+      Start::SaveRam(ram.to_vec())
+    },
+    START_SNAPSHOT => return Err(MovieError::Unsupported("movie starts from a VBA snapshot".to_string())),
+    _ => return Err(MovieError::Invalid("movie starts from both a snapshot and SRAM".to_string())),
+  };
+
+  // 複数のコントローラが記録されていても、使うのは1つめだけ
+  let stride = 2 * (controllers.count_ones() as usize).max(1);
+  let inputs = data
+    .get(input_offset..input_offset + frame_count * stride)
+    .ok_or_else(|| MovieError::Invalid("input data is truncated".to_string()))?;
+  let mut frames = Vec::with_capacity(frame_count);
+  for (index, input) in inputs.chunks(stride).enumerate() {
+    let input = u16::from_le_bytes([input[0], input[1]]);
+    // 最初のフレームのリセットは、電源を入れた直後から始まるのと同じ
+    if input & RESET != 0 && index != 0 {
+      return Err(MovieError::Unsupported(format!("reset at frame {}", index)));
+    }
+    let buttons = BUTTONS.iter().filter(|&&(bit, _)| input & bit != 0).fold(0, |buttons, &(_, mask)| buttons | mask);
+    frames.push(Frame { buttons, hash: None });
+  }
+
+  let model = match u32_at(0x20) {
+    EMULATOR_TYPE_GB => Model::Dmg,
+    EMULATOR_TYPE_GBC => Model::Cgb,
+    _ if system_flags & SYSTEM_GBC != 0 => Model::Cgb,
+    _ => Model::Dmg,
+  };
+  // VBA はブートROMを使わず、RTC の初期値も記録しない
+  Ok(Movie { rom_crc32: crc32(rom), model, boot_rom_crc32: None, rtc_seed: 0, start, frames })
+}
+
+// .vbm を書き出す。状態のハッシュは VBM にはないので捨てる
+pub fn export(movie: &Movie, rom: &[u8]) -> Result<Vec<u8>, MovieError> {
+  if movie.boot_rom_crc32.is_some() {
+    return Err(MovieError::Unsupported("VBA does not run the boot ROM".to_string()));
+  }
+  let ram = match &movie.start {
+    Start::PowerOn => None,
+    Start::SaveRam(ram) => Some(ram.as_slice()),
+    Start::SaveState(_) => return Err(MovieError::Unsupported("VBA cannot start from our save states".to_string())),
+  };
+
+  let mut data = vec![0; HEADER_LENGTH];
+  data[..4].copy_from_slice(&SIGNATURE);
+  data[0x04..0x08].copy_from_slice(&1u32.to_le_bytes());
+  data[0x0C..0x10].copy_from_slice(&(movie.frames.len() as u32).to_le_bytes());
+  data[0x14] = if ram.is_some() { START_SRAM } else { 0 };
+  data[0x15] = 0x01; // コントローラ1だけ
+  let (system_flags, emulator_type) = match movie.model {
+    Model::Dmg => (0, EMULATOR_TYPE_GB),
+    Model::Cgb => (SYSTEM_GBC, EMULATOR_TYPE_GBC),
+  };
+  data[0x16] = system_flags;
+  data[0x20..0x24].copy_from_slice(&emulator_type.to_le_bytes());
+  let header = |offset: usize| rom.get(offset).copied().unwrap_or(0);
+  for (index, byte) in data[0x24..0x30].iter_mut().enumerate() {
+    *byte = header(0x134 + index);
+  }
+  data[0x30] = 1; // マイナーバージョン
+  data[0x31] = header(0x14D);
+  data[0x32..0x34].copy_from_slice(&global_checksum(rom).to_le_bytes());
+
+  if let Some(ram) = ram {
+    let ram_offset = data.len() as u32;
+    data[0x38..0x3C].copy_from_slice(&ram_offset.to_le_bytes());
+    data.extend_from_slice(ram);
+  }
+  let input_offset = data.len() as u32;
+  data[0x3C..0x40].copy_from_slice(&input_offset.to_le_bytes());
+  for frame in &movie.frames {
+    let input = BUTTONS.iter().filter(|&&(_, mask)| frame.buttons & mask != 0).fold(0u16, |input, &(bit, _)| input | bit);
+    data.extend_from_slice(&input.to_le_bytes());
+  }
+  Ok(data)
+}
+
+// カートリッジのヘッダにあるグローバルチェックサム (0x14E〜0x14F、ビッグエンディアン)
+fn global_checksum(rom: &[u8]) -> u16 {
+  u16::from_be_bytes([rom.get(0x14E).copied().unwrap_or(0), rom.get(0x14F).copied().unwrap_or(0)])
+}
+
+fn identity_of(rom: &[u8]) -> String {
+  let mut title = [0; 12];
+  for (index, byte) in title.iter_mut().enumerate() {
+    *byte = rom.get(0x134 + index).copied().unwrap_or(0);
+  }
+  rom_identity(&title, rom.get(0x14D).copied().unwrap_or(0), global_checksum(rom))
+}
+
+// VBM が記録している ROM のタイトル（12バイト）、ヘッダチェックサム、グローバルチェックサム
+fn rom_identity(title: &[u8], header_checksum: u8, global_checksum: u16) -> String {
+  let title: String = title.iter().take_while(|&&c| c != 0).map(|&c| c as char).collect();
+  format!("\"{}\", header checksum {:02X}, global checksum {:04X}", title, header_checksum, global_checksum)
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::condition::Condition;
+
+// 実際に起きたメモリアクセスの種類
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+  Read,
+  Write,
+  // 命令のフェッチ（オペコードの1バイト目）
+  Execute,
+}
+
+// ウォッチポイントが反応するアクセスの種類
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WatchKind {
+  Read,
+  Write,
+  // 読み込みと書き込みの両方
+  Access,
+  Execute,
+}
+
+impl WatchKind {
+  fn matches(self, access: Access) -> bool {
+    matches!(
+      (self, access),
+      (WatchKind::Read, Access::Read)
+        | (WatchKind::Write, Access::Write)
+        | (WatchKind::Access, Access::Read | Access::Write)
+        | (WatchKind::Execute, Access::Execute)
+    )
+  }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+  pub id: usize,
+  pub kind: WatchKind,
+  pub range: RangeInclusive<u16>,
+  // Some のときは、読んだ値・書き込む値・オペコードがこの値のときだけ反応する
+  pub value: Option<u8>,
+  // Some のときは、アクセスした命令を実行し終えた時点でこの条件が成り立つときだけ反応する
+  pub condition: Option<Condition>,
+}
+
+// ウォッチポイントが反応したときの記録
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WatchHit {
+  pub id: usize,
+  pub access: Access,
+  pub address: u16,
+  // アクセスした命令のアドレス（割り込み処理中のアクセスでは割り込まれた命令のアドレス）
+  pub pc: u16,
+  // 読み込みと実行では old_value と new_value は同じ値
+  pub old_value: u8,
+  pub new_value: u8,
+}
+
+impl fmt::Display for WatchHit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.access {
+      Access::Read => write!(f, "Watchpoint {}: read {:04X} = {:02X}", self.id, self.address, self.new_value)?,
+      Access::Write => write!(
+        f,
+        "Watchpoint {}: write {:04X} {:02X} -> {:02X}",
+        self.id, self.address, self.old_value, self.new_value
+      )?,
+      Access::Execute => write!(f, "Watchpoint {}: execute {:04X}", self.id, self.address)?,
+    }
+    write!(f, " (PC={:04X})", self.pc)
+  }
+}
+
+pub enum WatchAction {
+  // 命令を実行し終えたところで止まる（take_hits で取り出すまで GameBoy の run_* は進まない）
+  Stop,
+  Callback(Box<dyn FnMut(&WatchHit)>),
+}
+
+struct Entry {
+  watchpoint: Watchpoint,
+  action: WatchAction,
+  // 範囲と値が一致した回数。条件式の hits になる
+  hits: u64,
+}
+
+// MemoryBus に置くウォッチポイントの一覧。
+// 条件式は CPU のレジスタを見るので、アクセスはいったん pending に溜め、命令の終わりに CPU が dispatch する
+#[derive(Default)]
+pub struct Watchpoints {
+  entries: Vec<Entry>,
+  next_id: usize,
+  // 今実行している命令のアドレス。CPU が命令ごとに設定する
+  pub pc: u16,
+  pending: Vec<WatchHit>,
+  stopped: Vec<WatchHit>,
+}
+
+impl Watchpoints {
+  pub fn new() -> Watchpoints {
+    Watchpoints::default()
+  }
+
+  pub fn add(&mut self, kind: WatchKind, range: RangeInclusive<u16>, value: Option<u8>) -> usize {
+    self.add_with_action(kind, range, value, WatchAction::Stop)
+  }
+
+  pub fn add_callback<F>(&mut self, kind: WatchKind, range: RangeInclusive<u16>, value: Option<u8>, callback: F) -> usize
+  where
+    F: FnMut(&WatchHit) + 'static,
+  {
+    self.add_with_action(kind, range, value, WatchAction::Callback(Box::new(callback)))
+  }
+
+  pub fn add_with_action(&mut self, kind: WatchKind, range: RangeInclusive<u16>, value: Option<u8>, action: WatchAction) -> usize {
+    self.next_id += 1;
+    let watchpoint = Watchpoint { id: self.next_id, kind, range, value, condition: None };
+    self.entries.push(Entry { watchpoint, action, hits: 0 });
+    self.next_id
+  }
+
+  pub fn set_condition(&mut self, id: usize, condition: Option<Condition>) -> bool {
+    match self.entries.iter_mut().find(|entry| entry.watchpoint.id == id) {
+      Some(entry) => {
+        entry.watchpoint.condition = condition;
+        true
+      },
+      None => false,
+    }
+  }
+
+  pub fn remove(&mut self, id: usize) -> bool {
+    let count = self.entries.len();
+    self.entries.retain(|entry| entry.watchpoint.id != id);
+    self.entries.len() != count
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  pub fn list(&self) -> impl Iterator<Item = &Watchpoint> {
+    self.entries.iter().map(|entry| &entry.watchpoint)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  // MemoryBus と CPU から、アクセスのたびに呼ばれる
+  pub fn check(&mut self, access: Access, address: u16, old_value: u8, new_value: u8) {
+    if self.entries.is_empty() {
+      return;
+    }
+    for entry in &self.entries {
+      let watchpoint = &entry.watchpoint;
+      if watchpoint.kind.matches(access)
+        && watchpoint.range.contains(&address)
+        && watchpoint.value.is_none_or(|value| value == new_value)
+      {
+        let hit = WatchHit { id: watchpoint.id, access, address, pc: self.pc, old_value, new_value };
+        self.pending.push(hit);
+      }
+    }
+  }
+
+  pub fn has_pending(&self) -> bool {
+    !self.pending.is_empty()
+  }
+
+  // 溜まったアクセスについてコールバックを呼び、Stop のものを stopped に移す。
+  // 条件付きのウォッチポイントは condition_holds(条件, hits) が true のものだけを扱う
+  pub fn dispatch<F>(&mut self, mut condition_holds: F)
+  where
+    F: FnMut(&Condition, u64) -> bool,
+  {
+    let pending = std::mem::take(&mut self.pending);
+    for hit in pending {
+      let Some(entry) = self.entries.iter_mut().find(|entry| entry.watchpoint.id == hit.id) else { continue };
+      entry.hits += 1;
+      if let Some(condition) = &entry.watchpoint.condition
+        && !condition_holds(condition, entry.hits)
+      {
+        continue;
+      }
+      match &mut entry.action {
+        WatchAction::Stop => self.stopped.push(hit),
+        WatchAction::Callback(callback) => callback(&hit),
+      }
+    }
+  }
+
+  pub fn stopped(&self) -> bool {
+    !self.stopped.is_empty()
+  }
+
+  pub fn take_hits(&mut self) -> Vec<WatchHit> {
+    std::mem::take(&mut self.stopped)
+  }
+
+  // ウォッチポイントごとの hits。逆実行でチェックポイントに戻るときに使う
+  pub fn hit_counts(&self) -> Vec<(usize, u64)> {
+    self.entries.iter().map(|entry| (entry.watchpoint.id, entry.hits)).collect()
+  }
+
+  // counts にないウォッチポイントは 0 にする
+  pub fn set_hit_counts(&mut self, counts: &[(usize, u64)]) {
+    for entry in &mut self.entries {
+      entry.hits = counts.iter().find(|&&(id, _)| id == entry.watchpoint.id).map_or(0, |&(_, hits)| hits);
+    }
+  }
+}
@@ -0,0 +1,294 @@
+use crate::png::crc32;
+
+// ZIP アーカイブの読み書き。BizHawk のムービー (.bk2) を扱うのに必要な分だけ実装している。
+// 読むときは無圧縮と Deflate に対応し、書くときは常に無圧縮で書く
+
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const CENTRAL_HEADER: u32 = 0x0201_4B50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4B50;
+// 1980年1月1日（MS-DOS の日付で表せる一番古い日）
+const DOS_DATE: u16 = 0x0021;
+
+pub struct Entry {
+  pub name: String,
+  pub data: Vec<u8>,
+}
+
+// アーカイブのすべてのファイルを展開する
+pub fn read(archive: &[u8]) -> Result<Vec<Entry>, String> {
+  let end = (0..archive.len().saturating_sub(21))
+    .rev()
+    .find(|&offset| u32_at(archive, offset) == Some(END_OF_CENTRAL_DIRECTORY))
+    .ok_or("not a ZIP archive")?;
+  let count = u16_at(archive, end + 10).ok_or("truncated ZIP archive")? as usize;
+  let mut offset = u32_at(archive, end + 16).ok_or("truncated ZIP archive")? as usize;
+
+  let mut entries = Vec::with_capacity(count);
+  for _ in 0..count {
+    if u32_at(archive, offset) != Some(CENTRAL_HEADER) {
+      return Err("broken ZIP central directory".to_string());
+    }
+    let field = |position: usize| u16_at(archive, offset + position).ok_or("truncated ZIP archive");
+    let method = field(10)?;
+    let crc = u32_at(archive, offset + 16).ok_or("truncated ZIP archive")?;
+    let compressed_size = u32_at(archive, offset + 20).ok_or("truncated ZIP archive")? as usize;
+    let size = u32_at(archive, offset + 24).ok_or("truncated ZIP archive")? as usize;
+    let name_length = field(28)? as usize;
+    let extra_length = field(30)? as usize;
+    let comment_length = field(32)? as usize;
+    let local = u32_at(archive, offset + 42).ok_or("truncated ZIP archive")? as usize;
+    let name = archive.get(offset + 46..offset + 46 + name_length).ok_or("truncated ZIP archive")?;
+    let name = String::from_utf8_lossy(name).into_owned();
+    offset += 46 + name_length + extra_length + comment_length;
+
+    if u32_at(archive, local) != Some(LOCAL_HEADER) {
+      return Err(format!("{}: broken ZIP local header", name));
+    }
+    let local_name_length = u16_at(archive, local + 26).ok_or("truncated ZIP archive")? as usize;
+    let local_extra_length = u16_at(archive, local + 28).ok_or("truncated ZIP archive")? as usize;
+    let start = local + 30 + local_name_length + local_extra_length;
+    let compressed = archive.get(start..start + compressed_size).ok_or_else(|| format!("{}: truncated", name))?;
+    let data = match method {
+      0 => compressed.to_vec(),
+      8 => inflate(compressed).map_err(|error| format!("{}: {}", name, error))?,
+      other => return Err(format!("{}: unsupported compression method {}", name, other)),
+    };
+    if data.len() != size || crc32(&data) != crc {
+      return Err(format!("{}: checksum mismatch", name));
+    }
+    entries.push(Entry { name, data });
+  }
+  Ok(entries)
+}
+
+// 無圧縮の ZIP アーカイブを作る
+pub fn write(entries: &[Entry]) -> Vec<u8> {
+  let mut archive = Vec::new();
+  let mut directory = Vec::new();
+  for entry in entries {
+    let offset = archive.len() as u32;
+    let crc = crc32(&entry.data);
+    let size = entry.data.len() as u32;
+    let name = entry.name.as_bytes();
+
+    archive.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+    write_common(&mut archive, crc, size, name.len());
+    archive.extend_from_slice(&0u16.to_le_bytes()); // 拡張フィールドの長さ
+    archive.extend_from_slice(name);
+    archive.extend_from_slice(&entry.data);
+
+    directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+    directory.extend_from_slice(&20u16.to_le_bytes()); // 作成したバージョン
+    write_common(&mut directory, crc, size, name.len());
+    // 拡張フィールドとコメントの長さ、ディスク番号、内部属性、外部属性
+    directory.extend_from_slice(&[0; 12]);
+    directory.extend_from_slice(&offset.to_le_bytes());
+    directory.extend_from_slice(name);
+  }
+
+  let directory_offset = archive.len() as u32;
+  archive.extend_from_slice(&directory);
+  archive.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+  archive.extend_from_slice(&[0; 4]); // ディスク番号
+  archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+  archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+  archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+  archive.extend_from_slice(&directory_offset.to_le_bytes());
+  archive.extend_from_slice(&0u16.to_le_bytes()); // コメントの長さ
+  archive
+}
+
+// ローカルヘッダとセントラルディレクトリに共通する、必要なバージョンからファイル名の長さまで
+fn write_common(out: &mut Vec<u8>, crc: u32, size: u32, name_length: usize) {
+  out.extend_from_slice(&20u16.to_le_bytes()); // 展開に必要なバージョン
+  out.extend_from_slice(&0u16.to_le_bytes()); // フラグ
+  out.extend_from_slice(&0u16.to_le_bytes()); // 無圧縮
+  out.extend_from_slice(&0u16.to_le_bytes()); // 時刻
+  out.extend_from_slice(&DOS_DATE.to_le_bytes());
+  out.extend_from_slice(&crc.to_le_bytes());
+  out.extend_from_slice(&size.to_le_bytes());
+  out.extend_from_slice(&size.to_le_bytes());
+  out.extend_from_slice(&(name_length as u16).to_le_bytes());
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+  8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// 動的ハフマンブロックで、符号長の符号長が並ぶ順番
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// 下位ビットから順に読む
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+  bit: u8,
+}
+
+impl BitReader<'_> {
+  fn bit(&mut self) -> Result<u32, String> {
+    let byte = *self.data.get(self.position).ok_or("unexpected end of compressed data")?;
+    let value = (byte >> self.bit) & 1;
+    self.bit += 1;
+    if self.bit == 8 {
+      self.bit = 0;
+      self.position += 1;
+    }
+    Ok(value as u32)
+  }
+
+  fn bits(&mut self, count: u8) -> Result<u32, String> {
+    (0..count).try_fold(0, |value, shift| Ok(value | self.bit()? << shift))
+  }
+
+  fn align(&mut self) {
+    if self.bit != 0 {
+      self.bit = 0;
+      self.position += 1;
+    }
+  }
+}
+
+// 符号長から作る正規ハフマン符号
+struct Huffman {
+  // 長さごとの符号の数
+  counts: [u16; 16],
+  // 符号の短い順、同じ長さなら値の小さい順に並べた記号
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Huffman {
+    let mut counts = [0u16; 16];
+    for &length in lengths {
+      counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut symbols = Vec::with_capacity(lengths.len());
+    for length in 1..16 {
+      symbols.extend((0..lengths.len() as u16).filter(|&symbol| lengths[symbol as usize] == length));
+    }
+    Huffman { counts, symbols }
+  }
+
+  // 1ビットずつ読み、その長さの符号の範囲に入ったら記号を返す
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+    let mut code = 0i32;
+    let mut first = 0i32;
+    let mut index = 0i32;
+    for length in 1..16 {
+      code |= reader.bit()? as i32;
+      let count = self.counts[length] as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("invalid Huffman code".to_string())
+  }
+}
+
+// Deflate (RFC 1951) で圧縮されたデータを展開する
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+  let mut reader = BitReader { data, position: 0, bit: 0 };
+  let mut out = Vec::new();
+  loop {
+    let last = reader.bit()? == 1;
+    match reader.bits(2)? {
+      0 => {
+        reader.align();
+        let header = data.get(reader.position..reader.position + 4).ok_or("unexpected end of compressed data")?;
+        let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let start = reader.position + 4;
+        out.extend_from_slice(data.get(start..start + length).ok_or("unexpected end of compressed data")?);
+        reader.position = start + length;
+      },
+      1 => {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        inflate_block(&mut reader, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+      },
+      2 => {
+        let (literals, distances) = dynamic_tables(&mut reader)?;
+        inflate_block(&mut reader, &mut out, &literals, &distances)?;
+      },
+      _ => return Err("invalid block type".to_string()),
+    }
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let literal_count = reader.bits(5)? as usize + 257;
+  let distance_count = reader.bits(5)? as usize + 1;
+  let code_length_count = reader.bits(4)? as usize + 4;
+  let mut code_lengths = [0u8; 19];
+  for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+    code_lengths[index] = reader.bits(3)? as u8;
+  }
+  let code_lengths = Huffman::new(&code_lengths);
+
+  let mut lengths = Vec::with_capacity(literal_count + distance_count);
+  while lengths.len() < literal_count + distance_count {
+    let (value, repeat) = match code_lengths.decode(reader)? {
+      symbol @ 0..=15 => (symbol as u8, 1),
+      16 => (*lengths.last().ok_or("repeat with no previous length")?, 3 + reader.bits(2)?),
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+  }
+  if lengths.len() > literal_count + distance_count {
+    return Err("too many code lengths".to_string());
+  }
+  Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+  loop {
+    let symbol = literals.decode(reader)? as usize;
+    match symbol {
+      0..=255 => out.push(symbol as u8),
+      256 => return Ok(()),
+      _ => {
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+          return Err("invalid length code".to_string());
+        }
+        let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+        let index = distances.decode(reader)? as usize;
+        if index >= DISTANCE_BASE.len() {
+          return Err("invalid distance code".to_string());
+        }
+        let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index])? as usize;
+        if distance > out.len() {
+          return Err("distance points before the start of the data".to_string());
+        }
+        // 距離より長くコピーすることがあるので、1バイトずつ
+        for _ in 0..length {
+          out.push(out[out.len() - distance]);
+        }
+      },
+    }
+  }
+}
@@ -0,0 +1,145 @@
+use emulator::asm;
+use emulator::assembler::{assemble, assemble_at};
+use emulator::cpu::CPU;
+use emulator::disassembler::disassemble;
+use emulator::instruction::Instruction;
+
+#[test]
+fn single_instructions() {
+    assert_eq!(asm!("ld bc, $0305"), vec![0x01, 0x05, 0x03]);
+    assert_eq!(asm!("LD (HL+),A"), vec![0x22]);
+    assert_eq!(asm!("ld [hli], a"), vec![0x22]);
+    assert_eq!(asm!("ld a, [hld]"), vec![0x3A]);
+    assert_eq!(asm!("ld (hl), $12"), vec![0x36, 0x12]);
+    assert_eq!(asm!("ld ($C000), sp"), vec![0x08, 0x00, 0xC0]);
+    assert_eq!(asm!("jp (hl)"), vec![0xE9]);
+    assert_eq!(asm!("rst $38"), vec![0xFF]);
+    assert_eq!(asm!("rlc (hl)"), vec![0xCB, 0x06]);
+    assert_eq!(asm!("bit 7, h"), vec![0xCB, 0x7C]);
+    assert_eq!(asm!("ldh ($FF44), a"), vec![0xE0, 0x44]);
+    assert_eq!(asm!("ld a, (c)"), vec![0xF2]);
+    assert_eq!(asm!("ld hl, sp+$05"), vec![0xF8, 0x05]);
+    assert_eq!(asm!("add sp, -2"), vec![0xE8, 0xFE]);
+    assert_eq!(asm!("stop"), vec![0x10, 0x00]);
+}
+
+#[test]
+fn register_c_and_carry_condition() {
+    assert_eq!(asm!("inc c"), vec![0x0C]);
+    assert_eq!(asm!("ret c"), vec![0xD8]);
+    assert_eq!(asm!("jp c, $1234"), vec![0xDA, 0x34, 0x12]);
+}
+
+#[test]
+fn optional_accumulator_operand() {
+    assert_eq!(asm!("sub a, b"), asm!("sub b"));
+    assert_eq!(asm!("cp a, (hl)"), vec![0xBE]);
+}
+
+#[test]
+fn multiple_lines_and_comments() {
+    let program = asm!(
+        "ld a, 1  ; カウンタ",
+        "",
+        "inc a",
+    );
+    assert_eq!(program, vec![0x3E, 0x01, 0x3C]);
+}
+
+#[test]
+fn labels_and_relative_jumps() {
+    let program = assemble_at(
+        "Main:
+            ld b, 3
+        .loop:
+            dec b
+            jr nz, .loop
+            jp Main",
+        0x0150,
+    )
+    .unwrap();
+    assert_eq!(program, vec![0x06, 0x03, 0x05, 0x20, 0xFD, 0xC3, 0x50, 0x01]);
+}
+
+#[test]
+fn forward_references() {
+    let program = asm!("jr End", "nop", "End: halt");
+    assert_eq!(program, vec![0x18, 0x01, 0x00, 0x76]);
+}
+
+// 1パス目ではまだ決まらないラベルを0として読むので、0x0150 から前向きに JR しても範囲外にならない
+#[test]
+fn forward_relative_jump_at_cartridge_entry() {
+    assert_eq!(assemble_at("jr foo\nfoo: nop", 0x0150).unwrap(), vec![0x18, 0x00, 0x00]);
+    assert_eq!(assemble_at("jr nz, End\nnop\nnop\nEnd: halt", 0x0150).unwrap(), vec![0x20, 0x02, 0x00, 0x00, 0x76]);
+}
+
+#[test]
+fn data_directives() {
+    assert_eq!(asm!("db 1, $FF, \"Hi\""), vec![0x01, 0xFF, b'H', b'i']);
+    assert_eq!(asm!("dw $1234, Table", "Table: db -1"), vec![0x34, 0x12, 0x04, 0x00, 0xFF]);
+}
+
+#[test]
+fn expressions() {
+    assert_eq!(asm!("ld a, 2 + 3 * 4"), vec![0x3E, 14]);
+    assert_eq!(asm!("ld a, (2 + 3) * 4"), vec![0x3E, 20]);
+    assert_eq!(asm!("ld a, %1010 | 1 << 4"), vec![0x3E, 0x1A]);
+    assert_eq!(asm!("ld hl, 0xFF00 + $40"), vec![0x21, 0x40, 0xFF]);
+    assert_eq!(asm!("ld a, ~0 & $0F"), vec![0x3E, 0x0F]);
+    assert_eq!(assemble_at("ld hl, @ + 3", 0x0200).unwrap(), vec![0x21, 0x03, 0x02]);
+}
+
+#[test]
+fn errors_report_line_numbers() {
+    let error = assemble("nop\nfoo a").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert_eq!(error.to_string(), "line 2: unknown instruction: FOO");
+
+    assert!(assemble("ld a, $100").unwrap_err().message.contains("out of range"));
+    assert!(assemble("ld (bc), b").unwrap_err().message.contains("invalid operands"));
+    assert!(assemble("jp Nowhere").unwrap_err().message.contains("undefined label: Nowhere"));
+    assert!(assemble("A: nop\nA: nop").unwrap_err().message.contains("duplicate label"));
+    assert!(assemble("jr $0200").unwrap_err().message.contains("out of range"));
+    // 途中の計算があふれる式はパニックせずにエラーになる
+    assert!(assemble("ld a, -(1 << 63)").is_err());
+    assert!(assemble("ld a, (1 << 62) * 4").is_err());
+}
+
+// デコーダの全オペコードを逆アセンブルしてからアセンブルし直し、同じバイト列に戻ることを確かめる。
+// Instruction::from_byte の表に重複や書き間違いがあるとここで見つかる
+#[test]
+fn round_trip_with_decoder() {
+    for prefixed in [false, true] {
+        for opcode in 0..=0xFFu8 {
+            if Instruction::from_byte(opcode, prefixed).is_none() {
+                continue;
+            }
+            let bytes: Vec<u8> = if prefixed { vec![0xCB, opcode, 0x12, 0x34] } else { vec![opcode, 0x12, 0x34] };
+            let disassembly = disassemble(&bytes, 0x0150);
+            let assembled = assemble_at(&disassembly.text, 0x0150)
+                .unwrap_or_else(|error| panic!("{} ({:02X}): {}", disassembly.text, opcode, error));
+            assert_eq!(assembled, disassembly.bytes, "{}", disassembly.text);
+        }
+    }
+}
+
+#[test]
+fn assembled_program_runs() {
+    let mut cpu = CPU::new();
+    let program = asm!(
+        "ld a, 0",
+        "ld b, 5",
+        "loop: add a, b",
+        "dec b",
+        "jr nz, loop",
+        "halt",
+    );
+    for (i, &byte) in program.iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    while !cpu.halted {
+        cpu.step();
+    }
+    assert_eq!(cpu.registers.a, 15);
+}
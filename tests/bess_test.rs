@@ -0,0 +1,127 @@
+use emulator::bess::footer_start;
+use emulator::gameboy::GameBoy;
+use emulator::savestate::{SaveStateError, MAGIC};
+
+// 0x0100から指定したプログラムを置いた32KB ROMを作る
+fn rom_with_program(program: &[u8], cartridge_type: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cartridge_type;
+    rom[0x14E] = 0x12;
+    rom[0x14F] = 0x34;
+    rom
+}
+
+// INC A; LD (HL+),A; JR -4 を繰り返し、WRAMに書き続ける
+fn counting_gameboy() -> GameBoy {
+    let program = [0x21, 0x00, 0xC0, 0x3C, 0x22, 0x18, 0xFC];
+    GameBoy::new(rom_with_program(&program, 0x00)).unwrap()
+}
+
+// BESS のブロックを (ID, 中身) の一覧にする
+fn blocks(state: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut position = footer_start(state).unwrap();
+    let mut blocks = Vec::new();
+    loop {
+        let id = String::from_utf8(state[position..position + 4].to_vec()).unwrap();
+        let length = u32::from_le_bytes(state[position + 4..position + 8].try_into().unwrap()) as usize;
+        blocks.push((id.clone(), state[position + 8..position + 8 + length].to_vec()));
+        position += 8 + length;
+        if id == "END " {
+            return blocks;
+        }
+    }
+}
+
+// ネイティブ形式の目印を消して、ほかのエミュレータのステートとして読ませる
+fn as_foreign(state: &[u8]) -> Vec<u8> {
+    let mut state = state.to_vec();
+    state[..MAGIC.len()].fill(0);
+    state
+}
+
+#[test]
+fn save_state_ends_with_bess_footer() {
+    let mut gameboy = counting_gameboy();
+    gameboy.run_cycles(1000);
+    let state = gameboy.save_state();
+
+    assert!(state.ends_with(b"BESS"));
+    let blocks = blocks(&state);
+    let ids: Vec<&str> = blocks.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["NAME", "INFO", "CORE", "END "]);
+
+    let info = &blocks[1].1;
+    assert_eq!(&info[..4], b"TEST");
+    assert_eq!(&info[0x10..], [0x12, 0x34]);
+
+    let core = &blocks[2].1;
+    assert_eq!(core.len(), 0xD0);
+    assert_eq!(&core[..8], b"\x01\x00\x01\x00GD  ");
+    assert_eq!(u16::from_le_bytes([core[0x08], core[0x09]]), gameboy.cpu.pc);
+    assert_eq!(u16::from_le_bytes([core[0x12], core[0x13]]), gameboy.cpu.sp);
+    assert_eq!(core[0x18 + 0x40], 0x91); // LCDC
+
+    // RAM のバッファはネイティブ形式の MEM セクションの中を指す
+    let size = u32::from_le_bytes(core[0x98..0x9C].try_into().unwrap()) as usize;
+    let offset = u32::from_le_bytes(core[0x9C..0xA0].try_into().unwrap()) as usize;
+    assert_eq!(size, 0x2000);
+    assert_eq!(state[offset + 0x10], gameboy.cpu.bus.peek_byte(0xC010));
+}
+
+#[test]
+fn foreign_state_is_loaded_from_bess_blocks() {
+    let mut gameboy = counting_gameboy();
+    gameboy.run_cycles(10_000);
+    let state = as_foreign(&gameboy.save_state());
+    let pc = gameboy.cpu.pc;
+    let registers = (gameboy.cpu.registers.get_af(), gameboy.cpu.registers.get_hl());
+    let wram = gameboy.cpu.bus.peek_byte(0xC010);
+
+    gameboy.run_cycles(50_000);
+    gameboy.load_state(&state).unwrap();
+
+    assert_eq!(gameboy.cpu.pc, pc);
+    assert_eq!((gameboy.cpu.registers.get_af(), gameboy.cpu.registers.get_hl()), registers);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xC010), wram);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xFF40), 0x91);
+}
+
+#[test]
+fn mbc_block_restores_banks() {
+    // MBC1+RAM、ROM 128KB、RAM 8KB
+    let mut rom = rom_with_program(&[0x18, 0xFE], 0x02);
+    rom.resize(0x20000, 0);
+    rom[0x149] = 0x02;
+    let mut gameboy = GameBoy::new(rom).unwrap();
+    gameboy.cpu.bus.write_byte(0x0000, 0x0A);
+    gameboy.cpu.bus.write_byte(0x2000, 0x05);
+    gameboy.cpu.bus.write_byte(0xA010, 0x77);
+    let state = as_foreign(&gameboy.save_state());
+    assert!(blocks(&state).iter().any(|(id, body)| id == "MBC " && body.len() == 12));
+
+    gameboy.cpu.bus.write_byte(0x2000, 0x01);
+    gameboy.cpu.bus.write_byte(0xA010, 0x00);
+    gameboy.cpu.bus.write_byte(0x0000, 0x00);
+    gameboy.load_state(&state).unwrap();
+
+    assert_eq!(gameboy.cpu.bus.rom_bank(0x4000), 5);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xA010), 0x77);
+}
+
+#[test]
+fn foreign_state_for_another_rom_is_rejected() {
+    let state = as_foreign(&counting_gameboy().save_state());
+    let mut rom = rom_with_program(&[0x00], 0x00);
+    rom[0x14F] = 0x35;
+    let mut other = GameBoy::new(rom).unwrap();
+    let pc = other.cpu.pc;
+
+    let error = other.load_state(&state).unwrap_err();
+    assert_eq!(
+        error,
+        SaveStateError::GlobalChecksumMismatch { saved: 0x1234, saved_title: "TEST".to_string(), current: 0x1235 }
+    );
+    assert_eq!(other.cpu.pc, pc);
+}
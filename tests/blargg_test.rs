@@ -0,0 +1,122 @@
+// Blargg のテストROMを実行し、シリアルに書き出された結果で合否を決める。
+// ROMはリポジトリに含めていないので、BLARGG_ROMS（既定は tests/roms/blargg）の下にないものは飛ばす
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use emulator::gameboy::{GameBoy, CYCLES_PER_FRAME};
+
+// 1秒あたりのフレーム数（約59.7）を切り上げたもの
+const FRAMES_PER_SECOND: u64 = 60;
+// 0xA001〜0xA003 にこの値があれば、0xA000 に結果が入っている
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+// 0xA000 の値。まだ実行中
+const RUNNING: u8 = 0x80;
+
+fn rom_directory() -> PathBuf {
+    match std::env::var_os("BLARGG_ROMS") {
+        Some(directory) => PathBuf::from(directory),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms/blargg"),
+    }
+}
+
+// directory の下から file_name を探す（配布物のディレクトリ構成が版によって違うため）
+fn find(directory: &Path, file_name: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(directory).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find(&path, file_name) {
+                return Some(found);
+            }
+        } else if path.file_name().is_some_and(|name| name == file_name) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+// 0xA000 からの結果。シグネチャがまだ書かれていない、または実行中なら None
+fn memory_result(gameboy: &GameBoy) -> Option<Outcome> {
+    let bus = &gameboy.cpu.bus;
+    let signature = [bus.peek_byte(0xA001), bus.peek_byte(0xA002), bus.peek_byte(0xA003)];
+    let status = bus.peek_byte(0xA000);
+    if signature != SIGNATURE || status == RUNNING {
+        return None;
+    }
+    if status == 0 {
+        return Some(Outcome::Passed);
+    }
+    let text: String = (0xA004..0xBFFF)
+        .map(|address| bus.peek_byte(address))
+        .take_while(|&byte| byte != 0)
+        .map(|byte| byte as char)
+        .collect();
+    Some(Outcome::Failed(format!("result code {}\n{}", status, text)))
+}
+
+// シリアルの出力に "Passed" か "Failed" が現れるか、0xA000 に結果が書かれるまで実行する
+fn run(rom: Vec<u8>, seconds: u64) -> Outcome {
+    let mut gameboy = GameBoy::new(rom).expect("valid test ROM");
+    let mut outcome = Outcome::Failed(format!("no result after {} seconds", seconds));
+    for _ in 0..seconds * FRAMES_PER_SECOND {
+        gameboy.run_cycles(CYCLES_PER_FRAME);
+        if let Some(opcode) = gameboy.cpu.illegal_opcode {
+            outcome = Outcome::Failed(format!("CPU locked up on illegal opcode ${:02X} at PC={:04X}", opcode, gameboy.cpu.pc));
+            break;
+        }
+        let serial = String::from_utf8_lossy(gameboy.serial_output()).into_owned();
+        if serial.contains("Passed") {
+            outcome = Outcome::Passed;
+            break;
+        }
+        if serial.contains("Failed") {
+            outcome = Outcome::Failed(String::new());
+            break;
+        }
+        if let Some(result) = memory_result(&gameboy) {
+            outcome = result;
+            break;
+        }
+    }
+    match outcome {
+        Outcome::Failed(message) => {
+            Outcome::Failed(format!("{}\nserial output:\n{}", message, String::from_utf8_lossy(gameboy.serial_output())))
+        },
+        passed => passed,
+    }
+}
+
+fn check(file_name: &str, seconds: u64) {
+    let directory = rom_directory();
+    let Some(path) = find(&directory, file_name) else {
+        eprintln!("skipping {}: not found under {}", file_name, directory.display());
+        return;
+    };
+    if let Outcome::Failed(message) = run(fs::read(&path).unwrap(), seconds) {
+        panic!("{} failed: {}", file_name, message);
+    }
+}
+
+#[test]
+fn cpu_instrs() {
+    check("cpu_instrs.gb", 70);
+}
+
+#[test]
+fn instr_timing() {
+    check("instr_timing.gb", 10);
+}
+
+#[test]
+fn mem_timing() {
+    check("mem_timing.gb", 10);
+}
+
+#[test]
+fn halt_bug() {
+    check("halt_bug.gb", 10);
+}
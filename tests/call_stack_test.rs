@@ -0,0 +1,151 @@
+use emulator::asm;
+use emulator::call_stack::{Anomaly, CallKind, Frame};
+use emulator::cpu::CPU;
+use emulator::symbols::Symbols;
+
+fn cpu_with_program(program: &[u8]) -> CPU {
+    let mut cpu = CPU::new();
+    for (i, &byte) in program.iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    cpu
+}
+
+fn run(cpu: &mut CPU, steps: usize) {
+    for _ in 0..steps {
+        cpu.step();
+    }
+}
+
+#[test]
+fn call_and_ret() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "nop", "Sub: ret"));
+
+    run(&mut cpu, 2);
+    assert_eq!(
+        cpu.call_stack.frames(),
+        &[Frame {
+            kind: CallKind::Call,
+            caller: 0x0003,
+            caller_bank: 0,
+            target: 0x0007,
+            target_bank: 0,
+            sp: 0xCFFE,
+            return_address: 0x0006,
+        }]
+    );
+
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0006);
+    assert!(cpu.call_stack.frames().is_empty());
+    assert_eq!(cpu.call_stack.anomalies().count(), 0);
+}
+
+#[test]
+fn nested_calls_and_rst() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "Loop: jr Loop", "Sub: rst $38", "ret"));
+    cpu.bus.write_byte(0x0038, 0xC9);
+
+    run(&mut cpu, 3);
+    let frames = cpu.call_stack.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[1].kind, CallKind::Rst);
+    assert_eq!((frames[1].caller, frames[1].target, frames[1].sp), (0x0008, 0x0038, 0xCFFC));
+
+    run(&mut cpu, 2);
+    assert_eq!(cpu.pc, 0x0006);
+    assert!(cpu.call_stack.frames().is_empty());
+}
+
+#[test]
+fn interrupt_dispatch_and_reti() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "ei", "nop", "Loop: jr Loop"));
+    cpu.bus.write_byte(0x0040, 0xD9);
+    cpu.bus.write_byte(0xFFFF, 0x01);
+
+    run(&mut cpu, 3);
+    cpu.bus.write_byte(0xFF0F, 0x01);
+    run(&mut cpu, 1);
+    let frame = cpu.call_stack.frames()[0];
+    assert_eq!((frame.kind, frame.caller, frame.target, frame.return_address), (CallKind::Interrupt, 0x0005, 0x0040, 0x0005));
+
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0005);
+    assert!(cpu.call_stack.frames().is_empty());
+    assert_eq!(cpu.call_stack.anomalies().count(), 0);
+}
+
+#[test]
+fn pop_based_return() {
+    // 呼び出し元の直後に置いたデータのアドレスを POP で受け取る書き方
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "db 1, 2", "Sub: pop hl"));
+
+    run(&mut cpu, 3);
+    assert!(cpu.call_stack.frames().is_empty());
+    let anomalies: Vec<_> = cpu.call_stack.anomalies().copied().collect();
+    assert!(matches!(anomalies[..], [Anomaly::PopReturn { pc: 0x0008, frame }] if frame.return_address == 0x0006));
+}
+
+#[test]
+fn unbalanced_return_drops_frames() {
+    // Inner は自分の戻りアドレスを捨てて、Outer の呼び出し元へ直接戻る
+    let mut cpu = cpu_with_program(&asm!(
+        "ld sp, $D000",
+        "call Outer",
+        "Loop: jr Loop",
+        "Outer: call Inner",
+        "Inner: inc sp",
+        "inc sp",
+        "ret"
+    ));
+
+    run(&mut cpu, 6);
+    assert_eq!(cpu.pc, 0x0006);
+    assert!(cpu.call_stack.frames().is_empty());
+    let anomalies: Vec<_> = cpu.call_stack.anomalies().copied().collect();
+    assert_eq!(anomalies, vec![Anomaly::Unbalanced { pc: 0x000D, sp: 0xCFFE, dropped: 1 }]);
+    assert_eq!(anomalies[0].to_string(), "000D: unbalanced return (SP=CFFE), dropped 1 frame(s)");
+}
+
+#[test]
+fn push_and_ret_is_unmatched() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "ld hl, Target", "push hl", "ret", "Target: nop"));
+
+    run(&mut cpu, 4);
+    assert_eq!(cpu.pc, 0x0008);
+    let anomalies: Vec<_> = cpu.call_stack.anomalies().copied().collect();
+    assert_eq!(anomalies, vec![Anomaly::Unmatched { pc: 0x0007, sp: 0xCFFE, address: 0x0008 }]);
+}
+
+#[test]
+fn overwritten_return_address() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "nop", "Sub: ld hl, $CFFE", "ld (hl), $20", "ret"));
+
+    run(&mut cpu, 5);
+    assert_eq!(cpu.pc, 0x0020);
+    assert!(cpu.call_stack.frames().is_empty());
+    let anomalies: Vec<_> = cpu.call_stack.anomalies().copied().collect();
+    assert_eq!(anomalies, vec![Anomaly::ReturnAddressChanged { pc: 0x000C, expected: 0x0006, actual: 0x0020 }]);
+}
+
+#[test]
+fn stack_reset_discards_stale_frames() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "Sub: ld sp, $D000", "call Sub2", "Sub2: nop"));
+
+    run(&mut cpu, 4);
+    let frames = cpu.call_stack.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].target, 0x000C);
+}
+
+#[test]
+fn backtrace_lists_innermost_first() {
+    let mut cpu = cpu_with_program(&asm!("ld sp, $D000", "call Sub", "nop", "Sub: rst $38"));
+    cpu.bus.write_byte(0x0038, 0x00);
+
+    run(&mut cpu, 3);
+    assert_eq!(
+        cpu.call_stack.backtrace(cpu.pc, 0, &Symbols::new()),
+        "#0  00:0038\n#1  00:0007  rst 00:0038  SP=CFFC\n#2  00:0003  call 00:0007  SP=CFFE"
+    );
+}
@@ -0,0 +1,73 @@
+// checklist.md の印をデコード表と突き合わせる。印は手で付けているので、
+// デコードできないオペコードに印が付いていたら失敗にし、あわせて表から数えた実装状況を表示する
+use std::fs;
+use std::path::Path;
+
+use emulator::instruction::{CB_OPCODES, OPCODES, lookup};
+
+struct Entry {
+    prefixed: bool,
+    opcode: u8,
+    checked: bool,
+    text: String,
+}
+
+// "- [x] 00 NOP" や "- [ ] CBFF SET 7,A" の行を読む
+fn read_checklist() -> Vec<Entry> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("checklist.md");
+    let text = fs::read_to_string(path).unwrap();
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        let Some(rest) = line.strip_prefix("- [") else { continue };
+        let checked = rest.starts_with('x');
+        let (code, text) = rest[3..].split_once(' ').unwrap();
+        let (prefixed, hex) = match code.strip_prefix("CB").filter(|hex| hex.len() == 2) {
+            Some(hex) => (true, hex),
+            None => (false, code),
+        };
+        let opcode = u8::from_str_radix(hex, 16).unwrap_or_else(|_| panic!("bad opcode in checklist: {}", line));
+        entries.push(Entry { prefixed, opcode, checked, text: text.to_string() });
+    }
+    entries
+}
+
+#[test]
+fn checklist_covers_every_opcode() {
+    let entries = read_checklist();
+    for prefixed in [false, true] {
+        let mut opcodes: Vec<u8> = entries.iter().filter(|entry| entry.prefixed == prefixed).map(|entry| entry.opcode).collect();
+        opcodes.sort();
+        opcodes.dedup();
+        // 未定義のオペコード（D3 など）も "— (undefined)" として載せている
+        assert_eq!(opcodes.len(), 256, "prefixed: {}", prefixed);
+    }
+}
+
+#[test]
+fn checked_opcodes_are_in_the_decode_table() {
+    let mut failures = Vec::new();
+    for entry in read_checklist() {
+        if entry.checked && lookup(entry.opcode, entry.prefixed).instruction.is_none() {
+            failures.push(format!("{}{:02X} {}", if entry.prefixed { "CB" } else { "" }, entry.opcode, entry.text));
+        }
+    }
+    assert!(failures.is_empty(), "checked in checklist.md but not decoded:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn coverage_report() {
+    let entries = read_checklist();
+    for (name, prefixed, table) in [("unprefixed", false, &OPCODES), ("CB", true, &CB_OPCODES)] {
+        let listed = entries.iter().filter(|entry| entry.prefixed == prefixed).count();
+        let decoded = table.iter().filter(|info| info.instruction.is_some()).count();
+        let checked = entries.iter().filter(|entry| entry.prefixed == prefixed && entry.checked).count();
+        eprintln!("{:<10} decoded {:>3}/{:<3} checked {:>3}", name, decoded, listed, checked);
+        // 印がなくてもデコードできるもの（実行がまだのものなど）
+        for entry in entries.iter().filter(|entry| entry.prefixed == prefixed && !entry.checked) {
+            if let Some(instruction) = lookup(entry.opcode, prefixed).instruction {
+                eprintln!("  decoded but unchecked: {:02X} {}", entry.opcode, instruction);
+            }
+        }
+        assert!(decoded <= listed);
+    }
+}
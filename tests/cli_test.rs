@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn emulator() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_emulator"))
+}
+
+// テストごとに別のディレクトリを使い、並列実行でぶつからないようにする
+fn temp_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("emulator_cli_test_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn write_rom(directory: &Path, name: &str, cartridge_type: u8, program: &[u8]) -> PathBuf {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x147] = cartridge_type;
+    rom[0x149] = if cartridge_type == 0x03 { 0x02 } else { 0x00 };
+    let path = directory.join(name);
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+#[test]
+fn missing_rom_is_usage_error() {
+    let output = emulator().output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn unknown_option_is_usage_error() {
+    let output = emulator().args(["game.gb", "--turbo"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn out_of_range_speed_is_usage_error() {
+    for speed in ["1e-300", "inf", "NaN", "-1"] {
+        let output = emulator().args(["game.gb", "--headless", "--speed", speed]).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "--speed {}", speed);
+    }
+}
+
+#[test]
+fn unreadable_rom_is_io_error() {
+    let output = emulator().args(["/nonexistent/game.gb", "--headless"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn headless_run_writes_screenshot() {
+    let directory = temp_dir("screenshot");
+    let rom = write_rom(&directory, "loop.gb", 0x00, &[0x18, 0xFE]); // JR -2
+    let screenshot = directory.join("out.png");
+
+    let status = emulator()
+        .arg(&rom)
+        .args(["--headless", "--frames", "2", "--screenshot"])
+        .arg(&screenshot)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let png = fs::read(&screenshot).unwrap();
+    assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+}
+
+#[test]
+fn battery_ram_is_written_to_save_dir() {
+    let directory = temp_dir("save");
+    let program = [
+        0x3E, 0x0A,       // LD A, 0x0A
+        0x21, 0x00, 0x00, // LD HL, 0x0000
+        0x77,             // LD (HL), A (外部RAMを有効化)
+        0x26, 0xA0,       // LD H, 0xA0
+        0x3E, 0x42,       // LD A, 0x42
+        0x77,             // LD (HL), A
+        0x18, 0xFE,       // JR -2
+    ];
+    let rom = write_rom(&directory, "battery.gb", 0x03, &program);
+    let saves = directory.join("saves");
+
+    let status = emulator()
+        .arg(&rom)
+        .args(["--headless", "--frames", "1", "--save-dir"])
+        .arg(&saves)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let save = fs::read(saves.join("battery.sav")).unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!(save[0], 0x42);
+}
+
+#[test]
+fn battery_save_keeps_dotted_rom_name() {
+    let directory = temp_dir("dotted_save");
+    // 外部RAMを有効にして、0xA000 に value を書く
+    let program = |value: u8| [
+        0x3E, 0x0A,       // LD A, 0x0A
+        0x21, 0x00, 0x00, // LD HL, 0x0000
+        0x77,             // LD (HL), A
+        0x26, 0xA0,       // LD H, 0xA0
+        0x3E, value,      // LD A, value
+        0x77,             // LD (HL), A
+        0x18, 0xFE,       // JR -2
+    ];
+    let first = write_rom(&directory, "game.v1.gb", 0x03, &program(0x11));
+    let second = write_rom(&directory, "game.v2.gb", 0x03, &program(0x22));
+
+    for rom in [&first, &second] {
+        let status = emulator().arg(rom).args(["--headless", "--frames", "1"]).status().unwrap();
+        assert!(status.success());
+    }
+
+    assert_eq!(fs::read(directory.join("game.v1.sav")).unwrap()[0], 0x11);
+    assert_eq!(fs::read(directory.join("game.v2.sav")).unwrap()[0], 0x22);
+    assert!(!directory.join("game.sav").exists());
+}
+
+#[test]
+fn unknown_instruction_is_emulation_error() {
+    let directory = temp_dir("unknown");
+    let rom = write_rom(&directory, "bad.gb", 0x00, &[0xD3]);
+
+    let output = emulator().arg(&rom).args(["--headless", "--frames", "1"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("CPU locked up on illegal opcode $D3 (PC=0100)"), "{}", stderr);
+}
+
+#[test]
+fn emulation_error_reports_call_stack() {
+    let directory = temp_dir("crash_call_stack");
+    let mut program = vec![0xCD, 0x10, 0x01];
+    program.resize(0x11, 0x00);
+    program[0x10] = 0xD3;
+    let rom = write_rom(&directory, "bad.gb", 0x00, &program);
+
+    let output = emulator().arg(&rom).args(["--headless", "--frames", "1"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("call stack:\n#0  00:0110\n#1  00:0100  call 00:0110  SP=FFFC"), "{}", stderr);
+}
+
+#[test]
+fn doctor_trace_matches_post_boot_state() {
+    let directory = temp_dir("trace");
+    let rom = write_rom(&directory, "loop.gb", 0x00, &[0x18, 0xFE]); // JR -2
+    let trace = directory.join("trace.log");
+
+    let status = emulator()
+        .arg(&rom)
+        .args(["--headless", "--frames", "1", "--trace-format", "doctor", "--trace"])
+        .arg(&trace)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let log = fs::read_to_string(&trace).unwrap();
+    assert_eq!(
+        log.lines().next().unwrap(),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:18,FE,00,00"
+    );
+    assert!(log.lines().count() > 1000);
+}
+
+#[test]
+fn disasm_prints_bank_listing() {
+    let directory = temp_dir("disasm");
+    let rom = write_rom(&directory, "loop.gb", 0x00, &[0x18, 0xFE]); // JR -2
+
+    let output = emulator().arg("disasm").arg(&rom).args(["--bank", "0"]).output().unwrap();
+
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(listing.lines().next(), Some("; bank 00"));
+    assert!(listing.contains("00:0100: 18 FE     JR $0100"));
+    assert!(!listing.contains("; bank 01"));
+}
+
+#[test]
+fn disasm_uses_symbol_file_next_to_rom() {
+    let directory = temp_dir("disasm_symbols");
+    let rom = write_rom(&directory, "loop.gb", 0x00, &[0x18, 0xFE]); // JR -2
+    fs::write(directory.join("loop.sym"), "; rgblink\n00:0100 Main.loop\n").unwrap();
+
+    let output = emulator().arg("disasm").arg(&rom).args(["--bank", "0"]).output().unwrap();
+
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert!(listing.contains("Main.loop:\n00:0100: 18 FE     JR Main.loop\n"), "{}", listing);
+}
+
+#[test]
+fn disasm_rejects_missing_bank() {
+    let directory = temp_dir("disasm_bank");
+    let rom = write_rom(&directory, "small.gb", 0x00, &[]);
+
+    let output = emulator().arg("disasm").arg(&rom).args(["--bank", "2"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn debugger_reads_commands_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let directory = temp_dir("debug");
+    let program = [
+        0x3E, 0x05, // LD A, 0x05
+        0x3C,       // INC A
+        0x18, 0xFE, // JR -2
+    ];
+    let rom = write_rom(&directory, "debug.gb", 0x00, &program);
+
+    let mut child = emulator()
+        .arg(&rom)
+        .arg("--debug")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"break 0103\ncontinue\nregs\nquit\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("Breakpoint 1, 00:0103"), "{}", text);
+    assert!(text.contains("AF=06"), "{}", text);
+}
+
+#[test]
+fn movie_desync_is_reported_with_frame() {
+    use emulator::gameboy::GameBoy;
+    use emulator::movie::Movie;
+
+    let directory = temp_dir("movie");
+    let program = [
+        0x21, 0x00, 0xFF, // LD HL, 0xFF00
+        0x3E, 0x10,       // LD A, 0x10
+        0x77,             // LD (HL), A
+        0x11, 0x00, 0xC0, // LD DE, 0xC000
+        0x7E,             // LD A, (HL)
+        0x12,             // LD (DE), A
+        0x18, 0xFC,       // JR -4
+    ];
+    let rom = write_rom(&directory, "input.gb", 0x00, &program);
+    let mut gameboy = GameBoy::new(fs::read(&rom).unwrap()).unwrap();
+    let mut movie = Movie::power_on(&gameboy, None);
+    for buttons in [0x00, 0x80, 0x80, 0x00] {
+        movie.record_frame(&mut gameboy, buttons);
+    }
+    let path = directory.join("input.gbm");
+    fs::write(&path, movie.to_bytes()).unwrap();
+
+    let status = emulator().arg(&rom).args(["--headless", "--movie"]).arg(&path).status().unwrap();
+    assert!(status.success());
+
+    movie.frames[2].buttons = 0x00;
+    fs::write(&path, movie.to_bytes()).unwrap();
+    let output = emulator().arg(&rom).args(["--headless", "--movie"]).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("desync at frame 2"), "{}", stderr);
+}
+
+#[test]
+fn convert_writes_other_movie_formats() {
+    use emulator::gameboy::GameBoy;
+    use emulator::movie::Movie;
+    use emulator::vbm;
+
+    let directory = temp_dir("convert");
+    let rom = write_rom(&directory, "loop.gb", 0x00, &[0x18, 0xFE]); // JR -2
+    let mut gameboy = GameBoy::new(fs::read(&rom).unwrap()).unwrap();
+    let mut movie = Movie::power_on(&gameboy, None);
+    for buttons in [0x00, 0x10, 0x80] {
+        movie.record_frame(&mut gameboy, buttons);
+    }
+    let native = directory.join("loop.gbm");
+    fs::write(&native, movie.to_bytes()).unwrap();
+    let converted = directory.join("loop.vbm");
+
+    let status = emulator().arg("convert").arg(&rom).arg(&native).arg(&converted).status().unwrap();
+    assert!(status.success());
+    let imported = vbm::import(&fs::read(&converted).unwrap(), &fs::read(&rom).unwrap()).unwrap();
+    assert_eq!(imported.frames.iter().map(|frame| frame.buttons).collect::<Vec<_>>(), [0x00, 0x10, 0x80]);
+
+    // 変換したムービーもそのまま再生できる
+    let status = emulator().arg(&rom).args(["--headless", "--movie"]).arg(&converted).status().unwrap();
+    assert!(status.success());
+}
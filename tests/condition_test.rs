@@ -0,0 +1,98 @@
+use emulator::condition::Condition;
+use emulator::cpu::CPU;
+use emulator::symbols::Symbols;
+
+fn value(source: &str, cpu: &CPU) -> i64 {
+    Condition::parse(source).unwrap().value(cpu, 0)
+}
+
+#[test]
+fn numbers() {
+    let cpu = CPU::new();
+    assert_eq!(value("42", &cpu), 42);
+    assert_eq!(value("$FF", &cpu), 0xFF);
+    assert_eq!(value("0x150", &cpu), 0x150);
+    assert_eq!(value("%1010", &cpu), 10);
+    assert_eq!(value("-1", &cpu), -1);
+}
+
+#[test]
+fn registers_and_flags() {
+    let mut cpu = CPU::new();
+    cpu.registers.a = 5;
+    cpu.registers.set_hl(0xC123);
+    cpu.registers.f.carry = true;
+    cpu.pc = 0x0150;
+    cpu.sp = 0xC0F0;
+
+    assert_eq!(value("a", &cpu), 5);
+    assert_eq!(value("HL", &cpu), 0xC123);
+    assert_eq!(value("h", &cpu), 0xC1);
+    assert_eq!(value("cf", &cpu), 1);
+    assert_eq!(value("zf", &cpu), 0);
+    assert_eq!(value("f", &cpu), 0x10);
+    assert!(Condition::parse("pc == $0150 && a > 3").unwrap().holds(&cpu, 0));
+    assert!(Condition::parse("bank == 0 && sp < $C100").unwrap().holds(&cpu, 0));
+    assert!(!Condition::parse("pc == $0150 && a > 5").unwrap().holds(&cpu, 0));
+}
+
+#[test]
+fn memory() {
+    let mut cpu = CPU::new();
+    cpu.registers.set_hl(0xC000);
+    cpu.bus.write_byte(0xC000, 0xFF);
+    cpu.bus.write_byte(0xC001, 0x12);
+
+    assert!(Condition::parse("[hl] == $FF").unwrap().holds(&cpu, 0));
+    assert_eq!(value("[hl + 1]", &cpu), 0x12);
+    assert_eq!(value("[$C001] << 8 | [$C000]", &cpu), 0x12FF);
+}
+
+#[test]
+fn precedence() {
+    let cpu = CPU::new();
+    assert_eq!(value("1 + 2 * 3", &cpu), 7);
+    assert_eq!(value("(1 + 2) * 3", &cpu), 9);
+    assert_eq!(value("10 - 4 - 3", &cpu), 3);
+    assert_eq!(value("1 | 2 == 2", &cpu), 1);
+    assert_eq!(value("1 << 4 > 8", &cpu), 1);
+    assert_eq!(value("0 || 1 && 0", &cpu), 0);
+    assert_eq!(value("!0 + ~0", &cpu), 0);
+    assert_eq!(value("7 / 0", &cpu), 0);
+}
+
+#[test]
+fn hits() {
+    let cpu = CPU::new();
+    let condition = Condition::parse("hits >= 3").unwrap();
+    assert!(!condition.holds(&cpu, 2));
+    assert!(condition.holds(&cpu, 3));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(Condition::parse("ix == 0").unwrap_err(), "unknown variable: ix");
+    assert!(Condition::parse("a ==").is_err());
+    assert!(Condition::parse("(a").is_err());
+    assert!(Condition::parse("[hl").is_err());
+    assert!(Condition::parse("a 3").is_err());
+}
+
+#[test]
+fn display_keeps_source() {
+    assert_eq!(Condition::parse("  a > 3 ").unwrap().to_string(), "a > 3");
+}
+
+#[test]
+fn symbols_resolve_to_addresses() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0xC0A0, 7);
+    let symbols = Symbols::parse_sym("00:c0a0 wPlayerX\n00:0150 Main.loop\n").unwrap();
+
+    let condition = Condition::parse_with_symbols("[wPlayerX] == 7 && pc != Main.loop", &symbols).unwrap();
+    assert!(condition.holds(&cpu, 0));
+    // レジスタ名が優先される
+    let symbols = Symbols::parse_sym("00:c0a0 a\n").unwrap();
+    assert_eq!(Condition::parse_with_symbols("a", &symbols).unwrap().value(&cpu, 0), 0);
+    assert!(Condition::parse("[wPlayerX] == 7").is_err());
+}
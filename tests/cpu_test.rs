@@ -1,4 +1,7 @@
+use emulator::asm;
 use emulator::cpu::CPU;
+use emulator::flat_bus::{BusAccess, FlatBus};
+use emulator::watchpoint::Access;
 
 #[test]
 fn nop() {
@@ -12,11 +15,8 @@ fn nop() {
 fn ld_bc_d16() {
     let mut cpu = CPU::new();
 
-    // LD BC, 0x0305 という命令のバイト列
-    // 0x01: LD BC, d16 のオペコード
-    // 0x05: 16ビット即値の下位バイト (d16のLSB)
-    // 0x03: 16ビット即値の上位バイト (d16のMSB)
-    let program = vec![0x01, 0x05, 0x03];
+    // LD BC, 0x0305 という命令のバイト列（0x01 0x05 0x03）
+    let program = asm!("ld bc, $0305");
 
     // プログラムをメモリに書き込む
     for (i, &byte) in program.iter().enumerate() {
@@ -205,7 +205,7 @@ fn ld_a16_sp() {
     // 0x08: LD (a16), SP のオペコード
     // 0x34: アドレス下位バイト
     // 0x12: アドレス上位バイト
-    let program = vec![0x08, 0x34, 0x12];
+    let program = [0x08, 0x34, 0x12];
 
     // プログラムをメモリに書き込む
     for (i, &byte) in program.iter().enumerate() {
@@ -3646,6 +3646,7 @@ fn ret_nz() {
   assert_eq!(cpu.sp, 0xFFFC);
 }
 
+#[test]
 fn pop_bc() {
   let mut cpu = CPU::new();
   // スタックに値を積む
@@ -3684,6 +3685,7 @@ fn jp_nz_a16() {
     assert_eq!(cpu.pc, 0x03);
 }
 
+#[test]
 fn jp_a16() {
   let mut cpu = CPU::new();
   cpu.bus.write_byte(0x00, 0xC3); // JP a16
@@ -4275,3 +4277,297 @@ fn rst_38() {
     assert_eq!(cpu.bus.read_byte(0xFFFC), 0x01); // LSB (0x800+1)
     assert_eq!(cpu.bus.read_byte(0xFFFD), 0x08); // MSB
 }
+
+#[test]
+fn alu_d8() {
+    let mut cpu = CPU::new();
+    cpu.registers.a = 0x3C;
+    // SUB d8 / AND d8 / CP d8
+    for (i, &byte) in asm!("sub $0F", "and $F0", "cp $20").iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    cpu.step();
+    assert_eq!(cpu.registers.a, 0x2D);
+    assert!(cpu.registers.f.subtract);
+    assert!(cpu.registers.f.half_carry);
+    assert_eq!(cpu.pc, 0x02);
+    cpu.step();
+    assert_eq!(cpu.registers.a, 0x20);
+    cpu.step();
+    assert!(cpu.registers.f.zero);
+    assert_eq!(cpu.registers.a, 0x20);
+    assert_eq!(cpu.pc, 0x06);
+}
+
+#[test]
+fn ldh_and_ld_c() {
+    let mut cpu = CPU::new();
+    cpu.registers.a = 0x42;
+    cpu.registers.c = 0x81;
+    for (i, &byte) in asm!("ldh ($FF80), a", "ld a, (c)", "ld ($C000), a", "ld a, ($FF80)").iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    cpu.bus.write_byte(0xFF81, 0x99);
+    assert_eq!(cpu.step(), 12);
+    assert_eq!(cpu.bus.read_byte(0xFF80), 0x42);
+    assert_eq!(cpu.step(), 8);
+    assert_eq!(cpu.registers.a, 0x99);
+    assert_eq!(cpu.step(), 16);
+    assert_eq!(cpu.bus.read_byte(0xC000), 0x99);
+    assert_eq!(cpu.step(), 16);
+    assert_eq!(cpu.registers.a, 0x42);
+    assert_eq!(cpu.pc, 0x09);
+}
+
+#[test]
+fn add_sp_r8() {
+    let mut cpu = CPU::new();
+    cpu.sp = 0xFFF8;
+    // ADD SP,-1 (0xE8 0xFF)。フラグは下位バイトを符号なしで足したときの繰り上がり
+    cpu.bus.write_byte(0x00, 0xE8);
+    cpu.bus.write_byte(0x01, 0xFF);
+    assert_eq!(cpu.step(), 16);
+    assert_eq!(cpu.sp, 0xFFF7);
+    assert!(!cpu.registers.f.zero);
+    assert!(cpu.registers.f.half_carry);
+    assert!(cpu.registers.f.carry);
+    assert_eq!(cpu.pc, 0x02);
+}
+
+#[test]
+fn ld_hl_sp_r8_and_ld_sp_hl() {
+    let mut cpu = CPU::new();
+    cpu.sp = 0x1000;
+    cpu.registers.f.zero = true;
+    for (i, &byte) in asm!("ld hl, sp+$05", "ld sp, hl").iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    assert_eq!(cpu.step(), 12);
+    assert_eq!(cpu.registers.get_hl(), 0x1005);
+    assert!(!cpu.registers.f.zero);
+    assert!(!cpu.registers.f.carry);
+    cpu.registers.set_hl(0xD000);
+    assert_eq!(cpu.step(), 8);
+    assert_eq!(cpu.sp, 0xD000);
+}
+
+#[test]
+fn stop_skips_its_second_byte() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0x00, 0x10);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x02);
+}
+
+#[test]
+fn cb_rotates_and_shifts() {
+    let mut cpu = CPU::new();
+    cpu.registers.b = 0x85;
+    cpu.registers.c = 0x01;
+    cpu.registers.d = 0x81;
+    cpu.registers.e = 0xF0;
+    cpu.registers.f.carry = false;
+    for (i, &byte) in asm!("rlc b", "srl c", "sra d", "swap e", "rr b").iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    assert_eq!(cpu.step(), 8);
+    assert_eq!(cpu.registers.b, 0x0B);
+    assert!(cpu.registers.f.carry);
+    cpu.step();
+    assert_eq!(cpu.registers.c, 0x00);
+    assert!(cpu.registers.f.zero);
+    assert!(cpu.registers.f.carry);
+    cpu.step();
+    assert_eq!(cpu.registers.d, 0xC0);
+    assert!(cpu.registers.f.carry);
+    cpu.step();
+    assert_eq!(cpu.registers.e, 0x0F);
+    assert!(!cpu.registers.f.carry);
+    // キャリーは0なので、最上位ビットには0が入る
+    cpu.step();
+    assert_eq!(cpu.registers.b, 0x05);
+    assert!(cpu.registers.f.carry);
+    assert_eq!(cpu.pc, 0x0A);
+}
+
+#[test]
+fn cb_bit_res_set_hl() {
+    let mut cpu = CPU::new();
+    cpu.registers.set_hl(0xC000);
+    cpu.bus.write_byte(0xC000, 0x80);
+    cpu.registers.f.carry = true;
+    for (i, &byte) in asm!("bit 7, (hl)", "res 7, (hl)", "bit 7, (hl)", "set 0, (hl)").iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    assert_eq!(cpu.step(), 12);
+    assert!(!cpu.registers.f.zero);
+    assert!(cpu.registers.f.half_carry);
+    assert!(cpu.registers.f.carry); // BIT はキャリーを変えない
+    assert_eq!(cpu.step(), 16);
+    assert_eq!(cpu.bus.read_byte(0xC000), 0x00);
+    cpu.step();
+    assert!(cpu.registers.f.zero);
+    cpu.step();
+    assert_eq!(cpu.bus.read_byte(0xC000), 0x01);
+}
+
+#[test]
+fn halt() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0x00, 0x76); // HALT
+    cpu.step();
+    assert!(cpu.halted);
+    assert_eq!(cpu.pc, 0x01);
+
+    // 割り込みが保留されるまでPCは進まない
+    cpu.step();
+    assert_eq!(cpu.pc, 0x01);
+
+    // IME=0でも、保留された割り込みでHALTは解除される
+    cpu.bus.write_byte(0xFFFF, 0x01);
+    cpu.bus.write_byte(0xFF0F, 0x01);
+    cpu.bus.write_byte(0x01, 0x00); // NOP
+    cpu.step();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, 0x02);
+}
+
+#[test]
+fn illegal_opcode_locks_up() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0x00, 0xD3);
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.illegal_opcode, Some(0xD3));
+    assert_eq!(cpu.pc, 0x00);
+
+    // 割り込みでも抜けない
+    cpu.ime = true;
+    cpu.bus.write_byte(0xFFFF, 0x01);
+    cpu.bus.write_byte(0xFF0F, 0x01);
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.pc, 0x00);
+    assert_eq!(cpu.sp, 0x00);
+}
+
+#[test]
+fn di() {
+    let mut cpu = CPU::new();
+    cpu.ime = true;
+    cpu.bus.write_byte(0x00, 0xF3); // DI
+    cpu.step();
+    assert!(!cpu.ime);
+    assert_eq!(cpu.pc, 0x01);
+}
+
+#[test]
+fn ei() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0x00, 0xFB); // EI
+    cpu.bus.write_byte(0x01, 0x00); // NOP
+    cpu.step();
+    // EIの直後はまだ有効にならない
+    assert!(!cpu.ime);
+    assert_eq!(cpu.pc, 0x01);
+    cpu.step();
+    assert!(cpu.ime);
+}
+
+#[test]
+fn reti() {
+    let mut cpu = CPU::new();
+    cpu.sp = 0xFFFC;
+    cpu.bus.write_byte(0xFFFC, 0x34); // LSB
+    cpu.bus.write_byte(0xFFFD, 0x12); // MSB
+    cpu.bus.write_byte(0x00, 0xD9); // RETI
+    cpu.step();
+    assert_eq!(cpu.pc, 0x1234);
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert!(cpu.ime);
+}
+
+#[test]
+fn interrupt_dispatch() {
+    let mut cpu = CPU::new();
+    cpu.ime = true;
+    cpu.pc = 0x200;
+    cpu.sp = 0xFFFE;
+    cpu.bus.write_byte(0xFFFF, 0x05); // VBlankとタイマーを許可
+    cpu.bus.write_byte(0xFF0F, 0x04); // タイマー割り込みを要求
+    let cycles = cpu.step();
+    assert_eq!(cycles, 20);
+    assert_eq!(cpu.pc, 0x50);
+    assert!(!cpu.ime);
+    assert_eq!(cpu.bus.read_byte(0xFF0F) & 0x1F, 0x00);
+    assert_eq!(cpu.bus.read_byte(0xFFFC), 0x00); // LSB
+    assert_eq!(cpu.bus.read_byte(0xFFFD), 0x02); // MSB
+}
+
+#[test]
+fn conditional_branch_cycles() {
+    let mut cpu = CPU::new();
+    cpu.registers.f.zero = false;
+    cpu.bus.write_byte(0x00, 0x20); // JR NZ, r8
+    cpu.bus.write_byte(0x01, 0x02);
+    assert_eq!(cpu.step(), 12);
+
+    let mut cpu = CPU::new();
+    cpu.registers.f.zero = true;
+    cpu.bus.write_byte(0x00, 0x20);
+    cpu.bus.write_byte(0x01, 0x02);
+    assert_eq!(cpu.step(), 8);
+}
+
+#[test]
+fn flat_bus_records_accesses_in_order() {
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    cpu.bus.memory[0x0000] = 0xC5; // PUSH BC
+    cpu.sp = 0xD000;
+    cpu.registers.set_bc(0x1234);
+
+    assert_eq!(cpu.step(), 16);
+
+    // フェッチ、SPを減らす内部サイクル、上位バイト、下位バイトの順に1Mサイクルずつ
+    let access = |access, address, value, cycle| BusAccess { access, address, value, cycle };
+    assert_eq!(
+        cpu.bus.take_accesses(),
+        vec![
+            access(Access::Read, 0x0000, 0xC5, 0),
+            access(Access::Write, 0xCFFF, 0x12, 8),
+            access(Access::Write, 0xCFFE, 0x34, 12),
+        ]
+    );
+    // step がかかったサイクル数だけバスを進める
+    assert_eq!(cpu.bus.cycles, 16);
+    assert!(cpu.bus.accesses().is_empty());
+}
+
+#[test]
+fn memory_accesses_happen_on_their_own_m_cycles() {
+    // INC (HL) は読んでから1Mサイクルあとに書く
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    cpu.bus.memory[0x0000] = 0x34; // INC (HL)
+    cpu.bus.memory[0xC000] = 0x41;
+    cpu.registers.set_hl(0xC000);
+    assert_eq!(cpu.step(), 12);
+    let cycles: Vec<(Access, u16, u64)> = cpu.bus.take_accesses().iter().map(|a| (a.access, a.address, a.cycle)).collect();
+    assert_eq!(cycles, vec![(Access::Read, 0x0000, 0), (Access::Read, 0xC000, 4), (Access::Write, 0xC000, 8)]);
+
+    // LD (a16),SP は下位バイトを書いた次のMサイクルで上位バイトを書く
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    cpu.bus.memory[0x0000..0x0003].copy_from_slice(&[0x08, 0x00, 0xC1]); // LD ($C100),SP
+    cpu.sp = 0xBEEF;
+    assert_eq!(cpu.step(), 20);
+    let cycles: Vec<(Access, u16, u8, u64)> =
+        cpu.bus.take_accesses().iter().map(|a| (a.access, a.address, a.value, a.cycle)).collect();
+    assert_eq!(
+        cycles,
+        vec![
+            (Access::Read, 0x0000, 0x08, 0),
+            (Access::Read, 0x0001, 0x00, 4),
+            (Access::Read, 0x0002, 0xC1, 8),
+            (Access::Write, 0xC100, 0xEF, 12),
+            (Access::Write, 0xC101, 0xBE, 16),
+        ]
+    );
+    assert_eq!(cpu.bus.cycles, 20);
+}
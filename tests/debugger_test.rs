@@ -0,0 +1,440 @@
+use emulator::assembler::assemble_at;
+use emulator::debugger::{parse_location, Debugger};
+use emulator::gameboy::GameBoy;
+use emulator::symbols::Symbols;
+
+// 0x0100から始まるプログラムをアセンブルして、MBCなしのROMに置く
+fn gameboy_with_program(source: &str) -> GameBoy {
+    let program = assemble_at(source, 0x0100).unwrap();
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    GameBoy::new(rom).unwrap()
+}
+
+const PROGRAM: &str = "
+    ld a, 0          ; 0100
+    call Add3        ; 0102
+    call Add3        ; 0105
+Loop:
+    jr Loop          ; 0108
+Add3:
+    inc a            ; 010A
+    inc a            ; 010B
+    inc a            ; 010C
+    ret              ; 010D
+";
+
+#[test]
+fn step_executes_instructions() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "step").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x0102);
+    assert!(output.contains("0102: CD 0A 01  CALL $010A"), "{}", output);
+
+    debugger.execute(&mut gameboy, "step 2").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010B);
+}
+
+#[test]
+fn next_steps_over_call() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "step").unwrap();
+    debugger.execute(&mut gameboy, "next").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x0105);
+    assert_eq!(gameboy.cpu.registers.a, 3);
+}
+
+#[test]
+fn finish_runs_until_return() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "step 3").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010B);
+    debugger.execute(&mut gameboy, "finish").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x0105);
+    assert_eq!(gameboy.cpu.sp, 0xFFFE);
+}
+
+#[test]
+fn continue_stops_at_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "break $010D").unwrap(), "Breakpoint 1 at 010D");
+    let output = debugger.execute(&mut gameboy, "continue").unwrap();
+    assert!(output.starts_with("Breakpoint 1, 00:010D"), "{}", output);
+    assert_eq!(gameboy.cpu.registers.a, 3);
+
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010D);
+    assert_eq!(gameboy.cpu.registers.a, 6);
+}
+
+#[test]
+fn step_stops_at_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break 010A").unwrap();
+    let output = debugger.execute(&mut gameboy, "step 10").unwrap();
+    assert!(output.starts_with("Breakpoint 1"));
+    assert_eq!(gameboy.cpu.pc, 0x010A);
+}
+
+#[test]
+fn banked_breakpoint_only_matches_its_bank() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break 01:010A").unwrap();
+    debugger.execute(&mut gameboy, "break 00:010D").unwrap();
+    let output = debugger.execute(&mut gameboy, "continue").unwrap();
+    assert!(output.starts_with("Breakpoint 2"), "{}", output);
+}
+
+#[test]
+fn delete_removes_breakpoints() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break 010A").unwrap();
+    debugger.execute(&mut gameboy, "break 010D").unwrap();
+    debugger.execute(&mut gameboy, "delete 1").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "break").unwrap(), "2: 010D");
+    assert!(debugger.execute(&mut gameboy, "delete 1").is_err());
+
+    debugger.execute(&mut gameboy, "delete").unwrap();
+    assert!(debugger.breakpoints().is_empty());
+}
+
+#[test]
+fn regs_and_set() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "set bc $1234").unwrap();
+    assert!(output.contains("BC=1234"), "{}", output);
+    debugger.execute(&mut gameboy, "set a 42").unwrap();
+    assert_eq!(gameboy.cpu.registers.a, 0x42);
+    debugger.execute(&mut gameboy, "set pc 0x010A").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010A);
+
+    assert!(debugger.execute(&mut gameboy, "set a 100").is_err());
+    assert!(debugger.execute(&mut gameboy, "set ix 0").is_err());
+    let regs = debugger.execute(&mut gameboy, "regs").unwrap();
+    assert!(regs.contains("AF=42B0") && regs.contains("PC=010A"), "{}", regs);
+}
+
+#[test]
+fn mem_dumps_bytes() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "mem 0100 4").unwrap(), "0100: 3E 00 CD 0A");
+    let output = debugger.execute(&mut gameboy, "mem 0100 20").unwrap();
+    assert_eq!(output.lines().count(), 2);
+    assert!(output.lines().nth(1).unwrap().starts_with("0110: "));
+}
+
+#[test]
+fn disasm_marks_current_instruction() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "disasm").unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "=> 00:0100: 3E 00     LD A,$00");
+    assert_eq!(lines[1], "   00:0102: CD 0A 01  CALL $010A");
+}
+
+#[test]
+fn unknown_command_is_error() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    assert!(debugger.execute(&mut gameboy, "jump 0100").is_err());
+    assert!(debugger.execute(&mut gameboy, "step many").is_err());
+    assert_eq!(debugger.execute(&mut gameboy, "").unwrap(), "");
+}
+
+#[test]
+fn locations() {
+    assert_eq!(parse_location("0150"), Ok((None, 0x0150)));
+    assert_eq!(parse_location("$0150"), Ok((None, 0x0150)));
+    assert_eq!(parse_location("02:4000"), Ok((Some(2), 0x4000)));
+    assert!(parse_location("zz").is_err());
+}
+
+#[test]
+fn watch_stops_on_write() {
+    let mut gameboy = gameboy_with_program(
+        "ld hl, $C000
+        ld (hl), 1
+        ld (hl), 2
+        Loop: jr Loop",
+    );
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "watch write C000 2").unwrap(), "Watchpoint 1: write C000 == 02");
+    assert_eq!(debugger.execute(&mut gameboy, "watch").unwrap(), "1: write C000 == 02");
+    let output = debugger.execute(&mut gameboy, "continue").unwrap();
+    assert!(output.starts_with("Watchpoint 1: write C000 01 -> 02 (PC=0105)"), "{}", output);
+    assert_eq!(gameboy.cpu.pc, 0x0107);
+
+    debugger.execute(&mut gameboy, "unwatch 1").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "watch").unwrap(), "No watchpoints");
+    assert!(debugger.execute(&mut gameboy, "watch sometimes C000").is_err());
+    assert!(debugger.execute(&mut gameboy, "watch read C100-C000").is_err());
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "break 010D if a > 3").unwrap();
+    assert_eq!(output, "Breakpoint 1 at 010D if a > 3");
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010D);
+    assert_eq!(gameboy.cpu.registers.a, 6);
+}
+
+#[test]
+fn condition_only_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break if a == 2").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "break").unwrap(), "1: any address if a == 2");
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010C);
+}
+
+#[test]
+fn hit_count_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break 010A if hits == 2").unwrap();
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.pc, 0x010A);
+    assert_eq!(gameboy.cpu.registers.a, 3);
+}
+
+#[test]
+fn cond_changes_condition() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    debugger.execute(&mut gameboy, "break 010D").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "cond 1 a == 6").unwrap(), "Breakpoint 1 at 010D if a == 6");
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.registers.a, 6);
+
+    assert_eq!(debugger.execute(&mut gameboy, "cond 1").unwrap(), "Breakpoint 1 at 010D");
+    assert!(debugger.execute(&mut gameboy, "cond 2 a == 1").is_err());
+    assert!(debugger.execute(&mut gameboy, "cond 1 a ==").is_err());
+}
+
+#[test]
+fn print_evaluates_expression() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "print pc + 2").unwrap(), "pc + 2 = $102 (258)");
+    assert_eq!(debugger.execute(&mut gameboy, "p [pc]").unwrap(), "[pc] = $3E (62)");
+    assert!(debugger.execute(&mut gameboy, "regs if a == 0").is_err());
+}
+
+#[test]
+fn watch_with_condition() {
+    let mut gameboy = gameboy_with_program(
+        "ld hl, $C000
+        Loop: inc (hl)
+        jr Loop",
+    );
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "watch write C000 if [hl] == 5 && hits > 1").unwrap();
+    assert_eq!(output, "Watchpoint 1: write C000 if [hl] == 5 && hits > 1");
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.bus.read_byte(0xC000), 5);
+}
+
+#[test]
+fn backtrace_shows_calls() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "bt").unwrap(), "#0  00:0100");
+    debugger.execute(&mut gameboy, "break 010B").unwrap();
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "bt").unwrap(), "#0  00:010B\n#1  00:0102  call 00:010A  SP=FFFC");
+
+    debugger.execute(&mut gameboy, "finish").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "backtrace").unwrap(), "#0  00:0105");
+}
+
+fn gameboy_with_symbols() -> GameBoy {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    gameboy.symbols = Symbols::parse_sym("00:0100 Main\n00:0108 Main.loop\n00:010a Add3\n00:c0a0 wPlayerX\n").unwrap();
+    gameboy.symbols.load_map("WRAM0 bank #0:\n\tSECTION: $c000-$c0ff ($0100 bytes) [\"Variables\"]\n");
+    gameboy
+}
+
+#[test]
+fn break_on_label() {
+    let mut gameboy = gameboy_with_symbols();
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "break Main.loop").unwrap(), "Breakpoint 1 at 0108");
+    let output = debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(output, "Breakpoint 1, 00:0108: 18 FE     JR Main.loop  <Main.loop>");
+    assert!(debugger.execute(&mut gameboy, "break Nowhere").is_err());
+}
+
+#[test]
+fn watch_label() {
+    let mut gameboy = gameboy_with_symbols();
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.execute(&mut gameboy, "watch write wPlayerX").unwrap(), "Watchpoint 1: write C0A0");
+    assert_eq!(debugger.execute(&mut gameboy, "print [wPlayerX] + 1").unwrap(), "[wPlayerX] + 1 = $1 (1)");
+}
+
+#[test]
+fn labels_in_listings() {
+    let mut gameboy = gameboy_with_symbols();
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "disasm Main").unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "Main:");
+    assert_eq!(lines[1], "=> 00:0100: 3E 00     LD A,$00");
+    assert_eq!(lines[2], "   00:0102: CD 0A 01  CALL Add3");
+
+    let output = debugger.execute(&mut gameboy, "mem C000 32").unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].ends_with("  ; WRAM0 \"Variables\""), "{}", lines[0]);
+    assert!(!lines[1].contains(';'));
+
+    debugger.execute(&mut gameboy, "step 2").unwrap();
+    assert_eq!(debugger.execute(&mut gameboy, "bt").unwrap(), "#0  00:010A <Add3>\n#1  00:0102 <Main+$2>  call 00:010A <Add3>  SP=FFFC");
+}
+
+// C000〜C0FF に順に、書き込むたびに1ずつ大きい値を書き続ける
+const COUNTER: &str = "
+    ld hl, $C000
+    ld a, 0
+Loop:
+    inc a
+    ld [hl], a
+    inc l
+    jr Loop
+";
+
+#[test]
+fn reverse_step_returns_to_earlier_instructions() {
+    let mut gameboy = gameboy_with_program(COUNTER);
+    let mut reference = gameboy_with_program(COUNTER);
+    let mut debugger = Debugger::new();
+    assert!(debugger.execute(&mut gameboy, "reverse-step").is_err());
+
+    // チェックポイントは1フレームごとなので、いくつかのチェックポイントをまたいで戻る
+    debugger.execute(&mut gameboy, "step 30000").unwrap();
+    debugger.execute(&mut gameboy, "reverse-step").unwrap();
+    debugger.execute(&mut gameboy, "rs 19999").unwrap();
+    for _ in 0..10000 {
+        reference.step();
+    }
+    assert_eq!(gameboy.save_state(), reference.save_state());
+
+    // 戻ったところから進めても同じ実行になる
+    debugger.execute(&mut gameboy, "step 10000").unwrap();
+    for _ in 0..10000 {
+        reference.step();
+    }
+    assert_eq!(gameboy.save_state(), reference.save_state());
+
+    let output = debugger.execute(&mut gameboy, "rs 100000").unwrap();
+    assert!(output.starts_with("Reached the oldest checkpoint\n00:0100:"), "{}", output);
+    assert_eq!(gameboy.cycles, 0);
+}
+
+#[test]
+fn reverse_continue_returns_to_previous_breakpoint() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+    debugger.execute(&mut gameboy, "break 010A if hits <= 2").unwrap();
+
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    debugger.execute(&mut gameboy, "continue").unwrap();
+    assert_eq!(gameboy.cpu.registers.a, 3);
+
+    let output = debugger.execute(&mut gameboy, "reverse-continue").unwrap();
+    assert!(output.starts_with("Breakpoint 1, 00:010A"), "{}", output);
+    assert_eq!(gameboy.cpu.registers.a, 0);
+    // hits もその時点の値に戻る
+    assert_eq!(debugger.breakpoints()[0].hits, 1);
+    // コールスタックもその時点のものに戻る
+    let backtrace = debugger.execute(&mut gameboy, "bt").unwrap();
+    assert!(backtrace.contains("#1  00:0102  call 00:010A"), "{}", backtrace);
+
+    let output = debugger.execute(&mut gameboy, "rc").unwrap();
+    assert!(output.starts_with("No earlier breakpoint or watchpoint hit"), "{}", output);
+    assert_eq!(gameboy.cpu.pc, 0x0100);
+}
+
+#[test]
+fn reverse_continue_finds_last_write() {
+    let mut gameboy = gameboy_with_program(COUNTER);
+    let mut debugger = Debugger::new();
+    debugger.execute(&mut gameboy, "step 20000").unwrap();
+    let value = gameboy.cpu.bus.peek_byte(0xC005);
+    debugger.execute(&mut gameboy, "watch write C005").unwrap();
+
+    let output = debugger.execute(&mut gameboy, "rc").unwrap();
+    assert!(output.starts_with("Watchpoint 1: write C005 "), "{}", output);
+    assert!(output.contains(&format!(" -> {:02X}", value)), "{}", output);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xC005), value);
+
+    // レジスタを書き換えると、それより前には戻れない
+    debugger.execute(&mut gameboy, "set a 0").unwrap();
+    assert!(debugger.execute(&mut gameboy, "rc").is_err());
+}
+
+#[test]
+fn mem_length_is_decimal() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "mem c000 16").unwrap();
+    assert_eq!(output.lines().count(), 1);
+    assert_eq!(output.split_whitespace().count(), 17, "{}", output);
+    assert!(debugger.execute(&mut gameboy, "mem c000 1f").is_err());
+}
+
+#[test]
+fn continue_gives_up_after_frame_limit() {
+    let mut gameboy = gameboy_with_program(PROGRAM);
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "continue 2").unwrap();
+    assert!(output.starts_with("No breakpoint hit in 2 frames, "), "{}", output);
+    assert_eq!(gameboy.cpu.pc, 0x0108);
+    assert!(gameboy.cycles >= 2 * 70224);
+}
+
+#[test]
+fn continue_stops_when_cpu_locks_up() {
+    let mut gameboy = gameboy_with_program("nop\nnop\ndb $D3");
+    let mut debugger = Debugger::new();
+
+    let output = debugger.execute(&mut gameboy, "continue").unwrap();
+    assert!(output.starts_with("CPU locked up on illegal opcode $D3, 00:0102"), "{}", output);
+}
@@ -0,0 +1,130 @@
+use emulator::cpu::{Bus, CPU};
+use emulator::disassembler::{disassemble, disassemble_at, disassemble_range, disassemble_with_symbols};
+use emulator::flat_bus::FlatBus;
+use emulator::symbols::Symbols;
+use emulator::instruction::*;
+
+#[test]
+fn instruction_display_uses_table_notation() {
+    let instruction = Instruction::from_byte(0x22, false).unwrap();
+    assert_eq!(instruction.to_string(), "LD (HL+),A");
+    assert_eq!(Instruction::from_byte(0x20, false).unwrap().to_string(), "JR NZ,r8");
+    assert_eq!(Instruction::from_byte(0xE9, false).unwrap().to_string(), "JP HL");
+    assert_eq!(Instruction::from_byte(0xFF, false).unwrap().to_string(), "RST $38");
+    assert_eq!(Instruction::from_byte(0x06, true).unwrap().to_string(), "RLC (HL)");
+}
+
+#[test]
+fn instruction_debug_shows_variants() {
+    let instruction = Instruction::from_byte(0x3A, false).unwrap();
+    assert_eq!(format!("{:?}", instruction), "LD(Byte(A, HLIM))");
+    assert_eq!(instruction, Instruction::LD(LoadType::Byte(Operand8::A, Operand8::HLIM)));
+}
+
+#[test]
+fn single_byte_instruction() {
+    let disassembly = disassemble(&[0x22], 0x0150);
+    assert_eq!(disassembly.text, "LD (HL+),A");
+    assert_eq!(disassembly.length(), 1);
+    assert_eq!(disassembly.next_address(), 0x0151);
+}
+
+#[test]
+fn immediate_operands() {
+    assert_eq!(disassemble(&[0x01, 0x05, 0x03], 0).text, "LD BC,$0305");
+    assert_eq!(disassemble(&[0x3E, 0x42], 0).text, "LD A,$42");
+    assert_eq!(disassemble(&[0xC3, 0x50, 0x01], 0).text, "JP $0150");
+    assert_eq!(disassemble(&[0xC4, 0x00, 0x40], 0).text, "CALL NZ,$4000");
+    assert_eq!(disassemble(&[0x08, 0x00, 0xC0], 0).text, "LD ($C000),SP");
+    assert_eq!(disassemble(&[0xC3, 0x50, 0x01], 0).length(), 3);
+}
+
+#[test]
+fn relative_jump_shows_target_address() {
+    assert_eq!(disassemble(&[0x20, 0x0E], 0x0140).text, "JR NZ,$0150");
+    assert_eq!(disassemble(&[0x18, 0xFE], 0x0100).text, "JR $0100");
+}
+
+#[test]
+fn prefixed_instruction_is_two_bytes() {
+    let disassembly = disassemble(&[0xCB, 0x07], 0);
+    assert_eq!(disassembly.text, "RLC A");
+    assert_eq!(disassembly.bytes, vec![0xCB, 0x07]);
+}
+
+#[test]
+fn unknown_opcode_is_data_byte() {
+    let disassembly = disassemble(&[0xD3, 0x00], 0);
+    assert_eq!(disassembly.text, "DB $D3");
+    assert_eq!(disassembly.length(), 1);
+}
+
+#[test]
+fn truncated_operand_is_data_byte() {
+    let disassembly = disassemble(&[0xC3, 0x50], 0x3FFE);
+    assert_eq!(disassembly.text, "DB $C3");
+    assert_eq!(disassembly.length(), 1);
+}
+
+#[test]
+fn display_includes_address_and_bytes() {
+    let disassembly = disassemble(&[0xC3, 0x50, 0x01], 0x0100);
+    assert_eq!(disassembly.to_string(), "0100: C3 50 01  JP $0150");
+}
+
+#[test]
+fn range_walks_instruction_boundaries() {
+    let lines = disassemble_range(&[0x00, 0x3E, 0x01, 0xC3, 0x00, 0x01], 0x0100);
+    let addresses: Vec<u16> = lines.iter().map(|line| line.address).collect();
+    assert_eq!(addresses, vec![0x0100, 0x0101, 0x0103]);
+    assert_eq!(lines[2].text, "JP $0100");
+}
+
+#[test]
+fn disassemble_from_bus() {
+    let mut cpu = CPU::new();
+    cpu.bus.write_byte(0xC000, 0x21);
+    cpu.bus.write_byte(0xC001, 0x34);
+    cpu.bus.write_byte(0xC002, 0x12);
+    assert_eq!(disassemble_at(&cpu.bus, 0xC000).text, "LD HL,$1234");
+
+    // peek で読むので、FlatBus のアクセスの記録には残らない
+    let mut bus = FlatBus::new();
+    bus.write(0x0000, 0xCD);
+    bus.take_accesses();
+    assert_eq!(disassemble_at(&bus, 0x0000).text, "CALL $0000");
+    assert!(bus.accesses().is_empty());
+}
+
+#[test]
+fn symbols_replace_addresses() {
+    let symbols = Symbols::parse_sym("00:0150 Main\n00:0153 Main.loop\n02:4000 Far\n00:c000 wCounter\n").unwrap();
+
+    assert_eq!(disassemble_with_symbols(&[0xC3, 0x50, 0x01], 0x0100, &symbols, 1).text, "JP Main");
+    assert_eq!(disassemble_with_symbols(&[0x18, 0xFE], 0x0153, &symbols, 1).text, "JR Main.loop");
+    assert_eq!(disassemble_with_symbols(&[0xCD, 0x00, 0x40], 0x0150, &symbols, 2).text, "CALL Far");
+    assert_eq!(disassemble_with_symbols(&[0xCD, 0x00, 0x40], 0x0150, &symbols, 1).text, "CALL $4000");
+    assert_eq!(disassemble_with_symbols(&[0x08, 0x00, 0xC0], 0, &symbols, 1).text, "LD (wCounter),SP");
+    // d16 は定数のことも多いので数値のまま
+    assert_eq!(disassemble_with_symbols(&[0x21, 0x00, 0xC0], 0, &symbols, 1).text, "LD HL,$C000");
+}
+
+// デコード表の命令長が、命令表の表記（d8/d16/a8/a16/r8）から分かるオペランドの長さと合っている
+#[test]
+fn opcode_lengths_match_operand_notation() {
+    for (prefixed, table) in [(false, &OPCODES), (true, &CB_OPCODES)] {
+        for info in table.iter() {
+            let Some(instruction) = info.instruction else { continue };
+            let template = instruction.to_string();
+            let operands = if template.contains("d16") || template.contains("a16") {
+                2
+            } else if template.contains("d8") || template.contains("a8") || template.contains("r8") {
+                1
+            } else {
+                0
+            };
+            let opcode_length = if prefixed { 2 } else { 1 };
+            assert_eq!(info.length, opcode_length + operands, "{}", template);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use emulator::gameboy::{GameBoy, Model, CYCLES_PER_FRAME};
+use emulator::joypad::Button;
+
+// 0x0100から指定したプログラムを置いた、MBCなしの32KB ROMを作る
+fn rom_with_program(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x147] = 0x00; // ROM ONLY
+    rom
+}
+
+#[test]
+fn new_starts_from_post_boot_state() {
+    let gameboy = GameBoy::new(rom_with_program(&[0x00])).unwrap();
+
+    assert_eq!(gameboy.cpu.pc, 0x0100);
+    assert_eq!(gameboy.cpu.sp, 0xFFFE);
+    assert_eq!(gameboy.cpu.registers.get_af(), 0x01B0);
+    assert_eq!(gameboy.cpu.registers.get_hl(), 0x014D);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xFF40), 0x91); // LCDオン
+}
+
+#[test]
+fn new_rejects_rom_without_header() {
+    assert!(GameBoy::new(vec![0; 0x100]).is_err());
+}
+
+#[test]
+fn step_advances_peripherals_by_consumed_cycles() {
+    // LD BC, d16 (12サイクル)
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x01, 0x34, 0x12])).unwrap();
+    let div_counter = gameboy.cpu.bus.timer.counter;
+
+    let cycles = gameboy.step();
+
+    assert_eq!(cycles, 12);
+    assert_eq!(gameboy.cycles, 12);
+    assert_eq!(gameboy.cpu.bus.timer.counter, div_counter.wrapping_add(12));
+}
+
+#[test]
+fn run_frame_stops_at_vblank() {
+    // JR -2 で無限ループ
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x18, 0xFE])).unwrap();
+
+    gameboy.run_frame();
+    assert_eq!(gameboy.cpu.bus.ppu.ly, 144);
+
+    let before = gameboy.cycles;
+    gameboy.run_frame();
+    let elapsed = gameboy.cycles - before;
+    assert!((CYCLES_PER_FRAME..CYCLES_PER_FRAME + 12).contains(&elapsed));
+    assert_eq!(gameboy.framebuffer().len(), 160 * 144);
+}
+
+#[test]
+fn run_cycles_runs_at_least_requested_cycles() {
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x18, 0xFE])).unwrap();
+
+    gameboy.run_cycles(1000);
+
+    assert!((1000..1012).contains(&gameboy.cycles));
+}
+
+#[test]
+fn run_until_stops_when_predicate_holds() {
+    // INC B; JR -3
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x04, 0x18, 0xFD])).unwrap();
+
+    gameboy.run_until(|gb| gb.cpu.registers.b == 0x05);
+
+    assert_eq!(gameboy.cpu.registers.b, 0x05);
+}
+
+#[test]
+fn timer_interrupt_wakes_halt_and_jumps_to_vector() {
+    let program = [
+        0x3E, 0x05,       // LD A, 0x05 (タイマー有効、16サイクル周期)
+        0x21, 0x07, 0xFF, // LD HL, 0xFF07
+        0x77,             // LD (HL), A
+        0x2E, 0xFF,       // LD L, 0xFF
+        0x3E, 0x04,       // LD A, 0x04
+        0x77,             // LD (HL), A (IE = タイマー)
+        0x2E, 0x0F,       // LD L, 0x0F
+        0xAF,             // XOR A
+        0x77,             // LD (HL), A (IF = 0)
+        0xFB,             // EI
+        0x76,             // HALT
+    ];
+    let mut gameboy = GameBoy::new(rom_with_program(&program)).unwrap();
+
+    gameboy.run_until(|gb| gb.cpu.pc == 0x0050);
+
+    assert!(!gameboy.cpu.ime);
+    assert!(!gameboy.cpu.halted);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF0F) & 0x04, 0);
+    // 戻り先はHALTの次の命令
+    assert_eq!(gameboy.cpu.bus.read_byte(gameboy.cpu.sp), 0x11);
+    assert_eq!(gameboy.cpu.bus.read_byte(gameboy.cpu.sp + 1), 0x01);
+}
+
+#[test]
+fn pressed_buttons_are_visible_through_p1() {
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x00])).unwrap();
+
+    gameboy.press(Button::A);
+    gameboy.press(Button::Down);
+
+    // ボタン側を選択
+    gameboy.cpu.bus.write_byte(0xFF00, 0x10);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF00) & 0x0F, 0x0E);
+    // 方向キー側を選択
+    gameboy.cpu.bus.write_byte(0xFF00, 0x20);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF00) & 0x0F, 0x07);
+    assert_ne!(gameboy.cpu.bus.read_byte(0xFF0F) & 0x10, 0);
+
+    gameboy.release(Button::A);
+    assert_eq!(gameboy.buttons(), Button::Down.mask());
+}
+
+#[test]
+fn audio_buffer_collects_samples() {
+    let mut gameboy = GameBoy::new(rom_with_program(&[0x18, 0xFE])).unwrap();
+
+    gameboy.run_frame();
+    gameboy.run_frame();
+
+    let samples = gameboy.take_audio_buffer();
+    // 1フレーム（約1/60秒）あたり約735サンプル × 2チャンネル
+    assert!(samples.len() > 1400);
+    assert!(gameboy.audio_buffer().is_empty());
+}
+
+#[test]
+fn serial_transfer_captures_output_and_requests_interrupt() {
+    let program = [
+        0x3E, 0x4F,       // LD A, 'O'
+        0x21, 0x01, 0xFF, // LD HL, 0xFF01
+        0x77,             // LD (HL), A (SB)
+        0x2E, 0x02,       // LD L, 0x02
+        0x3E, 0x81,       // LD A, 0x81 (内部クロックで転送開始)
+        0x77,             // LD (HL), A (SC)
+        0x18, 0xFE,       // JR -2
+    ];
+    let mut gameboy = GameBoy::new(rom_with_program(&program)).unwrap();
+
+    gameboy.run_cycles(100);
+    assert_eq!(gameboy.serial_output(), b"O");
+    assert_ne!(gameboy.cpu.bus.read_byte(0xFF02) & 0x80, 0);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF0F) & 0x08, 0);
+
+    // 1バイトの転送は4096サイクルで終わる
+    gameboy.run_cycles(4096);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF02) & 0x80, 0);
+    assert_eq!(gameboy.cpu.bus.read_byte(0xFF01), 0xFF);
+    assert_ne!(gameboy.cpu.bus.read_byte(0xFF0F) & 0x08, 0);
+
+    assert_eq!(gameboy.take_serial_output(), b"O");
+    assert!(gameboy.serial_output().is_empty());
+}
+
+#[test]
+fn ld_b_b_stops_run_only_when_enabled() {
+    let program = [
+        0x06, 0x03, // LD B, 0x03
+        0x40,       // LD B, B
+        0x0E, 0x05, // LD C, 0x05
+        0x18, 0xFE, // JR -2
+    ];
+    let mut gameboy = GameBoy::new(rom_with_program(&program)).unwrap();
+    gameboy.run_cycles(100);
+    assert!(!gameboy.cpu.software_breakpoint_hit);
+    assert_eq!(gameboy.cpu.registers.c, 0x05);
+
+    let mut gameboy = GameBoy::new(rom_with_program(&program)).unwrap();
+    gameboy.cpu.break_on_ld_b_b = true;
+    gameboy.run_cycles(100);
+    assert!(gameboy.cpu.software_breakpoint_hit);
+    // LD B,B を実行し終えたところで止まる
+    assert_eq!(gameboy.cpu.pc, 0x0103);
+    assert_eq!(gameboy.cpu.registers.b, 0x03);
+
+    gameboy.cpu.software_breakpoint_hit = false;
+    gameboy.run_cycles(100);
+    assert_eq!(gameboy.cpu.registers.c, 0x05);
+}
+
+#[test]
+fn boot_rom_is_mapped_until_disabled() {
+    let mut boot_rom = vec![0; 0x100];
+    // LD A, 0x01; LDH (0x50), A は未実装なので LD HL, 0xFF50; LD (HL), A で無効化する
+    boot_rom[..6].copy_from_slice(&[0x3E, 0x01, 0x21, 0x50, 0xFF, 0x77]);
+    let mut rom = rom_with_program(&[0x00]);
+    rom[0x0000] = 0xAA;
+    let mut gameboy = GameBoy::with_config(rom, Model::Dmg, Some(boot_rom)).unwrap();
+
+    assert_eq!(gameboy.cpu.pc, 0x0000);
+    assert_eq!(gameboy.cpu.bus.read_byte(0x0000), 0x3E);
+
+    gameboy.step();
+    gameboy.step();
+    gameboy.step();
+
+    assert_eq!(gameboy.cpu.bus.read_byte(0x0000), 0xAA);
+}
+
+#[test]
+fn cgb_model_starts_with_cgb_registers() {
+    let gameboy = GameBoy::with_config(rom_with_program(&[0x00]), Model::Cgb, None).unwrap();
+
+    assert_eq!(gameboy.cpu.registers.a, 0x11);
+    assert_eq!(gameboy.model, Model::Cgb);
+}
@@ -0,0 +1,211 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use emulator::asm;
+use emulator::gameboy::GameBoy;
+use emulator::gdb::{GdbServer, TARGET_XML};
+
+const PROGRAM: &str = "
+    ld a, 5
+    ld hl, $C000
+    ld (hl), a
+    inc a
+Loop:
+    jr Loop";
+
+// RSP を1パケットずつ送って応答を読む、テスト用の GDB の代わり
+struct Client {
+    stream: TcpStream,
+    server: JoinHandle<()>,
+    no_ack: bool,
+}
+
+impl Client {
+    fn connect() -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let program = asm!(PROGRAM);
+            let mut rom = vec![0; 0x8000];
+            rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+            let mut gameboy = GameBoy::new(rom).unwrap();
+            GdbServer::new().accept(&mut gameboy, &listener).unwrap();
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        Client { stream, server, no_ack: false }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+        if !self.no_ack {
+            assert_eq!(self.read_byte(), b'+');
+        }
+    }
+
+    fn receive(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let digits = [self.read_byte(), self.read_byte()];
+        let checksum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 16).unwrap(), checksum);
+        if !self.no_ack {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+
+    fn detach(mut self) {
+        assert_eq!(self.request("D"), "OK");
+        self.server.join().unwrap();
+    }
+}
+
+#[test]
+fn handshake_and_target_description() {
+    let mut client = Client::connect();
+    let supported = client.request("qSupported:multiprocess+;swbreak+;hwbreak+");
+    assert!(supported.contains("qXfer:features:read+"));
+    assert!(supported.contains("swbreak+"));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("qAttached"), "1");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+
+    let first = client.request("qXfer:features:read:target.xml:0,20");
+    assert_eq!(first, format!("m{}", &TARGET_XML[..0x20]));
+    let rest = client.request(&format!("qXfer:features:read:target.xml:20,{:x}", TARGET_XML.len()));
+    assert_eq!(rest, format!("l{}", &TARGET_XML[0x20..]));
+    assert!(TARGET_XML.contains(r#"<reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>"#));
+    client.detach();
+}
+
+#[test]
+fn registers() {
+    let mut client = Client::connect();
+    // a f b c d e h l sp(リトルエンディアン) pc(リトルエンディアン)
+    assert_eq!(client.request("g"), "01b0001300d8014dfeff0001");
+    assert_eq!(client.request("p9"), "0001");
+    assert_eq!(client.request("P0=42"), "OK");
+    assert_eq!(client.request("P8=00d0"), "OK");
+    assert_eq!(client.request("p0"), "42");
+    assert_eq!(client.request("G0a00000000000000f0ff0201"), "OK");
+    assert_eq!(client.request("g"), "0a00000000000000f0ff0201");
+    assert_eq!(client.request("pa"), "E01");
+    assert_eq!(client.request("P0=1234"), "E01");
+    client.detach();
+}
+
+#[test]
+fn memory_read_and_write() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("m100,3"), "3e0521");
+    assert_eq!(client.request("Mc000,2:1234"), "OK");
+    assert_eq!(client.request("mc000,2"), "1234");
+    assert_eq!(client.request("mffff,4"), "00");
+    assert_eq!(client.request("Mc000,2:12"), "E01");
+    // 終わりのアドレスが u64 をあふれる
+    assert_eq!(client.request("Mffffffffffffffff,1:00"), "E01");
+    assert_eq!(client.request("Mffff,2:0000"), "E01");
+    assert_eq!(client.request("Mffff,1:42"), "OK");
+    assert_eq!(client.request("mffff,1"), "42");
+    client.detach();
+}
+
+#[test]
+fn single_step() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p9"), "0201");
+    assert_eq!(client.request("vCont;s:1"), "S05");
+    assert_eq!(client.request("p9"), "0501");
+    assert_eq!(client.request("vCont?"), "vCont;c;C;s;S");
+    client.detach();
+}
+
+#[test]
+fn software_and_hardware_breakpoints() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("Z0,105,1"), "OK");
+    assert_eq!(client.request("Z1,107,1"), "OK");
+    assert_eq!(client.request("c"), "T05swbreak:;");
+    assert_eq!(client.request("p9"), "0501");
+    assert_eq!(client.request("c"), "T05hwbreak:;");
+    assert_eq!(client.request("p9"), "0701");
+    assert_eq!(client.request("p0"), "06");
+
+    assert_eq!(client.request("z1,107,1"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p9"), "0701");
+    client.detach();
+}
+
+#[test]
+fn watchpoints() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("Z2,c000,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:c000;");
+    // ウォッチポイントは書き込んだ命令を実行し終えたところで止まる
+    assert_eq!(client.request("p9"), "0601");
+    assert_eq!(client.request("mc000,1"), "05");
+    assert_eq!(client.request("z2,c000,1"), "OK");
+    assert_eq!(client.request("z2,c000,1"), "E01");
+    assert_eq!(client.request("Z3,0,2"), "OK");
+    assert_eq!(client.request("Z5,0,1"), "E01");
+    client.detach();
+}
+
+#[test]
+fn interrupt_stops_continue() {
+    let mut client = Client::connect();
+    client.send("c");
+    thread::sleep(Duration::from_millis(50));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.receive(), "S02");
+    assert_eq!(client.request("p9"), "0701");
+    client.detach();
+}
+
+#[test]
+fn bad_checksum_is_rejected() {
+    let mut client = Client::connect();
+    client.stream.write_all(b"$g#00").unwrap();
+    assert_eq!(client.read_byte(), b'-');
+    assert_eq!(client.request("p0"), "01");
+    client.detach();
+}
+
+#[test]
+fn no_ack_mode() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.no_ack = true;
+    assert_eq!(client.request("p0"), "01");
+    client.detach();
+}
+
+#[test]
+fn kill_ends_session() {
+    let mut client = Client::connect();
+    client.send("k");
+    client.server.join().unwrap();
+}
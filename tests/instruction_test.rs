@@ -0,0 +1,152 @@
+use emulator::cpu::CPU;
+use emulator::flat_bus::FlatBus;
+use emulator::instruction::*;
+
+#[test]
+fn lookup_gives_opcode_facts() {
+    let info = lookup(0x20, false);
+    assert_eq!(info.mnemonic, "JR");
+    assert_eq!((info.length, info.cycles, info.branch_cycles), (2, 8, 12));
+    assert_eq!(info.flags, FlagEffects::from_notation("----"));
+
+    let info = lookup(0x7C, true);
+    assert_eq!(info.instruction.unwrap().to_string(), "BIT 7,H");
+    assert_eq!(info.mnemonic, "BIT");
+    assert_eq!((info.length, info.cycles), (2, 8));
+    assert_eq!(info.flags.zero, FlagEffect::Affected);
+    assert_eq!(info.flags.subtract, FlagEffect::Reset);
+    assert_eq!(info.flags.half_carry, FlagEffect::Set);
+    assert_eq!(info.flags.carry, FlagEffect::Unchanged);
+
+    assert_eq!(lookup(0xE0, false).mnemonic, "LDH");
+    assert_eq!(lookup(0xCB, false).mnemonic, "PREFIX");
+    assert_eq!(lookup(0xD3, false).mnemonic, "");
+    assert!(lookup(0xD3, false).instruction.is_none());
+}
+
+#[test]
+fn mnemonic_matches_display() {
+    for info in OPCODES.iter().chain(CB_OPCODES.iter()) {
+        let Some(instruction) = info.instruction else { continue };
+        let text = instruction.to_string();
+        assert_eq!(text.split(' ').next().unwrap(), info.mnemonic, "{}", text);
+    }
+}
+
+// 再現できるように、乱数は固定の種から作る（xorshift）
+struct Random(u32);
+
+impl Random {
+    fn byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        // 4回に1回は、フラグが変わりやすい境目の値にする
+        const EDGES: [u8; 8] = [0x00, 0x01, 0x0F, 0x10, 0x7F, 0x80, 0xFE, 0xFF];
+        if self.0 >> 30 == 0 { EDGES[(self.0 & 7) as usize] } else { (self.0 >> 8) as u8 }
+    }
+
+    fn word(&mut self) -> u16 {
+        u16::from_le_bytes([self.byte(), self.byte()])
+    }
+}
+
+const TRIALS: usize = 300;
+
+// 全オペコードをランダムな状態から実行し、フラグが OpcodeInfo の宣言どおりに変わることを確かめる。
+// Set/Reset は毎回その値に、Unchanged は毎回実行前の値になる。Affected は少なくとも1回は実行前と違う値になる
+#[test]
+fn execute_matches_declared_flag_effects() {
+    let mut random = Random(0x2468_ACE1);
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    let opcodes: Vec<(bool, u8, &OpcodeInfo)> = (0..=0xFF)
+        .map(|byte| (false, byte, lookup(byte, false)))
+        .chain((0..=0xFF).map(|byte| (true, byte, lookup(byte, true))))
+        .filter(|(_, _, info)| info.instruction.is_some())
+        .collect();
+    // オペコードごと、フラグごとに、実行前と違う値になったことがあるか
+    let mut changed = vec![[false; 4]; opcodes.len()];
+
+    for _ in 0..TRIALS {
+        for byte in cpu.bus.memory.iter_mut() {
+            *byte = random.byte();
+        }
+        for (index, &(prefixed, byte, info)) in opcodes.iter().enumerate() {
+            cpu.pc = random.word();
+            cpu.sp = random.word();
+            cpu.halted = false;
+            let registers = &mut cpu.registers;
+            registers.a = random.byte();
+            registers.f = (random.byte() & 0xF0).into();
+            registers.b = random.byte();
+            registers.c = random.byte();
+            registers.d = random.byte();
+            registers.e = random.byte();
+            registers.h = random.byte();
+            registers.l = random.byte();
+            let opcode = if prefixed { vec![0xCB, byte] } else { vec![byte] };
+            for (offset, &value) in opcode.iter().enumerate() {
+                cpu.bus.memory[cpu.pc.wrapping_add(offset as u16) as usize] = value;
+            }
+
+            let before = cpu.registers.f;
+            cpu.step();
+            let after = cpu.registers.f;
+            cpu.bus.take_accesses();
+
+            let name = info.instruction.unwrap().to_string();
+            let flags = [
+                ("Z", info.flags.zero, before.zero, after.zero),
+                ("N", info.flags.subtract, before.subtract, after.subtract),
+                ("H", info.flags.half_carry, before.half_carry, after.half_carry),
+                ("C", info.flags.carry, before.carry, after.carry),
+            ];
+            for (flag, (label, effect, before, after)) in flags.into_iter().enumerate() {
+                match effect {
+                    FlagEffect::Set => assert!(after, "{}: {} should be set", name, label),
+                    FlagEffect::Reset => assert!(!after, "{}: {} should be reset", name, label),
+                    FlagEffect::Unchanged => assert_eq!(after, before, "{}: {} should be unchanged", name, label),
+                    FlagEffect::Affected => changed[index][flag] |= after != before,
+                }
+            }
+        }
+    }
+
+    for (index, (_, _, info)) in opcodes.iter().enumerate() {
+        let effects = [info.flags.zero, info.flags.subtract, info.flags.half_carry, info.flags.carry];
+        for (flag, label) in ["Z", "N", "H", "C"].into_iter().enumerate() {
+            // SBC A,A は A - A - C なので、借りが出るのはちょうど C が1のとき。C は書き換えるが値は変わらない
+            let same_value = info.instruction == Some(Instruction::SBC(Operand8::A)) && label == "C";
+            if effects[flag] == FlagEffect::Affected && !same_value {
+                assert!(changed[index][flag], "{}: {} is declared affected but never changed", info.instruction.unwrap(), label);
+            }
+        }
+    }
+}
+
+// SBC A,$FF をキャリーありで実行すると、引く値は 0x100 になるので必ず借りが出る。
+// ランダムな状態ではこの組み合わせをほとんど引かないので、すべての A とキャリーで確かめる
+#[test]
+fn sbc_borrows_when_operand_and_carry_overflow_a_byte() {
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    for a in 0..=0xFFu8 {
+        for value in [0x00, 0x0F, 0xFE, 0xFF] {
+            for carry in [false, true] {
+                cpu.pc = 0;
+                cpu.bus.memory[0] = 0xDE; // SBC A,d8
+                cpu.bus.memory[1] = value;
+                cpu.registers.a = a;
+                cpu.registers.f.carry = carry;
+                cpu.step();
+
+                let subtrahend = value as u16 + carry as u16;
+                let expected = (a as u16).wrapping_sub(subtrahend) as u8;
+                let name = format!("A={:02X} SBC A,${:02X} C={}", a, value, carry);
+                assert_eq!(cpu.registers.a, expected, "{}", name);
+                assert_eq!(cpu.registers.f.carry, (a as u16) < subtrahend, "{}: C", name);
+                assert_eq!(cpu.registers.f.half_carry, (a & 0x0F) < (value & 0x0F) + carry as u8, "{}: H", name);
+                assert_eq!(cpu.registers.f.zero, expected == 0, "{}: Z", name);
+            }
+        }
+    }
+}
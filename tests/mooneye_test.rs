@@ -0,0 +1,108 @@
+// Mooneye のテストROMをまとめて実行する。テストROMは終わると LD B,B を実行するので、
+// そこで止めてレジスタがフィボナッチ数列（3, 5, 8, 13, 21, 34）になっていれば成功。
+// ROMはリポジトリに含めていないので、MOONEYE_ROMS（既定は tests/roms/mooneye）がなければ飛ばす
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use emulator::gameboy::GameBoy;
+
+// 1秒あたりのフレーム数（約59.7）を切り上げたもの
+const FRAMES_PER_SECOND: u64 = 60;
+// どのテストも実機では数秒以内に終わる
+const TIMEOUT_SECONDS: u64 = 10;
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+fn rom_directory() -> PathBuf {
+    match std::env::var_os("MOONEYE_ROMS") {
+        Some(directory) => PathBuf::from(directory),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms/mooneye"),
+    }
+}
+
+// directory の下の .gb をすべて集める。manual-only は画面を目で見て確かめるテストなので除く
+fn collect_roms(directory: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name != "manual-only") {
+                collect_roms(&path, roms);
+            }
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+// ファイル名の末尾（-dmgABC、-GS など）が対象の機種を表す。付いていなければどの機種でも通るテスト。
+// エミュレートしているのはブートROM終了後の DMG（リビジョンA〜C）なので、それに当てはまるものだけ実行する
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let Some((_, models)) = stem.rsplit_once('-') else { return true };
+    if models.contains("dmgABC") {
+        return true;
+    }
+    // 大文字だけならグループ（G: DMG と MGB、S: SGB、C: CGB、A: AGB）
+    models.chars().all(|c| c.is_ascii_uppercase()) && models.contains('G')
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+fn run(rom: Vec<u8>) -> Outcome {
+    let mut gameboy = match GameBoy::new(rom) {
+        Ok(gameboy) => gameboy,
+        Err(error) => return Outcome::Failed(error.to_string()),
+    };
+    gameboy.cpu.break_on_ld_b_b = true;
+    for _ in 0..TIMEOUT_SECONDS * FRAMES_PER_SECOND {
+        gameboy.run_frame();
+        if let Some(opcode) = gameboy.cpu.illegal_opcode {
+            return Outcome::Failed(format!("CPU locked up on illegal opcode ${:02X} at PC={:04X}", opcode, gameboy.cpu.pc));
+        }
+        if gameboy.cpu.software_breakpoint_hit {
+            let registers = &gameboy.cpu.registers;
+            let values = [registers.b, registers.c, registers.d, registers.e, registers.h, registers.l];
+            if values == PASSED {
+                return Outcome::Passed;
+            }
+            return Outcome::Failed(format!(
+                "B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X}",
+                values[0], values[1], values[2], values[3], values[4], values[5]
+            ));
+        }
+    }
+    Outcome::Failed(format!("timed out after {} seconds", TIMEOUT_SECONDS))
+}
+
+#[test]
+fn mooneye() {
+    let directory = rom_directory();
+    let mut roms = Vec::new();
+    collect_roms(&directory, &mut roms);
+    if roms.is_empty() {
+        eprintln!("skipping: no Mooneye ROMs under {}", directory.display());
+        return;
+    }
+    roms.sort();
+
+    let mut failures = Vec::new();
+    let mut passed = 0;
+    for path in roms.iter().filter(|path| runs_on_dmg(path)) {
+        let name = path.strip_prefix(&directory).unwrap_or(path).display().to_string();
+        match run(fs::read(path).unwrap()) {
+            Outcome::Passed => {
+                passed += 1;
+                eprintln!("pass {}", name);
+            },
+            Outcome::Failed(message) => {
+                eprintln!("FAIL {}: {}", name, message);
+                failures.push(name);
+            },
+        }
+    }
+    eprintln!("{} passed, {} failed", passed, failures.len());
+    assert!(failures.is_empty(), "failed Mooneye ROMs:\n{}", failures.join("\n"));
+}
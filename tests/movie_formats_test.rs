@@ -0,0 +1,170 @@
+use emulator::bk2;
+use emulator::gameboy::{GameBoy, Model};
+use emulator::movie::{Movie, MovieError, Start};
+use emulator::vbm;
+use emulator::zip::{self, inflate, Entry};
+
+// 0x0100から指定したプログラムを置いた32KB ROMを作る
+fn rom_with_program(program: &[u8], cartridge_type: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cartridge_type;
+    rom
+}
+
+// ボタンの状態を読んでWRAM (0xC000〜0xC0FF) に書き続ける
+fn input_rom() -> Vec<u8> {
+    let program = [
+        0x21, 0x00, 0xFF, // LD HL, 0xFF00
+        0x3E, 0x10,       // LD A, 0x10 (ボタンを選ぶ)
+        0x77,             // LD (HL), A
+        0x11, 0x00, 0xC0, // LD DE, 0xC000
+        0x7E,             // LD A, (HL)
+        0x12,             // LD (DE), A
+        0x1C,             // INC E
+        0x18, 0xFB,       // JR -5
+    ];
+    rom_with_program(&program, 0x00)
+}
+
+fn recorded_movie() -> Movie {
+    let mut gameboy = GameBoy::new(input_rom()).unwrap();
+    let mut movie = Movie::power_on(&gameboy, None);
+    for buttons in [0x00, 0x10, 0x84, 0x00, 0x22, 0xC0] {
+        movie.record_frame(&mut gameboy, buttons);
+    }
+    movie
+}
+
+fn buttons(movie: &Movie) -> Vec<u8> {
+    movie.frames.iter().map(|frame| frame.buttons).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+}
+
+// BizHawk 2.9 と同じ形の .bk2（Deflate で圧縮した ZIP）。input_rom() で30フレーム記録したもの
+const BIZHAWK_MOVIE: &str = concat!(
+    "504b03041400000008004db7525d3f7dfa3393000000a90000000a0000004865616465722e747874358ccb0e82301000",
+    "effb15fb05a42df2f0584a858bc604e2bdea1a89964d4ac1c4afb7170f73994ce6c8db44170acbc43336d3b7779f176e",
+    "2a139900bdc627075cd813cf04d6af6f1753f7cf55b6cf249c937c70f0d835d0394fa7048e761861e8b5c4aa6c8caab5",
+    "b252964268bdab726b0eba286cde56b96c452dacaef70a0c07c234b8ba18090205ba71b81b5ee78852c10f504b030414",
+    "00000008004db7525d4d5418caa2000000c70100000d000000496e707574204c6f672e7478748bf6cc2b282d89e5f2c9",
+    "4ff74eadb4520e3054082da801922ef9e57920da2735ad04440765a6678019c125894510466a4e6a3298e504221c4144",
+    "407e796a510d574da81e040440d9c1200e90ade7e2039100b16100c20ed2d373c2220e528f290e36bf188b392ea86cb8",
+    "bd6037209be3e40837bf1849bc18c97c981a643321e2d8ed45710fcc7c1cee04b9078b78a89e4f10b29b51f416eb21dc",
+    "037173b43e34fa00504b03041400000008004db7525ddbe120b29a000000cb0000001100000053796e6353657474696e",
+    "67732e6a736f6e758ec10ac23010447f45166f86a21e736c2cb5072dd8fe406ab7b29866a559955afaef46c4a3a7c70c",
+    "8f612660d0132c65bc216848e9b5b7cf6b92f5776785d82786070cc991bca06f39c96d8f0d8f3fae221b2b82d5e8cf15",
+    "8a90bf04b5f833030a326f1b87695156a03beb022a30ec033b3c701b1f6c62ce53c38e87007aade0549bc293907535f5",
+    "1f61fb2d77f4a0c043d97501259af3fc06504b03041400000008004db7525d0000000002000000000000000c00000043",
+    "6f6d6d656e74732e7478740300504b010214031400000008004db7525d3f7dfa3393000000a90000000a000000000000",
+    "00000000008001000000004865616465722e747874504b010214031400000008004db7525d4d5418caa2000000c70100",
+    "000d00000000000000000000008001bb000000496e707574204c6f672e747874504b010214031400000008004db7525d",
+    "dbe120b29a000000cb00000011000000000000000000000080018801000053796e6353657474696e67732e6a736f6e50",
+    "4b010214031400000008004db7525d0000000002000000000000000c0000000000000000000000800151020000436f6d",
+    "6d656e74732e747874504b05060000000004000400ec0000007d0200000000",
+);
+
+#[test]
+fn bizhawk_movie_is_imported() {
+    let movie = bk2::import(&from_hex(BIZHAWK_MOVIE), &input_rom(), None).unwrap();
+
+    assert_eq!(movie.model, Model::Dmg);
+    assert_eq!(movie.rtc_seed, 120);
+    assert_eq!(movie.start, Start::PowerOn);
+    assert_eq!(movie.len(), 30);
+    // |U.......P| |U...S....| |.DL......|。最初のフレームの Power は電源投入そのもの
+    assert_eq!(buttons(&movie)[..3], [0x04, 0x84, 0x0A]);
+    assert!(movie.frames.iter().all(|frame| frame.hash.is_none()));
+
+    // ハッシュがないので、再生してもずれは調べない
+    let mut gameboy = movie.start(input_rom(), None).unwrap();
+    movie.play(&mut gameboy).unwrap();
+}
+
+#[test]
+fn bk2_round_trip_keeps_inputs_and_settings() {
+    let mut movie = recorded_movie();
+    movie.start = Start::SaveRam(vec![1, 2, 3]);
+    movie.model = Model::Cgb;
+    movie.rtc_seed = 3600;
+
+    let imported = bk2::import(&bk2::export(&movie, &input_rom()).unwrap(), &input_rom(), None).unwrap();
+    assert_eq!(buttons(&imported), buttons(&movie));
+    assert_eq!(imported.start, movie.start);
+    assert_eq!(imported.model, Model::Cgb);
+    assert_eq!(imported.rtc_seed, 3600);
+    assert_eq!(imported.rom_crc32, movie.rom_crc32);
+}
+
+#[test]
+fn bk2_for_another_rom_is_rejected() {
+    let data = bk2::export(&recorded_movie(), &input_rom()).unwrap();
+
+    let error = bk2::import(&data, &rom_with_program(&[0x18, 0xFE], 0x00), None).unwrap_err();
+    assert!(matches!(error, MovieError::ForeignRomMismatch { .. }), "{}", error);
+    assert!(error.to_string().starts_with("movie was recorded with another ROM (SHA1 "), "{}", error);
+}
+
+#[test]
+fn vbm_round_trip_keeps_inputs() {
+    let movie = recorded_movie();
+    let data = vbm::export(&movie, &input_rom()).unwrap();
+    assert_eq!(&data[..4], b"VBM\x1A");
+    // Start + Up は VBA では 0x0048
+    assert_eq!(data[0x100 + 2 * 2..0x100 + 2 * 2 + 2], [0x48, 0x00]);
+
+    let imported = vbm::import(&data, &input_rom()).unwrap();
+    assert_eq!(buttons(&imported), buttons(&movie));
+    assert_eq!(imported.model, Model::Dmg);
+
+    // 変換したムービーも同じ入力で再生できる
+    let mut gameboy = imported.start(input_rom(), None).unwrap();
+    imported.play(&mut gameboy).unwrap();
+    let mut expected = movie.start(input_rom(), None).unwrap();
+    movie.play(&mut expected).unwrap();
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xC000), expected.cpu.bus.peek_byte(0xC000));
+}
+
+#[test]
+fn vbm_from_sram_and_unsupported_starts() {
+    let mut movie = recorded_movie();
+    movie.start = Start::SaveRam(vec![0x42; 0x2000]);
+    let data = vbm::export(&movie, &input_rom()).unwrap();
+    assert_eq!(data[0x14], 0x02);
+    assert_eq!(vbm::import(&data, &input_rom()).unwrap().start, movie.start);
+
+    // VBA のスナップショットから始まるものは読めない
+    let mut snapshot = data.clone();
+    snapshot[0x14] = 0x01;
+    assert!(matches!(vbm::import(&snapshot, &input_rom()), Err(MovieError::Unsupported(_))));
+
+    movie.start = Start::SaveState(Vec::new());
+    assert!(matches!(vbm::export(&movie, &input_rom()), Err(MovieError::Unsupported(_))));
+    assert!(matches!(bk2::export(&movie, &input_rom()), Err(MovieError::Unsupported(_))));
+}
+
+#[test]
+fn zip_round_trip_and_stored_deflate_blocks() {
+    let entries = [
+        Entry { name: "a.txt".to_string(), data: b"hello".to_vec() },
+        Entry { name: "empty".to_string(), data: Vec::new() },
+    ];
+    let read = zip::read(&zip::write(&entries)).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[0].name, "a.txt");
+    assert_eq!(read[0].data, b"hello");
+    assert!(read[1].data.is_empty());
+
+    assert_eq!(inflate(&from_hex("010c00f3ff73746f72656420626c6f636b")).unwrap(), b"stored block");
+    assert!(zip::read(b"not a zip").is_err());
+}
+
+#[test]
+fn sha1_matches_known_digest() {
+    let digest: String = bk2::sha1(b"abc").iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(digest, "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
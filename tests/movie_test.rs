@@ -0,0 +1,116 @@
+use emulator::gameboy::{GameBoy, Model};
+use emulator::joypad::Button;
+use emulator::movie::{state_hash, Movie, MovieError, Start};
+
+// 0x0100から指定したプログラムを置いた32KB ROMを作る
+fn rom_with_program(program: &[u8], cartridge_type: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cartridge_type;
+    rom
+}
+
+// ボタンの状態を読んでWRAM (0xC000〜0xC0FF) に書き続ける
+fn input_rom() -> Vec<u8> {
+    let program = [
+        0x21, 0x00, 0xFF, // LD HL, 0xFF00
+        0x3E, 0x10,       // LD A, 0x10 (ボタンを選ぶ)
+        0x77,             // LD (HL), A
+        0x11, 0x00, 0xC0, // LD DE, 0xC000
+        0x7E,             // LD A, (HL)
+        0x12,             // LD (DE), A
+        0x1C,             // INC E
+        0x18, 0xFB,       // JR -5
+    ];
+    rom_with_program(&program, 0x00)
+}
+
+fn record(gameboy: &mut GameBoy, movie: &mut Movie, inputs: &[u8]) {
+    for &buttons in inputs {
+        movie.record_frame(gameboy, buttons);
+    }
+}
+
+const INPUTS: [u8; 8] = [0, 0, 0x10, 0x10, 0x80, 0, 0x30, 0];
+
+#[test]
+fn playback_reproduces_recorded_frames() {
+    let mut gameboy = GameBoy::new(input_rom()).unwrap();
+    let mut movie = Movie::power_on(&gameboy, None);
+    record(&mut gameboy, &mut movie, &INPUTS);
+    assert_eq!(movie.len(), INPUTS.len());
+    assert_eq!(movie.frames[2].buttons, Button::A.mask());
+
+    let mut replay = movie.start(input_rom(), None).unwrap();
+    movie.play(&mut replay).unwrap();
+    assert_eq!(state_hash(&replay), state_hash(&gameboy));
+    assert_eq!(replay.cycles, gameboy.cycles);
+}
+
+#[test]
+fn desync_is_reported_at_the_frame_it_happens() {
+    let mut gameboy = GameBoy::new(input_rom()).unwrap();
+    let mut movie = Movie::power_on(&gameboy, None);
+    record(&mut gameboy, &mut movie, &INPUTS);
+
+    // 5フレーム目の入力だけ変える
+    let mut edited = movie.clone();
+    edited.frames[4].buttons = Button::B.mask();
+    let mut replay = movie.start(input_rom(), None).unwrap();
+    let error = edited.play(&mut replay).unwrap_err();
+    assert!(matches!(error, MovieError::Desync { frame: 4, .. }), "{}", error);
+    assert!(error.to_string().starts_with("desync at frame 4: state hash"), "{}", error);
+}
+
+#[test]
+fn movie_survives_serialization() {
+    let mut gameboy = GameBoy::with_config(input_rom(), Model::Cgb, None).unwrap();
+    gameboy.run_frame();
+    let mut movie = Movie::from_state(&gameboy);
+    record(&mut gameboy, &mut movie, &INPUTS);
+
+    let parsed = Movie::parse(&movie.to_bytes()).unwrap();
+    assert_eq!(parsed, movie);
+    assert!(matches!(parsed.start, Start::SaveState(_)));
+
+    // セーブステートから始めたムービーも、同じところまで再生できる
+    let mut replay = parsed.start(input_rom(), None).unwrap();
+    assert_eq!(replay.model, Model::Cgb);
+    parsed.play(&mut replay).unwrap();
+    assert_eq!(state_hash(&replay), state_hash(&gameboy));
+
+    let bytes = movie.to_bytes();
+    assert_eq!(Movie::parse(b"hello"), Err(MovieError::Invalid("not a movie file".to_string())));
+    assert_eq!(Movie::parse(&bytes[..bytes.len() - 1]), Err(MovieError::Invalid("file is truncated".to_string())));
+}
+
+#[test]
+fn initial_conditions_are_checked() {
+    let gameboy = GameBoy::new(input_rom()).unwrap();
+    let movie = Movie::power_on(&gameboy, None);
+
+    let error = movie.start(rom_with_program(&[0x18, 0xFE], 0x00), None).err().unwrap();
+    assert!(matches!(error, MovieError::RomMismatch { .. }), "{}", error);
+
+    let error = movie.start(input_rom(), Some(vec![0; 0x100])).err().unwrap();
+    assert_eq!(error.to_string(), "movie was recorded with boot ROM none but got CRC32 0D968558");
+}
+
+#[test]
+fn rtc_seed_is_restored_on_power_on() {
+    // MBC3+TIMER+RAM+BATTERY
+    let rom = rom_with_program(&[0x18, 0xFE], 0x10);
+    let mut gameboy = GameBoy::new(rom.clone()).unwrap();
+    let rtc = gameboy.cpu.bus.cartridge.as_mut().unwrap().rtc.as_mut().unwrap();
+    rtc.days = 3;
+    rtc.hours = 4;
+    rtc.minutes = 5;
+    rtc.seconds = 6;
+    let movie = Movie::power_on(&gameboy, None);
+    assert_eq!(movie.rtc_seed, 3 * 86400 + 4 * 3600 + 5 * 60 + 6);
+
+    let replay = movie.start(rom, None).unwrap();
+    let rtc = replay.cpu.bus.cartridge.as_ref().unwrap().rtc.as_ref().unwrap();
+    assert_eq!((rtc.days, rtc.hours, rtc.minutes, rtc.seconds), (3, 4, 5, 6));
+}
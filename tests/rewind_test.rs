@@ -0,0 +1,62 @@
+use emulator::gameboy::GameBoy;
+use emulator::rewind::Rewind;
+
+// INC A; LD (HL+),A; JR -4 を繰り返し、WRAMに書き続ける
+fn counting_gameboy() -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    let program = [0x21, 0x00, 0xC0, 0x3C, 0x22, 0x18, 0xFC];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    GameBoy::new(rom).unwrap()
+}
+
+#[test]
+fn step_back_returns_to_previous_frames_in_order() {
+    let mut gameboy = counting_gameboy();
+    let mut rewind = Rewind::new(1).with_keyframe_interval(4);
+    let mut states = Vec::new();
+    for _ in 0..10 {
+        rewind.push(&gameboy);
+        states.push(gameboy.save_state());
+        gameboy.run_frame();
+    }
+    assert_eq!(rewind.len(), 10);
+
+    for expected in states.iter().rev() {
+        assert!(rewind.step_back(&mut gameboy).unwrap());
+        assert_eq!(&gameboy.save_state(), expected);
+    }
+    assert!(!rewind.step_back(&mut gameboy).unwrap());
+    assert!(rewind.is_empty());
+}
+
+#[test]
+fn oldest_snapshots_are_dropped() {
+    // 1秒は約59.7フレーム
+    assert_eq!(Rewind::new(1).capacity(), 60);
+    assert_eq!(Rewind::new(10).capacity(), 598);
+
+    let mut gameboy = counting_gameboy();
+    let mut rewind = Rewind::with_capacity(10).with_keyframe_interval(4);
+    for _ in 0..30 {
+        rewind.push(&gameboy);
+        gameboy.run_frame();
+    }
+    // 古いものはキーフレームごとに捨てるので、少しだけ多く残る
+    assert!((10..14).contains(&rewind.len()), "{}", rewind.len());
+}
+
+#[test]
+fn deltas_use_less_memory_than_full_states() {
+    // JR -2 で止まっているので、フレームごとに変わるのは PPU とタイマーくらい
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+    let mut gameboy = GameBoy::new(rom).unwrap();
+    let mut rewind = Rewind::with_capacity(30);
+    for _ in 0..30 {
+        rewind.push(&gameboy);
+        gameboy.run_frame();
+    }
+
+    let full = gameboy.save_state().len() * 30;
+    assert!(rewind.memory_usage() * 20 < full, "{} of {} bytes", rewind.memory_usage(), full);
+}
@@ -0,0 +1,177 @@
+use emulator::bess::footer_start;
+use emulator::gameboy::GameBoy;
+use emulator::png::crc32;
+use emulator::savestate::{SaveStateError, MAGIC};
+
+// 0x0100から指定したプログラムを置いた32KB ROMを作る
+fn rom_with_program(program: &[u8], cartridge_type: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cartridge_type;
+    rom
+}
+
+// INC A; LD (HL+),A; JR -4 を繰り返し、WRAMに書き続ける
+fn counting_gameboy() -> GameBoy {
+    let program = [0x21, 0x00, 0xC0, 0x3C, 0x22, 0x18, 0xFC];
+    GameBoy::new(rom_with_program(&program, 0x00)).unwrap()
+}
+
+// セクションの開始位置（ヘッダの直後）
+fn first_section(state: &[u8]) -> usize {
+    let title_length = state[MAGIC.len() + 6] as usize;
+    MAGIC.len() + 7 + title_length
+}
+
+#[test]
+fn load_restores_saved_machine() {
+    let mut gameboy = counting_gameboy();
+    gameboy.run_cycles(10_000);
+    let state = gameboy.save_state();
+    let pc = gameboy.cpu.pc;
+    let a = gameboy.cpu.registers.a;
+    let wram = gameboy.cpu.bus.peek_byte(0xC010);
+
+    gameboy.run_cycles(50_000);
+    let expected = gameboy.save_state();
+    assert_ne!(gameboy.cpu.registers.a, a);
+
+    gameboy.load_state(&state).unwrap();
+    assert_eq!(gameboy.cpu.pc, pc);
+    assert_eq!(gameboy.cpu.registers.a, a);
+    assert_eq!(gameboy.cpu.bus.peek_byte(0xC010), wram);
+    assert_eq!(gameboy.save_state(), state);
+
+    // 同じ状態から同じだけ実行すれば同じ結果になる
+    gameboy.run_cycles(50_000);
+    assert_eq!(gameboy.save_state(), expected);
+}
+
+#[test]
+fn cartridge_ram_and_rtc_are_restored() {
+    // MBC3+TIMER+RAM+BATTERY、RAM 8KB
+    let mut rom = rom_with_program(&[0x18, 0xFE], 0x10);
+    rom[0x149] = 0x02;
+    let mut gameboy = GameBoy::new(rom).unwrap();
+    gameboy.cpu.bus.write_byte(0x0000, 0x0A); // RAM有効
+    gameboy.cpu.bus.write_byte(0xA123, 0x5A);
+    gameboy.cpu.bus.write_byte(0x4000, 0x08); // RTC 秒
+    gameboy.cpu.bus.write_byte(0xA000, 42);
+    let state = gameboy.save_state();
+
+    gameboy.cpu.bus.write_byte(0x4000, 0x00);
+    gameboy.cpu.bus.write_byte(0xA123, 0x00);
+    gameboy.cpu.bus.write_byte(0x0000, 0x00);
+    gameboy.load_state(&state).unwrap();
+
+    let cartridge = gameboy.cpu.bus.cartridge.as_ref().unwrap();
+    assert!(cartridge.ram_enabled);
+    assert_eq!(cartridge.ram_bank, 0x08);
+    assert_eq!(cartridge.ram[0x123], 0x5A);
+    assert_eq!(cartridge.rtc.as_ref().unwrap().seconds, 42);
+}
+
+#[test]
+fn state_from_another_rom_is_rejected() {
+    let state = counting_gameboy().save_state();
+    let mut other = GameBoy::new(rom_with_program(&[0x00], 0x00)).unwrap();
+
+    let error = other.load_state(&state).unwrap_err();
+    assert!(matches!(error, SaveStateError::RomMismatch { .. }));
+    assert!(error.to_string().contains("another ROM (\"TEST\""), "{}", error);
+}
+
+#[test]
+fn unsupported_version_is_rejected() {
+    let mut gameboy = counting_gameboy();
+    let mut state = gameboy.save_state();
+    state[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&99u16.to_le_bytes());
+
+    let error = gameboy.load_state(&state).unwrap_err();
+    assert_eq!(error, SaveStateError::UnsupportedVersion(99));
+    assert_eq!(error.to_string(), "unsupported save state version 99 (this build reads version 1)");
+}
+
+#[test]
+fn broken_states_are_rejected_without_changes() {
+    let mut gameboy = counting_gameboy();
+    let state = gameboy.save_state();
+    gameboy.run_cycles(1000);
+    let current = gameboy.save_state();
+
+    assert_eq!(gameboy.load_state(b"hello"), Err(SaveStateError::NotASaveState));
+    assert_eq!(gameboy.load_state(&state[..first_section(&state) + 20]), Err(SaveStateError::Truncated));
+
+    let mut corrupted = state.clone();
+    let start = first_section(&state);
+    corrupted[start + 8] ^= 0xFF;
+    assert_eq!(gameboy.load_state(&corrupted), Err(SaveStateError::ChecksumMismatch(*b"SYS ")));
+
+    assert_eq!(gameboy.save_state(), current);
+}
+
+#[test]
+fn unknown_sections_are_skipped() {
+    let mut gameboy = counting_gameboy();
+    let state = gameboy.save_state();
+    // 新しい版で足されたセクションを想定。BESS のフッタはなくても読める
+    let footer = footer_start(&state).unwrap();
+    let body = [1, 2, 3];
+    let mut section = b"NEW!".to_vec();
+    section.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    section.extend_from_slice(&body);
+    section.extend_from_slice(&crc32(&body).to_le_bytes());
+    let mut state = state[..footer].to_vec();
+    state.extend_from_slice(&section);
+
+    gameboy.run_cycles(1000);
+    assert!(gameboy.load_state(&state).is_ok());
+}
+
+#[test]
+fn serial_is_restored_from_its_own_section() {
+    let mut gameboy = counting_gameboy();
+    gameboy.cpu.bus.serial.data = 0x5A;
+    gameboy.cpu.bus.serial.control = 0x81;
+    gameboy.cpu.bus.serial.remaining = 300;
+    let state = gameboy.save_state();
+
+    // SERL を取り除いたステート。シリアルを足す前に保存したものを想定
+    let mut position = first_section(&state);
+    let mut without_serial = state[..position].to_vec();
+    let footer = footer_start(&state).unwrap();
+    while position < footer {
+        let length = u32::from_le_bytes(state[position + 4..position + 8].try_into().unwrap()) as usize;
+        let end = position + 8 + length + 4;
+        if &state[position..position + 4] != b"SERL" {
+            without_serial.extend_from_slice(&state[position..end]);
+        }
+        position = end;
+    }
+    assert_eq!(without_serial.len(), footer - 16);
+
+    gameboy.cpu.bus.serial.data = 0;
+    gameboy.cpu.bus.serial.control = 0;
+    gameboy.cpu.bus.serial.remaining = 0;
+    gameboy.load_state(&without_serial).unwrap();
+    assert_eq!(gameboy.cpu.bus.serial.data, 0);
+
+    gameboy.load_state(&state).unwrap();
+    assert_eq!(gameboy.cpu.bus.serial.data, 0x5A);
+    assert_eq!(gameboy.cpu.bus.serial.control, 0x81);
+    assert_eq!(gameboy.cpu.bus.serial.remaining, 300);
+}
+
+#[test]
+fn rom_crc32_is_kept_on_cartridge() {
+    // CRC-32/ISO-HDLC の検査値
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+    let gameboy = counting_gameboy();
+    let cartridge = gameboy.cpu.bus.cartridge.as_ref().unwrap();
+    assert_eq!(cartridge.rom_crc32, crc32(&cartridge.rom));
+    let state = gameboy.save_state();
+    let saved = u32::from_le_bytes(state[MAGIC.len() + 2..MAGIC.len() + 6].try_into().unwrap());
+    assert_eq!(saved, cartridge.rom_crc32);
+}
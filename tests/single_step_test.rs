@@ -0,0 +1,463 @@
+// SingleStepTests (sm83) の JSON を読み、1命令ずつ実行して結果を比べる。
+// オペコードごとに1000件のランダムな初期状態と、実行後の状態、Mサイクルごとのバスの動きが入っている。
+// ファイルはリポジトリに含めていないので、SM83_TESTS（既定は tests/roms/sm83）がなければ飛ばす
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use emulator::cpu::CPU;
+use emulator::flat_bus::FlatBus;
+use emulator::register::FlagsRegister;
+use emulator::watchpoint::Access;
+
+fn test_directory() -> PathBuf {
+    match std::env::var_os("SM83_TESTS") {
+        Some(directory) => PathBuf::from(directory),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms/sm83"),
+    }
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "json") {
+            files.push(path);
+        }
+    }
+}
+
+// テストファイルを読むのに足りるだけの JSON
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    fn as_u16(&self) -> Option<u16> {
+        match self {
+            Json::Number(number) => Some(*number as u16),
+            Json::Bool(value) => Some(*value as u16),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Json::String(string) => string,
+            _ => "",
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text: text.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.text.len() {
+            return Err(format!("unexpected data at {}", parser.position));
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.position).is_some_and(u8::is_ascii_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.text.get(self.position) != Some(&byte) {
+            return Err(format!("expected '{}' at {}", byte as char, self.position));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.position..].starts_with(keyword.as_bytes()) {
+            return Err(format!("unexpected character at {}", self.position));
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.text.get(self.position) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(_) => self.number(),
+            None => Err("unexpected end of data".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.position) == Some(&b'}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.text.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                },
+                _ => return Err(format!("expected ',' or '}}' at {}", self.position)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.text.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                },
+                _ => return Err(format!("expected ',' or ']' at {}", self.position)),
+            }
+        }
+    }
+
+    // テストファイルの文字列は名前とピンの状態だけなので、\uXXXX のサロゲートペアは扱わない
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        loop {
+            let Some(&byte) = self.text.get(self.position) else { return Err("unterminated string".to_string()) };
+            self.position += 1;
+            match byte {
+                b'"' => return Ok(string),
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.position) else { return Err("unterminated string".to_string()) };
+                    self.position += 1;
+                    match escape {
+                        b'n' => string.push('\n'),
+                        b't' => string.push('\t'),
+                        b'r' => string.push('\r'),
+                        b'b' => string.push('\u{8}'),
+                        b'f' => string.push('\u{c}'),
+                        b'u' => {
+                            let digits = self.text.get(self.position..self.position + 4).ok_or("truncated escape")?;
+                            let code = u32::from_str_radix(std::str::from_utf8(digits).unwrap_or(""), 16)
+                                .map_err(|_| format!("invalid escape at {}", self.position))?;
+                            string.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                            self.position += 4;
+                        },
+                        other => string.push(other as char),
+                    }
+                },
+                _ => {
+                    // 複数バイトの UTF-8 はそのまま続きのバイトごと取り込む
+                    let start = self.position - 1;
+                    let mut end = self.position;
+                    while end < self.text.len() && (self.text[end] & 0xC0) == 0x80 {
+                        end += 1;
+                    }
+                    string.push_str(std::str::from_utf8(&self.text[start..end]).map_err(|error| error.to_string())?);
+                    self.position = end;
+                },
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self.text.get(self.position).is_some_and(|&c| c.is_ascii_digit() || b"+-.eE".contains(&c)) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.text[start..self.position]).unwrap_or("");
+        text.parse().map(Json::Number).map_err(|_| format!("invalid number at {}", start))
+    }
+}
+
+// バスの1回のアクセス。書き込みかどうか、アドレス、値
+type Transfer = (bool, u16, u8);
+
+struct State {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    ime: bool,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn parse(json: &Json) -> Result<State, String> {
+        let field = |name: &str| json.get(name).and_then(Json::as_u16).ok_or_else(|| format!("missing \"{}\"", name));
+        let ram = json
+            .get("ram")
+            .map(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| match entry.as_array() {
+                [address, value] => Ok((address.as_u16().unwrap_or(0), value.as_u16().unwrap_or(0) as u8)),
+                _ => Err("invalid ram entry".to_string()),
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(State {
+            pc: field("pc")?,
+            sp: field("sp")?,
+            a: field("a")? as u8,
+            b: field("b")? as u8,
+            c: field("c")? as u8,
+            d: field("d")? as u8,
+            e: field("e")? as u8,
+            f: field("f")? as u8,
+            h: field("h")? as u8,
+            l: field("l")? as u8,
+            ime: json.get("ime").and_then(Json::as_u16).unwrap_or(0) != 0,
+            ram,
+        })
+    }
+}
+
+struct Case {
+    name: String,
+    initial: State,
+    expected: State,
+    // Mサイクルごとのアクセス。何もアクセスしないMサイクルは None
+    cycles: Vec<Option<Transfer>>,
+}
+
+impl Case {
+    fn parse(json: &Json) -> Result<Case, String> {
+        let mut cycles = Vec::new();
+        for cycle in json.get("cycles").map(Json::as_array).unwrap_or_default() {
+            // [アドレス, データ, ピン]。ピンは "r-m"（読み込み）、"-wm"（書き込み）、"---"（アクセスなし）
+            let [address, value, pins] = cycle.as_array() else { return Err("invalid cycle".to_string()) };
+            let pins = pins.as_str();
+            let access = pins.starts_with('r') || pins.contains('w');
+            cycles.push(access.then(|| (pins.contains('w'), address.as_u16().unwrap_or(0), value.as_u16().unwrap_or(0) as u8)));
+        }
+        Ok(Case {
+            name: json.get("name").map(Json::as_str).unwrap_or_default().to_string(),
+            initial: State::parse(json.get("initial").ok_or("missing \"initial\"")?)?,
+            expected: State::parse(json.get("final").ok_or("missing \"final\"")?)?,
+            cycles,
+        })
+    }
+}
+
+// テストは「オペコードはフェッチ済みで、PC はその次を指している」状態から始まり、
+// 最後のMサイクルで次のオペコードをフェッチして終わる。
+// このCPUはオペコードのフェッチから命令を実行するので、PC を1つ戻して始め、
+// 最初のMサイクル（オペコードのフェッチ）を除いて、終わりに次のオペコードのフェッチを足して比べる
+fn run_case(cpu: &mut CPU<FlatBus>, case: &Case) -> Result<(), String> {
+    let initial = &case.initial;
+    cpu.pc = initial.pc.wrapping_sub(1);
+    cpu.sp = initial.sp;
+    cpu.registers.a = initial.a;
+    cpu.registers.b = initial.b;
+    cpu.registers.c = initial.c;
+    cpu.registers.d = initial.d;
+    cpu.registers.e = initial.e;
+    cpu.registers.f = FlagsRegister::from(initial.f);
+    cpu.registers.h = initial.h;
+    cpu.registers.l = initial.l;
+    cpu.ime = initial.ime;
+    cpu.ime_scheduled = false;
+    cpu.halted = false;
+    cpu.illegal_opcode = None;
+    cpu.call_stack.clear();
+    for &(address, value) in &initial.ram {
+        cpu.bus.memory[address as usize] = value;
+    }
+    cpu.bus.take_accesses();
+    let start = cpu.bus.cycles;
+
+    let cycles = cpu.step();
+    if let Some(opcode) = cpu.illegal_opcode {
+        return Err(format!("CPU locked up on illegal opcode ${:02X}", opcode));
+    }
+
+    let mut problems = Vec::new();
+    let mut actual_cycles = vec![None; cycles as usize / 4];
+    let next_fetch = (Access::Read, cpu.pc, cpu.bus.memory[cpu.pc as usize], cycles as u64);
+    let accesses = cpu.bus.take_accesses();
+    let accesses = accesses.iter().skip(1).map(|access| (access.access, access.address, access.value, access.cycle - start));
+    for (access, address, value, cycle) in accesses.chain([next_fetch]) {
+        let Some(slot) = (cycle as usize / 4).checked_sub(1).and_then(|index| actual_cycles.get_mut(index)) else { continue };
+        if slot.is_some() {
+            problems.push(format!("two accesses on M-cycle {}", cycle / 4));
+        }
+        *slot = Some((access == Access::Write, address, value));
+    }
+
+    let expected = &case.expected;
+    let registers = &cpu.registers;
+    let actual_registers = [registers.a, u8::from(registers.f), registers.b, registers.c, registers.d, registers.e, registers.h, registers.l];
+    let expected_registers = [expected.a, expected.f, expected.b, expected.c, expected.d, expected.e, expected.h, expected.l];
+    for ((name, actual), expected) in ["A", "F", "B", "C", "D", "E", "H", "L"].iter().zip(actual_registers).zip(expected_registers) {
+        if actual != expected {
+            problems.push(format!("{}={:02X} (expected {:02X})", name, actual, expected));
+        }
+    }
+    let pc = cpu.pc.wrapping_add(1);
+    if pc != expected.pc {
+        problems.push(format!("PC={:04X} (expected {:04X})", pc, expected.pc));
+    }
+    if cpu.sp != expected.sp {
+        problems.push(format!("SP={:04X} (expected {:04X})", cpu.sp, expected.sp));
+    }
+    if cpu.ime != expected.ime {
+        problems.push(format!("IME={} (expected {})", cpu.ime as u8, expected.ime as u8));
+    }
+    for &(address, value) in &expected.ram {
+        let actual = cpu.bus.memory[address as usize];
+        if actual != value {
+            problems.push(format!("[{:04X}]={:02X} (expected {:02X})", address, actual, value));
+        }
+    }
+    if actual_cycles.len() != case.cycles.len() {
+        problems.push(format!("{} M-cycles (expected {})", actual_cycles.len(), case.cycles.len()));
+    }
+    if actual_cycles != case.cycles {
+        problems.push(format!("bus {} (expected {})", format_cycles(&actual_cycles), format_cycles(&case.cycles)));
+    }
+    if problems.is_empty() { Ok(()) } else { Err(problems.join(", ")) }
+}
+
+fn format_cycles(cycles: &[Option<Transfer>]) -> String {
+    let cycles: Vec<String> = cycles
+        .iter()
+        .map(|cycle| match cycle {
+            Some((write, address, value)) => format!("{}{:04X}={:02X}", if *write { "W" } else { "R" }, address, value),
+            None => "----".to_string(),
+        })
+        .collect();
+    cycles.join(" ")
+}
+
+struct Report {
+    passed: usize,
+    failed: usize,
+    first_failure: Option<String>,
+}
+
+fn run_file(cpu: &mut CPU<FlatBus>, path: &Path) -> Result<Report, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let json = Parser::parse(&text)?;
+    let mut report = Report { passed: 0, failed: 0, first_failure: None };
+    for case in json.as_array() {
+        let case = Case::parse(case)?;
+        match run_case(cpu, &case) {
+            Ok(()) => report.passed += 1,
+            Err(problem) => {
+                report.failed += 1;
+                report.first_failure.get_or_insert_with(|| format!("{}: {}", case.name, problem));
+            },
+        }
+        // 次のケースに残らないよう、触ったアドレスだけ0に戻す
+        for &(address, _) in case.initial.ram.iter().chain(&case.expected.ram) {
+            cpu.bus.memory[address as usize] = 0;
+        }
+    }
+    Ok(report)
+}
+
+#[test]
+fn sm83() {
+    let directory = test_directory();
+    let mut files = Vec::new();
+    collect_files(&directory, &mut files);
+    if files.is_empty() {
+        eprintln!("skipping: no SingleStepTests files under {}", directory.display());
+        return;
+    }
+    files.sort();
+
+    // テストは64KBすべてを平らなメモリとして扱うので、周辺機器のない FlatBus で実行する
+    let mut cpu = CPU::with_bus(FlatBus::new());
+
+    let mut reports = HashMap::new();
+    for path in &files {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        reports.insert(name, run_file(&mut cpu, path));
+    }
+
+    let mut names: Vec<&String> = reports.keys().collect();
+    names.sort();
+    let mut failed_opcodes = Vec::new();
+    let (mut passed, mut total) = (0, 0);
+    for name in names {
+        match &reports[name] {
+            Ok(report) => {
+                let count = report.passed + report.failed;
+                passed += report.passed;
+                total += count;
+                let rate = if count == 0 { 100.0 } else { report.passed as f64 * 100.0 / count as f64 };
+                eprintln!("{:<6} {:>4}/{:<4} {:>5.1}%", name, report.passed, count, rate);
+                if let Some(failure) = &report.first_failure {
+                    eprintln!("       {}", failure);
+                    failed_opcodes.push(name.clone());
+                }
+            },
+            Err(error) => {
+                eprintln!("{:<6} unreadable: {}", name, error);
+                failed_opcodes.push(name.clone());
+            },
+        }
+    }
+    eprintln!("{}/{} cases passed", passed, total);
+    assert!(failed_opcodes.is_empty(), "opcodes with failures: {}", failed_opcodes.join(" "));
+}
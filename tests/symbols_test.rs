@@ -0,0 +1,104 @@
+use emulator::symbols::{Section, SymbolError, Symbols};
+
+const SYM: &str = "; File generated by rgblink
+00:0000 RST_00
+00:0150 Main
+00:0153 Main.loop
+01:4000 Bank1Start
+02:4000 Bank2Start
+02:4010 Bank2Start.inner
+00:c000 wPlayerX   ; コメント
+00:c001 wPlayerY
+";
+
+const MAP: &str = "SUMMARY:
+\tROM0: 339 bytes used / 16045 free
+
+ROM0 bank #0:
+\tSECTION: $0000-$0007 ($0008 bytes) [\"RST_00\"]
+\t         $0000 = RST_00
+\tSECTION: $0150-$0160 ($0011 bytes) [\"Main\"]
+\t         $0150 = Main
+\t         $015a = Main.extra
+\tEMPTY: $0161-$3fff ($3e9f bytes)
+
+ROMX bank #2:
+\tSECTION: $4000-$40ff ($0100 bytes) [\"Bank 2 code\"]
+\tSECTION: $4100 ($0000 bytes) [\"Empty\"]
+
+WRAM0 bank #0:
+\tSECTION: $c000-$c0ff ($0100 bytes) [\"Variables\"]
+";
+
+#[test]
+fn parses_sym_file() {
+    let symbols = Symbols::parse_sym(SYM).unwrap();
+    assert_eq!(symbols.symbols().len(), 8);
+    let symbol = symbols.find("Main.loop").unwrap();
+    assert_eq!((symbol.bank, symbol.address), (0, 0x0153));
+    assert_eq!(symbols.find("wPlayerX").unwrap().address, 0xC000);
+    assert!(symbols.find("main").is_none());
+}
+
+#[test]
+fn reports_line_of_bad_entry() {
+    let error = Symbols::parse_sym("00:0150 Main\n0150 Broken\n").unwrap_err();
+    assert_eq!(error, SymbolError { line: 2, message: "expected bank:address: 0150".to_string() });
+    assert_eq!(error.to_string(), "line 2: expected bank:address: 0150");
+    assert_eq!(Symbols::parse_sym("00:0150").unwrap_err().message, "expected 'bank:address name': 00:0150");
+    assert!(Symbols::parse_sym("zz:0150 Main").is_err());
+}
+
+#[test]
+fn switchable_bank_labels_need_matching_bank() {
+    let symbols = Symbols::parse_sym(SYM).unwrap();
+    assert_eq!(symbols.label(1, 0x4000), Some("Bank1Start"));
+    assert_eq!(symbols.label(2, 0x4000), Some("Bank2Start"));
+    assert_eq!(symbols.label(3, 0x4000), None);
+    // バンク0と RAM はバンクを問わない
+    assert_eq!(symbols.label(5, 0x0150), Some("Main"));
+    assert_eq!(symbols.label(5, 0xC001), Some("wPlayerY"));
+}
+
+#[test]
+fn describes_nearest_label() {
+    let symbols = Symbols::parse_sym(SYM).unwrap();
+    assert_eq!(symbols.describe(0, 0x0150).as_deref(), Some("Main"));
+    assert_eq!(symbols.describe(0, 0x0152).as_deref(), Some("Main+$2"));
+    assert_eq!(symbols.describe(0, 0x0160).as_deref(), Some("Main.loop+$D"));
+    assert_eq!(symbols.describe(2, 0x4012).as_deref(), Some("Bank2Start.inner+$2"));
+    assert_eq!(symbols.describe(1, 0x4012).as_deref(), Some("Bank1Start+$12"));
+    // 別の領域のラベルは使わない
+    assert_eq!(symbols.describe(0, 0x8000), None);
+}
+
+#[test]
+fn loads_sections_from_map_file() {
+    let mut symbols = Symbols::parse_sym(SYM).unwrap();
+    symbols.load_map(MAP);
+
+    assert_eq!(symbols.sections().len(), 4);
+    assert_eq!(
+        symbols.section(2, 0x4080),
+        Some(&Section { region: "ROMX".to_string(), bank: 2, start: 0x4000, end: 0x40FF, name: "Bank 2 code".to_string() })
+    );
+    assert_eq!(symbols.section(1, 0x4080), None);
+    assert_eq!(symbols.section(0, 0xC0A0).unwrap().name, "Variables");
+    assert_eq!(symbols.section(0, 0x0170), None);
+
+    // .sym になかったラベルだけ足される
+    assert_eq!(symbols.label(0, 0x015A), Some("Main.extra"));
+    assert_eq!(symbols.symbols().iter().filter(|symbol| symbol.name == "Main").count(), 1);
+}
+
+#[test]
+fn loads_old_map_format() {
+    let mut symbols = Symbols::new();
+    symbols.load_map(
+        "ROM Bank #0 (HOME):\n  SECTION: $0000-$0007 ($0008 bytes) [\"Vectors\"]\n           $0000 = Reset\nHRAM:\n  SECTION: $FF80-$FF8F ($0010 bytes) [\"HRAM vars\"]\n",
+    );
+    let section = symbols.section(0, 0xFF81).unwrap();
+    assert_eq!((section.region.as_str(), section.name.as_str()), ("HRAM", "HRAM vars"));
+    assert_eq!(symbols.section(0, 0x0003).unwrap().region, "ROM");
+    assert_eq!(symbols.label(0, 0x0000), Some("Reset"));
+}
@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator::cpu::CPU;
+use emulator::symbols::Symbols;
+use emulator::trace::{TraceFormat, TraceRecord, TraceSink, WriterTraceSink};
+
+struct RecordingSink(Rc<RefCell<Vec<TraceRecord>>>);
+
+impl TraceSink for RecordingSink {
+    fn record(&mut self, record: &TraceRecord) {
+        self.0.borrow_mut().push(*record);
+    }
+}
+
+#[test]
+fn step_records_state_before_each_instruction() {
+    let mut cpu = CPU::new();
+    let records = Rc::new(RefCell::new(Vec::new()));
+    cpu.tracer = Some(Box::new(RecordingSink(records.clone())));
+
+    // LD BC, 0x1234; INC B
+    for (i, &byte) in [0x01, 0x34, 0x12, 0x04].iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    cpu.step();
+    cpu.step();
+
+    let records = records.borrow();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].pc, 0x0000);
+    assert_eq!(records[0].memory, [0x01, 0x34, 0x12, 0x04]);
+    assert_eq!(records[0].cycles, 0);
+    assert_eq!(records[1].pc, 0x0003);
+    assert_eq!(records[1].b, 0x12);
+    assert_eq!(records[1].c, 0x34);
+    assert_eq!(records[1].cycles, 12);
+}
+
+#[test]
+fn halted_cpu_does_not_record() {
+    let mut cpu = CPU::new();
+    let records = Rc::new(RefCell::new(Vec::new()));
+    cpu.tracer = Some(Box::new(RecordingSink(records.clone())));
+    cpu.bus.write_byte(0x00, 0x76); // HALT
+
+    cpu.step();
+    cpu.step();
+    cpu.step();
+
+    assert_eq!(records.borrow().len(), 1);
+}
+
+#[test]
+fn gameboy_doctor_format() {
+    let record = TraceRecord {
+        pc: 0x0100,
+        memory: [0x00, 0xC3, 0x13, 0x02],
+        a: 0x01, f: 0xB0, b: 0x00, c: 0x13, d: 0x00, e: 0xD8, h: 0x01, l: 0x4D,
+        sp: 0xFFFE,
+        rom_bank: 1,
+        cycles: 0,
+    };
+
+    assert_eq!(
+        record.format(TraceFormat::GameboyDoctor),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+    );
+    assert_eq!(record.flags(), "Z-HC");
+}
+
+#[test]
+fn writer_sink_writes_one_line_per_record() {
+    let mut cpu = CPU::new();
+    cpu.step();
+    cpu.step();
+
+    let mut sink = WriterTraceSink::new(Vec::new(), TraceFormat::Full);
+    sink.record(&cpu.trace_record());
+    sink.flush().unwrap();
+    let text = String::from_utf8(sink.into_inner()).unwrap();
+
+    assert_eq!(text.lines().count(), 1);
+    assert!(text.starts_with("0002: 00        NOP"));
+    assert!(text.contains(" A:00 F:---- "));
+    assert!(text.trim_end().ends_with("CY:8"));
+}
+
+#[test]
+fn full_format_shows_labels() {
+    let mut cpu = CPU::new();
+    // JR -2
+    cpu.bus.write_byte(0x0000, 0x18);
+    cpu.bus.write_byte(0x0001, 0xFE);
+    let symbols = Symbols::parse_sym("00:0000 Main.loop\n").unwrap();
+
+    let mut sink = WriterTraceSink::new(Vec::new(), TraceFormat::Full).with_symbols(symbols.clone());
+    sink.record(&cpu.trace_record());
+    let text = String::from_utf8(sink.into_inner()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "Main.loop:");
+    assert!(lines[1].starts_with("0000: 18 FE     JR Main.loop "), "{}", lines[1]);
+
+    // Gameboy Doctor 形式はラベルを入れない
+    let record = cpu.trace_record();
+    assert!(record.format_with_symbols(TraceFormat::GameboyDoctor, &symbols).starts_with("A:00"));
+}
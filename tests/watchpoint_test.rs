@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator::asm;
+use emulator::condition::Condition;
+use emulator::cpu::CPU;
+use emulator::gameboy::GameBoy;
+use emulator::watchpoint::{Access, WatchHit, WatchKind};
+
+fn cpu_with_program(program: &[u8]) -> CPU {
+    let mut cpu = CPU::new();
+    for (i, &byte) in program.iter().enumerate() {
+        cpu.bus.write_byte(i as u16, byte);
+    }
+    cpu
+}
+
+#[test]
+fn write_reports_pc_and_values() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C000", "ld a, $42", "ld (hl), a"));
+    cpu.bus.write_byte(0xC000, 0x11);
+    let id = cpu.bus.watchpoints.add(WatchKind::Write, 0xC000..=0xC000, None);
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+    cpu.step();
+
+    let hits = cpu.bus.watchpoints.take_hits();
+    assert_eq!(
+        hits,
+        vec![WatchHit { id, access: Access::Write, address: 0xC000, pc: 0x0005, old_value: 0x11, new_value: 0x42 }]
+    );
+    assert_eq!(hits[0].to_string(), "Watchpoint 1: write C000 11 -> 42 (PC=0005)");
+    assert!(!cpu.bus.watchpoints.stopped());
+}
+
+#[test]
+fn value_condition() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C010", "ld (hl), 1", "ld (hl), 2"));
+    cpu.bus.watchpoints.add(WatchKind::Write, 0xC000..=0xC0FF, Some(2));
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+    cpu.step();
+    assert_eq!(cpu.bus.watchpoints.take_hits()[0].new_value, 2);
+}
+
+#[test]
+fn read_watchpoint_matches_range() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C005", "ld a, (hl)", "ld hl, $C100", "ld a, (hl)"));
+    cpu.bus.watchpoints.add(WatchKind::Read, 0xC000..=0xC0FF, None);
+
+    cpu.step();
+    cpu.step();
+    let hits = cpu.bus.watchpoints.take_hits();
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].access, hits[0].address), (Access::Read, 0xC005));
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+}
+
+#[test]
+fn access_watchpoint_matches_reads_and_writes() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C000", "inc (hl)"));
+    cpu.bus.watchpoints.add(WatchKind::Access, 0xC000..=0xC000, None);
+
+    cpu.step();
+    cpu.step();
+    let accesses: Vec<Access> = cpu.bus.watchpoints.take_hits().iter().map(|hit| hit.access).collect();
+    assert_eq!(accesses, vec![Access::Read, Access::Write]);
+}
+
+#[test]
+fn execute_watchpoint() {
+    let mut cpu = cpu_with_program(&asm!("nop", "nop", "inc a"));
+    cpu.bus.watchpoints.add(WatchKind::Execute, 0x0002..=0x0002, None);
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+    cpu.step();
+    let hits = cpu.bus.watchpoints.take_hits();
+    assert_eq!((hits[0].access, hits[0].pc, hits[0].new_value), (Access::Execute, 0x0002, 0x3C));
+}
+
+#[test]
+fn callback_does_not_stop() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C000", "ld (hl), 7", "ld (hl), 8"));
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&seen);
+    cpu.bus.watchpoints.add_callback(WatchKind::Write, 0xC000..=0xC000, None, move |hit| {
+        log.borrow_mut().push((hit.pc, hit.new_value));
+    });
+
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+    assert_eq!(*seen.borrow(), vec![(0x0003, 7), (0x0005, 8)]);
+}
+
+#[test]
+fn expression_condition() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C000", "inc (hl)", "inc (hl)", "inc (hl)"));
+    let id = cpu.bus.watchpoints.add(WatchKind::Write, 0xC000..=0xC000, None);
+    assert!(cpu.bus.watchpoints.set_condition(id, Some(Condition::parse("hits >= 2 && [hl] == 2").unwrap())));
+    assert!(!cpu.bus.watchpoints.set_condition(id + 1, None));
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+    cpu.step();
+    assert_eq!(cpu.bus.watchpoints.take_hits()[0].pc, 0x0004);
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+}
+
+#[test]
+fn remove_watchpoint() {
+    let mut cpu = cpu_with_program(&asm!("ld hl, $C000", "ld (hl), 7"));
+    let id = cpu.bus.watchpoints.add(WatchKind::Write, 0xC000..=0xC000, None);
+    assert!(cpu.bus.watchpoints.remove(id));
+    assert!(!cpu.bus.watchpoints.remove(id));
+
+    cpu.step();
+    cpu.step();
+    assert!(!cpu.bus.watchpoints.stopped());
+}
+
+#[test]
+fn peek_and_trace_do_not_trigger() {
+    let mut cpu = cpu_with_program(&asm!("nop"));
+    cpu.bus.watchpoints.add(WatchKind::Read, 0x0000..=0xFFFF, None);
+
+    cpu.bus.peek_byte(0xC000);
+    cpu.trace_record();
+    cpu.bus.watchpoints.dispatch(|_, _| true);
+    assert!(!cpu.bus.watchpoints.stopped());
+}
+
+#[test]
+fn gameboy_run_stops_after_hit() {
+    let program = asm!("ld hl, $C000", "loop: inc (hl)", "jr loop");
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    let mut gameboy = GameBoy::new(rom).unwrap();
+    gameboy.cpu.bus.watchpoints.add(WatchKind::Write, 0xC000..=0xC000, Some(3));
+
+    gameboy.run_frame();
+
+    assert!(gameboy.watchpoint_hit());
+    assert_eq!(gameboy.cpu.bus.read_byte(0xC000), 3);
+    assert_eq!(gameboy.cpu.pc, 0x0104);
+    assert_eq!(gameboy.cpu.bus.watchpoints.take_hits()[0].pc, 0x0103);
+    assert!(!gameboy.watchpoint_hit());
+}